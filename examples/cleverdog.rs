@@ -4,92 +4,775 @@ extern crate clap;
 extern crate log;
 
 use std::{
+    collections::HashMap,
+    env,
     error::Error,
-    io::{BufWriter, Write},
-    net::{SocketAddr, TcpStream, UdpSocket},
+    fs::{self, File},
+    io::{self, BufRead, BufWriter, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream, UdpSocket},
+    path::Path,
+    process::Command,
     sync::{
-        mpsc::{self, Receiver, SyncSender},
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
         Arc,
     },
     thread,
-    time::Duration,
+    time::{Duration, Instant, SystemTime},
 };
 
-use clap::{App, AppSettings, Arg, SubCommand};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+#[cfg(any(unix, windows))]
+use cleverdog::sink::FifoSink;
+#[cfg(unix)]
+use cleverdog::sink::{
+    shm::{self, ShmRing},
+    UnixKind, UnixSink,
+};
+#[cfg(feature = "decode")]
+use cleverdog::snapshot::{Decoder, KeyframeGrabber};
+use cleverdog::{
+    bandwidth::{Adaptive, Profile, ProfileSwitch, RateEstimator, Shed},
+    conformance,
+    control::ControlLog,
+    corpus::Corpus,
+    failover::Failover,
+    h264::{Depacketizer, FrameAssembler, Nal, StreamInfo},
+    http::FlvServer,
+    impair::{Impaired, Impairment},
+    metadata::Metadata,
+    mux::{
+        fmp4,
+        hls::{self, HlsWriter, SegmentFormat},
+        record::{Container, FileTemplate, Recorder},
+    },
+    pipeline::Threaded,
+    protocol::{Cid, LookupInfo, Token, VIDEO_SSRC},
+    proxy::Proxy,
+    resolve::{self, StaticResolver, SystemResolver},
+    retry::{self, RetryPolicy},
+    rtsp::{PathTemplate, Publisher, Server},
+    security::CAMERA_LINK_SECURITY,
+    sink::{self, AnnexBSink, Destination, DiskGuard, Endpoint, Enforcement, FileSink, Framing, Sink, UdpFanOut},
+    soak::{self, EventKind},
+    thermal::ThermalMonitor,
+    Cidr, DiscoveryCache, DiscoveryEvent, DiscoveryWatcher, Filter, LookupOptions, StreamHandle, StreamOptions,
+    SweepOptions, Target, WakePolicy,
+};
+#[cfg(feature = "srt")]
+use cleverdog::{
+    h264::{self, NalType},
+    mux::mpegts::TsWriter,
+    sink::{SrtOptions, SrtSink},
+};
 use rmpv::ValueRef;
 
-#[derive(Debug)]
-enum Address {
-    Udp(SocketAddr),
-    Https(String, u16),
+/// Starts publishing the given camera to the RTSP server under the specified path.
+fn bridge(endpoint: &Endpoint, path: &str, info: &LookupInfo) -> Result<StreamHandle, Box<dyn Error>> {
+    let mut publisher = Publisher::connect(&SystemResolver, endpoint, path, Duration::new(5, 0))?;
+    info!("camera {} is available at {}", info.cid(), publisher.url());
+
+    let handle = cleverdog::spawn_stream(info.cid(), info.addr(), StreamOptions::new(), move |buf| {
+        publisher.send(buf)
+    })?;
+
+    Ok(handle)
 }
 
-impl Address {
-    pub fn from_str(addr: &str) -> Result<Self, Box<dyn Error>> {
-        if !addr.contains("://") {
-            return Err("invalid address - must be an URL".into());
+/// Streams from the camera for the given duration, reconnecting whenever the session ends, and
+/// rewrites both reports after each sample so that an interrupted run still leaves one behind.
+fn soak_run(duration: Duration, interval: Duration, json: &str, html: &str) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    let mut report = soak::Report::new(SystemTime::now());
+    let mut handle: Option<StreamHandle> = None;
+    let mut last = Default::default();
+
+    while started.elapsed() < duration {
+        let elapsed = started.elapsed();
+
+        match handle.take() {
+            Some(session) if session.is_finished() => {
+                let detail = match session.join() {
+                    Ok(()) => "session ended".to_string(),
+                    Err(err) => err.to_string(),
+                };
+                warn!("streaming session ended: {}", detail);
+                report.disconnected(elapsed, &last, &detail);
+                last = Default::default();
+            }
+            Some(session) => {
+                last = session.stats();
+                report.sample(elapsed, &last, soak::resident_memory().ok());
+                handle = Some(session);
+            }
+            None => {}
         }
 
-        let mut it = addr.splitn(2, "://");
+        if handle.is_none() {
+            let spawned = cleverdog::lookup().map_err(Into::into).and_then(|info| {
+                cleverdog::spawn_stream(info.cid(), info.addr(), StreamOptions::new(), |_| Ok(()))
+                    .map(|session| (info, session))
+                    .map_err(Box::<dyn Error>::from)
+            });
+            match spawned {
+                Ok((info, session)) => {
+                    report.event(
+                        elapsed,
+                        EventKind::Started,
+                        &format!("{} at {}", info.cid(), info.addr()),
+                    );
+                    handle = Some(session);
+                }
+                Err(err) => report.disconnected(elapsed, &Default::default(), &err.to_string()),
+            }
+        }
 
-        let protocol = match it.next() {
-            Some(protocol) => protocol,
-            None => return Err("missing protocol".into()),
-        };
+        report.encode(&mut BufWriter::new(File::create(json)?))?;
+        report.encode_html(&mut BufWriter::new(File::create(html)?))?;
+        thread::sleep(interval.min(duration.saturating_sub(started.elapsed())));
+    }
 
-        let addr = match it.next() {
-            Some(addr) => addr,
-            None => return Err("missing address".into()),
-        };
+    if let Some(session) = handle {
+        last = session.stats();
+        if let Err(err) = session.stop() {
+            warn!("streaming session ended: {}", err);
+        }
+        report.sample(started.elapsed(), &last, soak::resident_memory().ok());
+    }
+    report.encode(&mut BufWriter::new(File::create(json)?))?;
+    report.encode_html(&mut BufWriter::new(File::create(html)?))?;
 
-        match protocol {
-            "udp" => {
-                let addr = addr.parse()?;
-                Ok(Address::Udp(addr))
-            }
-            "https" => {
-                let (host, port) = split_host_port(addr)?;
-                Ok(Address::Https(host.into(), port))
+    println!("Duration:    {:?}", started.elapsed());
+    println!("Reconnects:  {}", report.reconnects());
+    println!("Stalls:      {}", report.stalls());
+    if let Some(memory) = report.peak_memory() {
+        println!("Peak memory: {} KiB", memory / 1024);
+    }
+    println!("Reports:     {}, {}", json, html);
+
+    Ok(())
+}
+
+/// Encodes a datagram into a tunnel frame, a MessagePack binary value.
+fn tunnel_frame(buf: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut msg = Vec::new();
+    rmpv::encode::write_value_ref(&mut msg, &ValueRef::Binary(buf))?;
+    Ok(msg)
+}
+
+/// Self-signed certificate for `localhost`, used by the tunnel self-test only.
+const SELFTEST_CERT: &[u8] = include_bytes!("selftest/localhost.pem");
+/// Private key of the self-test certificate.
+const SELFTEST_KEY: &[u8] = include_bytes!("selftest/localhost.key");
+
+/// Runs the tunnel uploader against an in-process receiver over loopback TLS, checking that the
+/// given number of generated RTP packets are reassembled intact and in order.
+fn tunnel_selftest(count: u16) -> Result<(), Box<dyn Error>> {
+    let packets: Vec<Vec<u8>> = (0..count)
+        .map(|seq| {
+            let mut buf = vec![0x80, 96];
+            buf.extend_from_slice(&seq.to_be_bytes());
+            buf.extend_from_slice(&(u32::from(seq) * 3000).to_be_bytes());
+            buf.extend_from_slice(&VIDEO_SSRC.to_be_bytes());
+            // Sizes cover MessagePack bin8, bin16 and TLS record boundaries.
+            buf.extend((0..usize::from(seq) * 37 % 1400).map(|v| v as u8));
+            buf
+        })
+        .collect();
+
+    let certs = rustls::internal::pemfile::certs(&mut &SELFTEST_CERT[..]).map_err(|()| "invalid certificate")?;
+    let mut keys = rustls::internal::pemfile::pkcs8_private_keys(&mut &SELFTEST_KEY[..]).map_err(|()| "invalid key")?;
+    let mut cfg = rustls::ServerConfig::new(rustls::NoClientAuth::new());
+    cfg.set_single_cert(certs, keys.pop().ok_or("no private key")?)?;
+    let cfg = Arc::new(cfg);
+
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+
+    let receiver = thread::spawn(move || -> Result<Vec<Vec<u8>>, String> {
+        let (mut sock, ..) = listener.accept().map_err(|err| err.to_string())?;
+        let mut session = rustls::ServerSession::new(&cfg);
+        let mut stream = rustls::Stream::new(&mut session, &mut sock);
+
+        let mut received = Vec::new();
+        while received.len() < usize::from(count) {
+            match rmpv::decode::read_value(&mut stream).map_err(|err| err.to_string())? {
+                rmpv::Value::Binary(buf) => received.push(buf),
+                value => return Err(format!("unexpected frame: {}", value)),
             }
-            protocol => Err(format!("unknown protocol: {}", protocol).into()),
         }
+
+        Ok(received)
+    });
+
+    let mut cfg = rustls::ClientConfig::new();
+    cfg.root_store
+        .add_pem_file(&mut &SELFTEST_CERT[..])
+        .map_err(|()| "invalid certificate")?;
+    let hostname = webpki::DNSNameRef::try_from_ascii_str("localhost").expect("ASCII hostname");
+    let mut session = rustls::ClientSession::new(&Arc::new(cfg), hostname);
+    let mut sock = TcpStream::connect(addr)?;
+    {
+        let mut stream = BufWriter::new(rustls::Stream::new(&mut session, &mut sock));
+        for buf in &packets {
+            stream.write_all(&tunnel_frame(buf)?)?;
+        }
+        stream.flush()?;
+    }
+
+    let received = receiver.join().map_err(|_| "receiver panicked")??;
+    if let Some(seq) = (0..packets.len()).find(|&idx| packets[idx] != received[idx]) {
+        return Err(format!("packet #{} is corrupted", seq).into());
     }
+
+    let bytes: usize = packets.iter().map(|v| v.len()).sum();
+    println!("tunnel self-test passed: {} packets, {} bytes", packets.len(), bytes);
+
+    Ok(())
 }
 
-fn split_host_port(addr: &str) -> Result<(&str, u16), Box<dyn Error>> {
-    let mut it = addr.rsplitn(2, ':');
-    let port = match it.next() {
-        Some(port) => port.parse()?,
-        None => 443,
+/// Opens the given URL with the default application.
+fn open(url: &str) -> Result<(), Box<dyn Error>> {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else {
+        "xdg-open"
     };
 
-    let host = match it.next() {
-        Some(host) => host,
-        None => return Err("missing hostname".into()),
-    };
+    Command::new(opener).arg(url).spawn()?;
+    Ok(())
+}
+
+#[cfg(all(feature = "arp", target_os = "linux"))]
+fn print_arp_verification(info: &LookupInfo) {
+    use cleverdog::arp::{self, Verification};
 
-    Ok((host, port))
+    match arp::verify(info) {
+        Ok(Verification::Match) => println!("ARP:     ok"),
+        Ok(Verification::Mismatch { resolved, .. }) => {
+            println!("ARP:     MISMATCH, {} resolves to {}", info.addr().ip(), resolved)
+        }
+        Ok(Verification::Unknown) => println!("ARP:     no entry"),
+        Err(err) => println!("ARP:     failed to read ARP table: {}", err),
+    }
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+#[cfg(not(all(feature = "arp", target_os = "linux")))]
+fn print_arp_verification(_info: &LookupInfo) {}
 
-    let matches = App::new(crate_name!())
+/// Returns the command line interface.
+fn app() -> App<'static, 'static> {
+    App::new(crate_name!())
         .version(crate_version!())
         .author(crate_authors!())
         .setting(AppSettings::SubcommandRequired)
-        .subcommand(SubCommand::with_name("scan").about("scan local network for cleverdog camera(s)"))
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("scan local network for cleverdog camera(s)")
+                .arg(
+                    Arg::with_name("target")
+                        .long("target")
+                        .value_name("ADDRESS")
+                        .help("broadcast/unicast/multicast address or CIDR range to scan, e.g. 10.0.0.0/24")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("cidr")
+                        .long("cidr")
+                        .value_name("RANGE")
+                        .help("send the Scan command unicast to every host of the range, e.g. 10.0.1.0/24")
+                        .takes_value(true)
+                        .conflicts_with("target"),
+                )
+                .arg(
+                    Arg::with_name("concurrency")
+                        .long("concurrency")
+                        .value_name("SOCKETS")
+                        .default_value("8")
+                        .help("number of sockets probing hosts of the --cidr range in parallel")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rate")
+                        .long("rate")
+                        .value_name("PPS")
+                        .default_value("200")
+                        .help("maximum number of Scan commands sent per second when sweeping a --cidr range")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ipv6")
+                        .long("ipv6")
+                        .help("scan using IPv6 all-nodes multicast on the given --interface instead of broadcast"),
+                )
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .default_value("3")
+                        .help("how long to collect replies for when scanning the local network")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("interface")
+                        .long("interface")
+                        .value_name("NAME")
+                        .help("network interface to scan, e.g. eth0")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("conformance")
+                .about("check which protocol features the camera firmware supports")
+                .arg(
+                    Arg::with_name("duration")
+                        .long("duration")
+                        .value_name("SECONDS")
+                        .default_value("10")
+                        .help("how long to observe the stream")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .value_name("FILE")
+                        .help("write the capability report as JSON into the given file")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("tunnel-selftest")
+                .about("check the TLS tunnel framing end-to-end over loopback, without a camera or relay")
+                .arg(
+                    Arg::with_name("packets")
+                        .long("packets")
+                        .value_name("COUNT")
+                        .default_value("1000")
+                        .help("number of generated RTP packets to send")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("soak")
+                .about("stream for hours, recording stats, memory usage and reconnects into a report")
+                .arg(
+                    Arg::with_name("hours")
+                        .long("hours")
+                        .value_name("HOURS")
+                        .default_value("24")
+                        .help("duration of the run")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .value_name("SECONDS")
+                        .default_value("60")
+                        .help("interval between samples")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .value_name("FILE")
+                        .default_value("soak.json")
+                        .help("file the JSON report is written into")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("html")
+                        .long("html")
+                        .value_name("FILE")
+                        .default_value("soak.html")
+                        .help("file the HTML report is written into")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("repair")
+                .about("finalize file output segments and fMP4 recordings left behind by a crash")
+                .arg(
+                    Arg::with_name("framing")
+                        .long("framing")
+                        .value_name("FRAMING")
+                        .possible_values(&["raw", "length"])
+                        .default_value("length")
+                        .help("framing the file output segments were written with, ignored for *.mp4 recordings")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("file")
+                        .value_name("FILE")
+                        .help("segment or recording to repair, usually *.part")
+                        .required(true)
+                        .multiple(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("view")
+                .about("discover the camera and serve it over HTTP to a local player")
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .default_value("8080")
+                        .help("local HTTP port the stream is served at")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("open")
+                        .long("open")
+                        .help("open the stream URL with the default handler"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("hls")
+                .about("write the camera as HLS into a directory, to be served by any HTTP server")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["ts", "fmp4"])
+                        .default_value("ts")
+                        .help("container of segments")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("segment")
+                        .long("segment")
+                        .value_name("SECONDS")
+                        .default_value("2")
+                        .help("minimum duration of a segment")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("window")
+                        .long("window")
+                        .value_name("COUNT")
+                        .default_value("6")
+                        .help("number of segments listed in the playlist")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .value_name("DIR")
+                        .help("directory segments and playlist.m3u8 are written into")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("record")
+                .about("record the camera into files rotated by duration or size, as a minimal NVR")
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["mkv", "fmp4"])
+                        .default_value("mkv")
+                        .help("container of files")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("segment")
+                        .long("segment")
+                        .value_name("SECONDS")
+                        .default_value("900")
+                        .help("duration after which the next keyframe starts a new file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-size")
+                        .long("max-size")
+                        .value_name("BYTES")
+                        .help("size after which the next keyframe starts a new file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("pre-record")
+                        .long("pre-record")
+                        .value_name("SECONDS")
+                        .help("stay idle buffering this much footage, toggling recording on each line read from stdin")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("template")
+                        .long("template")
+                        .value_name("TEMPLATE")
+                        .default_value("{cid}/{date}/{time}")
+                        .help("path of files without the extension, with {cid}, {date} and {time} placeholders")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dir")
+                        .value_name("DIR")
+                        .help("directory files are written into")
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("snapshot")
+                .about("save the next keyframe as JPEG, decoded by ffmpeg (requires the decode feature)")
+                .arg(
+                    Arg::with_name("timeout")
+                        .long("timeout")
+                        .value_name("SECONDS")
+                        .default_value("10")
+                        .help("how long to wait for a keyframe")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .value_name("FILE")
+                        .default_value("snapshot.jpg")
+                        .help("file the image is written into"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("serve the camera over RTSP to players and NVRs, or as HTTP-FLV to browsers")
+                .arg(
+                    Arg::with_name("url")
+                        .value_name("URL")
+                        .default_value("rtsp://0.0.0.0:8554/live")
+                        .help("address and path the stream is served at, or http://ADDR to serve /live.flv"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("bridge")
+                .about("publish every discovered camera to an RTSP server, e.g. MediaMTX")
+                .arg(
+                    Arg::with_name("server")
+                        .value_name("URL")
+                        .help("RTSP server to publish to, e.g. rtsp://localhost:8554/")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("alias")
+                        .long("alias")
+                        .value_name("CID=NAME")
+                        .help("publish the camera with the given ID under the given name instead of its ID")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("path-template")
+                        .long("path-template")
+                        .value_name("TEMPLATE")
+                        .default_value("/{alias}")
+                        .help(
+                            "path each camera is published under, relative to the server URL, \
+                             e.g. /cam/{alias}/{profile}",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("interval")
+                        .long("interval")
+                        .value_name("SECONDS")
+                        .default_value("30")
+                        .help("how often to rescan the network for cameras")
+                        .takes_value(true),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("stream")
                 .about("stream H264 from camera")
                 .arg(
                     Arg::with_name("addr")
                         .long("addr")
+                        .alias("output")
                         .value_name("ADDRESS")
-                        .help("network address, udp:// or https://")
+                        .help(
+                            "destination URL: udp://, tls:// (https://), file://, fifo://, shm://, unix://, \
+                             unixgram://, rtsp://, srt:// (with the srt feature) or - for Annex-B on stdout",
+                        )
                         .required(true)
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("interface")
+                        .long("interface")
+                        .value_name("NAME")
+                        .help("network interface to scan and receive RTP on, e.g. eth0")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("camera")
+                        .long("camera")
+                        .value_name("MAC|CID")
+                        .help("stream only from the camera with the given MAC address or ID")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("cache")
+                        .long("cache")
+                        .value_name("PATH")
+                        .help("remember discovered cameras in the given file and try the cached address first")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .value_name("TOKEN")
+                        .help("38-character authentication token for cameras bound to a vendor account")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("bind")
+                        .long("bind")
+                        .value_name("ADDRESS")
+                        .help("local address to receive RTP on, e.g. 0.0.0.0:40000")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("stall-timeout")
+                        .long("stall-timeout")
+                        .value_name("SECONDS")
+                        .help("time without video after which the camera is considered stalled")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("restarts")
+                        .long("restarts")
+                        .value_name("N")
+                        .help("how many times to re-send StartRtp to a stalled camera before giving up")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("advertise-port")
+                        .long("advertise-port")
+                        .value_name("PORT")
+                        .help("RTP port advertised to the camera, when behind NAT relative to it")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("corpus")
+                        .long("corpus")
+                        .value_name("DIR")
+                        .help("save malformed datagrams into the given fuzzing corpus directory")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("control-log")
+                        .long("control-log")
+                        .value_name("FILE")
+                        .help("record command frames exchanged with the camera into a JSONL file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("impair")
+                        .long("impair")
+                        .value_name("SPEC")
+                        .help("impair the stream before forwarding, e.g. loss=2%,jitter=30ms,reorder=1%")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("sni")
+                        .long("sni")
+                        .value_name("NAME")
+                        .help("server name sent in the TLS handshake, defaults to the relay host")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("backup")
+                        .long("backup")
+                        .value_name("URL")
+                        .help("backup TLS relay used when the primary one fails, in priority order")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("proxy")
+                        .long("proxy")
+                        .value_name("URL")
+                        .help(
+                            "connect to the relay through the given proxy, \
+                             e.g. http://proxy:3128 or socks5://proxy:1080",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("resolve")
+                        .long("resolve")
+                        .value_name("HOST:ADDRESS")
+                        .help("use the given IP address for the relay host instead of DNS")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("framing")
+                        .long("framing")
+                        .value_name("FRAMING")
+                        .possible_values(&["raw", "length"])
+                        .default_value("length")
+                        .help("file, FIFO and Unix stream socket output framing, raw or length-prefixed packets")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rotate-size")
+                        .long("rotate-size")
+                        .value_name("BYTES")
+                        .help("start a new file output segment after the given size")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rotate-every")
+                        .long("rotate-every")
+                        .value_name("SECONDS")
+                        .help("start a new file output segment after the given time")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("min-free")
+                        .long("min-free")
+                        .value_name("BYTES")
+                        .help("keep at least the given space free on the file output volume")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("on-low-space")
+                        .long("on-low-space")
+                        .value_name("ACTION")
+                        .possible_values(&["delete", "pause"])
+                        .default_value("delete")
+                        .help("delete oldest file output segments or pause recording when running out of space")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-temp")
+                        .long("max-temp")
+                        .value_name("CELSIUS")
+                        .help(
+                            "forward keyframes only while the host CPU temperature exceeds the given value, \
+                             recovering 10°C below",
+                        )
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("alias")
+                        .long("alias")
+                        .value_name("NAME")
+                        .help("camera name stored in recording metadata")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("timezone")
+                        .long("timezone")
+                        .value_name("TZ")
+                        .help("camera timezone stored in recording metadata, e.g. Europe/Berlin")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("require-encryption")
+                        .long("require-encryption")
+                        .help("refuse to forward the stream over plaintext network legs"),
+                )
                 .arg(
                     Arg::with_name("retries")
                         .long("retries")
@@ -97,63 +780,592 @@ fn main() -> Result<(), Box<dyn Error>> {
                         .default_value("18446744073709551615")
                         .help("number of retries in case of camera hanging")
                         .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("wake-interval")
+                        .long("wake-interval")
+                        .value_name("MILLISECONDS")
+                        .help("interval after the first probe waking up a camera before a retry, doubled after each")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("wake-max-interval")
+                        .long("wake-max-interval")
+                        .value_name("MILLISECONDS")
+                        .help("maximum interval between probes waking up a camera")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("wake-attempts")
+                        .long("wake-attempts")
+                        .value_name("N")
+                        .help("how many probes to send waking up a camera before a retry")
+                        .takes_value(true),
                 ),
         )
-        .get_matches();
+}
+
+/// Returns the number of stream attempts, saturated to what a retry policy can count.
+///
+/// The default is `u64::MAX`, i.e. attempting for as long as the process lives.
+fn retries(matches: &ArgMatches) -> Result<u32, Box<dyn Error>> {
+    // This cannot panic because of CLAP default value.
+    let num: u64 = matches.value_of("retries").unwrap().parse()?;
+    Ok(num.min(u64::from(u32::MAX)) as u32)
+}
+
+/// Returns the policy of waking up the camera before a retry, defaulting unset parameters.
+fn wake_policy(matches: &ArgMatches) -> Result<WakePolicy, Box<dyn Error>> {
+    let default = WakePolicy::default();
+    let millis = |name| -> Result<Option<Duration>, Box<dyn Error>> {
+        match matches.value_of(name) {
+            Some(v) => Ok(Some(Duration::from_millis(v.parse()?))),
+            None => Ok(None),
+        }
+    };
+
+    let initial = millis("wake-interval")?.unwrap_or_else(|| default.initial());
+    let max = millis("wake-max-interval")?.unwrap_or_else(|| default.max());
+    let attempts = match matches.value_of("wake-attempts") {
+        Some(v) => v.parse()?,
+        None => default.attempts(),
+    };
+
+    Ok(WakePolicy::new(initial, max, attempts))
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    env_logger::init();
+
+    let matches = app().get_matches();
 
     match matches.subcommand() {
-        ("scan", ..) => {
+        ("scan", Some(matches)) => {
+            let infos = match matches.values_of("target") {
+                None if matches.is_present("cidr") => {
+                    let cidr: Cidr = matches.value_of("cidr").unwrap().parse()?;
+                    let opts = SweepOptions::new()
+                        .concurrency(matches.value_of("concurrency").unwrap().parse()?)
+                        .rate(matches.value_of("rate").unwrap().parse()?);
+                    cleverdog::sweep(&cidr, &opts)?
+                }
+                Some(targets) => {
+                    let targets = targets.map(str::parse).collect::<Result<Vec<Target>, _>>()?;
+                    cleverdog::lookup_targets(&targets)?
+                }
+                None => {
+                    let timeout = Duration::from_secs(matches.value_of("timeout").unwrap().parse()?);
+                    let mut opts = LookupOptions::new();
+                    if let Some(interface) = matches.value_of("interface") {
+                        opts = opts.interface(interface);
+                    }
+                    if matches.is_present("ipv6") {
+                        opts = opts.target(Target::all_nodes(0));
+                    }
+                    cleverdog::lookup_all_with(&opts, timeout)?
+                }
+            };
+
+            for (idx, info) in infos.iter().enumerate() {
+                if idx > 0 {
+                    println!();
+                }
+
+                println!("Address: {}", info.addr());
+                println!("CID:     {}", info.cid());
+                println!("MAC:     {}", info.mac());
+                println!("Version: {}", info.version());
+                println!("Link:    {} UDP", CAMERA_LINK_SECURITY);
+                print_arp_verification(info);
+            }
+        }
+        ("conformance", Some(matches)) => {
+            // This cannot panic because of CLAP default value.
+            let duration = Duration::from_secs(matches.value_of("duration").unwrap().parse()?);
+
+            let info = cleverdog::lookup()?;
+            println!("Camera {} at {}", info.cid(), info.addr());
+            println!();
+
+            let report = conformance::run(&info, duration);
+            print!("{}", report);
+
+            if let Some(path) = matches.value_of("json") {
+                report.encode(&mut File::create(path)?)?;
+            }
+        }
+        ("tunnel-selftest", Some(matches)) => {
+            // This cannot panic because of CLAP default value.
+            tunnel_selftest(matches.value_of("packets").unwrap().parse()?)?;
+        }
+        ("soak", Some(matches)) => {
+            // This cannot panic because of CLAP default values.
+            let hours: f64 = matches.value_of("hours").unwrap().parse()?;
+            let interval = Duration::from_secs(matches.value_of("interval").unwrap().parse()?);
+            let json = matches.value_of("json").unwrap();
+            let html = matches.value_of("html").unwrap();
+
+            soak_run(Duration::from_secs_f64(hours * 3600.0), interval, json, html)?;
+        }
+        ("repair", Some(matches)) => {
+            let framing = match matches.value_of("framing") {
+                Some("raw") => Framing::Raw,
+                _ => Framing::LengthPrefixed,
+            };
+
+            // This cannot panic because of CLAP required flag.
+            for path in matches.values_of("file").unwrap() {
+                if path.strip_suffix(".part").unwrap_or(path).ends_with(".mp4") {
+                    let repaired = fmp4::repair(path)?;
+                    println!(
+                        "{}: {} fragments, {} keyframes indexed, {} bytes truncated",
+                        repaired.path.display(),
+                        repaired.fragments,
+                        repaired.keyframes,
+                        repaired.truncated
+                    );
+                    continue;
+                }
+
+                let repaired = sink::repair(path, framing)?;
+
+                print!("{}: ", repaired.path.display());
+                if let Some(records) = repaired.records {
+                    print!("{} records, ", records);
+                }
+                println!("{} bytes truncated", repaired.truncated);
+            }
+        }
+        ("view", Some(matches)) => {
+            // This cannot panic because of CLAP default value.
+            let port: u16 = matches.value_of("port").unwrap().parse()?;
+
+            let info = cleverdog::lookup()?;
+            let mut sink = FlvServer::bind((Ipv4Addr::LOCALHOST, port))?;
+
+            println!("Camera {} at {}", info.cid(), info.addr());
+            println!();
+            println!("Open {} in VLC or mpv, or run:", sink.url());
+            println!("  ffplay {}", sink.url());
+
+            if matches.is_present("open") {
+                open(&sink.url())?;
+            }
+
+            let mut depacketizer = Some(Depacketizer::new());
+            cleverdog::stream(info.cid(), info.addr(), |buf| {
+                // Print the stream parameters once the first sequence parameter set arrives.
+                if let Some(units) = depacketizer.as_mut().and_then(|v| v.push(buf).ok()) {
+                    if let Some(stream) = units
+                        .iter()
+                        .filter_map(|v| Nal::new(v))
+                        .find_map(|v| StreamInfo::from_sps(&v).ok())
+                    {
+                        println!("Stream: {}", stream);
+                        depacketizer = None;
+                    }
+                }
+                sink.send(buf)
+            })?;
+        }
+        ("hls", Some(matches)) => {
+            // This cannot panic because of CLAP required flag and default values.
+            let dir = matches.value_of("dir").unwrap();
+            let format = match matches.value_of("format") {
+                Some("fmp4") => SegmentFormat::Fmp4,
+                _ => SegmentFormat::Ts,
+            };
+            let segment = Duration::from_secs_f64(matches.value_of("segment").unwrap().parse()?);
+            let window: usize = matches.value_of("window").unwrap().parse()?;
+
+            fs::create_dir_all(dir)?;
+            let mut hls = HlsWriter::new(dir)
+                .format(format)
+                .target_duration(segment)
+                .window(window);
+            let mut assembler = FrameAssembler::new();
+
+            let info = cleverdog::lookup()?;
+            println!("Camera {} at {}", info.cid(), info.addr());
+            println!("Playlist: {}", Path::new(dir).join(hls::PLAYLIST).display());
+
+            cleverdog::stream(info.cid(), info.addr(), |packet| {
+                match assembler.push(packet) {
+                    Ok(frames) => {
+                        for frame in frames {
+                            hls.write_frame(&frame)?;
+                        }
+                    }
+                    Err(err) => debug!("skipping packet: {}", err),
+                }
+                Ok(())
+            })?;
+        }
+        ("record", Some(matches)) => {
+            // This cannot panic because of CLAP required flag and default values.
+            let dir = matches.value_of("dir").unwrap();
+            let container = match matches.value_of("format") {
+                Some("fmp4") => Container::Fmp4,
+                _ => Container::Mkv,
+            };
+            let segment = Duration::from_secs_f64(matches.value_of("segment").unwrap().parse()?);
+            let template: FileTemplate = matches.value_of("template").unwrap().parse()?;
+
             let info = cleverdog::lookup()?;
-            println!("Address: {}", info.addr());
-            println!("CID:     {}", core::str::from_utf8(info.cid())?);
-            println!("MAC:     {}", info.mac());
-            println!("Version: {}", info.version());
+            println!("Camera {} at {}", info.cid(), info.addr());
+
+            let mut recorder = Recorder::new(dir, info.cid())
+                .container(container)
+                .template(template)
+                .max_duration(segment);
+            if let Some(size) = matches.value_of("max-size") {
+                recorder = recorder.max_size(size.parse()?);
+            }
+            let (tx, triggers) = mpsc::channel();
+            if let Some(duration) = matches.value_of("pre-record") {
+                recorder = recorder.pre_record(Duration::from_secs_f64(duration.parse()?));
+                println!("Press Enter to start or stop recording");
+                thread::spawn(move || {
+                    for _ in io::stdin().lock().lines() {
+                        if tx.send(()).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            let mut assembler = FrameAssembler::new();
+
+            let result = cleverdog::stream(info.cid(), info.addr(), |packet| {
+                while triggers.try_recv().is_ok() {
+                    if recorder.is_recording() {
+                        recorder.stop()?;
+                        println!("Recording stopped");
+                    } else {
+                        recorder.start()?;
+                        println!("Recording started");
+                    }
+                }
+                match assembler.push(packet) {
+                    Ok(frames) => {
+                        for frame in frames {
+                            recorder.write_frame(&frame)?;
+                        }
+                    }
+                    Err(err) => debug!("skipping packet: {}", err),
+                }
+                Ok(())
+            });
+            recorder.finish()?;
+            result?;
+        }
+        #[cfg(feature = "decode")]
+        ("snapshot", Some(matches)) => {
+            // This cannot panic because of CLAP default values.
+            let output = matches.value_of("output").unwrap();
+            let timeout = Duration::from_secs(matches.value_of("timeout").unwrap().parse()?);
+
+            let info = cleverdog::lookup()?;
+            println!("Camera {} at {}", info.cid(), info.addr());
+
+            let (tx, rx) = mpsc::sync_channel(1);
+            let mut assembler = FrameAssembler::new();
+            let mut grabber = KeyframeGrabber::new();
+            let handle = cleverdog::spawn_stream(info.cid(), info.addr(), StreamOptions::new(), move |packet| {
+                match assembler.push(packet) {
+                    Ok(frames) => {
+                        for keyframe in frames.iter().filter_map(|v| grabber.push(v)) {
+                            let _ = tx.try_send(keyframe);
+                        }
+                    }
+                    Err(err) => debug!("skipping packet: {}", err),
+                }
+                Ok(())
+            })?;
+            let keyframe = rx.recv_timeout(timeout);
+            handle.stop()?;
+            let keyframe = keyframe.map_err(|_| "no keyframe received in time")?;
+
+            fs::write(output, Decoder::new().jpeg(&keyframe)?)?;
+            match keyframe.stream_info() {
+                Some(stream) => println!("Saved snapshot ({}) into {}", stream, output),
+                None => println!("Saved snapshot into {}", output),
+            }
+        }
+        #[cfg(not(feature = "decode"))]
+        ("snapshot", Some(..)) => return Err("snapshots require the decode feature".into()),
+        ("serve", Some(matches)) => {
+            // This cannot panic because of CLAP default value.
+            let url = matches.value_of("url").unwrap();
+            let (mut server, url): (Box<dyn Sink>, _) = match url.strip_prefix("http://") {
+                Some(addr) => {
+                    let server = FlvServer::bind(addr.trim_end_matches('/'))?;
+                    let url = server.url();
+                    (Box::new(server), url)
+                }
+                None => {
+                    let (endpoint, path) = match url.parse()? {
+                        Destination::Rtsp { endpoint, path } => (endpoint, path),
+                        dst => return Err(format!("serve address must be an RTSP or HTTP URL: {}", dst).into()),
+                    };
+                    let server = Server::bind((endpoint.host(), endpoint.port()), &path)?;
+                    let url = server.url();
+                    (Box::new(server), url)
+                }
+            };
+
+            let info = cleverdog::lookup()?;
+            println!("Camera {} at {}", info.cid(), info.addr());
+            println!("Serving at {}", url);
+
+            cleverdog::stream(info.cid(), info.addr(), |buf| server.send(buf))?;
+        }
+        ("bridge", Some(matches)) => {
+            // This cannot panic because of CLAP required flag.
+            let (endpoint, base) = match matches.value_of("server").unwrap().parse()? {
+                Destination::Rtsp { endpoint, path } => (endpoint, path),
+                dst => return Err(format!("bridge destination must be an RTSP server: {}", dst).into()),
+            };
+            let aliases = matches
+                .values_of("alias")
+                .into_iter()
+                .flatten()
+                .map(|v| match v.split_once('=') {
+                    Some((cid, name)) => Ok((cid.to_string(), name.to_string())),
+                    None => Err(format!("alias must be in CID=NAME form: {}", v)),
+                })
+                .collect::<Result<HashMap<_, _>, _>>()?;
+            let template: PathTemplate = matches.value_of("path-template").unwrap().parse()?;
+            let interval = Duration::from_secs(matches.value_of("interval").unwrap().parse()?);
+
+            let start = |info: &LookupInfo| {
+                let cid = info.cid().to_string();
+                let alias = aliases.get(&cid).map(|v| v.as_str());
+                // Cameras are relayed as is, so only the main profile is ever published.
+                let path = template.render(info, alias, Profile::Main);
+                match bridge(
+                    &endpoint,
+                    &format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/')),
+                    info,
+                ) {
+                    Ok(handle) => Some(handle),
+                    Err(err) => {
+                        error!("failed to bridge camera {}: {}", cid, err);
+                        None
+                    }
+                }
+            };
+
+            let watcher = DiscoveryWatcher::spawn(LookupOptions::new(), interval)?;
+            let mut bridged: HashMap<Cid, (LookupInfo, StreamHandle)> = HashMap::new();
+
+            loop {
+                match watcher.events().recv_timeout(interval) {
+                    Ok(DiscoveryEvent::CameraAppeared(info)) | Ok(DiscoveryEvent::CameraChangedAddr { info, .. }) => {
+                        bridged.remove(info.cid());
+                        if let Some(handle) = start(&info) {
+                            bridged.insert(*info.cid(), (info, handle));
+                        }
+                    }
+                    Ok(DiscoveryEvent::CameraDisappeared(info)) => {
+                        info!("camera {} disappeared, unpublishing", info.cid());
+                        bridged.remove(info.cid());
+                    }
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+
+                // Sessions end on errors, e.g. when the server restarts; they are retried on rescans.
+                let finished: Vec<Cid> = bridged
+                    .iter()
+                    .filter(|(.., (.., handle))| handle.is_finished())
+                    .map(|(cid, ..)| *cid)
+                    .collect();
+                for cid in finished {
+                    if let Some((info, handle)) = bridged.remove(&cid) {
+                        if let Err(err) = handle.join() {
+                            warn!("bridging camera {} stopped: {}", info.cid(), err);
+                        }
+                        if let Some(handle) = start(&info) {
+                            bridged.insert(cid, (info, handle));
+                        }
+                    }
+                }
+            }
         }
         ("stream", Some(matches)) => {
             // This cannot panic because of CLAP required flag.
             let dst = matches.value_of("addr").unwrap();
-            let mut num: u64 = matches.value_of("retries").unwrap().parse()?;
+            let num = retries(matches)?;
 
-            let addr = Address::from_str(dst)?;
+            let mut addr: Destination = dst.parse()?;
+            if let Some(name) = matches.value_of("sni") {
+                addr = addr.with_sni(name)?;
+            }
             info!("Destination address: {:?}", addr);
 
-            let info = cleverdog::lookup()?;
+            let mut opts = StreamOptions::new();
+            let mut lookup_opts = LookupOptions::new();
+            if let Some(interface) = matches.value_of("interface") {
+                opts = opts.interface(interface);
+                lookup_opts = lookup_opts.interface(interface);
+            }
+            if let Some(camera) = matches.value_of("camera") {
+                lookup_opts = lookup_opts.filter(camera.parse::<Filter>()?);
+            }
+            if let Some(token) = matches.value_of("token") {
+                let token: Token = token.parse()?;
+                opts = opts.token(token);
+                lookup_opts = lookup_opts.token(token);
+            }
+            if let Some(bind) = matches.value_of("bind") {
+                opts = opts.bind(bind.parse()?);
+            }
+            if let Some(port) = matches.value_of("advertise-port") {
+                opts = opts.advertised_port(port.parse()?);
+            }
+            if let Some(timeout) = matches.value_of("stall-timeout") {
+                opts = opts.stall_timeout(Duration::from_secs(timeout.parse()?));
+            }
+            if let Some(restarts) = matches.value_of("restarts") {
+                opts = opts.restarts(restarts.parse()?);
+            }
+            if let Some(dir) = matches.value_of("corpus") {
+                let corpus = Arc::new(Corpus::new(dir)?);
+                opts = opts.corpus(corpus.clone());
+                lookup_opts = lookup_opts.corpus(corpus);
+            }
+            if let Some(path) = matches.value_of("control-log") {
+                opts = opts.control_log(Arc::new(ControlLog::create(path)?));
+            }
+
+            let mut resolver = StaticResolver::new(SystemResolver);
+            for spec in matches.values_of("resolve").into_iter().flatten() {
+                let mut it = spec.splitn(2, ':');
+                match (it.next(), it.next()) {
+                    (Some(host), Some(addr)) => resolver.push(host, addr.parse()?),
+                    (..) => return Err(format!("invalid host override: {}", spec).into()),
+                }
+            }
+
+            let impairment: Impairment = match matches.value_of("impair") {
+                Some(v) => v.parse()?,
+                None => Impairment::default(),
+            };
+
+            let thermal = ProfileSwitch::new();
+            if let Some(high) = matches.value_of("max-temp") {
+                let high: f32 = high.parse()?;
+                let mut monitor = ThermalMonitor::new(high, high - 10.0);
+                let thermal = thermal.clone();
+
+                thread::spawn(move || loop {
+                    match monitor.poll() {
+                        Ok(Some(event)) => {
+                            warn!(
+                                "{} mode at {:.1}°C, switching to {} stream",
+                                event.mode,
+                                event.celsius,
+                                event.mode.profile()
+                            );
+                            thermal.set(event.mode.profile());
+                        }
+                        Ok(None) => {}
+                        Err(err) => {
+                            warn!("failed to read host temperature, thermal monitoring disabled: {}", err);
+                            break;
+                        }
+                    }
+                    thread::sleep(Duration::from_secs(10));
+                });
+            }
+
+            info!("Transport security:");
+            info!("  camera -> host:  {} UDP", CAMERA_LINK_SECURITY);
+            match addr.security() {
+                Some(security) => {
+                    info!("  host -> relay:   {}", security);
+                    if !security.is_encrypted() {
+                        if matches.is_present("require-encryption") {
+                            return Err("plaintext destination refused by --require-encryption".into());
+                        }
+                        warn!("the stream is forwarded unencrypted");
+                    }
+                }
+                None => info!("  host -> output:  local"),
+            }
+
+            let mut info = match matches.value_of("cache") {
+                Some(path) => cleverdog::lookup_cached(&mut DiscoveryCache::open(path)?, &lookup_opts)?,
+                None => cleverdog::lookup_with(&lookup_opts)?,
+            };
             info!("Successfully resolved camera");
             info!("  Address: {}", info.addr());
-            info!("  CID:     {}", core::str::from_utf8(info.cid())?);
+            info!("  CID:     {}", info.cid());
             info!("  MAC:     {}", info.mac());
             info!("  Version: {}", info.version());
 
+            let framing = match matches.value_of("framing") {
+                Some("raw") => Framing::Raw,
+                _ => Framing::LengthPrefixed,
+            };
+
             match addr {
-                Address::Udp(addr) => {
-                    let sock = UdpSocket::bind("0.0.0.0:0")?;
+                Destination::Udp(addr) => {
+                    let sink = UdpFanOut::new(UdpSocket::bind("0.0.0.0:0")?).with(addr);
+                    let mut sink = Impaired::new(Shed::new(sink, thermal.clone()), impairment);
 
-                    cleverdog::stream(info.cid(), info.addr(), |buf| {
-                        debug!("-> {}", buf.len());
-                        sock.send_to(buf, addr)?;
-                        Ok(())
-                    })?;
+                    cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf))?;
                 }
-                Address::Https(host, port) => {
-                    let addr = format!("{}:{}", host, port);
+                Destination::Tls { endpoint, sni } => {
+                    let sni = sni.ok_or("server name is required for IP literal hosts, use --sni")?;
+
+                    let mut failover = Failover::new((endpoint, sni));
+                    for backup in matches.values_of("backup").into_iter().flatten() {
+                        match backup.parse()? {
+                            Destination::Tls {
+                                endpoint,
+                                sni: Some(sni),
+                            } => failover = failover.backup((endpoint, sni)),
+                            Destination::Tls { sni: None, .. } => {
+                                return Err(format!("server name is required for backup relay {}", backup).into())
+                            }
+                            _ => return Err(format!("backup relay must be a TLS destination: {}", backup).into()),
+                        }
+                    }
 
                     let (tx, rx): (SyncSender<Vec<u8>>, Receiver<Vec<u8>>) = mpsc::sync_channel(4096);
 
+                    let proxy: Option<Proxy> = match matches.value_of("proxy") {
+                        Some(v) => Some(v.parse()?),
+                        None => None,
+                    };
+                    let dial = move |endpoint: &Endpoint| match &proxy {
+                        Some(proxy) => proxy.connect(&resolver, endpoint.host(), endpoint.port(), Duration::new(5, 0)),
+                        None => resolve::connect(&resolver, endpoint.host(), endpoint.port(), Duration::new(5, 0)),
+                    };
+
                     let thread = thread::spawn(move || {
                         let mut cfg = rustls::ClientConfig::new();
                         cfg.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
                         let cfg = Arc::new(cfg);
-                        let hostname = webpki::DNSNameRef::try_from_ascii_str(&host).expect("ASCII hostname");
+
+                        let reconnect = RetryPolicy::exponential(Duration::new(1, 0), Duration::new(30, 0)).jitter(0.2);
+                        let mut delays = reconnect.delays();
 
                         loop {
+                            let (endpoint, sni) = failover.active().clone();
+                            let addr = endpoint.to_string();
+                            let hostname = webpki::DNSNameRef::try_from_ascii_str(&sni).expect("ASCII hostname");
                             let mut session = rustls::ClientSession::new(&cfg, hostname);
 
                             debug!("connecting to {}", addr);
-                            let mut stream = match TcpStream::connect(&addr) {
+                            // Resolve on every attempt to follow DNS changes of the relay.
+                            let mut stream = match dial(&endpoint) {
                                 Ok(stream) => stream,
                                 Err(err) => {
                                     error!("failed to connect to {}: {}", addr, err);
-                                    thread::sleep(Duration::new(1, 0));
+                                    failover.on_failure(Instant::now());
+                                    thread::sleep(delays.next().unwrap_or_default());
                                     continue;
                                 }
                             };
@@ -161,44 +1373,198 @@ fn main() -> Result<(), Box<dyn Error>> {
                             let mut stream = BufWriter::new(rustls::Stream::new(&mut session, &mut stream));
 
                             info!("successfully connected to {}", addr);
+                            failover.on_success();
+                            delays = reconnect.delays();
 
                             while let Ok(buf) = rx.recv() {
                                 if let Err(err) = stream.write_all(&buf) {
                                     error!("failed to send bytes: {}", err);
+                                    failover.on_failure(Instant::now());
                                     break;
                                 }
+
+                                let now = Instant::now();
+                                if failover.should_probe(now) {
+                                    let (primary, ..) = failover.primary();
+                                    let ok = dial(primary).is_ok();
+                                    if failover.on_probe(ok, now).is_some() {
+                                        break;
+                                    }
+                                }
                             }
 
-                            thread::sleep(Duration::new(1, 0));
+                            thread::sleep(delays.next().unwrap_or_default());
                         }
                     });
 
-                    let on_data = |buf: &[u8]| {
+                    let mut offered = RateEstimator::new(Duration::from_secs(2));
+                    let mut delivered = RateEstimator::new(Duration::from_secs(2));
+                    let mut adaptive = Adaptive::new(Duration::from_secs(30));
+                    let switch = ProfileSwitch::new();
+
+                    let on_data = |buf: &[u8]| -> Result<(), Box<dyn Error>> {
                         debug!("-> {}", buf.len());
 
-                        let mut msg = Vec::new();
-                        if let Err(err) = rmpv::encode::write_value_ref(&mut msg, &ValueRef::Binary(buf)) {
-                            error!("failed to encode datagram: {}", err);
+                        let msg = match tunnel_frame(buf) {
+                            Ok(msg) => msg,
+                            Err(err) => {
+                                error!("failed to encode datagram: {}", err);
+                                return Ok(());
+                            }
+                        };
+
+                        let now = Instant::now();
+                        let len = msg.len();
+                        offered.push(len, now);
+                        match tx.try_send(msg) {
+                            Ok(()) => delivered.push(len, now),
+                            Err(..) => error!("failed to send datagram due to backpressuring"),
                         }
 
-                        if let Err(..) = tx.try_send(msg) {
-                            error!("failed to send datagram due to backpressuring");
+                        if let Some(profile) = adaptive.update(&offered, &delivered, now) {
+                            warn!(
+                                "uplink delivers {:.0} of {:.0} bps, switching to {} stream",
+                                delivered.rate().unwrap_or_default(),
+                                offered.rate().unwrap_or_default(),
+                                profile
+                            );
+                            switch.set(profile);
                         }
 
                         Ok(())
                     };
 
-                    while num > 0 {
-                        if let Err(err) = cleverdog::stream(info.cid(), info.addr(), on_data) {
-                            warn!("streaming stopped: {}", err);
-                        }
+                    let sink = Shed::new(Shed::new(on_data, switch.clone()), thermal.clone());
+                    let mut sink = Impaired::new(sink, impairment);
+                    let policy = wake_policy(matches)?;
+                    let restart = RetryPolicy::fixed(Duration::new(1, 0)).retries(num.saturating_sub(1));
 
-                        num -= 1;
-                        thread::sleep(Duration::new(1, 0));
+                    let result = retry::retry(
+                        &restart,
+                        |err| {
+                            warn!("streaming stopped: {}", err);
+                            true
+                        },
+                        |attempt| {
+                            if attempt > 0 {
+                                match cleverdog::wake(info.addr().into(), &policy) {
+                                    Ok(v) => info = v,
+                                    Err(err) => warn!("camera did not wake up: {}", err),
+                                }
+                            }
+                            cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf))
+                        },
+                    );
+                    if let Err(err) = result {
+                        error!("giving up after {} attempt(s): {}", num, err);
                     }
 
                     thread.join().unwrap();
                 }
+                Destination::File(path) => {
+                    let mut metadata = Metadata::new(&info);
+                    if let Some(alias) = matches.value_of("alias") {
+                        metadata = metadata.alias(alias);
+                    }
+                    if let Some(timezone) = matches.value_of("timezone") {
+                        metadata = metadata.timezone(timezone);
+                    }
+
+                    let mut sink = FileSink::new(path).framing(framing).metadata(metadata);
+                    if let Some(size) = matches.value_of("rotate-size") {
+                        sink = sink.max_size(size.parse()?);
+                    }
+                    if let Some(secs) = matches.value_of("rotate-every") {
+                        sink = sink.max_age(Duration::from_secs(secs.parse()?));
+                    }
+                    if let Some(size) = matches.value_of("min-free") {
+                        let enforcement = match matches.value_of("on-low-space") {
+                            Some("pause") => Enforcement::Pause,
+                            _ => Enforcement::DeleteOldest,
+                        };
+                        sink = sink.guard(DiskGuard::new(size.parse()?).enforcement(enforcement));
+                    }
+                    // Write on a dedicated thread, so that disk stalls never cause receive drops.
+                    let sink = Threaded::spawn("file", sink, 4096)?;
+                    let metrics = sink.metrics().clone();
+                    let mut sink = Impaired::new(Shed::new(sink, thermal.clone()), impairment);
+
+                    let result = cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf));
+                    let snapshot = metrics.snapshot();
+                    info!(
+                        "file stage: {} written, {} dropped, max queue depth {}",
+                        snapshot.processed, snapshot.dropped, snapshot.max_depth
+                    );
+                    result?;
+                }
+                #[cfg(any(unix, windows))]
+                Destination::Fifo(path) => {
+                    let sink = FifoSink::new(path)?.framing(framing);
+                    let mut sink = Impaired::new(Shed::new(sink, thermal.clone()), impairment);
+
+                    cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf))?;
+                }
+                #[cfg(unix)]
+                Destination::Shm(path) => {
+                    let sink = ShmRing::create(path, shm::DEFAULT_CAPACITY)?;
+                    let mut sink = Impaired::new(Shed::new(sink, thermal.clone()), impairment);
+
+                    cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf))?;
+                }
+                #[cfg(unix)]
+                Destination::Unix { path, datagram } => {
+                    let kind = if datagram { UnixKind::Datagram } else { UnixKind::Stream };
+                    let sink = UnixSink::new(path, kind).framing(framing);
+                    let mut sink = Impaired::new(Shed::new(sink, thermal.clone()), impairment);
+
+                    cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf))?;
+                }
+                Destination::Rtsp { endpoint, path } => {
+                    let sink = Publisher::connect(&resolver, &endpoint, &path, Duration::new(5, 0))?;
+                    let mut sink = Impaired::new(Shed::new(sink, thermal.clone()), impairment);
+
+                    cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf))?;
+                }
+                #[cfg(feature = "srt")]
+                Destination::Srt { endpoint, stream_id } => {
+                    let mut srt_opts = SrtOptions::new();
+                    if let Some(id) = stream_id {
+                        srt_opts = srt_opts.stream_id(&id);
+                    }
+                    // SRT receivers expect MPEG-TS rather than bare RTP.
+                    let mut wr = TsWriter::new(SrtSink::connect(&resolver, &endpoint, &srt_opts)?);
+                    let mut assembler = FrameAssembler::new();
+
+                    cleverdog::stream_with(info.cid(), info.addr(), &opts, |packet| {
+                        let nal_type = h264::payload_nal_type(packet.payload());
+                        if thermal.get() == Profile::Sub && nal_type == Some(NalType::NonIdr) {
+                            return Ok(());
+                        }
+
+                        match assembler.push(packet) {
+                            Ok(frames) => {
+                                for frame in frames {
+                                    wr.write_frame(&frame)?;
+                                }
+                            }
+                            Err(err) => debug!("skipping packet: {}", err),
+                        }
+                        Ok(())
+                    })?;
+                }
+                Destination::Stdout => {
+                    // Bare Annex-B, so that `ffmpeg -i -` or `mpv -` can read it as is.
+                    let stdout = io::stdout();
+                    let mut stdout = stdout.lock();
+                    let sink = AnnexBSink::new(move |buf: &[u8]| {
+                        stdout.write_all(buf)?;
+                        Ok(stdout.flush()?)
+                    });
+                    let mut sink = Impaired::new(Shed::new(sink, thermal.clone()), impairment);
+
+                    cleverdog::stream_with(info.cid(), info.addr(), &opts, |buf| sink.send(buf))?;
+                }
+                addr => return Err(format!("unsupported destination: {}", addr).into()),
             }
         }
         (..) => unreachable!(),
@@ -207,5 +1573,32 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-// ffmpeg -protocol_whitelist file,udp,rtp -i /mnt/hls/camera.sdp -preset
-// ultrafast -vcodec libx264 -r 15 -b 300k -f flv rtmp://localhost/show/camera0
+// The stream served by `view` can be transcoded as well, e.g.:
+// ffmpeg -i http://127.0.0.1:8080/live.flv -preset ultrafast -vcodec libx264 -r 15
+// -b 300k -f flv rtmp://localhost/show/camera0
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_retries() {
+        let matches = app().get_matches_from(["cleverdog", "stream", "--addr", "udp://127.0.0.1:9"]);
+        let (_, matches) = matches.subcommand();
+
+        assert_eq!(u32::MAX, retries(matches.unwrap()).unwrap());
+    }
+
+    #[test]
+    fn test_wake_policy() {
+        let args = ["cleverdog", "stream", "--addr", "udp://127.0.0.1:9"];
+        let matches = app().get_matches_from(args);
+        let (_, matches) = matches.subcommand();
+        assert_eq!(WakePolicy::default(), wake_policy(matches.unwrap()).unwrap());
+
+        let matches = app().get_matches_from(args.iter().chain(&["--wake-interval", "100", "--wake-attempts", "3"]));
+        let (_, matches) = matches.subcommand();
+        let expected = WakePolicy::new(Duration::from_millis(100), WakePolicy::default().max(), 3);
+        assert_eq!(expected, wake_policy(matches.unwrap()).unwrap());
+    }
+}
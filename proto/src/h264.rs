@@ -0,0 +1,15 @@
+//! H.264 video carried by the RTP stream.
+
+pub use self::{
+    annexb::AnnexBWriter,
+    depacketize::{payload_nal_type, DepacketizeError, Depacketizer},
+    frame::{Frame, FrameAssembler},
+    nal::{AnnexB, Nal, NalType},
+    sps::{SpsError, StreamInfo},
+};
+
+mod annexb;
+mod depacketize;
+mod frame;
+mod nal;
+mod sps;
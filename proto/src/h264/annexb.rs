@@ -0,0 +1,184 @@
+use std::io::{self, Write};
+
+use super::{Nal, NalType};
+
+/// Start code preceding each NAL unit.
+const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Writes NAL units as an Annex-B elementary stream, directly playable by `ffplay` and most
+/// other players.
+///
+/// Decoding can only start at an IDR picture, so units preceding the first one are dropped.
+/// The most recent SPS and PPS are repeated before each IDR picture not already preceded by
+/// them, which lets players join the stream at any keyframe.
+///
+/// ```
+/// use cleverdog_proto::h264::{AnnexBWriter, Nal};
+///
+/// let mut wr = AnnexBWriter::new(Vec::new());
+/// for unit in [&[0x41, 1][..], &[0x67, 2], &[0x68, 3], &[0x41, 4], &[0x65, 5], &[0x41, 6]] {
+///     wr.write_nal(&Nal::new(unit).unwrap()).unwrap();
+/// }
+///
+/// assert_eq!(
+///     &[0, 0, 0, 1, 0x67, 2, 0, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 5, 0, 0, 0, 1, 0x41, 6][..],
+///     &wr.into_inner()[..]
+/// );
+/// ```
+#[derive(Debug)]
+pub struct AnnexBWriter<W> {
+    wr: W,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    /// Whether the first IDR picture has been written.
+    started: bool,
+    /// Whether parameter sets have been written since the last picture.
+    sps_written: bool,
+    pps_written: bool,
+    /// Whether the last slice written belongs to an IDR picture, whose further slices must not
+    /// be separated by parameter sets.
+    idr: bool,
+}
+
+impl<W: Write> AnnexBWriter<W> {
+    /// Constructs a new writer of the elementary stream into the given writer.
+    pub fn new(wr: W) -> Self {
+        Self {
+            wr,
+            sps: None,
+            pps: None,
+            started: false,
+            sps_written: false,
+            pps_written: false,
+            idr: false,
+        }
+    }
+
+    /// Returns whether the first IDR picture has been written.
+    #[inline]
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.wr
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.wr
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.wr
+    }
+
+    /// Writes the given NAL unit, prefixed with a start code.
+    pub fn write_nal(&mut self, nal: &Nal) -> Result<(), io::Error> {
+        match nal.nal_type() {
+            NalType::Sps => {
+                self.sps = Some(nal.as_bytes().to_vec());
+                self.sps_written = self.started;
+            }
+            NalType::Pps => {
+                self.pps = Some(nal.as_bytes().to_vec());
+                self.pps_written = self.started;
+            }
+            NalType::Idr => {
+                let repeated = self.sps_written && self.pps_written;
+                if !(self.idr || repeated) {
+                    for unit in self.sps.iter().chain(&self.pps) {
+                        self.wr.write_all(&START_CODE)?;
+                        self.wr.write_all(unit)?;
+                    }
+                }
+                self.started = true;
+            }
+            _ => {}
+        }
+
+        if !self.started {
+            return Ok(());
+        }
+
+        self.wr.write_all(&START_CODE)?;
+        self.wr.write_all(nal.as_bytes())?;
+
+        match nal.nal_type() {
+            NalType::Idr => {
+                self.idr = true;
+                self.sps_written = false;
+                self.pps_written = false;
+            }
+            NalType::NonIdr | NalType::Other(2..=4) => {
+                self.idr = false;
+                self.sps_written = false;
+                self.pps_written = false;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn write(units: &[&[u8]]) -> Vec<u8> {
+        let mut wr = AnnexBWriter::new(Vec::new());
+        for unit in units {
+            wr.write_nal(&Nal::new(unit).unwrap()).unwrap();
+        }
+        wr.into_inner()
+    }
+
+    #[test]
+    fn test_parameter_sets_not_repeated() {
+        let buf = write(&[
+            &[0x67, 1],
+            &[0x68, 2],
+            &[0x65, 3],
+            &[0x41, 4],
+            &[0x67, 1],
+            &[0x68, 2],
+            &[0x65, 5],
+        ]);
+
+        assert_eq!(
+            vec![
+                &[0x67, 1][..],
+                &[0x68, 2],
+                &[0x65, 3],
+                &[0x41, 4],
+                &[0x67, 1],
+                &[0x68, 2],
+                &[0x65, 5]
+            ],
+            crate::h264::AnnexB::new(&buf).map(|v| v.as_bytes()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_parameter_sets_repeated_before_each_idr() {
+        // Two slices of the first IDR picture, followed by another IDR picture.
+        let buf = write(&[&[0x67, 1], &[0x68, 2], &[0x65, 3], &[0x65, 4], &[0x41, 5], &[0x65, 6]]);
+
+        assert_eq!(
+            vec![
+                &[0x67, 1][..],
+                &[0x68, 2],
+                &[0x65, 3],
+                &[0x65, 4],
+                &[0x41, 5],
+                &[0x67, 1],
+                &[0x68, 2],
+                &[0x65, 6]
+            ],
+            crate::h264::AnnexB::new(&buf).map(|v| v.as_bytes()).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,270 @@
+use core::fmt::{self, Display, Formatter};
+use std::error::Error;
+
+use super::NalType;
+use crate::rtp::RtpPacket;
+
+/// Single-time aggregation packet, as defined in RFC 6184.
+const STAP_A: u8 = 24;
+/// Fragmentation unit without decoding order numbers.
+const FU_A: u8 = 28;
+
+/// Error of depacketizing a malformed or unsupported H.264 RTP payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepacketizeError {
+    /// The payload is empty.
+    Empty,
+    /// The payload is too short for the structure its header announces.
+    Truncated,
+    /// The payload is of a packetization type the depacketizer does not handle, e.g. STAP-B,
+    /// MTAP or FU-B, which are used only in interleaved mode.
+    Unsupported(u8),
+}
+
+impl Display for DepacketizeError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            DepacketizeError::Empty => write!(fmt, "empty H.264 payload"),
+            DepacketizeError::Truncated => write!(fmt, "truncated H.264 payload"),
+            DepacketizeError::Unsupported(ty) => write!(fmt, "unsupported H.264 packetization type {}", ty),
+        }
+    }
+}
+
+impl Error for DepacketizeError {}
+
+/// Returns the type of the NAL unit carried by the given RTP payload, without reassembling it.
+///
+/// Fragments report the type of the unit they are part of, and aggregates the type of their
+/// first unit. Returns `None` for truncated or unsupported payloads.
+pub fn payload_nal_type(payload: &[u8]) -> Option<NalType> {
+    let hdr = *payload.first()?;
+
+    match hdr & 0x1f {
+        1..=23 => Some(NalType::from(hdr)),
+        STAP_A => payload.get(3).map(|&v| NalType::from(v)),
+        FU_A => payload.get(1).map(|&v| NalType::from(v)),
+        _ => None,
+    }
+}
+
+/// NAL unit being reassembled from fragmentation units.
+#[derive(Debug)]
+struct Fragment {
+    buf: Vec<u8>,
+    /// Sequence number of the last fragment.
+    seq: u16,
+}
+
+/// Reassembles H.264 NAL units from RTP payloads packetized in the non-interleaved mode of
+/// RFC 6184: single NAL unit packets, STAP-A aggregates and FU-A fragments.
+///
+/// Packets must be pushed in sequence order, e.g. as released by a
+/// [`JitterBuffer`](crate::rtp::JitterBuffer). A NAL unit missing any of its fragments is
+/// dropped, since it cannot be decoded anyway.
+///
+/// ```
+/// use std::time::Instant;
+///
+/// use cleverdog_proto::{h264::Depacketizer, rtp::RtpPacket};
+///
+/// let mut depacketizer = Depacketizer::new();
+/// let mut units = Vec::new();
+///
+/// // An IDR slice split into two FU-A fragments.
+/// for (seq, payload) in [(1u8, &[0x7c, 0x85, 1, 2][..]), (2, &[0x7c, 0x45, 3])] {
+///     let mut buf = vec![0x80, 96, 0, seq, 0, 0, 0, 0, 0, 0, 0, 16];
+///     buf.extend_from_slice(payload);
+///     units.extend(depacketizer.push(&RtpPacket::new(&buf, 1, Instant::now()).unwrap()).unwrap());
+/// }
+///
+/// assert_eq!(vec![vec![0x65, 1, 2, 3]], units);
+/// ```
+#[derive(Debug, Default)]
+pub struct Depacketizer {
+    fragment: Option<Fragment>,
+    dropped: u64,
+}
+
+impl Depacketizer {
+    /// Constructs a new depacketizer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of NAL units dropped because of lost fragments.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Consumes the payload of the given packet, returning NAL units it completes, without
+    /// start codes.
+    pub fn push(&mut self, packet: &RtpPacket) -> Result<Vec<Vec<u8>>, DepacketizeError> {
+        self.push_payload(packet.header().sequence_number(), packet.payload())
+    }
+
+    /// Consumes the given payload of the packet with the specified sequence number, returning
+    /// NAL units it completes, without start codes.
+    pub fn push_payload(&mut self, seq: u16, payload: &[u8]) -> Result<Vec<Vec<u8>>, DepacketizeError> {
+        let hdr = *payload.first().ok_or(DepacketizeError::Empty)?;
+
+        match hdr & 0x1f {
+            1..=23 => {
+                self.abandon();
+                Ok(vec![payload.to_vec()])
+            }
+            STAP_A => {
+                self.abandon();
+                aggregated(&payload[1..])
+            }
+            FU_A => self.fragment(seq, payload),
+            ty => Err(DepacketizeError::Unsupported(ty)),
+        }
+    }
+
+    fn fragment(&mut self, seq: u16, payload: &[u8]) -> Result<Vec<Vec<u8>>, DepacketizeError> {
+        if payload.len() < 2 {
+            return Err(DepacketizeError::Truncated);
+        }
+        let (indicator, hdr) = (payload[0], payload[1]);
+        let (start, end) = (hdr & 0x80 != 0, hdr & 0x40 != 0);
+
+        if start {
+            self.abandon();
+            let mut buf = Vec::with_capacity(payload.len() - 1);
+            // The NAL header is split between the indicator and the fragment header.
+            buf.push(indicator & 0xe0 | hdr & 0x1f);
+            self.fragment = Some(Fragment {
+                buf,
+                seq: seq.wrapping_sub(1),
+            });
+        }
+
+        let mut fragment = match self.fragment.take() {
+            Some(fragment) if fragment.seq.wrapping_add(1) == seq => fragment,
+            Some(..) => {
+                // A fragment in between has been lost.
+                self.dropped += 1;
+                return Ok(Vec::new());
+            }
+            // The rest of a unit whose start has been lost, which is already accounted.
+            None => return Ok(Vec::new()),
+        };
+        fragment.buf.extend_from_slice(&payload[2..]);
+        fragment.seq = seq;
+
+        match end {
+            true => Ok(vec![fragment.buf]),
+            false => {
+                self.fragment = Some(fragment);
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Drops the unit being reassembled, which misses its last fragment.
+    fn abandon(&mut self) {
+        if self.fragment.take().is_some() {
+            self.dropped += 1;
+        }
+    }
+}
+
+/// Splits the body of an aggregation packet into the NAL units it carries, each prefixed with
+/// its 16-bit size.
+fn aggregated(mut buf: &[u8]) -> Result<Vec<Vec<u8>>, DepacketizeError> {
+    let mut units = Vec::new();
+
+    while !buf.is_empty() {
+        if buf.len() < 2 {
+            return Err(DepacketizeError::Truncated);
+        }
+        let size = usize::from(u16::from_be_bytes([buf[0], buf[1]]));
+        if size == 0 || buf.len() < 2 + size {
+            return Err(DepacketizeError::Truncated);
+        }
+        units.push(buf[2..2 + size].to_vec());
+        buf = &buf[2 + size..];
+    }
+
+    Ok(units)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_payload_nal_type() {
+        assert_eq!(Some(NalType::NonIdr), payload_nal_type(&[0x41, 1, 2]));
+        assert_eq!(
+            Some(NalType::Sps),
+            payload_nal_type(&[0x18, 0, 2, 0x67, 1, 0, 2, 0x68, 1])
+        );
+        assert_eq!(Some(NalType::Idr), payload_nal_type(&[0x7c, 0x45, 3]));
+        assert_eq!(None, payload_nal_type(&[0x7c]));
+        assert_eq!(None, payload_nal_type(&[0x19, 0]));
+        assert_eq!(None, payload_nal_type(&[]));
+    }
+
+    #[test]
+    fn test_single() {
+        let mut depacketizer = Depacketizer::new();
+
+        assert_eq!(
+            vec![vec![0x41, 1, 2]],
+            depacketizer.push_payload(1, &[0x41, 1, 2]).unwrap()
+        );
+        assert_eq!(Err(DepacketizeError::Empty), depacketizer.push_payload(2, &[]));
+        assert_eq!(
+            Err(DepacketizeError::Unsupported(25)),
+            depacketizer.push_payload(3, &[0x19, 0])
+        );
+    }
+
+    #[test]
+    fn test_aggregated() {
+        let mut depacketizer = Depacketizer::new();
+
+        // SPS and PPS, aggregated.
+        let payload = [0x78, 0, 3, 0x67, 1, 2, 0, 2, 0x68, 3];
+        assert_eq!(
+            vec![vec![0x67, 1, 2], vec![0x68, 3]],
+            depacketizer.push_payload(1, &payload).unwrap()
+        );
+        assert_eq!(
+            Err(DepacketizeError::Truncated),
+            depacketizer.push_payload(2, &payload[..payload.len() - 1])
+        );
+    }
+
+    #[test]
+    fn test_fragment_lost() {
+        let mut depacketizer = Depacketizer::new();
+
+        assert!(depacketizer.push_payload(0xffff, &[0x5c, 0x81, 1]).unwrap().is_empty());
+        // The middle fragment 0 is lost.
+        assert!(depacketizer.push_payload(1, &[0x5c, 0x41, 3]).unwrap().is_empty());
+        assert_eq!(1, depacketizer.dropped());
+
+        // Fragments across the sequence wraparound.
+        assert!(depacketizer.push_payload(0xffff, &[0x5c, 0x81, 1]).unwrap().is_empty());
+        assert!(depacketizer.push_payload(0, &[0x5c, 0x01, 2]).unwrap().is_empty());
+        assert_eq!(
+            vec![vec![0x41, 1, 2, 3]],
+            depacketizer.push_payload(1, &[0x5c, 0x41, 3]).unwrap()
+        );
+        assert_eq!(1, depacketizer.dropped());
+    }
+
+    #[test]
+    fn test_fragment_interrupted() {
+        let mut depacketizer = Depacketizer::new();
+
+        assert!(depacketizer.push_payload(1, &[0x5c, 0x81, 1]).unwrap().is_empty());
+        // A single unit, while the fragmented one misses its end.
+        assert_eq!(vec![vec![0x41, 2]], depacketizer.push_payload(2, &[0x41, 2]).unwrap());
+        assert_eq!(1, depacketizer.dropped());
+    }
+}
@@ -0,0 +1,147 @@
+use super::{DepacketizeError, Depacketizer, Nal};
+use crate::rtp::RtpPacket;
+
+/// Access unit, i.e. all NAL units of a single picture, sharing the same RTP timestamp.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// RTP timestamp of the picture, at the 90 kHz clock.
+    pub timestamp: u32,
+    /// Whether the picture is an IDR picture, which decoding can start from.
+    pub keyframe: bool,
+    /// NAL units in decoding order, without start codes.
+    pub nals: Vec<Vec<u8>>,
+}
+
+/// Groups NAL units depacketized from RTP packets into access units.
+///
+/// A frame is complete once the packet carrying its last unit, marked by the RTP marker bit,
+/// arrives. Cameras not setting the marker still get their frames delivered, when the first
+/// packet of the next picture changes the timestamp.
+///
+/// ```
+/// use std::time::Instant;
+///
+/// use cleverdog_proto::{h264::FrameAssembler, rtp::RtpPacket};
+///
+/// let mut assembler = FrameAssembler::new();
+/// let mut frames = Vec::new();
+///
+/// // SPS, PPS and a marked IDR slice of the picture at timestamp 3000.
+/// for (seq, payload) in [(1u8, &[0x67, 1][..]), (2, &[0x68, 2]), (3, &[0x65, 3])] {
+///     let marker = if seq == 3 { 0x80 } else { 0 };
+///     let mut buf = vec![0x80, marker | 96, 0, seq, 0, 0, 0x0b, 0xb8, 0, 0, 0, 16];
+///     buf.extend_from_slice(payload);
+///     frames.extend(assembler.push(&RtpPacket::new(&buf, 1, Instant::now()).unwrap()).unwrap());
+/// }
+///
+/// assert_eq!(1, frames.len());
+/// assert_eq!(3000, frames[0].timestamp);
+/// assert!(frames[0].keyframe);
+/// assert_eq!(3, frames[0].nals.len());
+/// ```
+#[derive(Debug, Default)]
+pub struct FrameAssembler {
+    depacketizer: Depacketizer,
+    frame: Option<Frame>,
+}
+
+impl FrameAssembler {
+    /// Constructs a new assembler.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the depacketizer reassembling NAL units, e.g. to inspect dropped units.
+    #[inline]
+    pub fn depacketizer(&self) -> &Depacketizer {
+        &self.depacketizer
+    }
+
+    /// Consumes the given packet, returning frames it completes.
+    ///
+    /// Packets must be pushed in sequence order.
+    pub fn push(&mut self, packet: &RtpPacket) -> Result<Vec<Frame>, DepacketizeError> {
+        let hdr = packet.header();
+        let mut frames = Vec::new();
+
+        if self
+            .frame
+            .as_ref()
+            .map(|v| v.timestamp != hdr.timestamp())
+            .unwrap_or(false)
+        {
+            frames.extend(self.frame.take());
+        }
+
+        let units = self.depacketizer.push(packet)?;
+        if !units.is_empty() {
+            let frame = self.frame.get_or_insert_with(|| Frame {
+                timestamp: hdr.timestamp(),
+                keyframe: false,
+                nals: Vec::new(),
+            });
+            frame.keyframe |= units.iter().filter_map(|v| Nal::new(v)).any(|v| v.is_keyframe());
+            frame.nals.extend(units);
+        }
+
+        if hdr.marker() {
+            frames.extend(self.frame.take());
+        }
+
+        Ok(frames)
+    }
+
+    /// Returns the frame being assembled, e.g. at the end of the stream.
+    pub fn flush(&mut self) -> Option<Frame> {
+        self.frame.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn push(assembler: &mut FrameAssembler, seq: u8, timestamp: u8, marker: bool, payload: &[u8]) -> Vec<Frame> {
+        let marker = if marker { 0x80 } else { 0 };
+        let mut buf = vec![0x80, marker | 96, 0, seq, 0, 0, 0, timestamp, 0, 0, 0, 16];
+        buf.extend_from_slice(payload);
+        assembler
+            .push(&RtpPacket::new(&buf, 1, Instant::now()).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_frames_without_marker() {
+        let mut assembler = FrameAssembler::new();
+
+        assert!(push(&mut assembler, 1, 1, false, &[0x41, 1]).is_empty());
+        assert!(push(&mut assembler, 2, 1, false, &[0x41, 2]).is_empty());
+
+        let frames = push(&mut assembler, 3, 2, false, &[0x41, 3]);
+        assert_eq!(
+            vec![Frame {
+                timestamp: 1,
+                keyframe: false,
+                nals: vec![vec![0x41, 1], vec![0x41, 2]],
+            }],
+            frames
+        );
+
+        assert_eq!(Some(vec![vec![0x41, 3]]), assembler.flush().map(|v| v.nals));
+        assert_eq!(None, assembler.flush());
+    }
+
+    #[test]
+    fn test_fragmented_keyframe() {
+        let mut assembler = FrameAssembler::new();
+
+        assert!(push(&mut assembler, 1, 1, false, &[0x7c, 0x85, 1]).is_empty());
+        let frames = push(&mut assembler, 2, 1, true, &[0x7c, 0x45, 2]);
+
+        assert_eq!(1, frames.len());
+        assert!(frames[0].keyframe);
+        assert_eq!(vec![vec![0x65, 1, 2]], frames[0].nals);
+    }
+}
@@ -0,0 +1,167 @@
+/// Type of a NAL unit, as far as the stream is concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NalType {
+    /// Slice of a picture referencing other pictures.
+    NonIdr,
+    /// Slice of an instantaneous decoding refresh picture, decodable on its own.
+    Idr,
+    /// Supplemental enhancement information.
+    Sei,
+    /// Sequence parameter set.
+    Sps,
+    /// Picture parameter set.
+    Pps,
+    /// Access unit delimiter.
+    AccessUnitDelimiter,
+    /// Any other type, e.g. a data partition or a filler.
+    Other(u8),
+}
+
+impl From<u8> for NalType {
+    /// Classifies the NAL unit by its header byte.
+    fn from(hdr: u8) -> Self {
+        match hdr & 0x1f {
+            1 => NalType::NonIdr,
+            5 => NalType::Idr,
+            6 => NalType::Sei,
+            7 => NalType::Sps,
+            8 => NalType::Pps,
+            9 => NalType::AccessUnitDelimiter,
+            ty => NalType::Other(ty),
+        }
+    }
+}
+
+/// NAL unit without a start code, starting with its header byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nal<'a>(&'a [u8]);
+
+impl<'a> Nal<'a> {
+    /// Wraps the given NAL unit, returning `None` if it is empty.
+    pub fn new(buf: &'a [u8]) -> Option<Self> {
+        match buf.is_empty() {
+            true => None,
+            false => Some(Self(buf)),
+        }
+    }
+
+    #[inline]
+    pub fn nal_type(&self) -> NalType {
+        NalType::from(self.0[0])
+    }
+
+    /// Returns the reference priority, zero for units no other picture depends on.
+    #[inline]
+    pub fn ref_idc(&self) -> u8 {
+        self.0[0] >> 5 & 0x03
+    }
+
+    /// Returns whether the unit is a slice of an IDR picture, which decoding can start from.
+    #[inline]
+    pub fn is_keyframe(&self) -> bool {
+        self.nal_type() == NalType::Idr
+    }
+
+    /// Returns the whole unit, including the header byte.
+    #[inline]
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.0
+    }
+
+    /// Returns the unit following the header byte, still with emulation prevention bytes.
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        &self.0[1..]
+    }
+}
+
+/// Iterator over NAL units of an Annex-B byte stream, i.e. units separated by `00 00 01` or
+/// `00 00 00 01` start codes.
+///
+/// ```
+/// use cleverdog_proto::h264::{AnnexB, NalType};
+///
+/// let buf = [0, 0, 0, 1, 0x67, 1, 2, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 4];
+/// let types: Vec<_> = AnnexB::new(&buf).map(|v| v.nal_type()).collect();
+///
+/// assert_eq!(vec![NalType::Sps, NalType::Pps, NalType::Idr], types);
+/// assert!(AnnexB::new(&buf).any(|v| v.is_keyframe()));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AnnexB<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> AnnexB<'a> {
+    /// Constructs a new iterator over the given byte stream, skipping anything before the first
+    /// start code.
+    pub fn new(buf: &'a [u8]) -> Self {
+        let buf = match find_start_code(buf) {
+            Some(pos) => &buf[pos + 3..],
+            None => &[],
+        };
+
+        Self { buf }
+    }
+}
+
+impl<'a> Iterator for AnnexB<'a> {
+    type Item = Nal<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.buf.is_empty() {
+                return None;
+            }
+
+            let (mut unit, rest) = match find_start_code(self.buf) {
+                Some(pos) => (&self.buf[..pos], &self.buf[pos + 3..]),
+                None => (self.buf, &[][..]),
+            };
+            self.buf = rest;
+
+            // Trailing zeros belong to the next four-byte start code or pad the stream.
+            while let [head @ .., 0] = unit {
+                unit = head;
+            }
+            if let Some(nal) = Nal::new(unit) {
+                return Some(nal);
+            }
+        }
+    }
+}
+
+/// Returns the position of the first three-byte start code in the given buffer.
+fn find_start_code(buf: &[u8]) -> Option<usize> {
+    buf.windows(3).position(|v| v == [0, 0, 1])
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_nal_type() {
+        let nal = Nal::new(&[0x65, 0x88]).unwrap();
+        assert_eq!(NalType::Idr, nal.nal_type());
+        assert_eq!(3, nal.ref_idc());
+        assert!(nal.is_keyframe());
+
+        let nal = Nal::new(&[0x06, 0x05]).unwrap();
+        assert_eq!(NalType::Sei, nal.nal_type());
+        assert_eq!(0, nal.ref_idc());
+        assert!(!nal.is_keyframe());
+
+        assert_eq!(NalType::Other(12), NalType::from(0x0c));
+        assert_eq!(None, Nal::new(&[]));
+    }
+
+    #[test]
+    fn test_annex_b_leading_garbage_and_empty_units() {
+        let buf = [0xff, 0, 0, 1, 0, 0, 1, 0x41, 0, 0, 0, 0, 1, 0x01, 0];
+        let units: Vec<_> = AnnexB::new(&buf).map(|v| v.as_bytes()).collect();
+
+        assert_eq!(vec![&[0x41][..], &[0x01]], units);
+        assert_eq!(0, AnnexB::new(&[0x41, 0x42]).count());
+    }
+}
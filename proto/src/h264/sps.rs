@@ -0,0 +1,357 @@
+use core::fmt::{self, Display, Formatter};
+use std::error::Error;
+
+use super::{Nal, NalType};
+
+/// Error of decoding a sequence parameter set.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpsError {
+    /// The NAL unit is not a sequence parameter set.
+    NotSps,
+    /// The parameter set ends before all fields required are read.
+    Truncated,
+    /// A field has a value out of the range the specification allows.
+    Invalid(&'static str),
+}
+
+impl Display for SpsError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            SpsError::NotSps => write!(fmt, "not a sequence parameter set"),
+            SpsError::Truncated => write!(fmt, "truncated sequence parameter set"),
+            SpsError::Invalid(field) => write!(fmt, "invalid {} in sequence parameter set", field),
+        }
+    }
+}
+
+impl Error for SpsError {}
+
+/// Parameters of the video stream, as announced by its sequence parameter set.
+///
+/// ```
+/// use cleverdog_proto::h264::{Nal, StreamInfo};
+///
+/// // Baseline 3.0, 640x480 at 25 fps.
+/// let sps = [
+///     0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+///     0xca, 0x10,
+/// ];
+/// let info = StreamInfo::from_sps(&Nal::new(&sps).unwrap()).unwrap();
+///
+/// assert_eq!((640, 480), (info.width, info.height));
+/// assert_eq!(Some(25.0), info.fps);
+/// assert_eq!("640x480, Baseline 3.0, 25 fps", info.to_string());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamInfo {
+    /// Width of the decoded pictures in pixels, after cropping.
+    pub width: u32,
+    /// Height of the decoded pictures in pixels, after cropping.
+    pub height: u32,
+    /// Profile indication, e.g. 66 for Baseline or 100 for High.
+    pub profile: u8,
+    /// Constraint flags following the profile.
+    pub constraints: u8,
+    /// Level indication, ten times the level number.
+    pub level: u8,
+    /// Frame rate, if the stream announces its timing.
+    pub fps: Option<f64>,
+}
+
+impl StreamInfo {
+    /// Decodes the given sequence parameter set.
+    pub fn from_sps(nal: &Nal) -> Result<Self, SpsError> {
+        if nal.nal_type() != NalType::Sps {
+            return Err(SpsError::NotSps);
+        }
+
+        let rbsp = unescape(nal.payload());
+        let mut rd = BitReader::new(&rbsp);
+
+        let profile = rd.read_bits(8)? as u8;
+        let constraints = rd.read_bits(8)? as u8;
+        let level = rd.read_bits(8)? as u8;
+        rd.read_ue()?;
+
+        let mut chroma_format = 1;
+        let mut separate_planes = false;
+        if let 100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135 = profile {
+            chroma_format = rd.read_ue()?;
+            if chroma_format > 3 {
+                return Err(SpsError::Invalid("chroma format"));
+            }
+            if chroma_format == 3 {
+                separate_planes = rd.read_bit()?;
+            }
+            // Bit depths of luma and chroma, and the lossless flag.
+            rd.read_ue()?;
+            rd.read_ue()?;
+            rd.read_bit()?;
+            if rd.read_bit()? {
+                for idx in 0..if chroma_format == 3 { 12 } else { 8 } {
+                    if rd.read_bit()? {
+                        skip_scaling_list(&mut rd, if idx < 6 { 16 } else { 64 })?;
+                    }
+                }
+            }
+        }
+
+        // Maximum frame number.
+        rd.read_ue()?;
+        match rd.read_ue()? {
+            0 => {
+                rd.read_ue()?;
+            }
+            1 => {
+                rd.read_bit()?;
+                rd.read_se()?;
+                rd.read_se()?;
+                for _ in 0..rd.read_ue()? {
+                    rd.read_se()?;
+                }
+            }
+            2 => {}
+            _ => return Err(SpsError::Invalid("picture order count type")),
+        }
+        // Reference frames and whether gaps in frame numbers are allowed.
+        rd.read_ue()?;
+        rd.read_bit()?;
+
+        let width_mbs = rd.read_ue()? + 1;
+        let height_map_units = rd.read_ue()? + 1;
+        let frame_mbs_only = rd.read_bit()?;
+        if !frame_mbs_only {
+            rd.read_bit()?;
+        }
+        rd.read_bit()?;
+
+        let (mut crop_x, mut crop_y) = (0, 0);
+        if rd.read_bit()? {
+            crop_x = rd.read_ue()? + rd.read_ue()?;
+            crop_y = rd.read_ue()? + rd.read_ue()?;
+        }
+
+        let fields = if frame_mbs_only { 1 } else { 2 };
+        let (unit_x, unit_y) = match (chroma_format, separate_planes) {
+            (0, _) | (3, true) => (1, fields),
+            (1, _) => (2, 2 * fields),
+            (2, _) => (2, fields),
+            _ => (1, fields),
+        };
+        let width = (width_mbs * 16).checked_sub(unit_x * crop_x);
+        let height = (height_map_units * 16 * fields).checked_sub(unit_y * crop_y);
+        let (width, height) = match (width, height) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Err(SpsError::Invalid("cropping")),
+        };
+
+        let fps = match rd.read_bit()? {
+            true => vui_fps(&mut rd)?,
+            false => None,
+        };
+
+        Ok(Self {
+            width,
+            height,
+            profile,
+            constraints,
+            level,
+            fps,
+        })
+    }
+
+    /// Returns the name of the profile, if it is a well-known one.
+    pub fn profile_name(&self) -> Option<&'static str> {
+        match self.profile {
+            66 => Some("Baseline"),
+            77 => Some("Main"),
+            88 => Some("Extended"),
+            100 => Some("High"),
+            110 => Some("High 10"),
+            122 => Some("High 4:2:2"),
+            244 => Some("High 4:4:4"),
+            _ => None,
+        }
+    }
+}
+
+impl Display for StreamInfo {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "{}x{}, ", self.width, self.height)?;
+        match self.profile_name() {
+            Some(name) => write!(fmt, "{}", name)?,
+            None => write!(fmt, "profile {}", self.profile)?,
+        }
+        write!(fmt, " {}.{}", self.level / 10, self.level % 10)?;
+        if let Some(fps) = self.fps {
+            write!(fmt, ", {} fps", fps)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the VUI parameters up to the timing information, returning the frame rate.
+fn vui_fps(rd: &mut BitReader) -> Result<Option<f64>, SpsError> {
+    // Aspect ratio, where 255 is an explicit one.
+    if rd.read_bit()? && rd.read_bits(8)? == 255 {
+        rd.read_bits(32)?;
+    }
+    // Overscan.
+    if rd.read_bit()? {
+        rd.read_bit()?;
+    }
+    // Video signal type, optionally with colour description.
+    if rd.read_bit()? {
+        rd.read_bits(4)?;
+        if rd.read_bit()? {
+            rd.read_bits(24)?;
+        }
+    }
+    // Chroma sample location.
+    if rd.read_bit()? {
+        rd.read_ue()?;
+        rd.read_ue()?;
+    }
+
+    if !rd.read_bit()? {
+        return Ok(None);
+    }
+    let units_in_tick = rd.read_bits(32)?;
+    let time_scale = rd.read_bits(32)?;
+
+    match units_in_tick {
+        0 => Ok(None),
+        // Each frame takes two ticks, one per field.
+        _ => Ok(Some(f64::from(time_scale) / f64::from(units_in_tick) / 2.0)),
+    }
+}
+
+/// Skips a scaling list of the given size.
+fn skip_scaling_list(rd: &mut BitReader, size: usize) -> Result<(), SpsError> {
+    let (mut last, mut next) = (8i64, 8i64);
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + i64::from(rd.read_se()?) + 256) % 256;
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Ok(())
+}
+
+/// Removes emulation prevention bytes, i.e. the `03` in each `00 00 03` sequence.
+fn unescape(buf: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(buf.len());
+    let mut zeros = 0;
+
+    for &byte in buf {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+
+    rbsp
+}
+
+/// Reader of the bit-oriented syntax of parameter sets, including exp-Golomb codes.
+struct BitReader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    fn read_bit(&mut self) -> Result<bool, SpsError> {
+        let byte = self.buf.get(self.pos / 8).ok_or(SpsError::Truncated)?;
+        let bit = byte >> (7 - self.pos % 8) & 1;
+        self.pos += 1;
+        Ok(bit == 1)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Result<u32, SpsError> {
+        debug_assert!(count <= 32);
+
+        let mut v = 0u64;
+        for _ in 0..count {
+            v = v << 1 | u64::from(self.read_bit()?);
+        }
+        Ok(v as u32)
+    }
+
+    /// Reads an unsigned exp-Golomb code.
+    fn read_ue(&mut self) -> Result<u32, SpsError> {
+        let mut zeros = 0;
+        while !self.read_bit()? {
+            zeros += 1;
+            if zeros > 31 {
+                return Err(SpsError::Invalid("exp-Golomb code"));
+            }
+        }
+
+        Ok(((1u64 << zeros) - 1 + u64::from(self.read_bits(zeros)?)) as u32)
+    }
+
+    /// Reads a signed exp-Golomb code.
+    fn read_se(&mut self) -> Result<i32, SpsError> {
+        let v = self.read_ue()?;
+        match v % 2 {
+            1 => Ok((v / 2 + 1) as i32),
+            _ => Ok(-((v / 2) as i32)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exp_golomb() {
+        // 1, 010, 011, 00100, 00101: 0, 1, 2, 3, 4.
+        let buf = [0b1010_0110, 0b0100_0010, 0b1000_0000];
+        let mut rd = BitReader::new(&buf);
+        let v: Vec<_> = (0..5).map(|_| rd.read_ue().unwrap()).collect();
+        assert_eq!(vec![0, 1, 2, 3, 4], v);
+
+        let mut rd = BitReader::new(&buf);
+        let v: Vec<_> = (0..5).map(|_| rd.read_se().unwrap()).collect();
+        assert_eq!(vec![0, 1, -1, 2, -2], v);
+
+        assert_eq!(Err(SpsError::Truncated), BitReader::new(&[0]).read_ue());
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(vec![0, 0, 1, 0, 0, 3], unescape(&[0, 0, 3, 1, 0, 0, 3, 3]));
+    }
+
+    #[test]
+    fn test_high_profile_cropped() {
+        // High 4.0, 1920x1088 cropped to 1080, with a scaling list and an explicit aspect ratio.
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xad, 0x84, 0x40, 0x6c, 0xa0, 0x3c, 0x01, 0x13, 0xf2, 0xff, 0xe0, 0x00, 0x80, 0x00,
+            0x62, 0x00, 0x00, 0x03, 0x00, 0x02, 0x00, 0x00, 0x03, 0x00, 0x79, 0x08,
+        ];
+        let info = StreamInfo::from_sps(&Nal::new(&sps).unwrap()).unwrap();
+
+        assert_eq!((1920, 1080), (info.width, info.height));
+        assert_eq!((100, 40), (info.profile, info.level));
+        assert_eq!("1920x1080, High 4.0, 30 fps", info.to_string());
+    }
+
+    #[test]
+    fn test_not_sps() {
+        let nal = Nal::new(&[0x68, 0xce, 0x38, 0x80]).unwrap();
+        assert_eq!(Err(SpsError::NotSps), StreamInfo::from_sps(&nal));
+
+        let nal = Nal::new(&[0x67, 0x42, 0xc0]).unwrap();
+        assert_eq!(Err(SpsError::Truncated), StreamInfo::from_sps(&nal));
+    }
+}
@@ -0,0 +1,12 @@
+//! Wire codecs of the Cleverdog camera protocol and its RTP stream.
+//!
+//! Everything here is pure: no sockets, threads or clocks beyond timestamps carried along with
+//! packets. The `cleverdog` crate builds discovery and streaming on top and re-exports these
+//! modules, while tools that only need to encode or decode traffic, e.g. firmware tooling or
+//! emulators, can depend on this crate alone.
+
+pub mod h264;
+pub mod mac;
+pub mod ntp;
+pub mod protocol;
+pub mod rtp;
@@ -33,7 +33,7 @@ impl Error for ParseError {
 }
 
 /// MAC address.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct MacAddr([u8; 6]);
 
 impl MacAddr {
@@ -46,12 +46,13 @@ impl MacAddr {
     /// Parses the specified string into MAC address.
     ///
     /// ```
-    /// use cleverdog::mac::MacAddr;
+    /// use cleverdog_proto::mac::MacAddr;
     ///
     /// let mac: MacAddr = "dc:a9:04:97:9d:9b".parse().unwrap();
     /// assert_eq!(mac.as_bytes(), [220, 169, 4, 151, 157, 155]);
     /// ```
     #[inline]
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Result<Self, ParseError> {
         <MacAddr as FromStr>::from_str(s)
     }
@@ -77,7 +78,7 @@ impl FromStr for MacAddr {
                 return Err(ParseError::InvalidLength);
             }
 
-            buf[idx] = u8::from_str_radix(b, 16).map_err(|err| ParseError::InvalidDigit(err))?;
+            buf[idx] = u8::from_str_radix(b, 16).map_err(ParseError::InvalidDigit)?;
             idx += 1;
         }
 
@@ -121,6 +122,8 @@ impl UpperHex for MacAddr {
 
 #[cfg(test)]
 mod test {
+    use proptest::prelude::*;
+
     use super::*;
 
     #[test]
@@ -147,4 +150,23 @@ mod test {
         let mac = MacAddr::new([220, 169, 4, 151, 157, 155]);
         assert_eq!(&format!("{:X}", mac), "DC:A9:04:97:9D:9B");
     }
+
+    proptest! {
+        #[test]
+        fn test_parse_display_roundtrip(buf in any::<[u8; 6]>()) {
+            let mac = MacAddr::new(buf);
+            prop_assert_eq!(mac, format!("{}", mac).parse::<MacAddr>().unwrap());
+            prop_assert_eq!(mac, format!("{:X}", mac).parse::<MacAddr>().unwrap());
+        }
+
+        #[test]
+        fn test_parse_never_panics(s in "\\PC*") {
+            let _ = s.parse::<MacAddr>();
+        }
+
+        #[test]
+        fn test_parse_never_panics_on_mac_like(s in "[0-9a-fA-F:]{0,24}") {
+            let _ = s.parse::<MacAddr>();
+        }
+    }
 }
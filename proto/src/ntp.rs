@@ -0,0 +1,155 @@
+//! NTP timestamp conversions, as used in RTCP reports.
+//!
+//! An NTP timestamp is a 64-bit fixed point number of seconds since 1900-01-01, with 32 bits of
+//! integer part and 32 bits of fraction. Like Unix time it does not count leap seconds, so
+//! conversions are a plain offset and never need a leap second table.
+//!
+//! The 32-bit seconds field wraps in February 2036. Following RFC 4330, timestamps with the most
+//! significant bit cleared are interpreted as belonging to the era starting at the wrap.
+
+use core::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds between the NTP epoch, 1900-01-01, and the Unix epoch.
+pub const UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// Length of a single 32-bit seconds era.
+const ERA: u64 = 1 << 32;
+
+/// NTP timestamp in the 64-bit format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NtpTimestamp(u64);
+
+impl NtpTimestamp {
+    /// Constructs a new timestamp from integer seconds and fraction parts.
+    #[inline]
+    pub fn new(seconds: u32, fraction: u32) -> Self {
+        Self(u64::from(seconds) << 32 | u64::from(fraction))
+    }
+
+    /// Converts the given system time into an NTP timestamp.
+    ///
+    /// Times before the NTP epoch saturate to zero.
+    pub fn from_system_time(at: SystemTime) -> Self {
+        let (seconds, nanos) = match at.duration_since(UNIX_EPOCH) {
+            Ok(v) => ((v.as_secs() + UNIX_OFFSET) % ERA, v.subsec_nanos()),
+            Err(err) => match Duration::from_secs(UNIX_OFFSET).checked_sub(err.duration()) {
+                Some(v) => (v.as_secs(), v.subsec_nanos()),
+                None => return Self(0),
+            },
+        };
+
+        let fraction = (u64::from(nanos) << 32) / 1_000_000_000;
+        Self::new(seconds as u32, fraction as u32)
+    }
+
+    /// Converts this timestamp into system time, resolving the era as described in the module
+    /// documentation.
+    pub fn to_system_time(&self) -> SystemTime {
+        let mut seconds = u64::from(self.seconds());
+        if seconds & 0x8000_0000 == 0 {
+            seconds += ERA;
+        }
+
+        let nanos = (u64::from(self.fraction()) * 1_000_000_000) >> 32;
+        let ntp = Duration::from_secs(seconds) + Duration::from_nanos(nanos);
+        match ntp.checked_sub(Duration::from_secs(UNIX_OFFSET)) {
+            Some(v) => UNIX_EPOCH + v,
+            None => UNIX_EPOCH - (Duration::from_secs(UNIX_OFFSET) - ntp),
+        }
+    }
+
+    /// Constructs a timestamp from its 64-bit wire representation.
+    #[inline]
+    pub fn from_u64(v: u64) -> Self {
+        Self(v)
+    }
+
+    /// Returns the 64-bit wire representation.
+    #[inline]
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns the integer seconds part.
+    #[inline]
+    pub fn seconds(&self) -> u32 {
+        (self.0 >> 32) as u32
+    }
+
+    /// Returns the fraction part, in units of 2^-32 seconds.
+    #[inline]
+    pub fn fraction(&self) -> u32 {
+        self.0 as u32
+    }
+
+    /// Returns the middle 32 bits, as carried in the "last SR" field of RTCP reception reports.
+    #[inline]
+    pub fn middle(&self) -> u32 {
+        (self.0 >> 16) as u32
+    }
+}
+
+impl From<SystemTime> for NtpTimestamp {
+    #[inline]
+    fn from(at: SystemTime) -> Self {
+        Self::from_system_time(at)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_unix_epoch() {
+        assert_eq!(NtpTimestamp::new(UNIX_OFFSET as u32, 0), UNIX_EPOCH.into());
+        assert_eq!(UNIX_EPOCH, NtpTimestamp::new(UNIX_OFFSET as u32, 0).to_system_time());
+    }
+
+    #[test]
+    fn test_fraction() {
+        let at = UNIX_EPOCH + Duration::from_millis(1_500);
+        let ts = NtpTimestamp::from(at);
+
+        assert_eq!(UNIX_OFFSET as u32 + 1, ts.seconds());
+        assert_eq!(0x8000_0000, ts.fraction());
+        assert_eq!(at, ts.to_system_time());
+    }
+
+    #[test]
+    fn test_roundtrip_is_precise() {
+        let at = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        let back = NtpTimestamp::from(at).to_system_time();
+
+        // The fraction has sub-nanosecond resolution, but truncation loses at most one.
+        assert!(at.duration_since(back).unwrap() <= Duration::from_nanos(1));
+    }
+
+    #[test]
+    fn test_era_wrap() {
+        // 2036-02-07T06:28:16Z, the first instant of era 1.
+        let wrap = UNIX_EPOCH + Duration::from_secs(ERA - UNIX_OFFSET);
+        let ts = NtpTimestamp::from(wrap + Duration::from_secs(10));
+
+        assert_eq!(10, ts.seconds());
+        assert_eq!(wrap + Duration::from_secs(10), ts.to_system_time());
+    }
+
+    #[test]
+    fn test_before_epoch() {
+        assert_eq!(
+            NtpTimestamp::new(UNIX_OFFSET as u32 - 1, 0x8000_0000),
+            NtpTimestamp::from(UNIX_EPOCH - Duration::from_millis(500))
+        );
+        assert_eq!(
+            NtpTimestamp::from_u64(0),
+            NtpTimestamp::from(UNIX_EPOCH - Duration::from_secs(UNIX_OFFSET + 1))
+        );
+    }
+
+    #[test]
+    fn test_middle() {
+        assert_eq!(0x5678_9abc, NtpTimestamp::from_u64(0x1234_5678_9abc_def0).middle());
+    }
+}
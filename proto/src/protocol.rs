@@ -0,0 +1,60 @@
+pub use crate::protocol::{
+    cid::{Cid, CidLengthError, Hex},
+    command::Command,
+    frame::{Frame, ProtocolError},
+    scan::ScanInfo,
+    token::{Token, TokenLengthError},
+    version::Version,
+};
+
+mod cid;
+mod command;
+mod frame;
+mod scan;
+mod token;
+mod version;
+
+/// UDP port cameras listen for the Scan command on.
+pub const DISCOVERY_PORT: u16 = 10008;
+
+/// Magic constant that is prepended to each camera frame.
+///
+/// Represents a big-endian integer representation of `[0x4d, 0x4a]` array.
+pub const MAGIC: u16 = 19786;
+
+/// Size of the camera ID field in command frames, including the trailing NUL byte.
+pub const CID_SIZE: usize = 16;
+
+/// Byte the camera ID of Scan commands, [`Cid::ANY`], is made of.
+pub const CID_FILLER: u8 = b'0';
+
+/// Size of the token field sent as the first argument of Scan and StartRtp commands.
+pub const TOKEN_SIZE: usize = 38;
+
+/// Size of the channel header prepended to each RTP and RTCP datagram.
+pub const CHANNEL_HEADER_SIZE: usize = 4;
+
+/// Offset of the channel byte within the channel header.
+pub const CHANNEL_OFFSET: usize = 2;
+
+/// Channel carrying the video stream and its RTCP reports.
+pub const VIDEO_CHANNEL: u8 = 1;
+
+/// SSRC of RTP packets carrying the video stream.
+pub const VIDEO_SSRC: u32 = 16;
+
+/// Channel header prepended to RTCP reports sent to the camera.
+pub const RTCP_CHANNEL_HEADER: [u8; CHANNEL_HEADER_SIZE] = [0x00, 0x00, VIDEO_CHANNEL, 0x00];
+
+/// SSRC used in RTCP reports sent to the camera.
+pub const RTCP_SSRC: u32 = 2;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_magic_endianess() {
+        assert_eq!([0x4d, 0x4a], MAGIC.to_be_bytes());
+    }
+}
@@ -0,0 +1,222 @@
+use core::{
+    fmt::{self, Debug, Display, Formatter, Write},
+    ops::Deref,
+};
+use std::error::Error;
+
+use crate::protocol::{CID_FILLER, CID_SIZE};
+
+/// Camera ID, as carried in the fixed-size field of command frames.
+///
+/// IDs are expected to be ASCII followed by NUL padding, but some cameras pad with other bytes,
+/// so formatting never fails: [`Display`] prints the ID up to the first NUL byte, escaping
+/// anything that is not printable ASCII as `\xNN`, and [`Cid::hex`] shows the whole raw field.
+///
+/// Commands carry the field as is, so the ID received from a camera is echoed back byte for byte.
+/// The field must contain a NUL byte terminating the ID, i.e. IDs are at most 15 bytes long.
+///
+/// ```
+/// use cleverdog_proto::protocol::Cid;
+///
+/// let cid = Cid::new(*b"xxxxS_AB12\xff\0\0\0\0\0");
+///
+/// assert_eq!(r"xxxxS_AB12\xff", cid.to_string());
+/// assert_eq!(None, cid.as_str());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cid([u8; CID_SIZE]);
+
+impl Cid {
+    /// Camera ID sent in Scan commands, which are not addressed to a particular camera.
+    pub const ANY: Cid = {
+        let mut raw = [CID_FILLER; CID_SIZE];
+        raw[CID_SIZE - 1] = 0;
+        Cid(raw)
+    };
+
+    /// Constructs a new camera ID from the raw field.
+    #[inline]
+    pub const fn new(raw: [u8; CID_SIZE]) -> Self {
+        Self(raw)
+    }
+
+    /// Constructs a new camera ID from the given ID bytes, padding the field with NUL bytes.
+    ///
+    /// IDs longer than 15 bytes are rejected instead of being truncated, since the camera would
+    /// silently ignore commands addressed to a mangled ID.
+    ///
+    /// ```
+    /// use cleverdog_proto::protocol::Cid;
+    ///
+    /// let cid = Cid::from_id(b"xxxxS_AB12CD").unwrap();
+    ///
+    /// assert_eq!(&b"xxxxS_AB12CD\0\0\0\0"[..], &cid[..]);
+    /// assert!(Cid::from_id(b"xxxxS_AB12CD0000").is_err());
+    /// ```
+    pub fn from_id(id: &[u8]) -> Result<Self, CidLengthError> {
+        if id.len() >= CID_SIZE {
+            return Err(CidLengthError(id.len()));
+        }
+
+        let mut raw = [0; CID_SIZE];
+        raw[..id.len()].copy_from_slice(id);
+        Ok(Self(raw))
+    }
+
+    /// Returns `true` if the field contains the NUL byte terminating the ID, which is required
+    /// for sending it in commands.
+    #[inline]
+    pub fn is_terminated(&self) -> bool {
+        self.0.contains(&0)
+    }
+
+    /// Returns the raw field, including padding.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; CID_SIZE] {
+        &self.0
+    }
+
+    /// Returns the ID bytes up to the first NUL byte.
+    pub fn id(&self) -> &[u8] {
+        let len = self.0.iter().position(|&ch| ch == 0).unwrap_or(CID_SIZE);
+        &self.0[..len]
+    }
+
+    /// Returns the ID as a string, if it consists of printable ASCII characters only.
+    pub fn as_str(&self) -> Option<&str> {
+        let id = self.id();
+        match id.iter().all(|ch| ch.is_ascii_graphic()) {
+            true => core::str::from_utf8(id).ok(),
+            false => None,
+        }
+    }
+
+    /// Returns a value that formats the whole raw field as lowercase hex.
+    #[inline]
+    pub fn hex(&self) -> Hex<'_> {
+        Hex(self)
+    }
+}
+
+impl From<[u8; CID_SIZE]> for Cid {
+    #[inline]
+    fn from(raw: [u8; CID_SIZE]) -> Self {
+        Self::new(raw)
+    }
+}
+
+impl Deref for Cid {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Display for Cid {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        for &ch in self.id() {
+            if ch.is_ascii_graphic() {
+                fmt.write_char(ch as char)?;
+            } else {
+                write!(fmt, "\\x{:02x}", ch)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Debug for Cid {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "Cid(\"{}\")", self)
+    }
+}
+
+/// Hex representation of a [`Cid`], returned by [`Cid::hex`].
+#[derive(Debug, Clone, Copy)]
+pub struct Hex<'a>(&'a Cid);
+
+impl Display for Hex<'_> {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        for ch in self.0.as_bytes() {
+            write!(fmt, "{:02x}", ch)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An error returned when a camera ID does not fit the command field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CidLengthError(pub(crate) usize);
+
+impl CidLengthError {
+    /// Returns the length of the rejected ID.
+    #[inline]
+    pub fn actual(&self) -> usize {
+        self.0
+    }
+}
+
+impl Display for CidLengthError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(
+            fmt,
+            "camera ID must be at most {} bytes long, got {}",
+            CID_SIZE - 1,
+            self.0
+        )
+    }
+}
+
+impl Error for CidLengthError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_any() {
+        assert_eq!(&b"000000000000000\0"[..], &Cid::ANY[..]);
+        assert!(Cid::ANY.is_terminated());
+    }
+
+    #[test]
+    fn test_from_id() {
+        assert_eq!(
+            Cid::new(*b"ABC\0\0\0\0\0\0\0\0\0\0\0\0\0"),
+            Cid::from_id(b"ABC").unwrap()
+        );
+        assert_eq!(Cid::new([0; CID_SIZE]), Cid::from_id(b"").unwrap());
+        assert!(Cid::from_id(&[b'A'; CID_SIZE - 1]).unwrap().is_terminated());
+        assert_eq!(Err(CidLengthError(16)), Cid::from_id(&[b'A'; CID_SIZE]));
+    }
+
+    #[test]
+    fn test_display_ascii() {
+        let cid = Cid::new(*b"xxxxS_AB12CD000\0");
+
+        assert_eq!("xxxxS_AB12CD000", cid.to_string());
+        assert_eq!(Some("xxxxS_AB12CD000"), cid.as_str());
+    }
+
+    #[test]
+    fn test_display_odd_bytes() {
+        let cid = Cid::new(*b"AB \x01\xd0\x9f\0\0\0\0\0\0\0\0\0\0");
+
+        assert_eq!(r"AB\x20\x01\xd0\x9f", cid.to_string());
+        assert_eq!(None, cid.as_str());
+        assert_eq!("41422001d09f00000000000000000000", cid.hex().to_string());
+    }
+
+    #[test]
+    fn test_display_without_nul() {
+        let cid = Cid::new([b'A'; CID_SIZE]);
+
+        assert_eq!("AAAAAAAAAAAAAAAA", cid.to_string());
+        assert_eq!(CID_SIZE, cid.id().len());
+        assert!(!cid.is_terminated());
+    }
+}
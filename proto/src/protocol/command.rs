@@ -0,0 +1,111 @@
+use std::io::{self, Cursor, ErrorKind, Write};
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use crate::protocol::{Cid, CidLengthError, MAGIC};
+
+/// Command sent to or by the camera, identified by the code following the magic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Discovery request, broadcast or sent to a particular camera.
+    Scan,
+    /// Discovery reply, carrying the camera ID, MAC address and firmware version.
+    ScanReply,
+    /// Request to start streaming RTP to the advertised port.
+    StartRtp,
+}
+
+impl Command {
+    /// Returns the command code.
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            Command::Scan => 0x1004,
+            Command::ScanReply => 0x100e,
+            Command::StartRtp => 0x1007,
+        }
+    }
+
+    /// Encodes the command addressed to the given camera, copying the ID field byte for byte.
+    ///
+    /// Fails if the field lacks the NUL byte terminating the ID.
+    pub fn encode(&self, cid: &Cid, args: &[u8]) -> Result<Vec<u8>, io::Error> {
+        if !cid.is_terminated() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, CidLengthError(cid.id().len())));
+        }
+
+        let mut buf = Cursor::new(Vec::new());
+
+        buf.write_u16::<BigEndian>(MAGIC)?;
+        buf.write_u16::<BigEndian>(self.as_u16())?;
+        buf.write_all(cid.as_bytes())?;
+        buf.write_all(args)?;
+
+        Ok(buf.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::protocol::{Frame, Token, CID_SIZE};
+
+    /// Scan command with the all-zero token, in the wire format used by the vendor app.
+    const CAPTURED_SCAN: &[u8] = b"\x4d\x4a\x10\x04000000000000000\0\
+        00000000000000000000000000000000000000";
+
+    /// StartRtp command in the wire format used by the vendor app, addressed to a camera with a
+    /// 15 byte ID and asking for the stream on port 39412.
+    const CAPTURED_START_RTP: &[u8] = b"\x4d\x4a\x10\x07xxxxS_AB12CD000\0\
+        0000000000000000000000000000000000000039412:39412\0";
+
+    #[test]
+    fn test_encode_captured_scan() {
+        let frame = Frame::parse(CAPTURED_SCAN).unwrap();
+
+        assert_eq!(Cid::ANY, Cid::new(frame.cid()));
+        assert_eq!(
+            CAPTURED_SCAN,
+            &Command::Scan.encode(&Cid::ANY, Token::ZERO.as_bytes()).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_encode_captured_start_rtp() {
+        let frame = Frame::parse(CAPTURED_START_RTP).unwrap();
+        let cid = Cid::new(frame.cid());
+
+        assert_eq!(Some("xxxxS_AB12CD000"), cid.as_str());
+        assert_eq!(cid, Cid::from_id(b"xxxxS_AB12CD000").unwrap());
+        assert_eq!(
+            CAPTURED_START_RTP,
+            &Command::StartRtp.encode(&cid, frame.payload()).unwrap()[..]
+        );
+    }
+
+    #[test]
+    fn test_encode_rejects_unterminated_cid() {
+        let err = Command::StartRtp.encode(&Cid::new([b'A'; CID_SIZE]), b"").unwrap_err();
+
+        assert_eq!(ErrorKind::InvalidInput, err.kind());
+    }
+
+    proptest! {
+        #[test]
+        fn test_encode_command_round_trip(
+            raw in any::<[u8; CID_SIZE - 1]>(),
+            args in prop::collection::vec(any::<u8>(), 0..64),
+        ) {
+            let mut cid = [0; CID_SIZE];
+            cid[..CID_SIZE - 1].copy_from_slice(&raw);
+
+            let buf = Command::StartRtp.encode(&Cid::new(cid), &args).unwrap();
+            let frame = Frame::parse(&buf).unwrap();
+
+            prop_assert_eq!(Command::StartRtp.as_u16(), frame.command());
+            prop_assert_eq!(cid, frame.cid());
+            prop_assert_eq!(&args[..], frame.payload());
+        }
+    }
+}
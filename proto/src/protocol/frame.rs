@@ -0,0 +1,117 @@
+use core::{
+    convert::TryInto,
+    fmt::{self, Display, Formatter},
+};
+use std::error::Error;
+
+use crate::protocol::{CID_SIZE, MAGIC};
+
+/// Size of the common frame header: magic, command and the camera ID field.
+pub const HEADER_SIZE: usize = 4 + CID_SIZE;
+
+/// An error that can occur during parsing a camera frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// The frame does not start with the protocol magic.
+    InvalidMagic(u16),
+    /// The frame is truncated or its fields are inconsistent with the frame length.
+    CorruptFrame,
+    /// The frame payload could not be parsed.
+    InvalidPayload(&'static str),
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ProtocolError::InvalidMagic(magic) => write!(fmt, "invalid magic header: {:#06x}", magic),
+            ProtocolError::CorruptFrame => fmt.write_str("corrupt frame"),
+            ProtocolError::InvalidPayload(reason) => write!(fmt, "invalid payload: {}", reason),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+/// Command frame, as exchanged with the camera over UDP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    command: u16,
+    cid: [u8; 16],
+    payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Parses the given datagram into a frame, validating its header.
+    pub fn parse(buf: &'a [u8]) -> Result<Self, ProtocolError> {
+        if buf.len() < 2 {
+            return Err(ProtocolError::CorruptFrame);
+        }
+
+        let magic = u16::from_be_bytes([buf[0], buf[1]]);
+        if magic != MAGIC {
+            return Err(ProtocolError::InvalidMagic(magic));
+        }
+
+        if buf.len() < HEADER_SIZE {
+            return Err(ProtocolError::CorruptFrame);
+        }
+
+        let command = u16::from_be_bytes([buf[2], buf[3]]);
+        let cid = buf[4..HEADER_SIZE].try_into().unwrap();
+        let payload = &buf[HEADER_SIZE..];
+
+        Ok(Self { command, cid, payload })
+    }
+
+    /// Returns the command code.
+    #[inline]
+    pub fn command(&self) -> u16 {
+        self.command
+    }
+
+    /// Returns the raw camera ID field.
+    #[inline]
+    pub fn cid(&self) -> [u8; 16] {
+        self.cid
+    }
+
+    /// Returns the command payload, following the header.
+    #[inline]
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_frame() {
+        let mut buf = vec![0x4d, 0x4a, 0x10, 0x0e];
+        buf.extend_from_slice(b"XXXXXXXXXXXXXXX\0");
+        buf.extend_from_slice(b"payload");
+
+        let frame = Frame::parse(&buf).unwrap();
+        assert_eq!(0x100e, frame.command());
+        assert_eq!(*b"XXXXXXXXXXXXXXX\0", frame.cid());
+        assert_eq!(b"payload", frame.payload());
+    }
+
+    #[test]
+    fn test_parse_frame_invalid_magic() {
+        assert_eq!(
+            Err(ProtocolError::InvalidMagic(0x0102)),
+            Frame::parse(&[0x01, 0x02, 0x10, 0x0e])
+        );
+    }
+
+    #[test]
+    fn test_parse_frame_truncated() {
+        assert_eq!(Err(ProtocolError::CorruptFrame), Frame::parse(&[0x4d]));
+        assert_eq!(
+            Err(ProtocolError::CorruptFrame),
+            Frame::parse(&[0x4d, 0x4a, 0x10, 0x0e, b'X'])
+        );
+    }
+}
@@ -1,9 +1,8 @@
 use core::{convert::TryFrom, str};
-use std::net::SocketAddr;
 
 use crate::{mac::MacAddr, protocol::version::Version};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ScanInfo {
     /// Camera MAC address.
     mac: MacAddr,
@@ -11,6 +10,32 @@ pub struct ScanInfo {
     version: Version,
 }
 
+impl ScanInfo {
+    #[inline]
+    pub fn new(mac: MacAddr, version: Version) -> Self {
+        Self { mac, version }
+    }
+
+    /// Returns the camera MAC address.
+    #[inline]
+    pub fn mac(&self) -> &MacAddr {
+        &self.mac
+    }
+
+    /// Returns the firmware version.
+    #[inline]
+    pub fn version(&self) -> &Version {
+        &self.version
+    }
+
+    /// Encodes this info into the ScanReply payload wire format.
+    ///
+    /// This is the inverse of the `TryFrom<&[u8]>` conversion.
+    pub fn encode(&self) -> Vec<u8> {
+        format!("{}\0{}\0", self.mac, self.version).into_bytes()
+    }
+}
+
 impl TryFrom<&[u8]> for ScanInfo {
     type Error = &'static str;
 
@@ -18,7 +43,7 @@ impl TryFrom<&[u8]> for ScanInfo {
         let mut it = v.split(|&ch| ch == b'\0');
 
         let mac = match it.next() {
-            Some(mac) => match str::from_utf8(mac.into()) {
+            Some(mac) => match str::from_utf8(mac) {
                 Ok(mac) => match MacAddr::from_str(mac) {
                     Ok(mac) => mac,
                     Err(..) => return Err("MAC address is invalid"),
@@ -29,7 +54,7 @@ impl TryFrom<&[u8]> for ScanInfo {
         };
 
         let version = match it.next() {
-            Some(version) => match str::from_utf8(version.into()) {
+            Some(version) => match str::from_utf8(version) {
                 Ok(version) => match Version::from_str(version) {
                     Ok(version) => version,
                     Err(..) => return Err("version is invalid"),
@@ -45,42 +70,22 @@ impl TryFrom<&[u8]> for ScanInfo {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct LookupInfo {
-    /// Camera endpoint.
-    addr: SocketAddr,
-    /// Camera ID.
-    cid: [u8; 16],
-    /// Scan info.
-    info: ScanInfo,
-}
-
-impl LookupInfo {
-    pub fn new(addr: SocketAddr, cid: [u8; 16], info: ScanInfo) -> Self {
-        Self { addr, cid, info }
-    }
-
-    /// Returns socket address where the camera is bound.
-    #[inline]
-    pub fn addr(&self) -> SocketAddr {
-        self.addr
-    }
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    /// Returns camera's client id.
-    #[inline]
-    pub fn cid(&self) -> &[u8] {
-        &self.cid[..]
+    fn info() -> ScanInfo {
+        ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]))
     }
 
-    /// Returns camera's MAC address.
-    #[inline]
-    pub fn mac(&self) -> &MacAddr {
-        &self.info.mac
+    #[test]
+    fn test_encode_scan_info() {
+        assert_eq!(&b"dc:a9:04:97:9d:9b\x001.2.3.4\x00"[..], &info().encode()[..]);
     }
 
-    /// Returns camera's firmware version.
-    #[inline]
-    pub fn version(&self) -> &Version {
-        &self.info.version
+    #[test]
+    fn test_scan_info_roundtrip() {
+        let info = info();
+        assert_eq!(info, ScanInfo::try_from(&info.encode()[..]).unwrap());
     }
 }
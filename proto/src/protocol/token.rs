@@ -0,0 +1,137 @@
+use core::{
+    fmt::{self, Debug, Display, Formatter},
+    ops::Deref,
+    str::FromStr,
+};
+use std::error::Error;
+
+use crate::protocol::TOKEN_SIZE;
+
+/// Authentication token, sent as the first argument of Scan and StartRtp commands.
+///
+/// The field has a fixed size of 38 bytes. Most cameras do not check it and accept a token made
+/// of ASCII zeros, which is the default, but some firmware requires the token issued for the
+/// vendor account the camera is bound to. Tokens of any other length are rejected instead of
+/// being truncated or padded, since a mangled token would be silently ignored by the camera.
+///
+/// [`Debug`] output redacts the token, so that options holding it can be logged safely.
+///
+/// ```
+/// use cleverdog_proto::protocol::Token;
+///
+/// let token: Token = "0123456789abcdef0123456789abcdef012345".parse().unwrap();
+///
+/// assert!(!token.is_zero());
+/// assert!("0123".parse::<Token>().is_err());
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Token([u8; TOKEN_SIZE]);
+
+impl Token {
+    /// Token made of ASCII zeros, accepted by cameras that do not enforce authentication.
+    pub const ZERO: Token = Token([b'0'; TOKEN_SIZE]);
+
+    /// Constructs a new token from the raw field.
+    #[inline]
+    pub const fn new(raw: [u8; TOKEN_SIZE]) -> Self {
+        Self(raw)
+    }
+
+    /// Constructs a new token from the given bytes, which must be exactly 38 bytes long.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, TokenLengthError> {
+        if buf.len() != TOKEN_SIZE {
+            return Err(TokenLengthError(buf.len()));
+        }
+
+        let mut raw = [0; TOKEN_SIZE];
+        raw.copy_from_slice(buf);
+        Ok(Self(raw))
+    }
+
+    /// Returns the raw field.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8; TOKEN_SIZE] {
+        &self.0
+    }
+
+    /// Returns `true` if this is the [`Token::ZERO`] token.
+    #[inline]
+    pub fn is_zero(&self) -> bool {
+        *self == Self::ZERO
+    }
+}
+
+impl Default for Token {
+    #[inline]
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl Deref for Token {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Debug for Token {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self.is_zero() {
+            true => write!(fmt, "Token(ZERO)"),
+            false => write!(fmt, "Token(<redacted>)"),
+        }
+    }
+}
+
+impl FromStr for Token {
+    type Err = TokenLengthError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_bytes(s.as_bytes())
+    }
+}
+
+/// An error returned when constructing a [`Token`] from bytes of a wrong length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TokenLengthError(usize);
+
+impl TokenLengthError {
+    /// Returns the length of the rejected token.
+    #[inline]
+    pub fn actual(&self) -> usize {
+        self.0
+    }
+}
+
+impl Display for TokenLengthError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "token must be {} bytes long, got {}", TOKEN_SIZE, self.0)
+    }
+}
+
+impl Error for TokenLengthError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zero() {
+        assert_eq!(&b"00000000000000000000000000000000000000"[..], &Token::ZERO[..]);
+        assert_eq!(Token::ZERO, Token::default());
+        assert_eq!("Token(ZERO)", format!("{:?}", Token::ZERO));
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let token = Token::from_bytes(&[b'x'; TOKEN_SIZE]).unwrap();
+
+        assert!(!token.is_zero());
+        assert_eq!("Token(<redacted>)", format!("{:?}", token));
+        assert_eq!(Err(TokenLengthError(37)), Token::from_bytes(&[b'x'; TOKEN_SIZE - 1]));
+        assert_eq!(Err(TokenLengthError(39)), Token::from_bytes(&[b'x'; TOKEN_SIZE + 1]));
+    }
+}
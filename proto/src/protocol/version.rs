@@ -0,0 +1,72 @@
+use core::{
+    fmt::{self, Display, Formatter},
+    num::ParseIntError,
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Version([u16; 4]);
+
+impl Version {
+    #[inline]
+    pub fn new(buf: [u16; 4]) -> Self {
+        Self(buf)
+    }
+
+    #[inline]
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(v: &str) -> Result<Self, ParseIntError> {
+        <Version as FromStr>::from_str(v)
+    }
+}
+
+impl FromStr for Version {
+    type Err = ParseIntError;
+
+    fn from_str(v: &str) -> Result<Self, Self::Err> {
+        let mut buf = [0u16; 4];
+
+        for (idx, b) in v.split('.').take(4).enumerate() {
+            buf[idx] = b.parse()?;
+        }
+
+        Ok(Self::new(buf))
+    }
+}
+
+impl Display for Version {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        let Version([major, minor, patch, release]) = self;
+        write!(fmt, "{}.{}.{}.{}", major, minor, patch, release)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Version::new([1, 2, 3, 4]), "1.2.3.4".parse().unwrap());
+    }
+
+    proptest! {
+        #[test]
+        fn test_parse_display_roundtrip(buf in any::<[u16; 4]>()) {
+            let version = Version::new(buf);
+            prop_assert_eq!(version, format!("{}", version).parse::<Version>().unwrap());
+        }
+
+        #[test]
+        fn test_parse_never_panics(s in "\\PC*") {
+            let _ = s.parse::<Version>();
+        }
+
+        #[test]
+        fn test_parse_never_panics_on_version_like(s in "[0-9.]{0,32}") {
+            let _ = s.parse::<Version>();
+        }
+    }
+}
@@ -0,0 +1,339 @@
+use core::ops::Deref;
+use std::{
+    convert::TryInto,
+    error::Error,
+    fmt::{self, Display, Formatter},
+    time::Instant,
+};
+
+pub use self::{
+    gap::{Gap, Gaps},
+    jitter::{BufferedPacket, JitterBuffer},
+    nack::{generic_nack, Retransmissions, RTCP_TRANSPORT_FEEDBACK},
+    rtcp::{parse_sender_report, receiver_report, Reception, ReportBlock, RTCP_RECEIVER_REPORT},
+};
+
+mod gap;
+mod jitter;
+mod nack;
+mod rtcp;
+
+/// Size of the fixed RTP header.
+pub const HEADER_SIZE: usize = 12;
+
+/// Size of the header extension preamble, holding the profile and the length.
+const EXTENSION_HEADER_SIZE: usize = 4;
+
+/// RTCP sender report packet type.
+pub const RTCP_SENDER_REPORT: u8 = 200;
+
+#[derive(Debug, Clone)]
+pub struct BufferTooSmall;
+
+impl Display for BufferTooSmall {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "buffer too small")
+    }
+}
+
+impl Error for BufferTooSmall {}
+
+/// RTP header, including the CSRC list and the header extension.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Header<'a>(&'a [u8]);
+
+impl<'a> Header<'a> {
+    /// Parses the header at the start of the given packet, validating that the CSRC list and
+    /// the header extension fit into it.
+    pub fn from_slice(buf: &'a [u8]) -> Result<Self, BufferTooSmall> {
+        if buf.len() < HEADER_SIZE {
+            return Err(BufferTooSmall);
+        }
+
+        let mut size = HEADER_SIZE + 4 * usize::from(buf[0] & 0x0f);
+        if buf[0] & 0x10 != 0 {
+            if buf.len() < size + EXTENSION_HEADER_SIZE {
+                return Err(BufferTooSmall);
+            }
+            let len = u16::from_be_bytes([buf[size + 2], buf[size + 3]]);
+            size += EXTENSION_HEADER_SIZE + 4 * usize::from(len);
+        }
+
+        if buf.len() < size {
+            return Err(BufferTooSmall);
+        }
+
+        Ok(Header(&buf[..size]))
+    }
+
+    #[inline]
+    pub fn version(&self) -> u8 {
+        let buf = self.as_slice();
+        let byte = buf[0];
+        byte >> 6
+    }
+
+    /// Returns `true` if the packet ends with padding, which [`RtpPacket::payload`] excludes.
+    #[inline]
+    pub fn has_padding(&self) -> bool {
+        self.0[0] & 0x20 != 0
+    }
+
+    /// Returns `true` if the header carries an extension.
+    #[inline]
+    pub fn has_extension(&self) -> bool {
+        self.0[0] & 0x10 != 0
+    }
+
+    /// Returns the number of CSRC identifiers following the fixed header.
+    #[inline]
+    pub fn csrc_count(&self) -> u8 {
+        self.0[0] & 0x0f
+    }
+
+    /// Returns the marker bit, which for H.264 marks the last packet of an access unit.
+    #[inline]
+    pub fn marker(&self) -> bool {
+        self.0[1] & 0x80 != 0
+    }
+
+    #[inline]
+    pub fn payload_type(&self) -> u8 {
+        self.0[1] & 0x7f
+    }
+
+    #[inline]
+    pub fn sequence_number(&self) -> u16 {
+        u16::from_be_bytes(self.as_slice()[2..4].try_into().unwrap())
+    }
+
+    #[inline]
+    pub fn timestamp(&self) -> u32 {
+        u32::from_be_bytes(self.as_slice()[4..8].try_into().unwrap())
+    }
+
+    #[inline]
+    pub fn ssrc(&self) -> u32 {
+        u32::from_be_bytes(self.as_slice()[8..12].try_into().unwrap())
+    }
+
+    /// Returns the contributing source identifiers.
+    pub fn csrcs(&self) -> impl Iterator<Item = u32> + 'a {
+        let end = HEADER_SIZE + 4 * usize::from(self.csrc_count());
+        self.0[HEADER_SIZE..end]
+            .chunks_exact(4)
+            .map(|v| u32::from_be_bytes(v.try_into().unwrap()))
+    }
+
+    /// Returns the header extension, if present.
+    pub fn extension(&self) -> Option<Extension<'a>> {
+        if !self.has_extension() {
+            return None;
+        }
+
+        let offset = HEADER_SIZE + 4 * usize::from(self.csrc_count());
+        let buf = self.0;
+        let extension = Extension {
+            profile: u16::from_be_bytes([buf[offset], buf[offset + 1]]),
+            data: &buf[offset + EXTENSION_HEADER_SIZE..],
+        };
+
+        Some(extension)
+    }
+
+    /// Returns the header bytes, including the CSRC list and the header extension.
+    #[inline]
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Header(buf) => buf,
+        }
+    }
+}
+
+/// RTP header extension.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Extension<'a> {
+    profile: u16,
+    data: &'a [u8],
+}
+
+impl<'a> Extension<'a> {
+    /// Returns the profile-defined identifier, e.g. `0xbede` for RFC 8285 one-byte extensions.
+    #[inline]
+    pub fn profile(&self) -> u16 {
+        self.profile
+    }
+
+    /// Returns the extension data, a multiple of 4 bytes long.
+    #[inline]
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// RTP packet received from the camera, as passed to streaming callbacks.
+///
+/// Dereferences to the raw packet bytes, starting with the RTP header, so it can be passed to
+/// any sink as is.
+#[derive(Debug, Copy, Clone)]
+pub struct RtpPacket<'a> {
+    buf: &'a [u8],
+    header: Header<'a>,
+    channel: u8,
+    arrival: Instant,
+    gap: Option<Gap>,
+}
+
+impl<'a> RtpPacket<'a> {
+    /// Constructs a new packet from the raw bytes, starting with the RTP header, the channel
+    /// byte of the datagram it was carried in, and its arrival time.
+    ///
+    /// Fails if the header or the padding do not fit into the packet.
+    pub fn new(buf: &'a [u8], channel: u8, arrival: Instant) -> Result<Self, BufferTooSmall> {
+        let header = Header::from_slice(buf)?;
+        if header.has_padding() {
+            match buf[header.as_slice().len()..].last() {
+                Some(&len) if len > 0 && usize::from(len) <= buf.len() - header.as_slice().len() => {}
+                _ => return Err(BufferTooSmall),
+            }
+        }
+
+        let packet = Self {
+            buf,
+            header,
+            channel,
+            arrival,
+            gap: None,
+        };
+
+        Ok(packet)
+    }
+
+    /// Attaches the gap preceding the packet.
+    #[inline]
+    pub fn with_gap(mut self, gap: Option<Gap>) -> Self {
+        self.gap = gap;
+        self
+    }
+
+    /// Returns the parsed RTP header.
+    #[inline]
+    pub fn header(&self) -> &Header<'a> {
+        &self.header
+    }
+
+    /// Returns the payload following the RTP header, CSRC list and header extension, without
+    /// the padding.
+    pub fn payload(&self) -> &'a [u8] {
+        let mut end = self.buf.len();
+        if self.header.has_padding() {
+            end -= usize::from(self.buf[end - 1]);
+        }
+
+        &self.buf[self.header.as_slice().len()..end]
+    }
+
+    /// Returns the channel byte of the camera datagram the packet was carried in.
+    #[inline]
+    pub fn channel(&self) -> u8 {
+        self.channel
+    }
+
+    /// Returns packets missing right before this one, so that consumers can conceal the loss at
+    /// the right place.
+    ///
+    /// Set for packets passed to streaming callbacks, in the order they are passed.
+    #[inline]
+    pub fn gap(&self) -> Option<Gap> {
+        self.gap
+    }
+
+    /// Returns the time the packet was received at.
+    #[inline]
+    pub fn arrival(&self) -> Instant {
+        self.arrival
+    }
+
+    /// Returns the raw packet bytes, starting with the RTP header.
+    #[inline]
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.buf
+    }
+}
+
+impl Deref for RtpPacket<'_> {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        self.buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_rtp() {
+        let header = Header(&[128, 96, 0, 17, 0, 0, 140, 160, 0, 0, 0, 16]);
+
+        assert_eq!(2, header.version());
+        assert!(!header.has_padding());
+        assert!(!header.has_extension());
+        assert!(!header.marker());
+        assert_eq!(96, header.payload_type());
+        assert_eq!(17, header.sequence_number());
+        assert_eq!(36000, header.timestamp());
+        assert_eq!(16, header.ssrc());
+        assert_eq!(0, header.csrcs().count());
+        assert_eq!(None, header.extension());
+    }
+
+    #[test]
+    fn test_parse_rtp_csrcs_and_extension() {
+        let buf = [
+            0xb2, 0xe0, 0, 17, 0, 0, 140, 160, 0, 0, 0, 16, // V=2, P, X, CC=2, M, PT=96
+            0, 0, 0, 1, 0, 0, 0, 2, // CSRCs
+            0xbe, 0xde, 0, 1, 0x10, 0xff, 0, 0, // one-byte extension, a single word
+            0x65, 0x88, 0, 0, 3, // payload and 3 bytes of padding
+        ];
+        let packet = RtpPacket::new(&buf, 1, Instant::now()).unwrap();
+        let header = packet.header();
+
+        assert!(header.has_padding());
+        assert!(header.marker());
+        assert_eq!(96, header.payload_type());
+        assert_eq!(vec![1, 2], header.csrcs().collect::<Vec<_>>());
+
+        let extension = header.extension().unwrap();
+        assert_eq!(0xbede, extension.profile());
+        assert_eq!(&[0x10, 0xff, 0, 0], extension.data());
+
+        assert_eq!(28, header.as_slice().len());
+        assert_eq!(&[0x65, 0x88], packet.payload());
+    }
+
+    #[test]
+    fn test_parse_rtp_truncated() {
+        // CSRC count beyond the packet.
+        assert!(Header::from_slice(&[0x81, 96, 0, 17, 0, 0, 140, 160, 0, 0, 0, 16]).is_err());
+        // Extension length beyond the packet.
+        assert!(Header::from_slice(&[0x90, 96, 0, 17, 0, 0, 140, 160, 0, 0, 0, 16, 0xbe, 0xde, 0, 1]).is_err());
+        // Padding longer than the payload.
+        let buf = [0xa0, 96, 0, 17, 0, 0, 140, 160, 0, 0, 0, 16, 0x65, 3];
+        assert!(RtpPacket::new(&buf, 1, Instant::now()).is_err());
+    }
+
+    #[test]
+    fn test_packet() {
+        let buf = [128, 96, 0, 17, 0, 0, 140, 160, 0, 0, 0, 16, 0x65, 0x88];
+        let packet = RtpPacket::new(&buf, 1, Instant::now()).unwrap();
+
+        assert_eq!(17, packet.header().sequence_number());
+        assert_eq!(&[0x65, 0x88], packet.payload());
+        assert_eq!(1, packet.channel());
+        assert_eq!(&buf[..], &packet[..]);
+        assert!(RtpPacket::new(&buf[..11], 1, Instant::now()).is_err());
+    }
+}
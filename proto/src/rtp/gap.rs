@@ -0,0 +1,122 @@
+use core::fmt::{self, Display, Formatter};
+
+/// Range of RTP sequence numbers missing from the stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    first: u16,
+    len: u16,
+}
+
+impl Gap {
+    /// Constructs a new gap of `len` packets, starting with `first`.
+    #[inline]
+    pub fn new(first: u16, len: u16) -> Self {
+        Self { first, len }
+    }
+
+    /// Returns the sequence number of the first missing packet.
+    #[inline]
+    pub fn first(&self) -> u16 {
+        self.first
+    }
+
+    /// Returns the sequence number of the last missing packet.
+    ///
+    /// An empty gap ends right before it starts, i.e. at `first - 1`.
+    #[inline]
+    pub fn last(&self) -> u16 {
+        self.first.wrapping_add(self.len.wrapping_sub(1))
+    }
+
+    /// Returns the number of missing packets.
+    #[inline]
+    pub fn len(&self) -> u16 {
+        self.len
+    }
+
+    /// Returns `true` if no packets are missing, which never holds for gaps found in a stream.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+impl Display for Gap {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self.len {
+            0 => write!(fmt, "none"),
+            1 => write!(fmt, "{}", self.first),
+            _ => write!(fmt, "{}-{}", self.first, self.last()),
+        }
+    }
+}
+
+/// Finds gaps in the sequence of delivered packets.
+#[derive(Debug, Default)]
+pub struct Gaps {
+    last: Option<u16>,
+}
+
+impl Gaps {
+    /// Constructs a new tracker expecting any sequence number first.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the given packet, returning the gap preceding it, if any.
+    ///
+    /// Packets older than the last registered one, i.e. reordered or duplicated, are ignored.
+    pub fn on_packet(&mut self, seq: u16) -> Option<Gap> {
+        let prev = match self.last {
+            Some(prev) => prev,
+            None => {
+                self.last = Some(seq);
+                return None;
+            }
+        };
+
+        let delta = seq.wrapping_sub(prev);
+        if delta == 0 || delta >= 0x8000 {
+            return None;
+        }
+        self.last = Some(seq);
+
+        match delta {
+            1 => None,
+            delta => Some(Gap::new(prev.wrapping_add(1), delta - 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_gaps() {
+        let mut gaps = Gaps::new();
+
+        assert_eq!(None, gaps.on_packet(0xfffd));
+        assert_eq!(None, gaps.on_packet(0xfffe));
+        assert_eq!(Some(Gap::new(0xffff, 3)), gaps.on_packet(2));
+        assert_eq!(None, gaps.on_packet(0));
+        assert_eq!(None, gaps.on_packet(2));
+        assert_eq!(Some(Gap::new(3, 2)), gaps.on_packet(5));
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!("65535-1", Gap::new(0xffff, 3).to_string());
+        assert_eq!("4", Gap::new(4, 1).to_string());
+        assert_eq!(1, Gap::new(0xffff, 3).last());
+    }
+
+    #[test]
+    fn test_zero_length() {
+        let gap = Gap::new(5, 0);
+        assert!(gap.is_empty());
+        assert_eq!(4, gap.last());
+        assert_eq!(0xffff, Gap::new(0, 0).last());
+        assert_eq!("none", gap.to_string());
+    }
+}
@@ -0,0 +1,226 @@
+use core::time::Duration;
+use std::{collections::BTreeMap, time::Instant};
+
+use super::RtpPacket;
+
+/// RTP packet copied into a [`JitterBuffer`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferedPacket {
+    buf: Vec<u8>,
+    channel: u8,
+    arrival: Instant,
+}
+
+impl BufferedPacket {
+    /// Returns the packet, as it has been received.
+    pub fn packet(&self) -> RtpPacket<'_> {
+        RtpPacket::new(&self.buf, self.channel, self.arrival).expect("buffered packets must be valid")
+    }
+}
+
+/// Jitter buffer, restoring the sequence order of RTP packets reordered by the network.
+///
+/// Packets are held until every packet preceding them has been released, but no longer than the
+/// configured latency and while no more than the configured depth of packets is waiting. Then
+/// the missing packets are given up on as lost. Packets arriving after their successors have
+/// been released, as well as duplicates, are dropped.
+///
+/// ```
+/// use core::time::Duration;
+/// use std::time::Instant;
+///
+/// use cleverdog_proto::rtp::{JitterBuffer, RtpPacket};
+///
+/// let mut buffer = JitterBuffer::new(16, Duration::from_millis(100));
+/// let now = Instant::now();
+///
+/// for seq in [1u16, 3, 2] {
+///     let mut buf = vec![0x80, 96, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16];
+///     buf[2..4].copy_from_slice(&seq.to_be_bytes());
+///     buffer.push(&RtpPacket::new(&buf, 1, now).unwrap());
+/// }
+///
+/// // Packets preceding the first one may still arrive within the latency.
+/// assert!(buffer.pop(now).is_none());
+///
+/// let later = now + Duration::from_millis(100);
+/// let seqs: Vec<_> = core::iter::from_fn(|| buffer.pop(later))
+///     .map(|v| v.packet().header().sequence_number())
+///     .collect();
+/// assert_eq!(vec![1, 2, 3], seqs);
+/// ```
+#[derive(Debug)]
+pub struct JitterBuffer {
+    depth: usize,
+    latency: Duration,
+    packets: BTreeMap<i64, BufferedPacket>,
+    /// Extended sequence number of the last pushed packet, tracking wraparounds.
+    last: Option<(u16, i64)>,
+    /// Extended sequence number of the next packet to release.
+    next: Option<i64>,
+    lost: u64,
+    dropped: u64,
+}
+
+impl JitterBuffer {
+    /// Constructs a new jitter buffer holding up to `depth` packets for up to `latency`.
+    pub fn new(depth: usize, latency: Duration) -> Self {
+        Self {
+            depth: depth.max(1),
+            latency,
+            packets: BTreeMap::new(),
+            last: None,
+            next: None,
+            lost: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns the number of packets currently held.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Returns `true` if no packets are held.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Returns the number of packets given up on as lost so far.
+    #[inline]
+    pub fn lost(&self) -> u64 {
+        self.lost
+    }
+
+    /// Returns the number of late and duplicate packets dropped so far.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Copies the given packet into the buffer, returning `false` if it has been dropped.
+    pub fn push(&mut self, packet: &RtpPacket) -> bool {
+        let seq = packet.header().sequence_number();
+        let ext = match self.last {
+            Some((prev, ext)) => ext + i64::from(seq.wrapping_sub(prev) as i16),
+            None => i64::from(seq),
+        };
+        self.last = Some((seq, ext));
+
+        if self.next.map(|v| ext < v).unwrap_or(false) || self.packets.contains_key(&ext) {
+            self.dropped += 1;
+            return false;
+        }
+
+        let packet = BufferedPacket {
+            buf: packet.as_bytes().to_vec(),
+            channel: packet.channel(),
+            arrival: packet.arrival(),
+        };
+        self.packets.insert(ext, packet);
+
+        true
+    }
+
+    /// Returns the next packet in sequence order, if it is available or the packets preceding
+    /// it are considered lost at the given time.
+    pub fn pop(&mut self, now: Instant) -> Option<BufferedPacket> {
+        let (&first, ..) = self.packets.iter().next()?;
+
+        // The very first packet is held as well, since packets preceding it may still arrive.
+        let overdue = || {
+            let oldest = self.packets.values().map(|v| v.arrival).min();
+            oldest
+                .map(|v| now.saturating_duration_since(v) >= self.latency)
+                .unwrap_or(false)
+        };
+        if self.next != Some(first) && self.packets.len() <= self.depth && !overdue() {
+            return None;
+        }
+
+        if let Some(next) = self.next {
+            self.lost += (first - next) as u64;
+        }
+        self.next = Some(first + 1);
+        self.packets.remove(&first)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rtp(seq: u16) -> Vec<u8> {
+        let mut buf = vec![0x80, 96, 0, 0, 0, 0, 0, 0, 0, 0, 0, 16];
+        buf[2..4].copy_from_slice(&seq.to_be_bytes());
+        buf
+    }
+
+    fn push(buffer: &mut JitterBuffer, seq: u16, arrival: Instant) -> bool {
+        buffer.push(&RtpPacket::new(&rtp(seq), 1, arrival).unwrap())
+    }
+
+    fn drain(buffer: &mut JitterBuffer, now: Instant) -> Vec<u16> {
+        core::iter::from_fn(|| buffer.pop(now))
+            .map(|v| v.packet().header().sequence_number())
+            .collect()
+    }
+
+    #[test]
+    fn test_reorder_across_wraparound() {
+        let mut buffer = JitterBuffer::new(8, Duration::from_secs(1));
+        let now = Instant::now();
+
+        for seq in [0xfffe, 0, 0xffff, 2, 1] {
+            push(&mut buffer, seq, now);
+        }
+
+        assert_eq!(Vec::<u16>::new(), drain(&mut buffer, now));
+        assert_eq!(
+            vec![0xfffe, 0xffff, 0, 1, 2],
+            drain(&mut buffer, now + Duration::from_secs(1))
+        );
+        assert_eq!(0, buffer.lost());
+    }
+
+    #[test]
+    fn test_gap_released_by_latency() {
+        let mut buffer = JitterBuffer::new(8, Duration::from_millis(100));
+        let now = Instant::now();
+
+        push(&mut buffer, 1, now);
+        assert_eq!(vec![1], drain(&mut buffer, now + Duration::from_millis(100)));
+
+        push(&mut buffer, 3, now + Duration::from_millis(100));
+        push(&mut buffer, 4, now + Duration::from_millis(110));
+        assert_eq!(Vec::<u16>::new(), drain(&mut buffer, now + Duration::from_millis(150)));
+        assert_eq!(vec![3, 4], drain(&mut buffer, now + Duration::from_millis(200)));
+        assert_eq!(1, buffer.lost());
+
+        // The missing packet arrives too late.
+        assert!(!push(&mut buffer, 2, now + Duration::from_millis(210)));
+        assert!(push(&mut buffer, 5, now + Duration::from_millis(220)));
+        assert!(!push(&mut buffer, 5, now + Duration::from_millis(230)));
+        assert_eq!(2, buffer.dropped());
+    }
+
+    #[test]
+    fn test_gap_released_by_depth() {
+        let mut buffer = JitterBuffer::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+
+        push(&mut buffer, 1, now);
+        push(&mut buffer, 2, now);
+        push(&mut buffer, 3, now);
+        assert_eq!(vec![1, 2, 3], drain(&mut buffer, now));
+
+        push(&mut buffer, 5, now);
+        push(&mut buffer, 6, now);
+        assert_eq!(Vec::<u16>::new(), drain(&mut buffer, now));
+        push(&mut buffer, 7, now);
+        assert_eq!(vec![5, 6, 7], drain(&mut buffer, now));
+        assert_eq!(1, buffer.lost());
+    }
+}
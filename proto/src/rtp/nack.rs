@@ -0,0 +1,127 @@
+use std::collections::VecDeque;
+
+/// RTCP transport layer feedback packet type.
+pub const RTCP_TRANSPORT_FEEDBACK: u8 = 205;
+
+/// Feedback message type of generic NACKs.
+const FMT_GENERIC_NACK: u8 = 1;
+
+/// Largest sequence gap retransmissions are requested for. Larger gaps, e.g. after a restart,
+/// are not worth repairing.
+const MAX_GAP: u16 = 32;
+
+/// Number of requested sequence numbers remembered for detecting retransmissions.
+const MAX_PENDING: usize = 128;
+
+/// Encodes an RTCP generic NACK, as defined in RFC 4585, requesting retransmission of the
+/// given sequence numbers of the media source.
+///
+/// ```
+/// use cleverdog_proto::rtp::generic_nack;
+///
+/// let buf = generic_nack(2, 16, &[100, 102, 116, 117]);
+///
+/// // A single FCI entry: PID 100 and BLP with bits for 102 and 116.
+/// assert_eq!(&[0x81, 205, 0, 4], &buf[..4]);
+/// assert_eq!(&[0, 100, 0x80, 0x02], &buf[12..16]);
+/// // 117 does not fit into the bitmask of 100 and starts another entry.
+/// assert_eq!(&[0, 117, 0, 0], &buf[16..]);
+/// ```
+pub fn generic_nack(sender_ssrc: u32, media_ssrc: u32, lost: &[u16]) -> Vec<u8> {
+    let mut entries: Vec<(u16, u16)> = Vec::new();
+    for &seq in lost {
+        match entries.last_mut() {
+            Some((pid, blp)) if seq.wrapping_sub(*pid).wrapping_sub(1) < 16 => {
+                *blp |= 1 << seq.wrapping_sub(*pid).wrapping_sub(1);
+            }
+            _ => entries.push((seq, 0)),
+        }
+    }
+
+    let mut buf = Vec::with_capacity(12 + 4 * entries.len());
+    buf.extend_from_slice(&[0x80 | FMT_GENERIC_NACK, RTCP_TRANSPORT_FEEDBACK]);
+    // Length in 32-bit words minus one.
+    buf.extend_from_slice(&(2 + entries.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&sender_ssrc.to_be_bytes());
+    buf.extend_from_slice(&media_ssrc.to_be_bytes());
+    for (pid, blp) in entries {
+        buf.extend_from_slice(&pid.to_be_bytes());
+        buf.extend_from_slice(&blp.to_be_bytes());
+    }
+
+    buf
+}
+
+/// Tracks sequence gaps to request retransmissions for, and retransmitted packets arriving.
+#[derive(Debug, Default)]
+pub struct Retransmissions {
+    last: Option<u16>,
+    pending: VecDeque<u16>,
+}
+
+impl Retransmissions {
+    /// Constructs a new tracker without requested packets.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the given packet, returning sequence numbers missing before it and whether it
+    /// is a retransmission of a requested packet.
+    pub fn on_packet(&mut self, seq: u16) -> (Vec<u16>, bool) {
+        if let Some(idx) = self.pending.iter().position(|&v| v == seq) {
+            self.pending.remove(idx);
+            return (Vec::new(), true);
+        }
+
+        let prev = match self.last.replace(seq) {
+            Some(prev) => prev,
+            None => return (Vec::new(), false),
+        };
+
+        let delta = seq.wrapping_sub(prev);
+        if !(2..=MAX_GAP).contains(&delta) {
+            // In order, a duplicate, reordered or too far ahead.
+            if delta >= 0x8000 {
+                self.last = Some(prev);
+            }
+            return (Vec::new(), false);
+        }
+
+        let lost: Vec<_> = (1..delta).map(|v| prev.wrapping_add(v)).collect();
+        self.pending.extend(&lost);
+        while self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+        }
+
+        (lost, false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generic_nack_wraparound() {
+        let buf = generic_nack(2, 16, &[0xffff, 0, 1]);
+
+        assert_eq!(
+            &[0x81, 205, 0, 3, 0, 0, 0, 2, 0, 0, 0, 16, 0xff, 0xff, 0, 0x03],
+            &buf[..]
+        );
+    }
+
+    #[test]
+    fn test_retransmissions() {
+        let mut retransmissions = Retransmissions::new();
+
+        assert_eq!((vec![], false), retransmissions.on_packet(0xfffe));
+        assert_eq!((vec![0xffff, 0], false), retransmissions.on_packet(1));
+        assert_eq!((vec![], false), retransmissions.on_packet(2));
+        assert_eq!((vec![], true), retransmissions.on_packet(0));
+        assert_eq!((vec![], false), retransmissions.on_packet(0));
+        // Too large gaps are not repaired.
+        assert_eq!((vec![], false), retransmissions.on_packet(2 + MAX_GAP + 1));
+        assert_eq!((vec![], true), retransmissions.on_packet(0xffff));
+    }
+}
@@ -0,0 +1,357 @@
+use std::time::Instant;
+
+use super::RTCP_SENDER_REPORT;
+use crate::ntp::NtpTimestamp;
+
+/// RTCP receiver report packet type.
+pub const RTCP_RECEIVER_REPORT: u8 = 201;
+
+/// Forward sequence jump treated as the source restarting its sequence rather than as loss.
+const MAX_DROPOUT: u16 = 3000;
+
+/// Largest cumulative number of lost packets representable in a report block, a signed 24-bit
+/// value.
+const MAX_CUMULATIVE_LOST: i64 = 0x7f_ffff;
+
+/// Reception report block of a single source, as defined in RFC 3550, section 6.4.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportBlock {
+    /// SSRC of the source the block is about.
+    pub ssrc: u32,
+    /// Fraction of packets lost since the previous report, in units of 1/256.
+    pub fraction_lost: u8,
+    /// Number of packets lost since the beginning of reception, clamped to 24 bits.
+    pub cumulative_lost: i32,
+    /// Highest sequence number received, extended with the number of sequence cycles.
+    pub highest_sequence: u32,
+    /// Interarrival jitter in timestamp units.
+    pub jitter: u32,
+    /// Middle 32 bits of the NTP timestamp of the last sender report received.
+    pub last_sr: u32,
+    /// Delay since the last sender report received, in units of 1/65536 seconds.
+    pub delay_since_last_sr: u32,
+}
+
+impl ReportBlock {
+    /// Size of an encoded report block.
+    pub const SIZE: usize = 24;
+
+    /// Appends the encoded block to the given buffer.
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.ssrc.to_be_bytes());
+        buf.push(self.fraction_lost);
+        buf.extend_from_slice(&self.cumulative_lost.to_be_bytes()[1..]);
+        buf.extend_from_slice(&self.highest_sequence.to_be_bytes());
+        buf.extend_from_slice(&self.jitter.to_be_bytes());
+        buf.extend_from_slice(&self.last_sr.to_be_bytes());
+        buf.extend_from_slice(&self.delay_since_last_sr.to_be_bytes());
+    }
+}
+
+/// Encodes an RTCP receiver report carrying the given report blocks, at most 31 of them.
+///
+/// ```
+/// use cleverdog_proto::rtp::{receiver_report, ReportBlock};
+///
+/// let block = ReportBlock {
+///     ssrc: 16,
+///     fraction_lost: 64,
+///     cumulative_lost: -1,
+///     highest_sequence: 0x1_0002,
+///     jitter: 90,
+///     last_sr: 0,
+///     delay_since_last_sr: 0,
+/// };
+/// let buf = receiver_report(2, &[block]);
+///
+/// assert_eq!(&[0x81, 201, 0, 7, 0, 0, 0, 2], &buf[..8]);
+/// assert_eq!(&[0, 0, 0, 16, 64, 0xff, 0xff, 0xff, 0, 1, 0, 2, 0, 0, 0, 90], &buf[8..24]);
+/// assert_eq!(32, buf.len());
+/// ```
+pub fn receiver_report(sender_ssrc: u32, blocks: &[ReportBlock]) -> Vec<u8> {
+    assert!(blocks.len() < 32, "report count must fit into 5 bits");
+
+    let mut buf = Vec::with_capacity(8 + ReportBlock::SIZE * blocks.len());
+    buf.extend_from_slice(&[0x80 | blocks.len() as u8, RTCP_RECEIVER_REPORT]);
+    // Length in 32-bit words minus one.
+    buf.extend_from_slice(&(1 + 6 * blocks.len() as u16).to_be_bytes());
+    buf.extend_from_slice(&sender_ssrc.to_be_bytes());
+    for block in blocks {
+        block.encode(&mut buf);
+    }
+
+    buf
+}
+
+/// Returns the sender SSRC and the NTP timestamp of the given RTCP sender report, or `None` if the
+/// packet is not one.
+///
+/// ```
+/// use cleverdog_proto::{ntp::NtpTimestamp, rtp::parse_sender_report};
+///
+/// let buf = [0x80, 200, 0, 6, 0, 0, 0, 16, 0xe1, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+/// assert_eq!(Some((16, NtpTimestamp::new(0xe100_0000, 0x8000_0000))), parse_sender_report(&buf));
+/// assert_eq!(None, parse_sender_report(&buf[..16]));
+/// ```
+pub fn parse_sender_report(buf: &[u8]) -> Option<(u32, NtpTimestamp)> {
+    if buf.len() < 28 || buf[0] >> 6 != 2 || buf[1] != RTCP_SENDER_REPORT {
+        return None;
+    }
+
+    let ssrc = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]);
+    let mut ntp = [0; 8];
+    ntp.copy_from_slice(&buf[8..16]);
+    Some((ssrc, NtpTimestamp::from_u64(u64::from_be_bytes(ntp))))
+}
+
+/// Reception statistics of a single source, maintained as described in RFC 3550, appendix A.
+///
+/// ```
+/// use std::time::Instant;
+///
+/// use cleverdog_proto::rtp::Reception;
+///
+/// let mut reception = Reception::new(90_000);
+/// let now = Instant::now();
+/// for seq in [1, 2, 4, 5] {
+///     reception.on_packet(16, seq, 0, now);
+/// }
+///
+/// let block = reception.report(now).unwrap();
+/// assert_eq!(1, block.cumulative_lost);
+/// // One of 5 expected packets, in units of 1/256.
+/// assert_eq!(51, block.fraction_lost);
+/// assert_eq!(5, block.highest_sequence);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Reception {
+    clock_rate: f64,
+    ssrc: Option<u32>,
+    base_seq: u16,
+    max_seq: u16,
+    /// Number of sequence wraparounds, shifted to the upper 16 bits.
+    cycles: u32,
+    received: u32,
+    expected_prior: u32,
+    received_prior: u32,
+    base: Option<Instant>,
+    transit: Option<f64>,
+    jitter: f64,
+    /// Timestamp of the last sender report along with the time it has been received at.
+    last_sr: Option<(NtpTimestamp, Instant)>,
+}
+
+impl Reception {
+    /// Constructs new empty statistics of a source with the given RTP clock rate.
+    pub fn new(clock_rate: u32) -> Self {
+        Self {
+            clock_rate: f64::from(clock_rate),
+            ssrc: None,
+            base_seq: 0,
+            max_seq: 0,
+            cycles: 0,
+            received: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            base: None,
+            transit: None,
+            jitter: 0.0,
+            last_sr: None,
+        }
+    }
+
+    /// Returns the SSRC of the source, once a packet has been received.
+    #[inline]
+    pub fn ssrc(&self) -> Option<u32> {
+        self.ssrc
+    }
+
+    /// Returns the interarrival jitter in timestamp units.
+    #[inline]
+    pub fn jitter(&self) -> u32 {
+        self.jitter as u32
+    }
+
+    /// Accounts a packet with the given header fields, received at the specified time.
+    ///
+    /// A packet from another source, or a sequence jump too large to be loss, restarts the
+    /// statistics.
+    pub fn on_packet(&mut self, ssrc: u32, seq: u16, timestamp: u32, arrival: Instant) {
+        let delta = seq.wrapping_sub(self.max_seq);
+        if self.ssrc != Some(ssrc) || (MAX_DROPOUT..0x8000).contains(&delta) {
+            *self = Self::new(self.clock_rate as u32);
+            self.ssrc = Some(ssrc);
+            self.base_seq = seq;
+            self.max_seq = seq;
+        } else if delta < 0x8000 {
+            if seq < self.max_seq {
+                self.cycles = self.cycles.wrapping_add(1 << 16);
+            }
+            self.max_seq = seq;
+        }
+        self.received = self.received.wrapping_add(1);
+
+        let base = *self.base.get_or_insert(arrival);
+        let transit = arrival.saturating_duration_since(base).as_secs_f64() * self.clock_rate - f64::from(timestamp);
+        if let Some(prev) = self.transit {
+            // RTP timestamps wrap around, which the difference of 32-bit values accounts for.
+            let delta = (transit - prev).rem_euclid(f64::from(u32::MAX) + 1.0);
+            let delta = delta.min(f64::from(u32::MAX) + 1.0 - delta);
+            self.jitter += (delta - self.jitter) / 16.0;
+        }
+        self.transit = Some(transit);
+    }
+
+    /// Accounts a sender report of the source with the given SSRC, received at the specified
+    /// time, so that the sender can compute the round-trip time from the next report.
+    ///
+    /// Reports of other sources are ignored.
+    pub fn on_sender_report(&mut self, ssrc: u32, ntp: NtpTimestamp, arrival: Instant) {
+        if self.ssrc == Some(ssrc) {
+            self.last_sr = Some((ntp, arrival));
+        }
+    }
+
+    /// Returns the report block for the packets received so far as of the given time, or `None`
+    /// before the first packet.
+    ///
+    /// The fraction lost covers the interval since the previous call.
+    pub fn report(&mut self, now: Instant) -> Option<ReportBlock> {
+        let ssrc = self.ssrc?;
+
+        let highest_sequence = self.cycles | u32::from(self.max_seq);
+        let expected = highest_sequence.wrapping_sub(u32::from(self.base_seq)).wrapping_add(1);
+        let lost = i64::from(expected) - i64::from(self.received);
+
+        let expected_interval = expected.wrapping_sub(self.expected_prior);
+        let received_interval = self.received.wrapping_sub(self.received_prior);
+        self.expected_prior = expected;
+        self.received_prior = self.received;
+
+        let lost_interval = i64::from(expected_interval) - i64::from(received_interval);
+        let fraction_lost = match (expected_interval, lost_interval) {
+            (0, _) => 0,
+            (_, v) if v <= 0 => 0,
+            (expected, lost) => ((lost << 8) / i64::from(expected)).min(255) as u8,
+        };
+
+        let (last_sr, delay_since_last_sr) = match self.last_sr {
+            Some((ntp, at)) => {
+                let delay = now.saturating_duration_since(at).as_secs_f64() * 65536.0;
+                (ntp.middle(), delay.min(f64::from(u32::MAX)) as u32)
+            }
+            None => (0, 0),
+        };
+
+        Some(ReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: lost.clamp(-MAX_CUMULATIVE_LOST - 1, MAX_CUMULATIVE_LOST) as i32,
+            highest_sequence,
+            jitter: self.jitter(),
+            last_sr,
+            delay_since_last_sr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_report_before_packets() {
+        assert_eq!(None, Reception::new(90_000).report(Instant::now()));
+    }
+
+    #[test]
+    fn test_report_wraparound() {
+        let mut reception = Reception::new(90_000);
+        let now = Instant::now();
+        for seq in [0xfffe, 0xffff, 1] {
+            reception.on_packet(16, seq, 0, now);
+        }
+
+        let block = reception.report(now).unwrap();
+        assert_eq!(0x1_0001, block.highest_sequence);
+        assert_eq!(1, block.cumulative_lost);
+        assert_eq!(64, block.fraction_lost);
+
+        // The late packet recovers the loss, while the fraction only covers the new interval.
+        reception.on_packet(16, 0, 0, now);
+        reception.on_packet(16, 2, 0, now);
+        let block = reception.report(now).unwrap();
+        assert_eq!(0x1_0002, block.highest_sequence);
+        assert_eq!(0, block.cumulative_lost);
+        assert_eq!(0, block.fraction_lost);
+    }
+
+    #[test]
+    fn test_restart_on_new_source() {
+        let mut reception = Reception::new(90_000);
+        let now = Instant::now();
+        reception.on_packet(16, 100, 0, now);
+        reception.on_packet(16, 110, 0, now);
+        reception.on_packet(17, 5000, 0, now);
+        reception.on_packet(17, 5001, 0, now);
+
+        let block = reception.report(now).unwrap();
+        assert_eq!(17, block.ssrc);
+        assert_eq!(0, block.cumulative_lost);
+        assert_eq!(5001, block.highest_sequence);
+    }
+
+    #[test]
+    fn test_jitter() {
+        let mut reception = Reception::new(90_000);
+        let now = Instant::now();
+        // Packets 40ms apart carrying timestamps 33ms apart, i.e. 630 timestamp units off each.
+        for idx in 0..100u16 {
+            reception.on_packet(
+                16,
+                idx,
+                u32::from(idx) * 2970,
+                now + Duration::from_millis(40) * u32::from(idx),
+            );
+        }
+
+        let jitter = reception.report(now).unwrap().jitter;
+        assert!(jitter > 620 && jitter <= 630, "{}", jitter);
+    }
+
+    #[test]
+    fn test_delay_since_last_sr() {
+        let mut reception = Reception::new(90_000);
+        let now = Instant::now();
+        // Reports before any packet and of other sources carry nothing to answer.
+        reception.on_sender_report(16, NtpTimestamp::new(1, 0), now);
+        reception.on_packet(16, 0, 0, now);
+        reception.on_sender_report(17, NtpTimestamp::new(1, 0), now);
+        let block = reception.report(now).unwrap();
+        assert_eq!((0, 0), (block.last_sr, block.delay_since_last_sr));
+
+        reception.on_sender_report(16, NtpTimestamp::new(0x1234_5678, 0x9abc_def0), now);
+        let block = reception.report(now + Duration::from_millis(1500)).unwrap();
+        assert_eq!(0x5678_9abc, block.last_sr);
+        assert_eq!(0x1_8000, block.delay_since_last_sr);
+    }
+
+    #[test]
+    fn test_encode_negative_lost() {
+        let block = ReportBlock {
+            ssrc: 0,
+            fraction_lost: 0,
+            cumulative_lost: -MAX_CUMULATIVE_LOST as i32 - 1,
+            highest_sequence: 0,
+            jitter: 0,
+            last_sr: 0,
+            delay_since_last_sr: 0,
+        };
+        let mut buf = Vec::new();
+        block.encode(&mut buf);
+
+        assert_eq!(&[0, 0x80, 0, 0], &buf[4..8]);
+    }
+}
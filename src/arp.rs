@@ -0,0 +1,107 @@
+//! ARP-assisted verification of discovered cameras.
+//!
+//! The MAC address reported in a ScanReply is cross-checked against the kernel's neighbour table
+//! entry for the IP address the reply came from. A mismatch indicates either a spoofed reply or a
+//! stale DHCP lease, where another device now holds the camera's former address.
+
+use std::{fs, io, net::IpAddr};
+
+use crate::{mac::MacAddr, protocol::LookupInfo};
+
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+
+/// Result of the ARP cross-check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// The ARP entry matches the reported MAC address.
+    Match,
+    /// The ARP entry differs from the reported MAC address.
+    Mismatch {
+        /// MAC address reported by the camera.
+        reported: MacAddr,
+        /// MAC address found in the ARP table.
+        resolved: MacAddr,
+    },
+    /// There is no complete ARP entry for the camera address, e.g. because the reply was routed.
+    Unknown,
+}
+
+/// Cross-checks the MAC address reported by the camera against the ARP table.
+pub fn verify(info: &LookupInfo) -> Result<Verification, io::Error> {
+    let v = match resolve(info.addr().ip())? {
+        Some(mac) if mac == *info.mac() => Verification::Match,
+        Some(mac) => Verification::Mismatch {
+            reported: *info.mac(),
+            resolved: mac,
+        },
+        None => Verification::Unknown,
+    };
+
+    Ok(v)
+}
+
+/// Returns the MAC address of the given IP address according to the ARP table.
+pub fn resolve(addr: IpAddr) -> Result<Option<MacAddr>, io::Error> {
+    let table = fs::read_to_string(ARP_TABLE_PATH)?;
+    Ok(find(&table, addr))
+}
+
+/// Finds a complete entry for the given address in the `/proc/net/arp` table content.
+fn find(table: &str, addr: IpAddr) -> Option<MacAddr> {
+    // Entries with zero flags are incomplete, i.e. the address is still being resolved.
+    const ATF_COM: u32 = 0x2;
+
+    for line in table.lines().skip(1) {
+        let mut it = line.split_whitespace();
+
+        let (ip, flags, mac) = match (it.next(), it.nth(1), it.next()) {
+            (Some(ip), Some(flags), Some(mac)) => (ip, flags, mac),
+            _ => continue,
+        };
+
+        if ip.parse::<IpAddr>().ok() != Some(addr) {
+            continue;
+        }
+
+        let flags = u32::from_str_radix(flags.trim_start_matches("0x"), 16).unwrap_or(0);
+        if flags & ATF_COM == 0 {
+            continue;
+        }
+
+        if let Ok(mac) = mac.parse() {
+            return Some(mac);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TABLE: &str = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.1      0x1         0x2         aa:bb:cc:dd:ee:ff     *        eth0
+192.168.1.71     0x1         0x2         dc:a9:04:97:9d:9b     *        eth0
+192.168.1.72     0x1         0x0         00:00:00:00:00:00     *        eth0
+";
+
+    #[test]
+    fn test_find() {
+        assert_eq!(
+            Some(MacAddr::new([220, 169, 4, 151, 157, 155])),
+            find(TABLE, "192.168.1.71".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_find_incomplete() {
+        assert_eq!(None, find(TABLE, "192.168.1.72".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_find_missing() {
+        assert_eq!(None, find(TABLE, "192.168.1.73".parse().unwrap()));
+    }
+}
@@ -0,0 +1,197 @@
+use core::time::Duration;
+
+/// Audio payload encoding, as identified by the RTP payload type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// G.711 µ-law, payload type 0.
+    Pcmu,
+    /// G.711 A-law, payload type 8.
+    Pcma,
+    /// Linear 16-bit big-endian PCM, payload types 10 and 11.
+    L16,
+}
+
+impl Codec {
+    /// Returns the codec of the given static RTP payload type, if it is a known audio one.
+    pub fn from_payload_type(pt: u8) -> Option<Self> {
+        match pt {
+            0 => Some(Codec::Pcmu),
+            8 => Some(Codec::Pcma),
+            10 | 11 => Some(Codec::L16),
+            _ => None,
+        }
+    }
+
+    /// Decodes the given payload into linear 16-bit samples, passing each one to the callback.
+    pub fn decode<F>(&self, buf: &[u8], mut f: F)
+    where
+        F: FnMut(i16),
+    {
+        match self {
+            Codec::Pcmu => buf.iter().for_each(|&v| f(ulaw(v))),
+            Codec::Pcma => buf.iter().for_each(|&v| f(alaw(v))),
+            Codec::L16 => buf.chunks_exact(2).for_each(|v| f(i16::from_be_bytes([v[0], v[1]]))),
+        }
+    }
+}
+
+/// Sound level over a metering period, with values normalized to the `0.0..=1.0` range of the
+/// full scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    /// Root mean square level.
+    pub rms: f32,
+    /// Peak level.
+    pub peak: f32,
+}
+
+impl Level {
+    /// Returns the RMS level in dBFS.
+    #[inline]
+    pub fn rms_dbfs(&self) -> f32 {
+        20.0 * self.rms.log10()
+    }
+
+    /// Returns the peak level in dBFS.
+    #[inline]
+    pub fn peak_dbfs(&self) -> f32 {
+        20.0 * self.peak.log10()
+    }
+}
+
+/// Computes RMS and peak audio levels directly from RTP payloads, without transcoding the stream.
+///
+/// A [`Level`] event is emitted each time the configured period worth of samples has been
+/// accumulated, which makes it suitable for "loud noise" triggers.
+#[derive(Debug, Clone)]
+pub struct LevelMeter {
+    codec: Codec,
+    period: usize,
+    count: usize,
+    sum: f64,
+    peak: u16,
+}
+
+impl LevelMeter {
+    /// Constructs a new meter for the given codec, sample rate and metering period.
+    pub fn new(codec: Codec, sample_rate: u32, period: Duration) -> Self {
+        let period = (sample_rate as f64 * period.as_secs_f64()).round().max(1.0) as usize;
+
+        Self {
+            codec,
+            period,
+            count: 0,
+            sum: 0.0,
+            peak: 0,
+        }
+    }
+
+    /// Feeds the given RTP payload into the meter, returning the levels of all periods completed
+    /// by it.
+    pub fn push(&mut self, payload: &[u8]) -> Vec<Level> {
+        let mut levels = Vec::new();
+
+        let codec = self.codec;
+        codec.decode(payload, |sample| {
+            self.sum += f64::from(sample) * f64::from(sample);
+            self.peak = self.peak.max(sample.unsigned_abs());
+            self.count += 1;
+
+            if self.count == self.period {
+                levels.push(self.take());
+            }
+        });
+
+        levels
+    }
+
+    fn take(&mut self) -> Level {
+        let level = Level {
+            rms: ((self.sum / self.count as f64).sqrt() / 32768.0) as f32,
+            peak: f32::from(self.peak) / 32768.0,
+        };
+
+        self.count = 0;
+        self.sum = 0.0;
+        self.peak = 0;
+
+        level
+    }
+}
+
+/// Decodes a G.711 µ-law sample.
+fn ulaw(v: u8) -> i16 {
+    let v = !v;
+    let exponent = (v >> 4) & 0x07;
+    let mantissa = i16::from(v & 0x0f);
+    let sample = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+
+    if v & 0x80 != 0 {
+        -sample
+    } else {
+        sample
+    }
+}
+
+/// Decodes a G.711 A-law sample.
+fn alaw(v: u8) -> i16 {
+    let v = v ^ 0x55;
+    let exponent = (v >> 4) & 0x07;
+    let mantissa = i16::from(v & 0x0f);
+    let sample = match exponent {
+        0 => (mantissa << 4) + 8,
+        exponent => ((mantissa << 4) + 0x108) << (exponent - 1),
+    };
+
+    if v & 0x80 != 0 {
+        sample
+    } else {
+        -sample
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ulaw() {
+        assert_eq!(0, ulaw(0xff));
+        assert_eq!(-32124, ulaw(0x00));
+        assert_eq!(32124, ulaw(0x80));
+    }
+
+    #[test]
+    fn test_alaw() {
+        assert_eq!(8, alaw(0xd5));
+        assert_eq!(-8, alaw(0x55));
+        assert_eq!(32256, alaw(0xaa));
+        assert_eq!(-32256, alaw(0x2a));
+    }
+
+    #[test]
+    fn test_level_meter() {
+        let mut meter = LevelMeter::new(Codec::L16, 8000, Duration::from_millis(1));
+
+        let mut buf = Vec::new();
+        for idx in 0..12 {
+            let v: i16 = if idx % 2 == 0 { 16384 } else { -16384 };
+            buf.extend_from_slice(&v.to_be_bytes());
+        }
+
+        let levels = meter.push(&buf);
+        assert_eq!(vec![Level { rms: 0.5, peak: 0.5 }], levels);
+        assert!((levels[0].rms_dbfs() + 6.0206).abs() < 1e-3);
+
+        // The remaining 4 samples complete the next period.
+        assert_eq!(1, meter.push(&buf[..8]).len());
+    }
+
+    #[test]
+    fn test_level_meter_silence() {
+        let mut meter = LevelMeter::new(Codec::Pcmu, 8000, Duration::from_millis(10));
+
+        let levels = meter.push(&[0xff; 80]);
+        assert_eq!(vec![Level { rms: 0.0, peak: 0.0 }], levels);
+    }
+}
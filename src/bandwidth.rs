@@ -0,0 +1,346 @@
+//! Bandwidth estimation and adaptive stream profile selection.
+//!
+//! The estimator compares the rate of data offered to an uplink, such as the tunnel, with the
+//! rate it actually delivers. When the link cannot keep up, the sub stream is recommended, and
+//! after a period of clean delivery the main stream again.
+//!
+//! Note that no command selecting the sub stream is known yet, so the sub stream is substituted
+//! locally by [`Shed`], which forwards keyframes only.
+
+use core::{
+    fmt::{self, Display, Formatter},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use std::{error::Error, sync::Arc, time::Instant};
+
+use crate::{
+    h264::{self, NalType},
+    rtp,
+    sink::Sink,
+};
+
+/// Shortest period a rate sample is computed over.
+const SAMPLE_PERIOD: Duration = Duration::from_millis(100);
+
+/// Exponentially weighted moving average of a data rate.
+#[derive(Debug, Clone)]
+pub struct RateEstimator {
+    half_life: Duration,
+    rate: Option<f64>,
+    start: Option<Instant>,
+    bytes: u64,
+}
+
+impl RateEstimator {
+    /// Constructs a new estimator, with older samples losing half of their weight every
+    /// `half_life`.
+    pub fn new(half_life: Duration) -> Self {
+        Self {
+            half_life,
+            rate: None,
+            start: None,
+            bytes: 0,
+        }
+    }
+
+    /// Accounts the given number of bytes transferred at the specified time.
+    pub fn push(&mut self, bytes: usize, at: Instant) {
+        let start = *self.start.get_or_insert(at);
+        self.bytes += bytes as u64;
+
+        let elapsed = at.saturating_duration_since(start);
+        if elapsed < SAMPLE_PERIOD {
+            return;
+        }
+
+        let sample = self.bytes as f64 * 8.0 / elapsed.as_secs_f64();
+        let weight = 0.5f64.powf(elapsed.as_secs_f64() / self.half_life.as_secs_f64());
+        self.rate = Some(match self.rate {
+            Some(rate) => rate * weight + sample * (1.0 - weight),
+            None => sample,
+        });
+
+        self.start = Some(at);
+        self.bytes = 0;
+    }
+
+    /// Returns the estimated rate in bits per second, if enough data has been seen.
+    #[inline]
+    pub fn rate(&self) -> Option<f64> {
+        self.rate
+    }
+}
+
+/// Stream profile of the camera.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Full resolution stream.
+    Main,
+    /// Lower resolution and bitrate stream.
+    Sub,
+}
+
+impl Display for Profile {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Profile::Main => fmt.write_str("main"),
+            Profile::Sub => fmt.write_str("sub"),
+        }
+    }
+}
+
+/// Picks the stream profile based on how well an uplink keeps up with the offered data.
+#[derive(Debug, Clone)]
+pub struct Adaptive {
+    profile: Profile,
+    threshold: f64,
+    hold: Duration,
+    changed_at: Option<Instant>,
+    clean_since: Option<Instant>,
+}
+
+impl Adaptive {
+    /// Constructs a new selector starting with the main stream.
+    ///
+    /// The link is considered congested when it delivers less than 95% of the offered rate. The
+    /// profile is never changed more often than once per `hold`, which is also how long delivery
+    /// must be clean before switching back to the main stream.
+    pub fn new(hold: Duration) -> Self {
+        Self {
+            profile: Profile::Main,
+            threshold: 0.95,
+            hold,
+            changed_at: None,
+            clean_since: None,
+        }
+    }
+
+    /// Sets the fraction of the offered rate below which the link is considered congested.
+    pub fn threshold(mut self, threshold: f64) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Returns the currently selected profile.
+    #[inline]
+    pub fn profile(&self) -> Profile {
+        self.profile
+    }
+
+    /// Updates the selection with the current rate estimates, returning the new profile if it has
+    /// changed.
+    pub fn update(&mut self, offered: &RateEstimator, delivered: &RateEstimator, at: Instant) -> Option<Profile> {
+        let (offered, delivered) = match (offered.rate(), delivered.rate()) {
+            (Some(offered), Some(delivered)) if offered > 0.0 => (offered, delivered),
+            _ => return None,
+        };
+
+        let congested = delivered < offered * self.threshold;
+        if congested {
+            self.clean_since = None;
+        } else if self.clean_since.is_none() {
+            self.clean_since = Some(at);
+        }
+
+        let held = self
+            .changed_at
+            .map(|v| at.saturating_duration_since(v) >= self.hold)
+            .unwrap_or(true);
+        if !held {
+            return None;
+        }
+
+        let profile = match (self.profile, congested, self.clean_since) {
+            (Profile::Main, true, ..) => Profile::Sub,
+            (Profile::Sub, false, Some(since)) if at.saturating_duration_since(since) >= self.hold => Profile::Main,
+            (profile, ..) => profile,
+        };
+
+        if profile == self.profile {
+            return None;
+        }
+
+        self.profile = profile;
+        self.changed_at = Some(at);
+        Some(profile)
+    }
+}
+
+/// Shared handle selecting the profile applied by [`Shed`] sinks, e.g. from a monitoring thread.
+#[derive(Debug, Clone, Default)]
+pub struct ProfileSwitch {
+    sub: Arc<AtomicBool>,
+}
+
+impl ProfileSwitch {
+    /// Constructs a new switch selecting the main stream.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects the given profile.
+    #[inline]
+    pub fn set(&self, profile: Profile) {
+        self.sub.store(profile == Profile::Sub, Ordering::Relaxed);
+    }
+
+    /// Returns the currently selected profile.
+    #[inline]
+    pub fn get(&self) -> Profile {
+        match self.sub.load(Ordering::Relaxed) {
+            true => Profile::Sub,
+            false => Profile::Main,
+        }
+    }
+}
+
+/// Sink substituting the sub stream locally while it is selected by the switch.
+///
+/// RTP packets carrying slices of non-IDR pictures are dropped, while parameter sets, IDR slices
+/// and anything unrecognized are passed to the inner sink. Viewers keep getting a picture, only
+/// refreshed once per GOP, at a fraction of the bitrate. Downstream, the dropped packets look
+/// like sequence gaps.
+///
+/// ```
+/// use cleverdog::{
+///     bandwidth::{Profile, ProfileSwitch, Shed},
+///     sink::Sink,
+/// };
+///
+/// let switch = ProfileSwitch::new();
+/// let mut sink = Shed::new(|_buf: &[u8]| Ok(()), switch.clone());
+///
+/// switch.set(Profile::Sub);
+/// sink.send(&[0x80, 96, 0, 1, 0, 0, 0, 0, 0, 0, 0, 16, 0x41, 1, 2])?;
+/// assert_eq!(1, sink.dropped());
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Shed<S> {
+    sink: S,
+    switch: ProfileSwitch,
+    dropped: u64,
+}
+
+impl<S: Sink> Shed<S> {
+    /// Constructs a new sink forwarding into the given one, following the switch.
+    pub fn new(sink: S, switch: ProfileSwitch) -> Self {
+        Self {
+            sink,
+            switch,
+            dropped: 0,
+        }
+    }
+
+    /// Returns the number of packets dropped so far.
+    #[inline]
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+impl<S: Sink> Sink for Shed<S> {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.switch.get() == Profile::Sub && is_non_idr(buf) {
+            self.dropped += 1;
+            return Ok(());
+        }
+
+        self.sink.send(buf)
+    }
+}
+
+/// Returns `true` if the given RTP packet carries a slice of a non-IDR picture.
+fn is_non_idr(buf: &[u8]) -> bool {
+    let hdr = match rtp::Header::from_slice(buf) {
+        Ok(hdr) => hdr,
+        Err(..) => return false,
+    };
+
+    h264::payload_nal_type(&buf[hdr.as_slice().len()..]) == Some(NalType::NonIdr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Feeds both estimators for the given number of 100ms ticks.
+    fn feed(offered: &mut RateEstimator, delivered: &mut RateEstimator, now: &mut Instant, ticks: u32, ratio: f64) {
+        for _ in 0..ticks {
+            *now += SAMPLE_PERIOD;
+            offered.push(12_500, *now);
+            delivered.push((12_500.0 * ratio) as usize, *now);
+        }
+    }
+
+    #[test]
+    fn test_rate() {
+        let mut estimator = RateEstimator::new(Duration::from_secs(1));
+        let mut now = Instant::now();
+        estimator.push(0, now);
+        assert_eq!(None, estimator.rate());
+
+        // 12.5 KB per 100ms is 1 Mbps.
+        for _ in 0..50 {
+            now += SAMPLE_PERIOD;
+            estimator.push(12_500, now);
+        }
+
+        assert!((estimator.rate().unwrap() - 1_000_000.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_adaptive() {
+        let mut offered = RateEstimator::new(Duration::from_millis(500));
+        let mut delivered = RateEstimator::new(Duration::from_millis(500));
+        let mut adaptive = Adaptive::new(Duration::from_secs(5));
+        let mut now = Instant::now();
+
+        feed(&mut offered, &mut delivered, &mut now, 20, 1.0);
+        assert_eq!(None, adaptive.update(&offered, &delivered, now));
+
+        feed(&mut offered, &mut delivered, &mut now, 20, 0.5);
+        assert_eq!(Some(Profile::Sub), adaptive.update(&offered, &delivered, now));
+
+        // Clean delivery must last for the whole hold period.
+        feed(&mut offered, &mut delivered, &mut now, 30, 1.0);
+        assert_eq!(None, adaptive.update(&offered, &delivered, now));
+        feed(&mut offered, &mut delivered, &mut now, 49, 1.0);
+        assert_eq!(None, adaptive.update(&offered, &delivered, now));
+        feed(&mut offered, &mut delivered, &mut now, 1, 1.0);
+        assert_eq!(Some(Profile::Main), adaptive.update(&offered, &delivered, now));
+    }
+
+    #[test]
+    fn test_shed() {
+        let packet = |payload: &[u8]| {
+            let mut buf = vec![0x80, 96, 0, 1, 0, 0, 0, 0, 0, 0, 0, 16];
+            buf.extend_from_slice(payload);
+            buf
+        };
+        let mut sent = 0;
+        let switch = ProfileSwitch::new();
+        let mut sink = Shed::new(
+            |_buf: &[u8]| {
+                sent += 1;
+                Ok(())
+            },
+            switch.clone(),
+        );
+
+        sink.send(&packet(&[0x41, 1])).unwrap();
+        switch.set(Profile::Sub);
+        // A P-slice fragment is dropped, while an IDR fragment and parameter sets pass.
+        sink.send(&packet(&[0x5c, 0x81, 1])).unwrap();
+        sink.send(&packet(&[0x7c, 0x85, 1])).unwrap();
+        sink.send(&packet(&[0x67, 1])).unwrap();
+        switch.set(Profile::Main);
+        sink.send(&packet(&[0x41, 2])).unwrap();
+
+        assert_eq!(1, sink.dropped());
+        drop(sink);
+        assert_eq!(4, sent);
+    }
+}
@@ -0,0 +1,289 @@
+//! CPU budget for features decoding the stream.
+//!
+//! Decoding for snapshots, motion detection or MJPEG is far more expensive than relaying RTP, and
+//! on weak hardware it can starve the relay itself. A [`Budget`] measures the CPU time consumed
+//! by the process and, while it exceeds the configured limit, admits fewer frames for decoding:
+//! every 2nd, every 4th and so on, down to keyframes only. Frames not admitted are simply not
+//! decoded; the relay path never consults the budget and is unaffected.
+
+use core::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+use std::{io, time::Instant};
+
+use log::{info, warn};
+
+/// Usage below this fraction of the limit lowers the decimation, so that the budget does not
+/// oscillate around the limit.
+const RECOVERY_RATIO: f64 = 0.8;
+
+/// Selects which frames are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decimation {
+    /// Every frame is decoded.
+    All,
+    /// Every Nth frame and every keyframe are decoded.
+    EveryNth(u32),
+    /// Only keyframes are decoded.
+    KeyframesOnly,
+}
+
+impl Display for Decimation {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Decimation::All => fmt.write_str("all frames"),
+            Decimation::EveryNth(n) => write!(fmt, "every {} frames", n),
+            Decimation::KeyframesOnly => fmt.write_str("keyframes only"),
+        }
+    }
+}
+
+/// Measures CPU time consumed by the current process.
+#[derive(Debug)]
+pub struct CpuMeter {
+    last: Option<(Instant, Duration)>,
+}
+
+impl CpuMeter {
+    /// Constructs a new meter.
+    #[inline]
+    pub fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Returns CPU usage since the previous call in cores, e.g. `1.5` for one and a half cores
+    /// fully busy.
+    ///
+    /// The first call only takes the initial measurement and returns `None`.
+    pub fn sample(&mut self) -> Result<Option<f64>, io::Error> {
+        let now = Instant::now();
+        let cpu = process_cpu_time()?;
+
+        let usage = self.last.and_then(|(at, prev)| {
+            let elapsed = now.duration_since(at).as_secs_f64();
+            match elapsed > 0.0 {
+                true => Some(cpu.saturating_sub(prev).as_secs_f64() / elapsed),
+                false => None,
+            }
+        });
+        self.last = Some((now, cpu));
+
+        Ok(usage)
+    }
+}
+
+impl Default for CpuMeter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns user and system CPU time consumed by the current process.
+#[cfg(unix)]
+fn process_cpu_time() -> Result<Duration, io::Error> {
+    let mut usage: libc::rusage = unsafe { core::mem::zeroed() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let time = |v: libc::timeval| Duration::new(v.tv_sec as u64, v.tv_usec as u32 * 1000);
+    Ok(time(usage.ru_utime) + time(usage.ru_stime))
+}
+
+/// Returns user and system CPU time consumed by the current process.
+#[cfg(not(unix))]
+fn process_cpu_time() -> Result<Duration, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "measuring CPU usage is not supported on this platform",
+    ))
+}
+
+/// Admits frames for decoding within the CPU usage limit.
+///
+/// ```
+/// use cleverdog::budget::{Budget, Decimation};
+///
+/// let mut budget = Budget::new(0.5);
+///
+/// budget.observe(0.9);
+/// assert_eq!(Decimation::EveryNth(2), budget.decimation());
+/// assert!(budget.admit(false));
+/// assert!(!budget.admit(false));
+/// assert!(budget.admit(true));
+/// ```
+#[derive(Debug)]
+pub struct Budget {
+    limit: f64,
+    interval: Duration,
+    max_stride: u32,
+    stride: u32,
+    frames: u64,
+    skipped: u64,
+    meter: CpuMeter,
+    sampled: Option<Instant>,
+}
+
+impl Budget {
+    /// Constructs a new budget limiting CPU usage of the whole process to the given number of
+    /// cores, e.g. `0.5` for half of a single core.
+    pub fn new(limit: f64) -> Self {
+        Self {
+            limit,
+            interval: Duration::from_secs(2),
+            max_stride: 8,
+            stride: 1,
+            frames: 0,
+            skipped: 0,
+            meter: CpuMeter::new(),
+            sampled: None,
+        }
+    }
+
+    /// Sets how often CPU usage is measured.
+    ///
+    /// Defaults to 2 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the largest N of [`Decimation::EveryNth`], beyond which only keyframes are decoded.
+    ///
+    /// Defaults to 8.
+    pub fn max_stride(mut self, stride: u32) -> Self {
+        self.max_stride = stride.max(1);
+        self
+    }
+
+    /// Returns the current decimation.
+    pub fn decimation(&self) -> Decimation {
+        match self.stride {
+            1 => Decimation::All,
+            n if n > self.max_stride => Decimation::KeyframesOnly,
+            n => Decimation::EveryNth(n),
+        }
+    }
+
+    /// Returns the number of frames not admitted so far.
+    #[inline]
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Adjusts the decimation with the given CPU usage in cores.
+    ///
+    /// Called by [`Budget::admit`] with measurements of the current process. Useful when usage
+    /// is measured elsewhere, e.g. for the whole host.
+    pub fn observe(&mut self, usage: f64) {
+        let prev = self.stride;
+
+        if usage > self.limit && self.stride <= self.max_stride {
+            self.stride *= 2;
+        } else if usage < self.limit * RECOVERY_RATIO && self.stride > 1 {
+            self.stride = match self.stride > self.max_stride {
+                true => self.max_stride,
+                false => self.stride / 2,
+            }
+            .max(1);
+        }
+
+        if self.stride > prev {
+            warn!(
+                "CPU usage {:.2} exceeds {:.2}, decoding {}",
+                usage,
+                self.limit,
+                self.decimation()
+            );
+        } else if self.stride < prev {
+            info!(
+                "CPU usage {:.2} is within {:.2}, decoding {}",
+                usage,
+                self.limit,
+                self.decimation()
+            );
+        }
+    }
+
+    /// Returns `true` if the next frame should be decoded.
+    ///
+    /// Keyframes are always admitted, since frames following them cannot be decoded otherwise.
+    pub fn admit(&mut self, keyframe: bool) -> bool {
+        let now = Instant::now();
+        if self
+            .sampled
+            .map(|v| now.duration_since(v) >= self.interval)
+            .unwrap_or(true)
+        {
+            self.sampled = Some(now);
+            match self.meter.sample() {
+                Ok(Some(usage)) => self.observe(usage),
+                Ok(None) => {}
+                Err(err) => warn!("failed to measure CPU usage: {}", err),
+            }
+        }
+
+        let admitted = match self.decimation() {
+            Decimation::All => true,
+            Decimation::EveryNth(n) => keyframe || self.frames.is_multiple_of(u64::from(n)),
+            Decimation::KeyframesOnly => keyframe,
+        };
+
+        self.frames += 1;
+        if !admitted {
+            self.skipped += 1;
+        }
+
+        admitted
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_escalate_and_recover() {
+        let mut budget = Budget::new(1.0).max_stride(4).interval(Duration::from_secs(3600));
+
+        budget.observe(2.0);
+        assert_eq!(Decimation::EveryNth(2), budget.decimation());
+        budget.observe(2.0);
+        assert_eq!(Decimation::EveryNth(4), budget.decimation());
+        budget.observe(2.0);
+        assert_eq!(Decimation::KeyframesOnly, budget.decimation());
+        budget.observe(2.0);
+        assert_eq!(Decimation::KeyframesOnly, budget.decimation());
+
+        let admitted: Vec<_> = [true, false, false, true].iter().map(|v| budget.admit(*v)).collect();
+        assert_eq!(vec![true, false, false, true], admitted);
+        assert_eq!(2, budget.skipped());
+
+        // Usage just below the limit keeps the decimation, avoiding oscillation.
+        budget.observe(0.9);
+        assert_eq!(Decimation::KeyframesOnly, budget.decimation());
+        budget.observe(0.5);
+        assert_eq!(Decimation::EveryNth(4), budget.decimation());
+        budget.observe(0.5);
+        budget.observe(0.5);
+        assert_eq!(Decimation::All, budget.decimation());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cpu_meter() {
+        let mut meter = CpuMeter::new();
+        assert_eq!(None, meter.sample().unwrap());
+
+        let start = Instant::now();
+        let mut v = 0u64;
+        while start.elapsed() < Duration::from_millis(50) {
+            v = v.wrapping_mul(31).wrapping_add(1);
+        }
+        assert!(v > 0);
+
+        let usage = meter.sample().unwrap().unwrap();
+        assert!(usage > 0.0);
+    }
+}
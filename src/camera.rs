@@ -0,0 +1,87 @@
+use core::sync::atomic::Ordering;
+use std::error::Error;
+
+use crate::{
+    protocol::LookupInfo,
+    rtp::RtpPacket,
+    security::{TransportSecurity, CAMERA_LINK_SECURITY},
+    session::{self, Shared, StreamOptions},
+    stats::StatsSnapshot,
+};
+
+/// A discovered camera, tracking statistics of its streaming sessions.
+///
+/// All methods take `&self`, so the camera can be shared between the streaming thread and
+/// monitoring threads, e.g. via `Arc`.
+#[derive(Debug)]
+pub struct Camera {
+    info: LookupInfo,
+    shared: Shared,
+}
+
+impl Camera {
+    /// Constructs a new camera from the lookup result.
+    pub fn new(info: LookupInfo) -> Self {
+        Self {
+            info,
+            shared: Shared::default(),
+        }
+    }
+
+    /// Returns camera info, as received during lookup.
+    #[inline]
+    pub fn info(&self) -> &LookupInfo {
+        &self.info
+    }
+
+    /// Streams RTP packets from the camera into the given callback, blocking the current thread.
+    ///
+    /// See [`stream`](crate::stream) for details.
+    pub fn stream<F>(&self, f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+    {
+        self.stream_with(&StreamOptions::default(), f)
+    }
+
+    /// Streams RTP packets from the camera into the given callback using the specified options.
+    pub fn stream_with<F>(&self, opts: &StreamOptions, f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+    {
+        session::run(self.info.cid(), self.info.addr(), opts, &self.shared, f)
+    }
+
+    /// Pauses delivery of packets to the streaming callbacks.
+    ///
+    /// The session itself is kept warm: the camera keeps sending and RTCP keepalives are still
+    /// sent, so resuming is instant. No command telling the camera to stop sending is known.
+    #[inline]
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Resumes delivery of packets paused with [`Camera::pause`].
+    #[inline]
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if packet delivery is paused.
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    /// Returns security of the link between the camera and this host, which is always plaintext.
+    #[inline]
+    pub fn transport_security(&self) -> TransportSecurity {
+        CAMERA_LINK_SECURITY
+    }
+
+    /// Returns a snapshot of the statistics accumulated over all streaming sessions.
+    #[inline]
+    pub fn stats(&self) -> StatsSnapshot {
+        self.shared.stats.snapshot()
+    }
+}
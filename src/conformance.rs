@@ -0,0 +1,415 @@
+//! Protocol conformance checks against a live camera.
+//!
+//! Runs a battery of command and stream tests, reporting which features the camera firmware
+//! supports. Features without a known command in the LAN protocol are reported as
+//! [`Support::Unknown`] rather than guessed.
+
+use core::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    io::{self, Write},
+    sync::{Mutex, OnceLock},
+    time::Instant,
+};
+
+use crate::{
+    camera::Camera,
+    protocol::{Cid, LookupInfo, Version},
+    session::StreamOptions,
+};
+
+/// A camera feature checked by the conformance suite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Replies to the Scan command.
+    Discovery,
+    /// Streams video over RTP after the StartRtp command.
+    Video,
+    /// Streams audio alongside video.
+    Audio,
+    /// Streams a lower resolution sub stream.
+    SubStream,
+    /// Pan-tilt-zoom control.
+    Ptz,
+    /// Access to recordings on the SD card.
+    SdCard,
+}
+
+impl Feature {
+    /// All features, in the order they are checked.
+    pub const ALL: [Feature; 6] = [
+        Feature::Discovery,
+        Feature::Video,
+        Feature::Audio,
+        Feature::SubStream,
+        Feature::Ptz,
+        Feature::SdCard,
+    ];
+
+    #[inline]
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+
+    /// Returns a short machine-readable name of the feature.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Feature::Discovery => "discovery",
+            Feature::Video => "video",
+            Feature::Audio => "audio",
+            Feature::SubStream => "sub_stream",
+            Feature::Ptz => "ptz",
+            Feature::SdCard => "sd_card",
+        }
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// Result of a single feature check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    /// The feature has been observed working.
+    Supported,
+    /// The feature has been checked and does not work.
+    Unsupported,
+    /// The feature could not be checked.
+    Unknown,
+}
+
+impl Support {
+    /// Returns a short machine-readable name of the result.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Support::Supported => "supported",
+            Support::Unsupported => "unsupported",
+            Support::Unknown => "unknown",
+        }
+    }
+}
+
+impl Display for Support {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// Outcome of a feature check with a human-readable explanation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub feature: Feature,
+    pub support: Support,
+    pub detail: String,
+}
+
+/// Capability report produced by the conformance suite.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns all checks, in the order they were run.
+    #[inline]
+    pub fn checks(&self) -> &[Check] {
+        &self.checks
+    }
+
+    /// Returns the result of the given feature check.
+    pub fn get(&self, feature: Feature) -> Support {
+        self.checks
+            .iter()
+            .find(|check| check.feature == feature)
+            .map(|check| check.support)
+            .unwrap_or(Support::Unknown)
+    }
+
+    /// Encodes the report as JSON, mapping each feature name to its result.
+    pub fn encode<W: Write>(&self, wr: &mut W) -> Result<(), io::Error> {
+        wr.write_all(b"{")?;
+        for (idx, check) in self.checks.iter().enumerate() {
+            if idx > 0 {
+                wr.write_all(b",")?;
+            }
+            write!(wr, "\"{}\":\"{}\"", check.feature, check.support)?;
+        }
+        wr.write_all(b"}\n")
+    }
+
+    fn push(&mut self, feature: Feature, support: Support, detail: String) {
+        self.checks.push(Check {
+            feature,
+            support,
+            detail,
+        });
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        for check in &self.checks {
+            writeln!(fmt, "{:<12} {:<12} {}", check.feature, check.support, check.detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Set of features detected by the conformance suite.
+///
+/// ```
+/// use cleverdog::conformance::{Capabilities, Feature, Support};
+///
+/// let caps = Capabilities::new().with(Feature::Video, Support::Supported);
+///
+/// assert!(caps.supports(Feature::Video));
+/// assert_eq!(Support::Unknown, caps.get(Feature::Ptz));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    /// Features that have been checked, either way.
+    known: u8,
+    /// Features that are supported.
+    supported: u8,
+}
+
+impl Capabilities {
+    /// Constructs a new capability set with all features unknown.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the given feature result, returning `self` for chaining.
+    pub fn with(mut self, feature: Feature, support: Support) -> Self {
+        let bit = feature.bit();
+        match support {
+            Support::Supported => {
+                self.known |= bit;
+                self.supported |= bit;
+            }
+            Support::Unsupported => {
+                self.known |= bit;
+                self.supported &= !bit;
+            }
+            Support::Unknown => {
+                self.known &= !bit;
+                self.supported &= !bit;
+            }
+        }
+        self
+    }
+
+    /// Returns the result for the given feature.
+    pub fn get(&self, feature: Feature) -> Support {
+        let bit = feature.bit();
+        match (self.known & bit != 0, self.supported & bit != 0) {
+            (true, true) => Support::Supported,
+            (true, false) => Support::Unsupported,
+            (false, ..) => Support::Unknown,
+        }
+    }
+
+    /// Returns `true` if the feature is known to be supported.
+    #[inline]
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.get(feature) == Support::Supported
+    }
+}
+
+impl From<&Report> for Capabilities {
+    fn from(report: &Report) -> Self {
+        report.checks.iter().fold(Capabilities::new(), |caps, check| {
+            caps.with(check.feature, check.support)
+        })
+    }
+}
+
+/// Capabilities detected within this process, keyed by camera ID and firmware version.
+type Cache = Mutex<HashMap<(Cid, Version), Capabilities>>;
+
+fn cache() -> &'static Cache {
+    static CACHE: OnceLock<Cache> = OnceLock::new();
+    CACHE.get_or_init(Default::default)
+}
+
+/// Returns capabilities detected for the given camera by a previous run of the suite, if any.
+///
+/// Results are invalidated when the camera reports a different firmware version.
+pub fn cached(info: &LookupInfo) -> Option<Capabilities> {
+    let cache = cache().lock().unwrap_or_else(|err| err.into_inner());
+    cache.get(&(*info.cid(), *info.version())).copied()
+}
+
+fn store(info: &LookupInfo, capabilities: Capabilities) {
+    let mut cache = cache().lock().unwrap_or_else(|err| err.into_inner());
+    cache.insert((*info.cid(), *info.version()), capabilities);
+}
+
+/// Stops the session once the observation window is over.
+#[derive(Debug)]
+struct Done;
+
+impl Display for Done {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str("observation window is over")
+    }
+}
+
+impl Error for Done {}
+
+/// Runs the conformance suite against the camera found during lookup, streaming for the given
+/// duration.
+pub fn run(info: &LookupInfo, duration: Duration) -> Report {
+    run_with(info, &StreamOptions::default(), duration)
+}
+
+/// Runs the conformance suite using the specified streaming options.
+///
+/// The detected capabilities are cached and attached to the camera's subsequent lookup results.
+pub fn run_with(info: &LookupInfo, opts: &StreamOptions, duration: Duration) -> Report {
+    let mut report = Report { checks: Vec::new() };
+
+    report.push(
+        Feature::Discovery,
+        Support::Supported,
+        format!("firmware {}", info.version()),
+    );
+
+    let camera = Camera::new(*info);
+    let start = Instant::now();
+    let result = camera.stream_with(opts, |_buf| match start.elapsed() >= duration {
+        true => Err(Done.into()),
+        false => Ok(()),
+    });
+    let stats = camera.stats();
+
+    match result {
+        Err(ref err) if !err.is::<Done>() => report.push(Feature::Video, Support::Unsupported, err.to_string()),
+        _ => report.push(
+            Feature::Video,
+            Support::Supported,
+            format!("{} packets in {:?}", stats.packets_delivered, start.elapsed()),
+        ),
+    }
+
+    // No command enabling audio is known, so its absence is not conclusive.
+    match stats.packets_non_video {
+        0 => report.push(Feature::Audio, Support::Unknown, "no non-video RTP packets".into()),
+        n => report.push(
+            Feature::Audio,
+            Support::Supported,
+            format!("{} non-video RTP packets", n),
+        ),
+    }
+
+    for &feature in &[Feature::SubStream, Feature::Ptz, Feature::SdCard] {
+        report.push(feature, Support::Unknown, "no command known".into());
+    }
+
+    store(info, Capabilities::from(&report));
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::UdpSocket, thread};
+
+    use super::*;
+    use crate::{
+        mac::MacAddr,
+        protocol::{ScanInfo, Version, VIDEO_CHANNEL, VIDEO_SSRC},
+        rtp,
+    };
+
+    fn rtp(channel: u8, ssrc: u32) -> Vec<u8> {
+        let mut buf = vec![0x00, 0x00, channel, 0x00, 0x80, 0x60, 0, 0, 0, 0, 0, 0];
+        buf.extend_from_slice(&ssrc.to_be_bytes());
+        buf
+    }
+
+    fn sender_report(ssrc: u32) -> Vec<u8> {
+        let mut buf = vec![0x00, 0x00, VIDEO_CHANNEL, 0x00, 0x80, rtp::RTCP_SENDER_REPORT, 0, 6];
+        buf.extend_from_slice(&ssrc.to_be_bytes());
+        buf.extend_from_slice(&[0; 20]);
+        buf
+    }
+
+    #[test]
+    fn test_run() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0; 1024];
+            let (_, peer) = sock.recv_from(&mut buf).unwrap();
+
+            for _ in 0..100 {
+                sock.send_to(&rtp(VIDEO_CHANNEL, VIDEO_SSRC), peer).unwrap();
+                sock.send_to(&rtp(VIDEO_CHANNEL + 1, 42), peer).unwrap();
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        let info = LookupInfo::new(
+            addr,
+            *b"CONFORMANCE0000\0",
+            ScanInfo::new(MacAddr::new([0; 6]), Version::new([1, 2, 3, 4])),
+        );
+        let opts = StreamOptions::new().bind("127.0.0.1:0".parse().unwrap());
+        let report = run_with(&info, &opts, Duration::from_millis(100));
+
+        assert_eq!(Support::Supported, report.get(Feature::Discovery));
+        assert_eq!(Support::Supported, report.get(Feature::Video));
+        assert_eq!(Support::Supported, report.get(Feature::Audio));
+        assert_eq!(Support::Unknown, report.get(Feature::Ptz));
+
+        let caps = cached(&info).unwrap();
+        assert!(caps.supports(Feature::Audio));
+        assert_eq!(Support::Unknown, caps.get(Feature::SdCard));
+
+        let mut buf = Vec::new();
+        report.encode(&mut buf).unwrap();
+        assert_eq!(
+            &b"{\"discovery\":\"supported\",\"video\":\"supported\",\"audio\":\"supported\",\
+               \"sub_stream\":\"unknown\",\"ptz\":\"unknown\",\"sd_card\":\"unknown\"}\n"[..],
+            &buf[..]
+        );
+    }
+
+    #[test]
+    fn test_sender_reports_are_not_audio() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0; 1024];
+            let (_, peer) = sock.recv_from(&mut buf).unwrap();
+
+            for _ in 0..100 {
+                sock.send_to(&rtp(VIDEO_CHANNEL, VIDEO_SSRC), peer).unwrap();
+                sock.send_to(&sender_report(VIDEO_SSRC), peer).unwrap();
+                thread::sleep(Duration::from_millis(5));
+            }
+        });
+
+        let info = LookupInfo::new(
+            addr,
+            *b"CONFORMANCE0001\0",
+            ScanInfo::new(MacAddr::new([0; 6]), Version::new([1, 2, 3, 4])),
+        );
+        let opts = StreamOptions::new().bind("127.0.0.1:0".parse().unwrap());
+        let report = run_with(&info, &opts, Duration::from_millis(100));
+
+        assert_eq!(Support::Supported, report.get(Feature::Video));
+        assert_eq!(Support::Unknown, report.get(Feature::Audio));
+    }
+}
@@ -0,0 +1,151 @@
+//! Recording of control-plane traffic.
+//!
+//! Every command frame sent to or received from the camera is appended to a JSON Lines log with
+//! a timestamp, for auditing and for studying how the camera reacts to command sequences.
+
+use core::fmt::{self, Debug, Formatter};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    net::SocketAddr,
+    path::Path,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::warn;
+
+use crate::{
+    json,
+    protocol::{Cid, Frame},
+};
+
+/// Direction of a recorded frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Sent to the camera.
+    Sent,
+    /// Received from the camera.
+    Received,
+}
+
+impl Direction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Sent => "sent",
+            Direction::Received => "received",
+        }
+    }
+}
+
+/// Control-plane log, one JSON object per line.
+///
+/// Each line has the `ts_ms` timestamp in milliseconds since the UNIX epoch, `dir`, peer `addr`,
+/// the `command` code and `cid` if the datagram is a valid frame, and the whole datagram as
+/// `hex`.
+pub struct ControlLog {
+    wr: Mutex<Box<dyn Write + Send>>,
+}
+
+impl ControlLog {
+    /// Opens the log at the given path, appending to it if it exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let file: File = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(BufWriter::new(file)))
+    }
+
+    /// Constructs a new log writing into the given writer.
+    pub fn new<W: Write + Send + 'static>(wr: W) -> Self {
+        Self {
+            wr: Mutex::new(Box::new(wr)),
+        }
+    }
+
+    /// Records the given datagram.
+    ///
+    /// Failures are logged and otherwise ignored, because the log must not affect the session.
+    pub fn record(&self, at: SystemTime, dir: Direction, addr: SocketAddr, buf: &[u8]) {
+        let line = encode(at, dir, addr, buf);
+
+        let mut wr = match self.wr.lock() {
+            Ok(wr) => wr,
+            Err(err) => err.into_inner(),
+        };
+        if let Err(err) = wr.write_all(line.as_bytes()).and_then(|()| wr.flush()) {
+            warn!("failed to write control log: {}", err);
+        }
+    }
+}
+
+impl Debug for ControlLog {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt.debug_struct("ControlLog").finish()
+    }
+}
+
+fn encode(at: SystemTime, dir: Direction, addr: SocketAddr, buf: &[u8]) -> String {
+    let ts = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let mut line = format!("{{\"ts_ms\":{},\"dir\":\"{}\",\"addr\":\"{}\"", ts, dir.as_str(), addr);
+
+    if let Ok(frame) = Frame::parse(buf) {
+        line.push_str(&format!(",\"command\":{},\"cid\":", frame.command()));
+        json::push_str(&mut line, &Cid::new(frame.cid()).to_string());
+    }
+
+    line.push_str(",\"hex\":\"");
+    for byte in buf {
+        line.push_str(&format!("{:02x}", byte));
+    }
+    line.push_str("\"}\n");
+
+    line
+}
+
+#[cfg(test)]
+mod test {
+    use core::time::Duration;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::protocol::{Cid, Command};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), io::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_record() {
+        let buf = Shared::default();
+        let log = ControlLog::new(buf.clone());
+
+        let at = UNIX_EPOCH + Duration::from_millis(1_500_000_000_123);
+        let addr = "192.168.1.71:10008".parse().unwrap();
+        let frame = Command::StartRtp
+            .encode(&Cid::from_id(b"AB\"C").unwrap(), b"\x01")
+            .unwrap();
+        log.record(at, Direction::Sent, addr, &frame);
+        log.record(at, Direction::Received, addr, b"\xff");
+
+        let lines = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        let lines = lines.lines().collect::<Vec<_>>();
+
+        assert_eq!(
+            "{\"ts_ms\":1500000000123,\"dir\":\"sent\",\"addr\":\"192.168.1.71:10008\",\"command\":4103,\
+             \"cid\":\"AB\\\"C\",\"hex\":\"4d4a10074142224300000000000000000000000001\"}",
+            lines[0]
+        );
+        assert_eq!(
+            "{\"ts_ms\":1500000000123,\"dir\":\"received\",\"addr\":\"192.168.1.71:10008\",\"hex\":\"ff\"}",
+            lines[1]
+        );
+    }
+}
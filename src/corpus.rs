@@ -0,0 +1,128 @@
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Default number of bytes kept from each input.
+///
+/// Enough for frame headers and the beginning of payloads, which is where parsers fail, while
+/// dropping the bulk of the video data.
+pub const DEFAULT_MAX_LEN: usize = 256;
+
+/// Kind of the captured input, determining the corpus subdirectory it is saved into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    /// Datagrams received during camera lookup.
+    ScanReply,
+    /// Datagrams received during streaming.
+    Rtp,
+}
+
+impl Kind {
+    /// Returns the corpus subdirectory name.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::ScanReply => "scan_reply",
+            Kind::Rtp => "rtp",
+        }
+    }
+}
+
+/// Collects inputs that failed to parse into a directory, suitable as a fuzzing corpus and for
+/// regression tests.
+///
+/// Each input is truncated before being saved, so that captures do not carry whole video frames,
+/// and deduplicated by content.
+#[derive(Debug)]
+pub struct Corpus {
+    dir: PathBuf,
+    max_len: usize,
+    seen: Mutex<HashSet<(Kind, u64)>>,
+}
+
+impl Corpus {
+    /// Constructs a new corpus rooted at the given directory, creating it if required.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self, io::Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let v = Self {
+            dir,
+            max_len: DEFAULT_MAX_LEN,
+            seen: Mutex::new(HashSet::new()),
+        };
+
+        Ok(v)
+    }
+
+    /// Sets the maximum number of bytes kept from each input.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Saves the given input, returning the path of the created file, or `None` if an identical
+    /// input has already been saved.
+    pub fn save(&self, kind: Kind, buf: &[u8]) -> Result<Option<PathBuf>, io::Error> {
+        let buf = &buf[..buf.len().min(self.max_len)];
+
+        let hash = fnv1a(buf);
+
+        if !self.seen.lock().unwrap().insert((kind, hash)) {
+            return Ok(None);
+        }
+
+        let dir = self.dir.join(kind.as_str());
+        fs::create_dir_all(&dir)?;
+
+        let path = dir.join(format!("{:016x}", hash));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => file.write_all(buf)?,
+            // Saved during one of the previous runs.
+            Err(ref err) if err.kind() == ErrorKind::AlreadyExists => return Ok(None),
+            Err(err) => return Err(err),
+        }
+
+        Ok(Some(path))
+    }
+}
+
+/// Computes the 64-bit FNV-1a hash, which is stable across runs and toolchains, unlike the
+/// standard library hasher.
+fn fnv1a(buf: &[u8]) -> u64 {
+    buf.iter().fold(0xcbf29ce484222325, |hash, &v| {
+        (hash ^ u64::from(v)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_fnv1a() {
+        assert_eq!(0xcbf29ce484222325, fnv1a(b""));
+        assert_eq!(0xaf63dc4c8601ec8c, fnv1a(b"a"));
+    }
+
+    #[test]
+    fn test_save() {
+        let dir = env::temp_dir().join(format!("cleverdog-corpus-{}", std::process::id()));
+        let corpus = Corpus::new(&dir).unwrap().max_len(4);
+
+        let path = corpus.save(Kind::Rtp, b"garbage").unwrap().unwrap();
+        assert!(path.starts_with(dir.join("rtp")));
+        assert_eq!(b"garb", &fs::read(&path).unwrap()[..]);
+
+        // Inputs identical after truncation are deduplicated.
+        assert_eq!(None, corpus.save(Kind::Rtp, b"garbage!").unwrap());
+        assert!(corpus.save(Kind::ScanReply, b"garbage").unwrap().is_some());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,1082 @@
+use core::{
+    convert::TryFrom,
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+use std::{
+    error::Error,
+    io::{self, ErrorKind},
+    net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket},
+    sync::Arc,
+    thread,
+    time::Instant,
+};
+
+use log::{debug, warn};
+
+pub use self::{
+    cache::{lookup_cached, DiscoveryCache},
+    sweep::{sweep, Cidr, SweepOptions},
+    watcher::{DiscoveryEvent, DiscoveryWatcher},
+};
+use crate::{
+    conformance,
+    corpus::{Corpus, Kind},
+    iface,
+    mac::MacAddr,
+    protocol::{Cid, Command, Frame, LookupInfo, ProtocolError, ScanInfo, Token, CID_SIZE, DISCOVERY_PORT},
+    retry::RetryPolicy,
+};
+
+mod cache;
+mod sweep;
+mod watcher;
+
+/// Number of Scan commands sent before giving up.
+const ATTEMPTS: u32 = 3;
+/// Time to wait for a ScanReply after each Scan command.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An error that can occur during camera lookup.
+#[derive(Debug)]
+pub enum LookupError {
+    /// No camera replied within the lookup window.
+    Timeout {
+        /// Total time spent waiting for replies.
+        waited: Duration,
+        /// Number of Scan commands sent.
+        attempts: u32,
+        /// Number of received datagrams that were not valid ScanReply frames.
+        ignored: usize,
+    },
+    /// Socket I/O error.
+    Io(io::Error),
+}
+
+impl Display for LookupError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            LookupError::Timeout {
+                waited,
+                attempts,
+                ignored,
+            } => write!(
+                fmt,
+                "no camera replied within {:?} after {} attempt(s), {} datagram(s) ignored",
+                waited, attempts, ignored
+            ),
+            LookupError::Io(err) => write!(fmt, "I/O error: {}", err),
+        }
+    }
+}
+
+impl Error for LookupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            LookupError::Timeout { .. } => None,
+            LookupError::Io(err) => Some(err),
+        }
+    }
+}
+
+impl From<io::Error> for LookupError {
+    fn from(err: io::Error) -> Self {
+        LookupError::Io(err)
+    }
+}
+
+/// A network destination the Scan command is sent to.
+///
+/// Parsed either from a plain IP address with an optional port, e.g. `192.168.1.255`,
+/// `192.168.1.71:10008`, `fd00::71` or `[ff02::1%2]:10008`, or from an IPv4 CIDR range, e.g.
+/// `10.0.0.0/24`, in which case the Scan command is sent to the directed broadcast address of
+/// that subnet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target(SocketAddr);
+
+impl Target {
+    /// Constructs a new target from the given socket address.
+    #[inline]
+    pub fn new(addr: SocketAddr) -> Self {
+        Self(addr)
+    }
+
+    /// Returns the limited broadcast target, `255.255.255.255` on the discovery port.
+    ///
+    /// Such datagrams are delivered to all hosts of the directly attached network regardless of
+    /// its addressing, but are never forwarded by routers.
+    #[inline]
+    pub fn broadcast() -> Self {
+        Self(SocketAddr::new(Ipv4Addr::BROADCAST.into(), DISCOVERY_PORT))
+    }
+
+    /// Returns the IPv6 all-nodes multicast target, `ff02::1` on the discovery port, scoped to
+    /// the interface with the given index.
+    ///
+    /// This is the IPv6 counterpart of [`Target::broadcast`], as IPv6 has no broadcast. With zero
+    /// scope the interface set in [`LookupOptions::interface`] is used, if any.
+    #[inline]
+    pub fn all_nodes(scope_id: u32) -> Self {
+        let ip = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+        Self(SocketAddrV6::new(ip, DISCOVERY_PORT, 0, scope_id).into())
+    }
+
+    /// Returns the socket address the Scan command is sent to.
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.0
+    }
+}
+
+impl From<SocketAddr> for Target {
+    fn from(addr: SocketAddr) -> Self {
+        Self::new(addr)
+    }
+}
+
+/// An error that can occur during parsing a scan target string.
+#[derive(Debug, Clone)]
+pub enum TargetParseError {
+    /// The address part is not a valid IP address or socket address.
+    InvalidAddr(AddrParseError),
+    /// The CIDR prefix length is not a number in the `0..=32` range.
+    InvalidPrefix,
+}
+
+impl Display for TargetParseError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            TargetParseError::InvalidAddr(err) => write!(fmt, "invalid address: {}", err),
+            TargetParseError::InvalidPrefix => fmt.write_str("invalid prefix length"),
+        }
+    }
+}
+
+impl Error for TargetParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TargetParseError::InvalidAddr(err) => Some(err),
+            TargetParseError::InvalidPrefix => None,
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = TargetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('/') {
+            let cidr: Cidr = s.parse()?;
+            return Ok(Self::new(SocketAddr::new(cidr.broadcast().into(), DISCOVERY_PORT)));
+        }
+
+        match s.parse::<SocketAddr>() {
+            Ok(addr) => Ok(Self::new(addr)),
+            Err(..) => {
+                let addr: IpAddr = s.parse().map_err(TargetParseError::InvalidAddr)?;
+                Ok(Self::new(SocketAddr::new(addr, DISCOVERY_PORT)))
+            }
+        }
+    }
+}
+
+/// Summary of a finished scan, used to build timeout errors.
+#[derive(Debug, Clone, Copy, Default)]
+struct Summary {
+    waited: Duration,
+    attempts: u32,
+    ignored: usize,
+}
+
+impl Summary {
+    fn merge(self, other: Summary) -> Self {
+        Self {
+            waited: self.waited.max(other.waited),
+            attempts: self.attempts.max(other.attempts),
+            ignored: self.ignored + other.ignored,
+        }
+    }
+
+    fn into_timeout(self) -> LookupError {
+        let Summary {
+            waited,
+            attempts,
+            ignored,
+        } = self;
+
+        LookupError::Timeout {
+            waited,
+            attempts,
+            ignored,
+        }
+    }
+}
+
+/// Looks up a camera in the local network, returning the first one that replies.
+///
+/// The Scan command is sent to the limited broadcast address, see [`Target::broadcast`]. To scan
+/// a specific subnet or camera, use [`lookup_targets`].
+///
+/// The Scan command is resent several times, each time waiting for a reply during a fixed window.
+/// Datagrams that are not valid ScanReply frames, for example ones belonging to other protocols
+/// sharing the port range, are counted and skipped without resetting the window.
+pub fn lookup() -> Result<LookupInfo, LookupError> {
+    lookup_with(&LookupOptions::default())
+}
+
+/// Identity of a specific camera to look up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    /// Matches the camera with the given MAC address.
+    Mac(MacAddr),
+    /// Matches the camera with the given ID, ignoring padding.
+    Cid(Cid),
+}
+
+impl Filter {
+    /// Returns `true` if the given reply comes from the camera identified by this filter.
+    pub fn matches(&self, info: &LookupInfo) -> bool {
+        match self {
+            Filter::Mac(mac) => info.mac() == mac,
+            Filter::Cid(cid) => info.cid().id() == cid.id(),
+        }
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Filter::Mac(mac) => write!(fmt, "MAC {}", mac),
+            Filter::Cid(cid) => write!(fmt, "CID {}", cid),
+        }
+    }
+}
+
+/// An error that can occur during parsing a filter string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterParseError;
+
+impl Display for FilterParseError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(
+            fmt,
+            "must be a MAC address or a camera ID of up to {} characters",
+            CID_SIZE - 1
+        )
+    }
+}
+
+impl Error for FilterParseError {}
+
+impl FromStr for Filter {
+    type Err = FilterParseError;
+
+    /// Parses a MAC address, e.g. `dc:a9:04:97:9d:9b`, falling back to a camera ID.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(mac) = s.parse() {
+            return Ok(Filter::Mac(mac));
+        }
+
+        if s.is_empty() || s.len() > CID_SIZE - 1 {
+            return Err(FilterParseError);
+        }
+
+        let mut raw = [0; CID_SIZE];
+        raw[..s.len()].copy_from_slice(s.as_bytes());
+        Ok(Filter::Cid(Cid::new(raw)))
+    }
+}
+
+/// Looks up the camera identified by the given filter, ignoring replies from others.
+///
+/// See [`lookup`] for details.
+pub fn lookup_by(filter: Filter) -> Result<LookupInfo, LookupError> {
+    lookup_with(&LookupOptions::default().filter(filter))
+}
+
+/// Discovery options.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// use cleverdog::{LookupOptions, Target};
+///
+/// let opts = LookupOptions::new()
+///     .target("192.168.1.255".parse::<Target>().unwrap())
+///     .attempts(5)
+///     .timeout(Duration::from_millis(500));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LookupOptions {
+    target: Target,
+    bind: Option<SocketAddr>,
+    interface: Option<String>,
+    attempts: u32,
+    timeout: Duration,
+    token: Token,
+    filter: Option<Filter>,
+    corpus: Option<Arc<Corpus>>,
+}
+
+impl LookupOptions {
+    /// Constructs new options with default values.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the destination the Scan command is sent to.
+    ///
+    /// Defaults to [`Target::broadcast`].
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    /// Sets the local address the discovery socket is bound to.
+    ///
+    /// Defaults to an ephemeral port on all interfaces, of the same address family as the
+    /// target.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    /// Restricts the discovery socket to the given network interface, e.g. `eth0`.
+    ///
+    /// On multi-homed hosts broadcasts are routed through the default interface regardless of
+    /// the bind address, so this is the way to scan another network. Only supported on Linux,
+    /// where older kernels require the `CAP_NET_RAW` capability.
+    ///
+    /// The interface also sets the scope of link-local IPv6 multicast targets that have none,
+    /// see [`Target::all_nodes`].
+    pub fn interface(mut self, name: &str) -> Self {
+        self.interface = Some(name.into());
+        self
+    }
+
+    /// Sets the number of Scan commands sent before giving up.
+    ///
+    /// Defaults to 3.
+    pub fn attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Sets the time to wait for a reply after each Scan command.
+    ///
+    /// Defaults to 1 second.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the authentication token sent with the Scan command.
+    ///
+    /// Defaults to [`Token::ZERO`].
+    pub fn token(mut self, token: Token) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Accepts only replies from cameras matching the given filter, ignoring others.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Saves datagrams that fail to decode as ScanReply frames into the given corpus.
+    pub fn corpus(mut self, corpus: Arc<Corpus>) -> Self {
+        self.corpus = Some(corpus);
+        self
+    }
+}
+
+impl LookupOptions {
+    /// Returns the target, scoped to the configured interface if needed.
+    fn scoped_target(&self) -> Result<Target, io::Error> {
+        match (self.target.addr(), &self.interface) {
+            (SocketAddr::V6(mut addr), Some(interface)) if addr.scope_id() == 0 && is_link_local(addr.ip()) => {
+                addr.set_scope_id(iface::index(interface)?);
+                Ok(Target::new(addr.into()))
+            }
+            _ => Ok(self.target),
+        }
+    }
+
+    /// Returns attempt windows, one per configured attempt.
+    fn schedule(&self) -> impl Iterator<Item = Duration> {
+        RetryPolicy::fixed(self.timeout).retries(self.attempts).delays()
+    }
+
+    /// Returns what received replies are checked against.
+    fn params(&self) -> ScanParams<'_> {
+        ScanParams {
+            token: &self.token,
+            filter: self.filter.as_ref(),
+            corpus: self.corpus.as_deref(),
+        }
+    }
+
+    /// Binds the discovery socket according to these options.
+    fn socket(&self) -> Result<UdpSocket, io::Error> {
+        let bind = self.bind.unwrap_or_else(|| iface::unspecified(&self.target.addr()));
+        iface::bind_udp(bind, self.interface.as_deref())
+    }
+}
+
+/// Returns `true` if the given address is a link-local unicast or multicast address, which are
+/// only meaningful with a scope.
+fn is_link_local(ip: &Ipv6Addr) -> bool {
+    let segment = ip.segments()[0];
+    segment & 0xffc0 == 0xfe80 || segment & 0xff0f == 0xff02
+}
+
+impl Default for LookupOptions {
+    fn default() -> Self {
+        Self {
+            target: Target::broadcast(),
+            bind: None,
+            interface: None,
+            attempts: ATTEMPTS,
+            timeout: ATTEMPT_TIMEOUT,
+            token: Token::ZERO,
+            filter: None,
+            corpus: None,
+        }
+    }
+}
+
+/// Looks up a camera using the given options, returning the first one that replies.
+///
+/// See [`lookup`] for details.
+pub fn lookup_with(opts: &LookupOptions) -> Result<LookupInfo, LookupError> {
+    let schedule = opts.schedule();
+
+    let mut result = None;
+    let summary = scan(
+        opts.scoped_target()?,
+        opts.socket()?,
+        schedule,
+        Until::FirstReply,
+        opts.params(),
+        |info| {
+            result = Some(info);
+        },
+    )?;
+
+    result.ok_or_else(|| summary.into_timeout())
+}
+
+/// Looks up all cameras in the local network, collecting replies until the given timeout.
+///
+/// Unlike [`lookup`], scanning does not stop after the first reply, so that every camera gets a
+/// chance to answer. The Scan command is resent every second to survive packet loss. Results are
+/// deduplicated by camera ID.
+pub fn lookup_all(timeout: Duration) -> Result<Vec<LookupInfo>, LookupError> {
+    lookup_all_with(&LookupOptions::default(), timeout)
+}
+
+/// Looks up all cameras using the given options, collecting replies until the given timeout.
+///
+/// The Scan command is resent every attempt window configured in the options, ignoring the
+/// number of attempts. See [`lookup_all`] for details.
+pub fn lookup_all_with(opts: &LookupOptions, timeout: Duration) -> Result<Vec<LookupInfo>, LookupError> {
+    let mut infos: Vec<LookupInfo> = Vec::new();
+    let schedule = schedule_until(timeout, opts.timeout);
+    let summary = scan(
+        opts.scoped_target()?,
+        opts.socket()?,
+        schedule,
+        Until::Exhausted,
+        opts.params(),
+        |info| {
+            if infos.iter().all(|v| v.cid() != info.cid()) {
+                infos.push(info);
+            }
+        },
+    )?;
+
+    match infos.is_empty() {
+        true => Err(summary.into_timeout()),
+        false => Ok(infos),
+    }
+}
+
+/// Looks up cameras by scanning all given targets concurrently, aggregating the replies.
+///
+/// Each target is scanned the same way as in [`lookup`], except that once some camera has
+/// replied, the scan keeps collecting replies until the end of the current attempt window, so
+/// that several cameras behind a single broadcast address are found. Results are deduplicated by
+/// camera ID.
+///
+/// Failing targets are logged and skipped, unless none of them produced any results.
+pub fn lookup_targets(targets: &[Target]) -> Result<Vec<LookupInfo>, LookupError> {
+    let threads: Vec<_> = targets
+        .iter()
+        .map(|&target| {
+            thread::spawn(move || {
+                let mut infos = Vec::new();
+                let summary = scan(
+                    target,
+                    iface::bind_udp(iface::unspecified(&target.addr()), None)?,
+                    default_schedule(),
+                    Until::ReplyWindow,
+                    ScanParams::default(),
+                    |info| {
+                        infos.push(info);
+                    },
+                )?;
+
+                Ok((infos, summary))
+            })
+        })
+        .collect();
+
+    let mut infos: Vec<LookupInfo> = Vec::new();
+    let mut summary = Summary::default();
+    let mut error = None;
+
+    for (target, thread) in targets.iter().zip(threads) {
+        let result: Result<_, io::Error> = thread.join().expect("scan thread must not panic");
+
+        match result {
+            Ok((v, s)) => {
+                for info in v {
+                    if infos.iter().all(|v| v.cid() != info.cid()) {
+                        infos.push(info);
+                    }
+                }
+                summary = summary.merge(s);
+            }
+            Err(err) => {
+                warn!("failed to scan {}: {}", target.addr(), err);
+                error.get_or_insert(err);
+            }
+        }
+    }
+
+    match (infos.is_empty(), error) {
+        (false, ..) => Ok(infos),
+        (true, Some(err)) => Err(err.into()),
+        (true, None) => Err(summary.into_timeout()),
+    }
+}
+
+/// Policy of waking up cameras that stopped answering.
+///
+/// Battery-powered and eco firmware stops answering scans while idle, but wakes up after a few
+/// probes. The policy describes a burst of Scan commands sent with escalating intervals, each
+/// interval being the time to wait for a reply before sending the next probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WakePolicy {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl WakePolicy {
+    /// Constructs a new wake policy.
+    ///
+    /// The interval starts at `initial` and doubles after each probe, up to `max`, for the given
+    /// number of `attempts`.
+    #[inline]
+    pub fn new(initial: Duration, max: Duration, attempts: u32) -> Self {
+        Self { initial, max, attempts }
+    }
+
+    /// Returns the interval after the first probe.
+    #[inline]
+    pub fn initial(&self) -> Duration {
+        self.initial
+    }
+
+    /// Returns the maximum interval between probes.
+    #[inline]
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Returns the number of probes.
+    #[inline]
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Returns intervals to wait after each probe.
+    pub fn intervals(&self) -> impl Iterator<Item = Duration> {
+        RetryPolicy::exponential(self.initial.min(self.max), self.max)
+            .retries(self.attempts)
+            .delays()
+    }
+}
+
+impl Default for WakePolicy {
+    /// Probes for about 15 seconds in total, starting with 250ms interval, up to 4s.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(250), Duration::from_secs(4), 7)
+    }
+}
+
+/// Wakes up the camera at the given target, returning its info once it replies.
+///
+/// Probes are sent according to the given policy, after which the camera is considered offline
+/// and [`LookupError::Timeout`] is returned.
+pub fn wake(target: Target, policy: &WakePolicy) -> Result<LookupInfo, LookupError> {
+    let mut result = None;
+    let summary = scan(
+        target,
+        iface::bind_udp(iface::unspecified(&target.addr()), None)?,
+        policy.intervals(),
+        Until::FirstReply,
+        ScanParams::default(),
+        |info| {
+            result = Some(info);
+        },
+    )?;
+
+    result.ok_or_else(|| summary.into_timeout())
+}
+
+/// Returns the attempt windows used for regular lookups.
+fn default_schedule() -> impl Iterator<Item = Duration> {
+    RetryPolicy::fixed(ATTEMPT_TIMEOUT).retries(ATTEMPTS).delays()
+}
+
+/// Returns attempt windows of the given length, adding up to the specified timeout.
+fn schedule_until(timeout: Duration, window: Duration) -> impl Iterator<Item = Duration> {
+    let mut left = timeout;
+    core::iter::from_fn(move || {
+        let window = left.min(window);
+        left -= window;
+        Some(window).filter(|v| *v > Duration::ZERO)
+    })
+}
+
+/// Describes when a scan stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Until {
+    /// As soon as the first reply is received.
+    FirstReply,
+    /// At the end of the first attempt window during which at least one reply has been received.
+    ReplyWindow,
+    /// At the end of the schedule.
+    Exhausted,
+}
+
+/// Token sent with the Scan command and checks applied to received replies.
+#[derive(Debug, Clone, Copy)]
+struct ScanParams<'a> {
+    token: &'a Token,
+    filter: Option<&'a Filter>,
+    corpus: Option<&'a Corpus>,
+}
+
+impl Default for ScanParams<'_> {
+    fn default() -> Self {
+        Self {
+            token: &Token::ZERO,
+            filter: None,
+            corpus: None,
+        }
+    }
+}
+
+/// Scans the given target using the specified socket, passing each received ScanReply matching
+/// the filter, if any, to the callback.
+///
+/// The Scan command is sent once per given attempt window, until the specified stop condition.
+fn scan<S, F>(
+    target: Target,
+    sock: UdpSocket,
+    schedule: S,
+    until: Until,
+    params: ScanParams,
+    mut f: F,
+) -> Result<Summary, io::Error>
+where
+    S: IntoIterator<Item = Duration>,
+    F: FnMut(LookupInfo),
+{
+    if target.addr().is_ipv4() {
+        sock.set_broadcast(true)?;
+    }
+
+    let comm = Command::Scan.encode(&Cid::ANY, params.token)?;
+
+    let start = Instant::now();
+    let mut summary = Summary::default();
+    let mut found = false;
+    let mut buf = [0; 4096];
+
+    for window in schedule {
+        if found && until != Until::Exhausted {
+            break;
+        }
+
+        sock.send_to(&comm, target.addr())?;
+        summary.attempts += 1;
+
+        let deadline = Instant::now() + window;
+
+        while let Some(timeout) = deadline
+            .checked_duration_since(Instant::now())
+            .filter(|v| *v > Duration::ZERO)
+        {
+            sock.set_read_timeout(Some(timeout))?;
+
+            let (size, addr) = match sock.recv_from(&mut buf[..]) {
+                Ok((size, addr)) => (size, addr),
+                Err(ref err) if is_timeout(err) => break,
+                Err(err) => return Err(err),
+            };
+
+            match accept(addr, &buf[..size], &mut summary, params.corpus) {
+                Some(info) if !params.filter.map(|v| v.matches(&info)).unwrap_or(true) => {
+                    summary.ignored += 1;
+                    debug!(
+                        "ignored ScanReply from camera {} at {} not matching the filter ({} ignored so far)",
+                        info.cid(),
+                        addr,
+                        summary.ignored
+                    );
+                }
+                Some(info) => {
+                    found = true;
+                    f(info);
+                    if until == Until::FirstReply {
+                        break;
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    summary.waited = start.elapsed();
+
+    Ok(summary)
+}
+
+/// Decodes a datagram received during a scan, counting and logging it in the summary if it is
+/// not a valid ScanReply frame. Undecodable datagrams are saved into the corpus, if any.
+fn accept(addr: SocketAddr, buf: &[u8], summary: &mut Summary, corpus: Option<&Corpus>) -> Option<LookupInfo> {
+    match decode_scan_reply(addr, buf) {
+        Ok(Some(info)) => return Some(info),
+        Ok(None) => {
+            summary.ignored += 1;
+            debug!(
+                "ignored non-ScanReply frame from {} ({} ignored so far)",
+                addr, summary.ignored
+            );
+        }
+        Err(err) => {
+            summary.ignored += 1;
+            debug!(
+                "ignored invalid datagram from {}: {} ({} ignored so far)",
+                addr, err, summary.ignored
+            );
+            if let Some(corpus) = corpus {
+                if let Err(err) = corpus.save(Kind::ScanReply, buf) {
+                    warn!("failed to save datagram into the corpus: {}", err);
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns `true` if the given error is caused by the socket read timeout, which is reported
+/// differently across platforms.
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Decodes a ScanReply datagram received from the given address.
+///
+/// Returns `None` if the datagram is a well-formed frame carrying another command.
+fn decode_scan_reply(addr: SocketAddr, buf: &[u8]) -> Result<Option<LookupInfo>, ProtocolError> {
+    let frame = Frame::parse(buf)?;
+
+    if frame.command() != Command::ScanReply.as_u16() {
+        return Ok(None);
+    }
+
+    // Both MAC address and version fields are NUL-terminated, anything shorter is a truncated
+    // datagram.
+    if frame.payload().iter().filter(|&&ch| ch == b'\0').count() < 2 {
+        return Err(ProtocolError::CorruptFrame);
+    }
+
+    let info = ScanInfo::try_from(frame.payload()).map_err(ProtocolError::InvalidPayload)?;
+    let mut info = LookupInfo::new(addr, frame.cid(), info);
+    if let Some(capabilities) = conformance::cached(&info) {
+        info = info.with_capabilities(capabilities);
+    }
+
+    Ok(Some(info))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::{mac::MacAddr, protocol::Version};
+
+    #[test]
+    fn test_parse_target() {
+        assert_eq!(
+            "192.168.1.255:10008".parse::<SocketAddr>().unwrap(),
+            "192.168.1.255".parse::<Target>().unwrap().addr()
+        );
+        assert_eq!(
+            "192.168.1.71:4242".parse::<SocketAddr>().unwrap(),
+            "192.168.1.71:4242".parse::<Target>().unwrap().addr()
+        );
+        assert_eq!(
+            "10.0.0.255:10008".parse::<SocketAddr>().unwrap(),
+            "10.0.0.0/24".parse::<Target>().unwrap().addr()
+        );
+        assert_eq!(
+            "10.0.3.255:10008".parse::<SocketAddr>().unwrap(),
+            "10.0.1.17/22".parse::<Target>().unwrap().addr()
+        );
+        assert_eq!(
+            "255.255.255.255:10008".parse::<SocketAddr>().unwrap(),
+            "0.0.0.0/0".parse::<Target>().unwrap().addr()
+        );
+        assert_eq!(
+            "[fd00::71]:10008".parse::<SocketAddr>().unwrap(),
+            "fd00::71".parse::<Target>().unwrap().addr()
+        );
+        assert_eq!(Target::all_nodes(2), "[ff02::1%2]:10008".parse::<Target>().unwrap());
+    }
+
+    #[test]
+    fn test_parse_target_invalid() {
+        assert!("10.0.0.0/33".parse::<Target>().is_err());
+        assert!("10.0.0.0/x".parse::<Target>().is_err());
+        assert!("camera.local".parse::<Target>().is_err());
+    }
+
+    /// Spawns a fake camera replying to a single Scan command on the loopback interface.
+    pub(super) fn spawn_camera(cid: [u8; 16]) -> Target {
+        spawn_camera_on("127.0.0.1:0", cid)
+    }
+
+    fn spawn_camera_on(addr: &str, cid: [u8; 16]) -> Target {
+        let sock = UdpSocket::bind(addr).unwrap();
+        let addr = sock.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let (size, peer) = sock.recv_from(&mut buf).unwrap();
+            let frame = Frame::parse(&buf[..size]).unwrap();
+            assert_eq!(Command::Scan.as_u16(), frame.command());
+
+            let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+            let info = LookupInfo::new(addr, cid, info);
+            sock.send_to(b"garbage", peer).unwrap();
+            sock.send_to(&info.encode(), peer).unwrap();
+        });
+
+        Target::new(addr)
+    }
+
+    #[test]
+    fn test_lookup_all_with() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+
+        // Replies to each Scan twice, as if received over two interfaces.
+        thread::spawn(move || {
+            sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+            let mut buf = [0; 4096];
+            while let Ok((_, peer)) = sock.recv_from(&mut buf) {
+                let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+                let info = LookupInfo::new(addr, *b"AAAAAAAAAAAAAAA\0", info);
+                sock.send_to(&info.encode(), peer).unwrap();
+                sock.send_to(&info.encode(), peer).unwrap();
+            }
+        });
+
+        let start = Instant::now();
+        let opts = LookupOptions::new().target(Target::new(addr));
+        let infos = lookup_all_with(&opts, Duration::from_millis(1200)).unwrap();
+
+        assert_eq!(1, infos.len());
+        assert!(start.elapsed() >= Duration::from_millis(1200));
+    }
+
+    #[test]
+    fn test_schedule_until() {
+        assert_eq!(
+            vec![ATTEMPT_TIMEOUT, ATTEMPT_TIMEOUT, Duration::from_millis(500)],
+            schedule_until(Duration::from_millis(2500), ATTEMPT_TIMEOUT).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_wake_policy_intervals() {
+        let policy = WakePolicy::new(Duration::from_millis(250), Duration::from_secs(1), 5);
+        let intervals: Vec<_> = policy.intervals().collect();
+
+        assert_eq!(
+            vec![
+                Duration::from_millis(250),
+                Duration::from_millis(500),
+                Duration::from_secs(1),
+                Duration::from_secs(1),
+                Duration::from_secs(1),
+            ],
+            intervals
+        );
+    }
+
+    #[test]
+    fn test_wake_offline() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let policy = WakePolicy::new(Duration::from_millis(10), Duration::from_millis(20), 3);
+
+        match wake(Target::new(sock.local_addr().unwrap()), &policy) {
+            Err(LookupError::Timeout { attempts, .. }) => assert_eq!(3, attempts),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_lookup_targets() {
+        let targets = [spawn_camera(*b"AAAAAAAAAAAAAAA\0"), spawn_camera(*b"BBBBBBBBBBBBBBB\0")];
+
+        let mut addrs: Vec<_> = lookup_targets(&targets)
+            .unwrap()
+            .iter()
+            .map(|info| info.addr())
+            .collect();
+        addrs.sort();
+        let mut expected: Vec<_> = targets.iter().map(|target| target.addr()).collect();
+        expected.sort();
+
+        assert_eq!(expected, addrs);
+    }
+
+    #[test]
+    fn test_lookup_with() {
+        let target = spawn_camera(*b"AAAAAAAAAAAAAAA\0");
+        let opts = LookupOptions::new()
+            .target(target)
+            .bind("127.0.0.1:0".parse().unwrap())
+            .attempts(1)
+            .timeout(Duration::from_secs(5));
+
+        assert_eq!(target.addr(), lookup_with(&opts).unwrap().addr());
+    }
+
+    #[test]
+    fn test_lookup_with_corpus() {
+        let dir = env::temp_dir().join(format!("cleverdog-discovery-corpus-{}", std::process::id()));
+        let target = spawn_camera(*b"AAAAAAAAAAAAAAA\0");
+        let opts = LookupOptions::new()
+            .target(target)
+            .attempts(1)
+            .timeout(Duration::from_secs(5))
+            .corpus(Arc::new(Corpus::new(&dir).unwrap()));
+
+        assert_eq!(target.addr(), lookup_with(&opts).unwrap().addr());
+
+        // The garbage sent ahead of the reply is captured, the reply itself is not.
+        let files: Vec<_> = fs::read_dir(dir.join("scan_reply")).unwrap().collect();
+        assert_eq!(1, files.len());
+        assert_eq!(b"garbage", &fs::read(files[0].as_ref().unwrap().path()).unwrap()[..]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_lookup_with_filter() {
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+
+        // A neighbour's camera replies first.
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let (_, peer) = sock.recv_from(&mut buf).unwrap();
+            for cid in &[*b"AAAAAAAAAAAAAAA\0", *b"BBBBBBBBBBBBBBB\0"] {
+                let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+                sock.send_to(&LookupInfo::new(addr, *cid, info).encode(), peer).unwrap();
+            }
+        });
+
+        let opts = LookupOptions::new()
+            .target(Target::new(addr))
+            .attempts(1)
+            .timeout(Duration::from_secs(5))
+            .filter("BBBBBBBBBBBBBBB".parse().unwrap());
+
+        assert_eq!(&b"BBBBBBBBBBBBBBB\0"[..], &lookup_with(&opts).unwrap().cid()[..]);
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!(
+            Filter::Mac(MacAddr::new([220, 169, 4, 151, 157, 155])),
+            "dc:a9:04:97:9d:9b".parse().unwrap()
+        );
+        assert_eq!(
+            Filter::Cid(Cid::new(*b"ABC\0\0\0\0\0\0\0\0\0\0\0\0\0")),
+            "ABC".parse().unwrap()
+        );
+        assert_eq!(Err(FilterParseError), "".parse::<Filter>());
+        assert_eq!(Err(FilterParseError), "AAAAAAAAAAAAAAAA".parse::<Filter>());
+    }
+
+    #[test]
+    fn test_lookup_ipv6() {
+        let target = spawn_camera_on("[::1]:0", *b"AAAAAAAAAAAAAAA\0");
+        let opts = LookupOptions::new()
+            .target(target)
+            .attempts(1)
+            .timeout(Duration::from_secs(5));
+
+        assert_eq!(target.addr(), lookup_with(&opts).unwrap().addr());
+    }
+
+    proptest! {
+        #[test]
+        fn test_decode_scan_reply_roundtrip(
+            cid in any::<[u8; 16]>(),
+            mac in any::<[u8; 6]>(),
+            version in any::<[u16; 4]>(),
+        ) {
+            let addr = "127.0.0.1:10008".parse().unwrap();
+            let info = LookupInfo::new(addr, cid, ScanInfo::new(MacAddr::new(mac), Version::new(version)));
+
+            prop_assert_eq!(Some(info), decode_scan_reply(addr, &info.encode()).unwrap());
+        }
+
+        #[test]
+        fn test_decode_truncated_scan_reply(
+            cid in any::<[u8; 16]>(),
+            mac in any::<[u8; 6]>(),
+            version in any::<[u16; 4]>(),
+            len in 0usize..20,
+        ) {
+            let addr = "127.0.0.1:10008".parse().unwrap();
+            let info = LookupInfo::new(addr, cid, ScanInfo::new(MacAddr::new(mac), Version::new(version)));
+            let buf = info.encode();
+            let buf = &buf[..buf.len() - 1 - len];
+
+            prop_assert_eq!(Err(ProtocolError::CorruptFrame), decode_scan_reply(addr, buf));
+        }
+
+        #[test]
+        fn test_decode_scan_reply_never_panics(buf in prop::collection::vec(any::<u8>(), 0..128)) {
+            let _ = decode_scan_reply("127.0.0.1:10008".parse().unwrap(), &buf);
+        }
+    }
+}
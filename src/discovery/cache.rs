@@ -0,0 +1,250 @@
+use core::time::Duration;
+use std::{
+    collections::HashMap,
+    fs, io,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, warn};
+
+use super::{lookup_with, Filter, LookupError, LookupOptions, Target};
+use crate::{
+    json,
+    protocol::{Cid, LookupInfo, ScanInfo, CID_SIZE},
+};
+
+/// On-disk cache of previously discovered cameras, keyed by camera ID.
+///
+/// The cache is stored as JSON Lines, one object per camera with the following fields: `cid`,
+/// the raw camera ID field in hex, `addr`, `mac` and `version` as reported in the ScanReply, and
+/// `seen_ms`, the Unix time in milliseconds the camera last replied at.
+///
+/// Lines that fail to parse are skipped, so that a corrupt cache never prevents discovery.
+#[derive(Debug)]
+pub struct DiscoveryCache {
+    path: PathBuf,
+    cameras: HashMap<Cid, (LookupInfo, SystemTime)>,
+}
+
+impl DiscoveryCache {
+    /// Opens the cache stored at the given path, which may not exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        let mut cameras = HashMap::new();
+
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                for line in content.lines().filter(|v| !v.trim().is_empty()) {
+                    match decode(line) {
+                        Some((info, seen)) => {
+                            cameras.insert(*info.cid(), (info, seen));
+                        }
+                        None => warn!("skipped malformed discovery cache entry in {}", path.display()),
+                    }
+                }
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self { path, cameras })
+    }
+
+    /// Returns the path the cache is stored at.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the cached info of the camera with the given ID.
+    pub fn get(&self, cid: &Cid) -> Option<&LookupInfo> {
+        self.cameras.get(cid).map(|(info, ..)| info)
+    }
+
+    /// Returns cached cameras, in no particular order.
+    pub fn infos(&self) -> impl Iterator<Item = &LookupInfo> {
+        self.cameras.values().map(|(info, ..)| info)
+    }
+
+    /// Returns the most recently seen camera matching the given filter, if any.
+    pub fn latest(&self, filter: Option<&Filter>) -> Option<&LookupInfo> {
+        self.cameras
+            .values()
+            .filter(|(info, ..)| filter.map(|v| v.matches(info)).unwrap_or(true))
+            .max_by_key(|(.., seen)| *seen)
+            .map(|(info, ..)| info)
+    }
+
+    /// Adds or refreshes the given camera, marking it as seen now.
+    pub fn insert(&mut self, info: LookupInfo) {
+        self.cameras.insert(*info.cid(), (info, SystemTime::now()));
+    }
+
+    /// Removes the camera with the given ID, returning its info.
+    pub fn remove(&mut self, cid: &Cid) -> Option<LookupInfo> {
+        self.cameras.remove(cid).map(|(info, ..)| info)
+    }
+
+    /// Writes the cache to disk, replacing the file atomically.
+    pub fn save(&self) -> Result<(), io::Error> {
+        let mut cameras: Vec<_> = self.cameras.values().collect();
+        cameras.sort_by(|(a, ..), (b, ..)| a.cid().as_bytes().cmp(b.cid().as_bytes()));
+
+        let mut buf = String::new();
+        for (info, seen) in cameras {
+            encode(&mut buf, info, *seen);
+        }
+
+        if let Some(dir) = self.path.parent().filter(|v| !v.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".part");
+        fs::write(&tmp, buf)?;
+        fs::rename(tmp, &self.path)
+    }
+}
+
+/// Looks up a camera trying the address remembered in the cache first.
+///
+/// The most recently seen cached camera matching the filter set in the options, if any, is
+/// probed with a single unicast Scan command. If it does not reply, e.g. because its address has
+/// changed, a regular lookup is performed using the given options. The camera found either way
+/// is stored back into the cache, failing to save which is only logged.
+///
+/// ```no_run
+/// use cleverdog::{DiscoveryCache, LookupOptions};
+///
+/// let mut cache = DiscoveryCache::open("/var/cache/cleverdog/cameras.jsonl").unwrap();
+/// let info = cleverdog::lookup_cached(&mut cache, &LookupOptions::new()).unwrap();
+/// ```
+pub fn lookup_cached(cache: &mut DiscoveryCache, opts: &LookupOptions) -> Result<LookupInfo, LookupError> {
+    let cached = cache.latest(opts.filter.as_ref()).copied();
+
+    let result = match cached {
+        Some(cached) => {
+            let unicast = opts
+                .clone()
+                .target(Target::new(cached.addr()))
+                .attempts(1)
+                .filter(Filter::Cid(*cached.cid()));
+
+            match lookup_with(&unicast) {
+                Ok(info) => Ok(info),
+                Err(err) => {
+                    debug!(
+                        "cached camera {} at {} did not reply: {}",
+                        cached.cid(),
+                        cached.addr(),
+                        err
+                    );
+                    lookup_with(opts)
+                }
+            }
+        }
+        None => lookup_with(opts),
+    };
+
+    let info = result?;
+    cache.insert(info);
+    if let Err(err) = cache.save() {
+        warn!("failed to save discovery cache to {}: {}", cache.path().display(), err);
+    }
+
+    Ok(info)
+}
+
+fn encode(buf: &mut String, info: &LookupInfo, seen: SystemTime) {
+    buf.push_str("{\"cid\":");
+    json::push_str(buf, &info.cid().hex().to_string());
+    buf.push_str(",\"addr\":");
+    json::push_str(buf, &info.addr().to_string());
+    buf.push_str(",\"mac\":");
+    json::push_str(buf, &info.mac().to_string());
+    buf.push_str(",\"version\":");
+    json::push_str(buf, &info.version().to_string());
+    let seen = seen.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    buf.push_str(&format!(",\"seen_ms\":{}}}\n", seen));
+}
+
+fn decode(line: &str) -> Option<(LookupInfo, SystemTime)> {
+    let fields = json::parse_flat(line)?;
+    let field = |name: &str| fields.iter().find(|(k, ..)| k == name).map(|(.., v)| v.as_str());
+
+    let hex = field("cid")?;
+    if hex.len() != CID_SIZE * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut cid = [0; CID_SIZE];
+    for (idx, v) in cid.iter_mut().enumerate() {
+        *v = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16).ok()?;
+    }
+
+    let addr = field("addr")?.parse().ok()?;
+    let info = ScanInfo::new(field("mac")?.parse().ok()?, field("version")?.parse().ok()?);
+    let seen = UNIX_EPOCH + Duration::from_millis(field("seen_ms")?.parse().ok()?);
+
+    Some((LookupInfo::new(addr, cid, info), seen))
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+    use crate::{mac::MacAddr, protocol::Version};
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("cleverdog-cache-{}-{}.jsonl", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let path = temp_path("roundtrip");
+        let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+        let info = LookupInfo::new("10.0.1.71:10008".parse().unwrap(), *b"AAAAAAAAAAAAAAA\0", info);
+
+        let mut cache = DiscoveryCache::open(&path).unwrap();
+        assert_eq!(None, cache.latest(None));
+        cache.insert(info);
+        cache.save().unwrap();
+
+        fs::write(&path, fs::read_to_string(&path).unwrap() + "garbage\n").unwrap();
+
+        let cache = DiscoveryCache::open(&path).unwrap();
+        assert_eq!(Some(&info), cache.get(info.cid()));
+        assert_eq!(Some(&info), cache.latest(Some(&Filter::Mac(*info.mac()))));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_lookup_cached_falls_back() {
+        let path = temp_path("fallback");
+        let target = super::super::test::spawn_camera(*b"AAAAAAAAAAAAAAA\0");
+
+        // Remember the camera under an address nobody listens on.
+        let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+        let mut cache = DiscoveryCache::open(&path).unwrap();
+        cache.insert(LookupInfo::new(
+            "127.0.0.1:9".parse().unwrap(),
+            *b"AAAAAAAAAAAAAAA\0",
+            info,
+        ));
+
+        let opts = LookupOptions::new()
+            .target(target)
+            .attempts(1)
+            .timeout(Duration::from_millis(500));
+        let found = lookup_cached(&mut cache, &opts).unwrap();
+
+        assert_eq!(target.addr(), found.addr());
+        assert_eq!(
+            Some(target.addr()),
+            DiscoveryCache::open(&path).unwrap().get(found.cid()).map(|v| v.addr())
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
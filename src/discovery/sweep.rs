@@ -0,0 +1,366 @@
+use core::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+use std::{
+    io,
+    net::{Ipv4Addr, SocketAddr, UdpSocket},
+    sync::Mutex,
+    thread,
+    time::Instant,
+};
+
+use log::{debug, warn};
+
+use super::{accept, is_timeout, LookupError, Summary, TargetParseError};
+use crate::protocol::{Cid, Command, LookupInfo, Token, DISCOVERY_PORT};
+
+/// IPv4 address range in CIDR notation, e.g. `10.0.0.0/24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: Ipv4Addr,
+    prefix: u32,
+}
+
+impl Cidr {
+    /// Constructs a new range from any address within it and the prefix length.
+    ///
+    /// Host bits of the address are cleared. Returns `None` if the prefix is longer than 32 bits.
+    pub fn new(addr: Ipv4Addr, prefix: u32) -> Option<Self> {
+        if prefix > 32 {
+            return None;
+        }
+
+        let network = Ipv4Addr::from(u32::from(addr) & mask(prefix));
+        Some(Self { network, prefix })
+    }
+
+    /// Returns the network address.
+    #[inline]
+    pub fn network(&self) -> Ipv4Addr {
+        self.network
+    }
+
+    /// Returns the prefix length.
+    #[inline]
+    pub fn prefix(&self) -> u32 {
+        self.prefix
+    }
+
+    /// Returns the directed broadcast address of the range.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.network) | !mask(self.prefix))
+    }
+
+    /// Returns host addresses of the range.
+    ///
+    /// The network and broadcast addresses are excluded, except for `/31` and `/32` ranges, which
+    /// have no such addresses.
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let first = u64::from(u32::from(self.network));
+        let last = u64::from(u32::from(self.broadcast()));
+        let range = match self.prefix {
+            31 | 32 => first..=last,
+            _ => first + 1..=last - 1,
+        };
+
+        range.map(|v| Ipv4Addr::from(v as u32))
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "{}/{}", self.network, self.prefix)
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = TargetParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s.split_once('/').ok_or(TargetParseError::InvalidPrefix)?;
+        let addr: Ipv4Addr = addr.parse().map_err(TargetParseError::InvalidAddr)?;
+        let prefix: u32 = prefix.parse().map_err(|_| TargetParseError::InvalidPrefix)?;
+
+        Self::new(addr, prefix).ok_or(TargetParseError::InvalidPrefix)
+    }
+}
+
+fn mask(prefix: u32) -> u32 {
+    u32::MAX.checked_shl(32 - prefix).unwrap_or(0)
+}
+
+/// Options of a unicast sweep, see [`sweep`].
+///
+/// ```
+/// use core::time::Duration;
+///
+/// use cleverdog::SweepOptions;
+///
+/// let opts = SweepOptions::new()
+///     .concurrency(4)
+///     .rate(50)
+///     .timeout(Duration::from_secs(2));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SweepOptions {
+    concurrency: usize,
+    rate: u32,
+    timeout: Duration,
+    port: u16,
+    token: Token,
+}
+
+impl SweepOptions {
+    /// Constructs new options with default values.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of sockets probing hosts in parallel.
+    ///
+    /// Defaults to 8.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Sets the maximum number of Scan commands sent per second, shared by all sockets.
+    ///
+    /// Defaults to 200.
+    pub fn rate(mut self, rate: u32) -> Self {
+        self.rate = rate.max(1);
+        self
+    }
+
+    /// Sets the time to keep waiting for replies after the last Scan command is sent.
+    ///
+    /// Defaults to 1 second.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sets the port the Scan command is sent to.
+    ///
+    /// Defaults to [`DISCOVERY_PORT`].
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// Sets the authentication token sent with the Scan command.
+    ///
+    /// Defaults to [`Token::ZERO`].
+    pub fn token(mut self, token: Token) -> Self {
+        self.token = token;
+        self
+    }
+}
+
+impl Default for SweepOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            rate: 200,
+            timeout: Duration::from_secs(1),
+            port: DISCOVERY_PORT,
+            token: Token::ZERO,
+        }
+    }
+}
+
+/// Spreads Scan commands of all sweep sockets evenly in time.
+#[derive(Debug)]
+struct Pacer {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Pacer {
+    fn new(rate: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(1) / rate,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves the next free sending slot, returning the instant it starts at.
+    fn reserve(&self) -> Instant {
+        let mut next = self.next.lock().expect("pacer lock must not be poisoned");
+        let at = (*next).max(Instant::now());
+        *next = at + self.interval;
+        at
+    }
+}
+
+/// Looks up cameras by sending the Scan command unicast to every host of the given range.
+///
+/// This is useful when broadcasts are filtered, e.g. between VLANs, at the cost of generating
+/// traffic proportional to the size of the range. Hosts are probed from several sockets in
+/// parallel, with the total rate of Scan commands limited as configured in the options. Results
+/// are deduplicated by camera ID.
+///
+/// ```no_run
+/// use cleverdog::SweepOptions;
+///
+/// for info in cleverdog::sweep(&"10.0.1.0/24".parse().unwrap(), &SweepOptions::new()).unwrap() {
+///     println!("{} at {}", info.cid(), info.addr());
+/// }
+/// ```
+pub fn sweep(cidr: &Cidr, opts: &SweepOptions) -> Result<Vec<LookupInfo>, LookupError> {
+    let comm = Command::Scan.encode(&Cid::ANY, &opts.token)?;
+    let hosts = Mutex::new(cidr.hosts());
+    let pacer = Pacer::new(opts.rate);
+    let start = Instant::now();
+
+    let results: Vec<Result<_, io::Error>> = thread::scope(|scope| {
+        let threads: Vec<_> = (0..opts.concurrency)
+            .map(|_| scope.spawn(|| probe(&hosts, &pacer, &comm, opts)))
+            .collect();
+
+        threads
+            .into_iter()
+            .map(|v| v.join().expect("sweep thread must not panic"))
+            .collect()
+    });
+
+    let mut infos: Vec<LookupInfo> = Vec::new();
+    let mut summary = Summary::default();
+    let mut error = None;
+
+    for result in results {
+        match result {
+            Ok((v, s)) => {
+                for info in v {
+                    if infos.iter().all(|v| v.cid() != info.cid()) {
+                        infos.push(info);
+                    }
+                }
+                summary.attempts += s.attempts;
+                summary.ignored += s.ignored;
+            }
+            Err(err) => {
+                warn!("failed to sweep {}: {}", cidr, err);
+                error.get_or_insert(err);
+            }
+        }
+    }
+    summary.waited = start.elapsed();
+
+    match (infos.is_empty(), error) {
+        (false, ..) => Ok(infos),
+        (true, Some(err)) => Err(err.into()),
+        (true, None) => Err(summary.into_timeout()),
+    }
+}
+
+/// Probes hosts taken from the shared iterator until it is exhausted, then waits for late
+/// replies.
+fn probe<I>(
+    hosts: &Mutex<I>,
+    pacer: &Pacer,
+    comm: &[u8],
+    opts: &SweepOptions,
+) -> Result<(Vec<LookupInfo>, Summary), io::Error>
+where
+    I: Iterator<Item = Ipv4Addr>,
+{
+    let sock = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let mut infos = Vec::new();
+    let mut summary = Summary::default();
+
+    loop {
+        let host = hosts.lock().expect("hosts lock must not be poisoned").next();
+        let host = match host {
+            Some(host) => host,
+            None => break,
+        };
+
+        receive(&sock, pacer.reserve(), &mut infos, &mut summary)?;
+
+        let addr = SocketAddr::new(host.into(), opts.port);
+        if let Err(err) = sock.send_to(comm, addr) {
+            // Unreachable hosts are reported back through ICMP, which is not worth failing for.
+            debug!("failed to send Scan to {}: {}", addr, err);
+        }
+        summary.attempts += 1;
+    }
+
+    receive(&sock, Instant::now() + opts.timeout, &mut infos, &mut summary)?;
+
+    Ok((infos, summary))
+}
+
+/// Collects replies received on the socket until the given deadline.
+fn receive(
+    sock: &UdpSocket,
+    deadline: Instant,
+    infos: &mut Vec<LookupInfo>,
+    summary: &mut Summary,
+) -> Result<(), io::Error> {
+    let mut buf = [0; 4096];
+
+    while let Some(timeout) = deadline
+        .checked_duration_since(Instant::now())
+        .filter(|v| *v > Duration::ZERO)
+    {
+        sock.set_read_timeout(Some(timeout))?;
+
+        let (size, addr) = match sock.recv_from(&mut buf[..]) {
+            Ok((size, addr)) => (size, addr),
+            Err(ref err) if is_timeout(err) => break,
+            // Linux reports ICMP port unreachable for previously probed hosts as a receive error.
+            Err(ref err) if err.kind() == io::ErrorKind::ConnectionRefused => continue,
+            Err(err) => return Err(err),
+        };
+
+        if let Some(info) = accept(addr, &buf[..size], summary, None) {
+            infos.push(info);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_cidr() {
+        let cidr: Cidr = "10.0.1.77/24".parse().unwrap();
+
+        assert_eq!(Ipv4Addr::new(10, 0, 1, 0), cidr.network());
+        assert_eq!(Ipv4Addr::new(10, 0, 1, 255), cidr.broadcast());
+        assert_eq!("10.0.1.0/24", cidr.to_string());
+        assert!("10.0.1.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.1.0".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_hosts() {
+        let hosts: Vec<_> = "10.0.0.0/30".parse::<Cidr>().unwrap().hosts().collect();
+        assert_eq!(vec![Ipv4Addr::new(10, 0, 0, 1), Ipv4Addr::new(10, 0, 0, 2)], hosts);
+
+        assert_eq!(2, "10.0.0.0/31".parse::<Cidr>().unwrap().hosts().count());
+        assert_eq!(1, "10.0.0.7/32".parse::<Cidr>().unwrap().hosts().count());
+        assert_eq!(254, "192.168.1.0/24".parse::<Cidr>().unwrap().hosts().count());
+    }
+
+    #[test]
+    fn test_sweep() {
+        let target = super::super::test::spawn_camera(*b"AAAAAAAAAAAAAAA\0");
+
+        let opts = SweepOptions::new()
+            .concurrency(2)
+            .port(target.addr().port())
+            .timeout(Duration::from_millis(500));
+        let infos = sweep(&"127.0.0.0/30".parse().unwrap(), &opts).unwrap();
+
+        assert_eq!(1, infos.len());
+        assert_eq!(target.addr(), infos[0].addr());
+    }
+}
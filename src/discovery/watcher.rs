@@ -0,0 +1,218 @@
+use core::time::Duration;
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread::{self, JoinHandle},
+};
+
+use log::{info, warn};
+
+use super::{scan, LookupOptions, Until};
+use crate::protocol::{Cid, LookupInfo};
+
+/// Number of consecutive scan rounds a camera may miss before it is considered gone.
+const MISSED_ROUNDS: u32 = 3;
+
+/// Change of the set of cameras observed by a [`DiscoveryWatcher`].
+#[derive(Debug, Clone)]
+pub enum DiscoveryEvent {
+    /// A new camera has replied.
+    CameraAppeared(LookupInfo),
+    /// A camera has stopped replying, with its last known info.
+    CameraDisappeared(LookupInfo),
+    /// A known camera has replied from another address, e.g. after its DHCP lease changed.
+    CameraChangedAddr {
+        /// The camera info with the new address.
+        info: LookupInfo,
+        /// Address the camera was previously known under.
+        old: SocketAddr,
+    },
+}
+
+/// Continuously tracks cameras in the network.
+///
+/// A background thread rebroadcasts the Scan command each interval, collecting replies during all
+/// attempt windows configured in the [`LookupOptions`], and reports changes through a channel. A
+/// camera is considered gone after missing three rounds in a row.
+///
+/// The thread is stopped when the watcher is dropped.
+///
+/// ```no_run
+/// use core::time::Duration;
+///
+/// use cleverdog::{DiscoveryEvent, DiscoveryWatcher, LookupOptions};
+///
+/// let watcher = DiscoveryWatcher::spawn(LookupOptions::new(), Duration::from_secs(30)).unwrap();
+///
+/// for event in watcher.events() {
+///     if let DiscoveryEvent::CameraChangedAddr { info, old } = event {
+///         println!("{} moved from {} to {}", info.cid(), old, info.addr());
+///     }
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DiscoveryWatcher {
+    events: Receiver<DiscoveryEvent>,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl DiscoveryWatcher {
+    /// Spawns a new watcher scanning with the given options every `interval`.
+    pub fn spawn(opts: LookupOptions, interval: Duration) -> Result<Self, io::Error> {
+        let (tx, events) = mpsc::channel();
+        let (stop, stopped) = mpsc::channel();
+
+        let thread = thread::Builder::new()
+            .name("discovery-watcher".into())
+            .spawn(move || watch(opts, interval, tx, stopped))?;
+
+        let watcher = Self {
+            events,
+            stop: Some(stop),
+            thread: Some(thread),
+        };
+
+        Ok(watcher)
+    }
+
+    /// Returns the channel events are delivered into.
+    #[inline]
+    pub fn events(&self) -> &Receiver<DiscoveryEvent> {
+        &self.events
+    }
+}
+
+impl Drop for DiscoveryWatcher {
+    fn drop(&mut self) {
+        // Disconnecting the channel wakes the thread up.
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn watch(opts: LookupOptions, interval: Duration, tx: Sender<DiscoveryEvent>, stopped: Receiver<()>) {
+    let mut tracker = Tracker::default();
+
+    loop {
+        let mut infos = Vec::new();
+        let schedule = opts.schedule();
+        let result = opts.scoped_target().and_then(|target| {
+            let sock = opts.socket()?;
+            scan(target, sock, schedule, Until::Exhausted, opts.params(), |info| {
+                infos.push(info)
+            })
+        });
+        if let Err(err) = result {
+            warn!("failed to scan {}: {}", opts.target.addr(), err);
+        }
+
+        for event in tracker.update(infos) {
+            if tx.send(event).is_err() {
+                return;
+            }
+        }
+
+        match stopped.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+/// Known cameras, with the number of rounds each one has missed.
+#[derive(Debug, Default)]
+struct Tracker {
+    cameras: HashMap<Cid, (LookupInfo, u32)>,
+}
+
+impl Tracker {
+    /// Updates known cameras with replies collected during a single round, returning changes.
+    fn update(&mut self, infos: Vec<LookupInfo>) -> Vec<DiscoveryEvent> {
+        let mut events = Vec::new();
+
+        for (.., missed) in self.cameras.values_mut() {
+            *missed += 1;
+        }
+
+        for info in infos {
+            match self.cameras.get_mut(info.cid()) {
+                Some((known, missed)) => {
+                    if known.addr() != info.addr() {
+                        info!("camera {} moved from {} to {}", info.cid(), known.addr(), info.addr());
+                        events.push(DiscoveryEvent::CameraChangedAddr {
+                            info,
+                            old: known.addr(),
+                        });
+                    }
+                    *known = info;
+                    *missed = 0;
+                }
+                None => {
+                    info!("camera {} appeared at {}", info.cid(), info.addr());
+                    events.push(DiscoveryEvent::CameraAppeared(info));
+                    self.cameras.insert(*info.cid(), (info, 0));
+                }
+            }
+        }
+
+        let gone: Vec<Cid> = self
+            .cameras
+            .iter()
+            .filter(|(.., (.., missed))| *missed >= MISSED_ROUNDS)
+            .map(|(cid, ..)| *cid)
+            .collect();
+        for cid in gone {
+            if let Some((info, ..)) = self.cameras.remove(&cid) {
+                info!("camera {} disappeared", cid);
+                events.push(DiscoveryEvent::CameraDisappeared(info));
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        mac::MacAddr,
+        protocol::{ScanInfo, Version},
+    };
+
+    fn info(cid: [u8; 16], addr: &str) -> LookupInfo {
+        let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+        LookupInfo::new(addr.parse().unwrap(), cid, info)
+    }
+
+    #[test]
+    fn test_tracker() {
+        let a = *b"AAAAAAAAAAAAAAA\0";
+        let b = *b"BBBBBBBBBBBBBBB\0";
+        let mut tracker = Tracker::default();
+
+        let events = tracker.update(vec![info(a, "10.0.0.2:10008"), info(b, "10.0.0.3:10008")]);
+        assert_eq!(2, events.len());
+        assert!(events.iter().all(|v| matches!(v, DiscoveryEvent::CameraAppeared(..))));
+
+        let events = tracker.update(vec![info(a, "10.0.0.4:10008")]);
+        match &events[..] {
+            [DiscoveryEvent::CameraChangedAddr { info, old }] => {
+                assert_eq!("10.0.0.2:10008".parse::<SocketAddr>().unwrap(), *old);
+                assert_eq!("10.0.0.4:10008".parse::<SocketAddr>().unwrap(), info.addr());
+            }
+            other => panic!("unexpected events: {:?}", other),
+        }
+
+        assert!(tracker.update(vec![info(a, "10.0.0.4:10008")]).is_empty());
+        match &tracker.update(vec![info(a, "10.0.0.4:10008")])[..] {
+            [DiscoveryEvent::CameraDisappeared(info)] => assert_eq!(&b[..], &info.cid()[..]),
+            other => panic!("unexpected events: {:?}", other),
+        }
+    }
+}
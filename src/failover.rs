@@ -0,0 +1,207 @@
+//! Priority failover between tunnel endpoints.
+//!
+//! Endpoints are ordered by priority, the first being the primary. After a number of consecutive
+//! failures the next endpoint becomes active. While a backup is active, the primary is
+//! periodically probed, and the tunnel fails back to it once it recovers.
+
+use core::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+use std::time::Instant;
+
+use log::{info, warn};
+
+/// Transition between endpoints of a [`Failover`], identified by their indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailoverEvent {
+    /// The active endpoint failed, switching to the next one.
+    FailedOver { from: usize, to: usize },
+    /// The primary endpoint recovered, switching back to it.
+    FailedBack { from: usize },
+}
+
+impl Display for FailoverEvent {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            FailoverEvent::FailedOver { from, to } => write!(fmt, "failed over from endpoint #{} to #{}", from, to),
+            FailoverEvent::FailedBack { from } => write!(fmt, "failed back from endpoint #{} to primary", from),
+        }
+    }
+}
+
+/// Selects the active endpoint among a primary and its backups.
+///
+/// ```
+/// use std::time::Instant;
+///
+/// use cleverdog::failover::{Failover, FailoverEvent};
+///
+/// let mut failover = Failover::new("primary").backup("backup").threshold(2);
+/// let now = Instant::now();
+///
+/// assert_eq!(None, failover.on_failure(now));
+/// assert_eq!(Some(FailoverEvent::FailedOver { from: 0, to: 1 }), failover.on_failure(now));
+/// assert_eq!(&"backup", failover.active());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Failover<T> {
+    endpoints: Vec<T>,
+    active: usize,
+    threshold: u32,
+    failures: u32,
+    probe_interval: Duration,
+    probed_at: Option<Instant>,
+}
+
+impl<T> Failover<T> {
+    /// Constructs a new failover with the given primary endpoint.
+    pub fn new(primary: T) -> Self {
+        Self {
+            endpoints: vec![primary],
+            active: 0,
+            threshold: 3,
+            failures: 0,
+            probe_interval: Duration::from_secs(30),
+            probed_at: None,
+        }
+    }
+
+    /// Adds the given backup endpoint, with lower priority than all previously added ones.
+    pub fn backup(mut self, endpoint: T) -> Self {
+        self.endpoints.push(endpoint);
+        self
+    }
+
+    /// Sets the number of consecutive failures after which the next endpoint becomes active.
+    ///
+    /// Defaults to 3.
+    pub fn threshold(mut self, threshold: u32) -> Self {
+        self.threshold = threshold.max(1);
+        self
+    }
+
+    /// Sets how often the primary endpoint is probed while a backup is active.
+    ///
+    /// Defaults to 30 seconds.
+    pub fn probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+
+    /// Returns all endpoints, in priority order.
+    #[inline]
+    pub fn endpoints(&self) -> &[T] {
+        &self.endpoints
+    }
+
+    /// Returns the primary endpoint.
+    #[inline]
+    pub fn primary(&self) -> &T {
+        &self.endpoints[0]
+    }
+
+    /// Returns the active endpoint.
+    #[inline]
+    pub fn active(&self) -> &T {
+        &self.endpoints[self.active]
+    }
+
+    /// Returns the index of the active endpoint, zero being the primary.
+    #[inline]
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    /// Records successful use of the active endpoint, resetting the failure count.
+    #[inline]
+    pub fn on_success(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Records a failure of the active endpoint, returning an event if it made the next endpoint
+    /// active.
+    ///
+    /// After the last backup fails, the primary becomes active again.
+    pub fn on_failure(&mut self, at: Instant) -> Option<FailoverEvent> {
+        self.failures += 1;
+        if self.failures < self.threshold || self.endpoints.len() < 2 {
+            return None;
+        }
+
+        let from = self.active;
+        self.active = (self.active + 1) % self.endpoints.len();
+        self.failures = 0;
+        self.probed_at = Some(at);
+
+        let event = FailoverEvent::FailedOver { from, to: self.active };
+        warn!("{}", event);
+        Some(event)
+    }
+
+    /// Returns `true` if a backup is active and the primary is due to be probed.
+    pub fn should_probe(&self, at: Instant) -> bool {
+        match (self.active, self.probed_at) {
+            (0, ..) => false,
+            (.., Some(probed_at)) => at.saturating_duration_since(probed_at) >= self.probe_interval,
+            (.., None) => true,
+        }
+    }
+
+    /// Records the outcome of probing the primary, returning an event if it became active again.
+    pub fn on_probe(&mut self, ok: bool, at: Instant) -> Option<FailoverEvent> {
+        self.probed_at = Some(at);
+        if !ok || self.active == 0 {
+            return None;
+        }
+
+        let event = FailoverEvent::FailedBack { from: self.active };
+        self.active = 0;
+        self.failures = 0;
+        info!("{}", event);
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_failover() {
+        let mut failover = Failover::new("a")
+            .backup("b")
+            .backup("c")
+            .threshold(2)
+            .probe_interval(Duration::from_secs(10));
+        let now = Instant::now();
+
+        // Successes in between reset the failure count.
+        assert_eq!(None, failover.on_failure(now));
+        failover.on_success();
+        assert_eq!(None, failover.on_failure(now));
+        assert_eq!(
+            Some(FailoverEvent::FailedOver { from: 0, to: 1 }),
+            failover.on_failure(now)
+        );
+
+        assert!(!failover.should_probe(now + Duration::from_secs(5)));
+        assert!(failover.should_probe(now + Duration::from_secs(10)));
+        assert_eq!(None, failover.on_probe(false, now + Duration::from_secs(10)));
+        assert!(!failover.should_probe(now + Duration::from_secs(15)));
+
+        assert_eq!(None, failover.on_failure(now));
+        assert_eq!(
+            Some(FailoverEvent::FailedOver { from: 1, to: 2 }),
+            failover.on_failure(now)
+        );
+        assert_eq!(&"c", failover.active());
+
+        assert_eq!(
+            Some(FailoverEvent::FailedBack { from: 2 }),
+            failover.on_probe(true, now + Duration::from_secs(20))
+        );
+        assert_eq!(&"a", failover.active());
+        assert!(!failover.should_probe(now + Duration::from_secs(60)));
+    }
+}
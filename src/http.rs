@@ -0,0 +1,380 @@
+//! HTTP serving of the live stream as FLV.
+//!
+//! Browsers play `GET /live.flv` using flv.js, and most players open it directly. Frames are
+//! written to each client as they arrive, without segmenting, which keeps the latency well below
+//! that of HLS.
+
+use core::time::Duration;
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    h264::{Frame, FrameAssembler, Nal, NalType},
+    mux::flv::FlvWriter,
+    protocol::VIDEO_CHANNEL,
+    rtp::RtpPacket,
+    sink::Sink,
+};
+
+/// Path the stream is served at.
+pub const FLV_PATH: &str = "/live.flv";
+
+/// Upper bound of the request head size, protecting against misbehaving clients.
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+
+/// Time a client may take to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time a write to a client may block before the client is dropped, so that a stalled player
+/// does not hold the camera stream up.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct Client {
+    peer: SocketAddr,
+    wr: FlvWriter<TcpStream>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    clients: Mutex<Vec<Client>>,
+    stopped: AtomicBool,
+}
+
+/// HTTP server streaming the camera as FLV to every client requesting [`FLV_PATH`].
+///
+/// Implements [`Sink`], so RTP packets produced by [`stream`](crate::stream) can be passed to it
+/// directly. Packets are assembled into frames, and each client starts receiving them from the
+/// next keyframe. Clients failing to keep up are dropped.
+///
+/// ```no_run
+/// use cleverdog::{http::FlvServer, sink::Sink};
+///
+/// let mut server = FlvServer::bind("0.0.0.0:8080").unwrap();
+/// println!("serving at {}", server.url());
+///
+/// let info = cleverdog::lookup().unwrap();
+/// cleverdog::stream(info.cid(), info.addr(), |buf| server.send(buf)).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct FlvServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+    assembler: FrameAssembler,
+    /// Last parameter sets, for keyframes lacking them, since each client needs them to start.
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+impl FlvServer {
+    /// Binds to the given address and starts accepting clients.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+
+        let shared = Arc::new(Shared::default());
+        let acceptor = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("http-server".into())
+                .spawn(move || accept(listener, shared))?
+        };
+
+        let server = Self {
+            addr,
+            shared,
+            acceptor: Some(acceptor),
+            assembler: FrameAssembler::new(),
+            sps: None,
+            pps: None,
+        };
+        info!("serving at {}", server.url());
+
+        Ok(server)
+    }
+
+    /// Returns the address the server is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the URL the stream is served at.
+    pub fn url(&self) -> String {
+        format!("http://{}{}", self.addr, FLV_PATH)
+    }
+
+    /// Returns the number of clients currently connected to the stream.
+    pub fn clients(&self) -> usize {
+        self.shared.clients.lock().expect("lock must not be poisoned").len()
+    }
+
+    /// Writes the given frame to every client, dropping those failing to receive it.
+    pub fn write_frame(&mut self, frame: &Frame) {
+        let mut has_sps = false;
+        let mut has_pps = false;
+        for nal in &frame.nals {
+            match Nal::new(nal).map(|v| v.nal_type()) {
+                Some(NalType::Sps) => {
+                    self.sps = Some(nal.clone());
+                    has_sps = true;
+                }
+                Some(NalType::Pps) => {
+                    self.pps = Some(nal.clone());
+                    has_pps = true;
+                }
+                _ => {}
+            }
+        }
+
+        let mut clients = self.shared.clients.lock().expect("lock must not be poisoned");
+        let completed;
+        let frame = match (&self.sps, &self.pps) {
+            (Some(sps), Some(pps)) if frame.keyframe && !(has_sps && has_pps) => {
+                let mut nals = vec![sps.clone(), pps.clone()];
+                nals.extend(frame.nals.iter().cloned());
+                completed = Frame { nals, ..*frame };
+                &completed
+            }
+            _ => frame,
+        };
+
+        clients.retain_mut(|client| match client.wr.write_frame(frame) {
+            Ok(()) => true,
+            Err(err) => {
+                info!("dropping client {}: {}", client.peer, err);
+                let _ = client.wr.get_ref().shutdown(Shutdown::Both);
+                false
+            }
+        });
+    }
+}
+
+impl Sink for FlvServer {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let frames = match RtpPacket::new(buf, VIDEO_CHANNEL, Instant::now())
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|packet| Ok(self.assembler.push(&packet)?))
+        {
+            Ok(frames) => frames,
+            Err(err) => {
+                debug!("skipping packet: {}", err);
+                return Ok(());
+            }
+        };
+
+        for frame in &frames {
+            self.write_frame(frame);
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for FlvServer {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+
+        // Wake the acceptor up with a connection of our own.
+        let mut addr = self.addr;
+        match addr {
+            SocketAddr::V4(..) if addr.ip().is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            SocketAddr::V6(..) if addr.ip().is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => {}
+        }
+        let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+
+        for client in self.shared.clients.lock().expect("lock must not be poisoned").drain(..) {
+            let _ = client.wr.get_ref().shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Accepts clients until the server is stopped, reading each request on its own thread.
+fn accept(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        if shared.stopped.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("failed to accept HTTP client: {}", err);
+                continue;
+            }
+        };
+
+        let shared = shared.clone();
+        let spawned = thread::Builder::new().name("http-client".into()).spawn(move || {
+            let peer = stream.peer_addr();
+            if let Err(err) = serve(stream, &shared) {
+                debug!("HTTP client {:?} disconnected: {}", peer, err);
+            }
+        });
+        if let Err(err) = spawned {
+            warn!("failed to spawn HTTP client thread: {}", err);
+        }
+    }
+}
+
+/// Reads the request of a single client, adding it to the clients on success.
+fn serve(mut stream: TcpStream, shared: &Shared) -> Result<(), io::Error> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+    let mut rd = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    rd.read_line(&mut line)?;
+
+    let mut size = line.len();
+    loop {
+        let mut v = String::new();
+        let len = rd.read_line(&mut v)?;
+        size += len;
+        if len == 0 || size > MAX_REQUEST_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid HTTP request"));
+        }
+        if v == "\r\n" || v == "\n" {
+            break;
+        }
+    }
+
+    let mut it = line.split_whitespace();
+    let (method, target) = (it.next().unwrap_or_default(), it.next().unwrap_or_default());
+    debug!("{} {} from {}", method, target, peer);
+
+    // Query strings, e.g. cache busters added by players, are ignored.
+    let path = target.split('?').next().unwrap_or_default();
+    let status = match (method, path) {
+        ("GET", FLV_PATH) => "200 OK",
+        (_, FLV_PATH) => "405 Method Not Allowed",
+        _ => "404 Not Found",
+    };
+    if !status.starts_with('2') {
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\nServer: cleverdog\r\n\r\n",
+            status
+        );
+        return stream.write_all(response.as_bytes());
+    }
+
+    // The body lasts until the connection is closed, there is no length to announce.
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\n\
+          Content-Type: video/x-flv\r\n\
+          Cache-Control: no-cache\r\n\
+          Access-Control-Allow-Origin: *\r\n\
+          Connection: close\r\n\
+          Server: cleverdog\r\n\r\n",
+    )?;
+    stream.set_read_timeout(None)?;
+
+    info!("HTTP client {} started playing", peer);
+    shared.clients.lock().expect("lock must not be poisoned").push(Client {
+        peer,
+        wr: FlvWriter::new(stream),
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use super::*;
+
+    const SPS: [u8; 20] = [
+        0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+        0xca, 0x10,
+    ];
+
+    /// Sends a request and reads the response status line and headers.
+    fn get(server: &FlvServer, request: &str) -> (BufReader<TcpStream>, String) {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut rd = BufReader::new(stream);
+        let mut status = String::new();
+        rd.read_line(&mut status).unwrap();
+        loop {
+            let mut line = String::new();
+            rd.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+        }
+
+        (rd, status.trim_end().to_string())
+    }
+
+    #[test]
+    fn test_not_found() {
+        let server = FlvServer::bind("127.0.0.1:0").unwrap();
+
+        let (_, status) = get(&server, "GET / HTTP/1.1\r\n\r\n");
+        assert_eq!("HTTP/1.1 404 Not Found", status);
+        let (_, status) = get(&server, "POST /live.flv HTTP/1.1\r\n\r\n");
+        assert_eq!("HTTP/1.1 405 Method Not Allowed", status);
+    }
+
+    #[test]
+    fn test_stream() {
+        let mut server = FlvServer::bind("127.0.0.1:0").unwrap();
+        assert_eq!(format!("http://{}/live.flv", server.local_addr()), server.url());
+
+        // Parameter sets are seen before the client connects.
+        server.write_frame(&Frame {
+            timestamp: 0,
+            keyframe: true,
+            nals: vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, 1]],
+        });
+
+        let (mut rd, status) = get(&server, "GET /live.flv?t=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert_eq!("HTTP/1.1 200 OK", status);
+        // The client is registered right after the response head is sent.
+        while server.clients() == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        server.write_frame(&Frame {
+            timestamp: 3600,
+            keyframe: false,
+            nals: vec![vec![0x41, 2]],
+        });
+        server.write_frame(&Frame {
+            timestamp: 7200,
+            keyframe: true,
+            nals: vec![vec![0x65, 3]],
+        });
+        drop(server);
+
+        let mut buf = Vec::new();
+        rd.read_to_end(&mut buf).unwrap();
+        assert_eq!(b"FLV\x01\x01", &buf[..5]);
+        // The sequence header, then the keyframe, skipping the frame preceding it.
+        assert_eq!(&[0x17, 0], &buf[24..26]);
+        assert_eq!(
+            &[0x17, 1, 0, 0, 0, 0, 0, 0, 2, 0x65, 3, 0, 0, 0, 22],
+            &buf[buf.len() - 15..]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::{
+    io::{self, ErrorKind},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+};
+
+/// Returns the unspecified address with an ephemeral port of the same family as the given peer,
+/// which is what sockets talking to that peer are bound to by default.
+pub(crate) fn unspecified(peer: &SocketAddr) -> SocketAddr {
+    match peer {
+        SocketAddr::V4(..) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(..) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+    }
+}
+
+/// Binds a UDP socket to the given local address, optionally restricting it to the specified
+/// network interface.
+///
+/// On multi-homed hosts binding to an address is not enough for broadcasts, which are routed
+/// through the default interface regardless of the source address. Binding to an interface is
+/// only supported on Linux, where it may require the `CAP_NET_RAW` capability on older kernels.
+pub(crate) fn bind_udp(addr: SocketAddr, interface: Option<&str>) -> Result<UdpSocket, io::Error> {
+    let sock = UdpSocket::bind(addr)?;
+    if let Some(interface) = interface {
+        bind_to_device(&sock, interface)?;
+    }
+
+    Ok(sock)
+}
+
+/// Sets the size of the kernel receive buffer of the socket, which absorbs bursts while the
+/// receiving thread is busy.
+///
+/// The kernel may round or cap the size, e.g. to `net.core.rmem_max` on Linux.
+#[cfg(unix)]
+pub(crate) fn set_recv_buffer_size(sock: &UdpSocket, size: usize) -> Result<(), io::Error> {
+    use core::convert::TryFrom;
+    use std::os::unix::io::AsRawFd;
+
+    let size = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_RCVBUF,
+            &size as *const libc::c_int as *const libc::c_void,
+            core::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Sets the size of the kernel receive buffer of the socket, which absorbs bursts while the
+/// receiving thread is busy.
+#[cfg(not(unix))]
+pub(crate) fn set_recv_buffer_size(_sock: &UdpSocket, _size: usize) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        ErrorKind::Other,
+        "setting the receive buffer size is not supported on this platform",
+    ))
+}
+
+/// Returns the index of the network interface with the given name, used as IPv6 scope.
+#[cfg(unix)]
+pub(crate) fn index(interface: &str) -> Result<u32, io::Error> {
+    use std::ffi::CString;
+
+    let name = CString::new(interface)?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+/// Returns the index of the network interface with the given name, used as IPv6 scope.
+#[cfg(not(unix))]
+pub(crate) fn index(interface: &str) -> Result<u32, io::Error> {
+    interface
+        .parse()
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "interface must be given by index"))
+}
+
+#[cfg(target_os = "linux")]
+fn bind_to_device(sock: &UdpSocket, interface: &str) -> Result<(), io::Error> {
+    use std::os::unix::io::AsRawFd;
+
+    if interface.is_empty() || interface.len() >= libc::IFNAMSIZ || interface.contains('\0') {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("invalid interface name: {:?}", interface),
+        ));
+    }
+
+    let rc = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            interface.as_ptr() as *const libc::c_void,
+            interface.len() as libc::socklen_t,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_to_device(_sock: &UdpSocket, _interface: &str) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        ErrorKind::Other,
+        "binding to an interface is not supported on this platform",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bind_invalid_interface() {
+        let addr = "127.0.0.1:0".parse().unwrap();
+
+        assert_eq!(
+            ErrorKind::InvalidInput,
+            bind_udp(addr, Some("an-interface-name-too-long")).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    fn test_set_recv_buffer_size() {
+        use std::os::unix::io::AsRawFd;
+
+        let sock = bind_udp("127.0.0.1:0".parse().unwrap(), None).unwrap();
+        set_recv_buffer_size(&sock, 64 * 1024).unwrap();
+
+        let mut size: libc::c_int = 0;
+        let mut len = core::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let rc = unsafe {
+            libc::getsockopt(
+                sock.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                &mut size as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(0, rc);
+        // Linux doubles the requested size to account for bookkeeping overhead.
+        assert_eq!(128 * 1024, size);
+    }
+}
@@ -0,0 +1,302 @@
+use core::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+use std::{
+    error::Error,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::sink::Sink;
+
+/// Network impairment applied to the received stream, for testing sinks against realistic
+/// network conditions.
+///
+/// Parsed from a comma-separated list of parameters, for example
+/// `loss=2%,jitter=30ms,reorder=1%`.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Impairment {
+    /// Probability of dropping a packet, in the `0.0..=1.0` range.
+    pub loss: f64,
+    /// Maximum random delay added to each packet.
+    pub jitter: Duration,
+    /// Probability of swapping a packet with the next one, in the `0.0..=1.0` range.
+    pub reorder: f64,
+}
+
+/// An error that can occur during parsing an impairment string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The parameter name is not known.
+    UnknownParameter(String),
+    /// The parameter value is malformed.
+    InvalidValue(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ParseError::UnknownParameter(name) => write!(fmt, "unknown parameter: {}", name),
+            ParseError::InvalidValue(value) => write!(fmt, "invalid value: {}", value),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+impl FromStr for Impairment {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut v = Impairment::default();
+
+        for param in s.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+            let (name, value) = param
+                .split_once('=')
+                .ok_or_else(|| ParseError::InvalidValue(param.into()))?;
+
+            match name.trim() {
+                "loss" => v.loss = parse_percent(value.trim())?,
+                "jitter" => v.jitter = parse_duration(value.trim())?,
+                "reorder" => v.reorder = parse_percent(value.trim())?,
+                name => return Err(ParseError::UnknownParameter(name.into())),
+            }
+        }
+
+        Ok(v)
+    }
+}
+
+fn parse_percent(s: &str) -> Result<f64, ParseError> {
+    let err = || ParseError::InvalidValue(s.into());
+
+    let v: f64 = s.strip_suffix('%').ok_or_else(err)?.parse().map_err(|_| err())?;
+    if !(0.0..=100.0).contains(&v) {
+        return Err(err());
+    }
+
+    Ok(v / 100.0)
+}
+
+fn parse_duration(s: &str) -> Result<Duration, ParseError> {
+    let err = || ParseError::InvalidValue(s.into());
+
+    let (value, unit) = match s.find(|ch: char| !ch.is_ascii_digit()) {
+        Some(idx) => s.split_at(idx),
+        None => return Err(err()),
+    };
+    let value: u64 = value.parse().map_err(|_| err())?;
+
+    match unit {
+        "us" => Ok(Duration::from_micros(value)),
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        _ => Err(err()),
+    }
+}
+
+/// Sink that impairs data before passing it to the inner sink.
+///
+/// Delayed packets are released when later packets arrive, so with a steady packet rate the
+/// added delay closely follows the configured jitter, which also reorders packets naturally.
+pub struct Impaired<S> {
+    sink: S,
+    impairment: Impairment,
+    rng: XorShift,
+    held: Option<Vec<u8>>,
+    queue: Vec<(Instant, Vec<u8>)>,
+}
+
+impl<S: Sink> Impaired<S> {
+    /// Wraps the given sink, seeding the random generator from the current time.
+    pub fn new(sink: S, impairment: Impairment) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|v| v.as_nanos() as u64)
+            .unwrap_or_default();
+
+        Self::with_seed(sink, impairment, seed)
+    }
+
+    /// Wraps the given sink, using the specified seed to make impairment reproducible.
+    pub fn with_seed(sink: S, impairment: Impairment, seed: u64) -> Self {
+        Self {
+            sink,
+            impairment,
+            rng: XorShift::new(seed),
+            held: None,
+            queue: Vec::new(),
+        }
+    }
+
+    fn send_at(&mut self, buf: &[u8], now: Instant) -> Result<(), Box<dyn Error>> {
+        if self.rng.next_f64() < self.impairment.loss {
+            return self.release(now);
+        }
+
+        let mut bufs = Vec::with_capacity(2);
+        if self.held.is_none() && self.rng.next_f64() < self.impairment.reorder {
+            self.held = Some(buf.to_vec());
+        } else {
+            bufs.push(buf.to_vec());
+            bufs.extend(self.held.take());
+        }
+
+        for buf in bufs {
+            let delay = self.impairment.jitter.mul_f64(self.rng.next_f64());
+            let idx = self.queue.partition_point(|(at, ..)| *at <= now + delay);
+            self.queue.insert(idx, (now + delay, buf));
+        }
+
+        self.release(now)
+    }
+
+    fn release(&mut self, now: Instant) -> Result<(), Box<dyn Error>> {
+        let idx = self.queue.partition_point(|(at, ..)| *at <= now);
+        for (.., buf) in self.queue.drain(..idx) {
+            self.sink.send(&buf)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Sink> Sink for Impaired<S> {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.send_at(buf, Instant::now())
+    }
+}
+
+/// Small xorshift64* pseudo-random generator, good enough for impairment decisions.
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        // Zero is the only state xorshift can't escape from.
+        Self(seed | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+
+    /// Returns a value uniformly distributed in the `0.0..1.0` range.
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    fn collector() -> (Rc<RefCell<Vec<u8>>>, impl Sink) {
+        let bufs = Rc::new(RefCell::new(Vec::new()));
+        let sink = {
+            let bufs = bufs.clone();
+            move |buf: &[u8]| {
+                bufs.borrow_mut().push(buf[0]);
+                Ok(())
+            }
+        };
+
+        (bufs, sink)
+    }
+
+    #[test]
+    fn test_parse() {
+        let v: Impairment = "loss=2%, jitter=30ms,reorder=1.5%".parse().unwrap();
+        assert_eq!(
+            Impairment {
+                loss: 0.02,
+                jitter: Duration::from_millis(30),
+                reorder: 0.015,
+            },
+            v
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            Err(ParseError::UnknownParameter("dup".into())),
+            "dup=1%".parse::<Impairment>()
+        );
+        assert!("loss=2".parse::<Impairment>().is_err());
+        assert!("loss=200%".parse::<Impairment>().is_err());
+        assert!("jitter=30".parse::<Impairment>().is_err());
+        assert!("jitter=ms".parse::<Impairment>().is_err());
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let (bufs, sink) = collector();
+        let mut sink = Impaired::new(sink, Impairment::default());
+
+        for v in 0..10u8 {
+            sink.send(&[v]).unwrap();
+        }
+
+        assert_eq!((0..10).collect::<Vec<u8>>(), *bufs.borrow());
+    }
+
+    #[test]
+    fn test_loss() {
+        let (bufs, sink) = collector();
+        let impairment = Impairment {
+            loss: 0.5,
+            ..Default::default()
+        };
+        let mut sink = Impaired::with_seed(sink, impairment, 42);
+
+        for v in 0..200u8 {
+            sink.send(&[v]).unwrap();
+        }
+
+        let len = bufs.borrow().len();
+        assert!(len > 60 && len < 140, "{}", len);
+    }
+
+    #[test]
+    fn test_reorder() {
+        let (bufs, sink) = collector();
+        let impairment = Impairment {
+            reorder: 1.0,
+            ..Default::default()
+        };
+        let mut sink = Impaired::with_seed(sink, impairment, 42);
+
+        for v in 0..4u8 {
+            sink.send(&[v]).unwrap();
+        }
+
+        assert_eq!(vec![1, 0, 3, 2], *bufs.borrow());
+    }
+
+    #[test]
+    fn test_jitter() {
+        let (bufs, sink) = collector();
+        let impairment = Impairment {
+            jitter: Duration::from_millis(30),
+            ..Default::default()
+        };
+        let mut sink = Impaired::with_seed(sink, impairment, 42);
+
+        let now = Instant::now();
+        for v in 0..10u8 {
+            sink.send_at(&[v], now + Duration::from_millis(10 * v as u64)).unwrap();
+        }
+        sink.release(now + Duration::from_secs(1)).unwrap();
+
+        let mut v = bufs.borrow().clone();
+        assert_eq!(10, v.len());
+        v.sort();
+        assert_eq!((0..10).collect::<Vec<u8>>(), v);
+    }
+}
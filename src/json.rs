@@ -0,0 +1,141 @@
+//! Minimal JSON helpers for the hand-written reports, logs and caches.
+
+use core::{iter::Peekable, str::Chars};
+
+/// Appends the given string to the buffer as a quoted and escaped JSON string.
+pub(crate) fn push_str(buf: &mut String, v: &str) {
+    buf.push('"');
+    for ch in v.chars() {
+        match ch {
+            '"' => buf.push_str("\\\""),
+            '\\' => buf.push_str("\\\\"),
+            ch if (ch as u32) < 0x20 => buf.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => buf.push(ch),
+        }
+    }
+    buf.push('"');
+}
+
+/// Parses a flat JSON object with string and number values, as written by this crate.
+///
+/// Values are returned as text, with strings unescaped. Returns `None` if the input is not such
+/// an object.
+pub(crate) fn parse_flat(v: &str) -> Option<Vec<(String, String)>> {
+    let mut chars = v.trim().chars().peekable();
+    let mut fields = Vec::new();
+
+    expect(&mut chars, '{')?;
+    skip_ws(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+        return chars.next().map_or(Some(fields), |_| None);
+    }
+
+    loop {
+        skip_ws(&mut chars);
+        expect(&mut chars, '"')?;
+        let key = parse_str(&mut chars)?;
+        skip_ws(&mut chars);
+        expect(&mut chars, ':')?;
+        skip_ws(&mut chars);
+
+        let value = match chars.peek()? {
+            '"' => {
+                chars.next();
+                parse_str(&mut chars)?
+            }
+            _ => {
+                let mut value = String::new();
+                while let Some(&ch) = chars.peek() {
+                    match ch {
+                        '0'..='9' | '-' | '+' | '.' | 'e' | 'E' => value.push(ch),
+                        _ => break,
+                    }
+                    chars.next();
+                }
+                if value.is_empty() {
+                    return None;
+                }
+                value
+            }
+        };
+        fields.push((key, value));
+
+        skip_ws(&mut chars);
+        match chars.next()? {
+            ',' => {}
+            '}' => break,
+            _ => return None,
+        }
+    }
+
+    chars.next().map_or(Some(fields), |_| None)
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Option<()> {
+    match chars.next()? == expected {
+        true => Some(()),
+        false => None,
+    }
+}
+
+fn skip_ws(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+        chars.next();
+    }
+}
+
+/// Parses the rest of a string after its opening quote.
+fn parse_str(chars: &mut Peekable<Chars>) -> Option<String> {
+    let mut buf = String::new();
+
+    loop {
+        match chars.next()? {
+            '"' => return Some(buf),
+            '\\' => match chars.next()? {
+                '"' => buf.push('"'),
+                '\\' => buf.push('\\'),
+                '/' => buf.push('/'),
+                'b' => buf.push('\u{8}'),
+                'f' => buf.push('\u{c}'),
+                'n' => buf.push('\n'),
+                'r' => buf.push('\r'),
+                't' => buf.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    buf.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                _ => return None,
+            },
+            ch => buf.push(ch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_push_str() {
+        let mut buf = String::new();
+        push_str(&mut buf, "a\"b\\c\n");
+        assert_eq!(r#""a\"b\\c\u000a""#, buf);
+    }
+
+    #[test]
+    fn test_parse_flat() {
+        let mut buf = String::from("{\"name\":");
+        push_str(&mut buf, "a\"b\\c\n");
+        buf.push_str(", \"size\": 42}");
+
+        assert_eq!(
+            Some(vec![("name".into(), "a\"b\\c\n".into()), ("size".into(), "42".into())]),
+            parse_flat(&buf)
+        );
+        assert_eq!(Some(vec![]), parse_flat("{}"));
+        assert_eq!(None, parse_flat("{\"name\":\"a\""));
+        assert_eq!(None, parse_flat("{\"name\":[]}"));
+        assert_eq!(None, parse_flat("{} {}"));
+    }
+}
@@ -0,0 +1,160 @@
+//! Self-describing metadata for recordings.
+//!
+//! Footage pulled off disk months later should tell which camera it came from and in which
+//! timezone its wall-clock times are meant. The same metadata is rendered for every output
+//! format: a JSON sidecar next to plain file segments, key-value tags for container formats and
+//! session data lines for HLS playlists.
+
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{json, protocol::LookupInfo};
+
+/// Prefix of HLS session data identifiers.
+const HLS_DATA_ID_PREFIX: &str = "com.cleverdog.";
+
+/// Metadata describing the camera a recording comes from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Metadata {
+    /// Human-readable camera name, e.g. `Front door`.
+    pub alias: Option<String>,
+    /// Camera ID.
+    pub cid: String,
+    /// Camera MAC address.
+    pub mac: String,
+    /// Timezone of the camera location, either an IANA name like `Europe/Berlin` or a UTC
+    /// offset like `+01:00`.
+    pub timezone: Option<String>,
+}
+
+impl Metadata {
+    /// Constructs metadata of the given camera.
+    pub fn new(info: &LookupInfo) -> Self {
+        Self {
+            alias: None,
+            cid: info.cid().to_string(),
+            mac: info.mac().to_string(),
+            timezone: None,
+        }
+    }
+
+    /// Sets the camera alias.
+    pub fn alias(mut self, alias: &str) -> Self {
+        self.alias = Some(alias.into());
+        self
+    }
+
+    /// Sets the camera timezone.
+    pub fn timezone(mut self, timezone: &str) -> Self {
+        self.timezone = Some(timezone.into());
+        self
+    }
+
+    /// Returns metadata as key-value tags, as stored in MKV tags or MP4 `udta` boxes.
+    ///
+    /// Unset fields are omitted.
+    pub fn tags(&self) -> Vec<(&'static str, &str)> {
+        let mut tags = Vec::new();
+        if let Some(alias) = &self.alias {
+            tags.push(("TITLE", alias.as_str()));
+        }
+        tags.push(("CAMERA_CID", self.cid.as_str()));
+        tags.push(("CAMERA_MAC", self.mac.as_str()));
+        if let Some(timezone) = &self.timezone {
+            tags.push(("TIMEZONE", timezone.as_str()));
+        }
+        tags
+    }
+
+    /// Returns `#EXT-X-SESSION-DATA` lines for HLS multivariant playlists.
+    pub fn hls_session_data(&self) -> Vec<String> {
+        self.tags()
+            .into_iter()
+            .map(|(key, value)| {
+                format!(
+                    "#EXT-X-SESSION-DATA:DATA-ID=\"{}{}\",VALUE=\"{}\"",
+                    HLS_DATA_ID_PREFIX,
+                    key.to_ascii_lowercase(),
+                    value.replace('"', "'")
+                )
+            })
+            .collect()
+    }
+
+    /// Encodes metadata as a JSON object.
+    pub fn encode<W: Write>(&self, wr: &mut W) -> Result<(), io::Error> {
+        let mut buf = String::from("{");
+        for (idx, (key, value)) in self.tags().into_iter().enumerate() {
+            if idx > 0 {
+                buf.push(',');
+            }
+            json::push_str(&mut buf, &key.to_ascii_lowercase());
+            buf.push(':');
+            json::push_str(&mut buf, value);
+        }
+        buf.push_str("}\n");
+
+        wr.write_all(buf.as_bytes())
+    }
+}
+
+/// Returns the path of the metadata sidecar for the given recording segment, e.g.
+/// `camera-0001.meta.json` for `camera-0001.mkv`.
+pub fn sidecar_path(segment: &Path) -> PathBuf {
+    segment.with_extension("meta.json")
+}
+
+/// Writes the metadata sidecar for the given recording segment next to it.
+pub fn write_sidecar(segment: &Path, metadata: &Metadata) -> Result<(), io::Error> {
+    let mut wr = BufWriter::new(File::create(sidecar_path(segment))?);
+    metadata.encode(&mut wr)?;
+    wr.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            alias: Some("Front \"door\"".into()),
+            cid: "xxxxS_AB12CD".into(),
+            mac: "dc:a9:04:97:9d:9b".into(),
+            timezone: Some("Europe/Berlin".into()),
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buf = Vec::new();
+        metadata().encode(&mut buf).unwrap();
+
+        assert_eq!(
+            "{\"title\":\"Front \\\"door\\\"\",\"camera_cid\":\"xxxxS_AB12CD\",\
+             \"camera_mac\":\"dc:a9:04:97:9d:9b\",\"timezone\":\"Europe/Berlin\"}\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hls_session_data() {
+        let lines = metadata().hls_session_data();
+
+        assert_eq!(
+            "#EXT-X-SESSION-DATA:DATA-ID=\"com.cleverdog.title\",VALUE=\"Front 'door'\"",
+            lines[0]
+        );
+        assert_eq!(4, lines.len());
+    }
+
+    #[test]
+    fn test_sidecar_path() {
+        assert_eq!(
+            Path::new("camera-0001.meta.json"),
+            sidecar_path(Path::new("camera-0001.mkv"))
+        );
+    }
+}
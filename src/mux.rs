@@ -0,0 +1,136 @@
+//! Containers the H.264 stream can be recorded into.
+//!
+//! Muxers consume [`Frame`](crate::h264::Frame)s, as assembled by a
+//! [`FrameAssembler`](crate::h264::FrameAssembler), and derive presentation times from their RTP
+//! timestamps, so recordings keep the camera's own timing regardless of network jitter.
+
+pub mod flv;
+pub mod fmp4;
+pub mod hls;
+pub mod mkv;
+pub mod mpegts;
+pub mod record;
+
+use std::io;
+
+use crate::h264::{Frame, Nal, NalType, SpsError, StreamInfo};
+
+/// RTP clock rate of the H.264 stream, used as the timescale of recorded tracks.
+const TIMESCALE: u32 = 90_000;
+
+/// Frame duration assumed when there is no following frame to derive it from, i.e. 25 fps.
+const DEFAULT_DURATION: u32 = TIMESCALE / 25;
+
+/// Parameter sets of the stream, remembered from the frames carrying them.
+#[derive(Debug, Default)]
+struct ParameterSets {
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+}
+
+impl ParameterSets {
+    /// Remembers parameter sets carried by the given frame.
+    fn update(&mut self, frame: &Frame) {
+        for nal in &frame.nals {
+            match Nal::new(nal).map(|v| v.nal_type()) {
+                Some(NalType::Sps) => self.sps = Some(nal.clone()),
+                Some(NalType::Pps) => self.pps = Some(nal.clone()),
+                _ => {}
+            }
+        }
+    }
+
+    fn get(&self) -> Option<(&[u8], &[u8])> {
+        match (&self.sps, &self.pps) {
+            (Some(sps), Some(pps)) => Some((sps, pps)),
+            _ => None,
+        }
+    }
+}
+
+/// Extends 32-bit RTP timestamps into a monotonic 64-bit timeline, starting at zero.
+#[derive(Debug, Default)]
+struct Timeline {
+    last: Option<u32>,
+    time: u64,
+    /// Duration of the last frame, used for the final one.
+    duration: Option<u32>,
+}
+
+impl Timeline {
+    /// Returns the time of the frame with the given timestamp, relative to the first one.
+    ///
+    /// Timestamps going backwards, e.g. after the camera restarts the stream, continue the
+    /// timeline with the previous frame duration instead.
+    fn push(&mut self, timestamp: u32) -> u64 {
+        if let Some(last) = self.last.replace(timestamp) {
+            let delta = match timestamp.wrapping_sub(last) {
+                delta if delta < 0x8000_0000 => delta,
+                _ => self.duration.unwrap_or(DEFAULT_DURATION),
+            };
+            self.duration = Some(delta);
+            self.time += u64::from(delta);
+        }
+        self.time
+    }
+
+    /// Returns the duration of the last frame, i.e. the one the next timestamp is yet unknown.
+    fn last_duration(&self) -> u32 {
+        self.duration.unwrap_or(DEFAULT_DURATION)
+    }
+}
+
+/// Returns the stream parameters and the AVC decoder configuration record, as stored by
+/// containers, of the given parameter sets.
+fn avc_config(sps: &[u8], pps: &[u8]) -> Result<(StreamInfo, Vec<u8>), io::Error> {
+    let info = Nal::new(sps)
+        .ok_or(SpsError::NotSps)
+        .and_then(|v| StreamInfo::from_sps(&v))
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    if sps.len() < 4 || sps.len() > 0xffff || pps.len() > 0xffff {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid parameter set size"));
+    }
+
+    // Four-byte unit lengths, a single SPS and a single PPS.
+    let mut buf = vec![1, sps[1], sps[2], sps[3], 0xfc | 3, 0xe0 | 1];
+    buf.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    buf.extend_from_slice(sps);
+    buf.push(1);
+    buf.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    buf.extend_from_slice(pps);
+
+    Ok((info, buf))
+}
+
+/// Returns units of the frame belonging to the sample rather than to the codec configuration,
+/// each prefixed with its four-byte length.
+fn sample_data(frame: &Frame) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for nal in &frame.nals {
+        match Nal::new(nal).map(|v| v.nal_type()) {
+            Some(NalType::Sps) | Some(NalType::Pps) | Some(NalType::AccessUnitDelimiter) | None => {}
+            Some(..) => {
+                buf.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                buf.extend_from_slice(nal);
+            }
+        }
+    }
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_timeline_wraps_and_restarts() {
+        let mut timeline = Timeline::default();
+
+        assert_eq!(0, timeline.push(u32::MAX - 1499));
+        assert_eq!(3000, timeline.push(1500));
+        // The timestamp went backwards, continue with the previous duration.
+        assert_eq!(6000, timeline.push(100));
+        assert_eq!(6100, timeline.push(200));
+        assert_eq!(100, timeline.last_duration());
+    }
+}
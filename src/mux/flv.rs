@@ -0,0 +1,186 @@
+//! FLV, as consumed by flv.js and most live players over plain HTTP.
+//!
+//! The stream is a header followed by tags, each carrying a single frame with its millisecond
+//! timestamp. There is no index or segmenting, so a player can start from any keyframe with
+//! little latency.
+
+use std::io::{self, Write};
+
+use super::{avc_config, sample_data, ParameterSets, Timeline, TIMESCALE};
+use crate::h264::Frame;
+
+/// Tag type of video data.
+const TAG_VIDEO: u8 = 9;
+
+/// Codec ID of H.264 in video tags.
+const CODEC_AVC: u8 = 7;
+
+const FRAME_KEY: u8 = 1 << 4;
+const FRAME_INTER: u8 = 2 << 4;
+
+const AVC_SEQUENCE_HEADER: u8 = 0;
+const AVC_NALU: u8 = 1;
+
+/// Writes frames as FLV.
+///
+/// Frames preceding the first keyframe with known parameter sets are dropped, since the AVC
+/// sequence header needs them. Tag timestamps are in milliseconds, derived from RTP timestamps of
+/// the frames.
+///
+/// ```
+/// use cleverdog::{h264::Frame, mux::flv::FlvWriter};
+///
+/// let sps = vec![
+///     0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+///     0xca, 0x10,
+/// ];
+/// let mut wr = FlvWriter::new(Vec::new());
+/// wr.write_frame(&Frame { timestamp: 0, keyframe: true, nals: vec![sps, vec![0x68, 0xce], vec![0x65, 1]] })?;
+/// wr.write_frame(&Frame { timestamp: 3600, keyframe: false, nals: vec![vec![0x41, 2]] })?;
+///
+/// let buf = wr.into_inner();
+/// assert_eq!(b"FLV\x01\x01", &buf[..5]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct FlvWriter<W> {
+    wr: W,
+    params: ParameterSets,
+    started: bool,
+    timeline: Timeline,
+}
+
+impl<W: Write> FlvWriter<W> {
+    /// Constructs a new writer into the given writer.
+    pub fn new(wr: W) -> Self {
+        Self {
+            wr,
+            params: ParameterSets::default(),
+            started: false,
+            timeline: Timeline::default(),
+        }
+    }
+
+    /// Returns whether the header has been written.
+    #[inline]
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Returns a reference to the inner writer.
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.wr
+    }
+
+    /// Returns the inner writer.
+    ///
+    /// There is nothing to finalize, the stream is complete after each frame.
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.wr
+    }
+
+    /// Writes the given frame, preceded by the header and the AVC sequence header if it is the
+    /// first keyframe.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), io::Error> {
+        self.params.update(frame);
+
+        let mut buf = Vec::new();
+        if !self.started {
+            let (sps, pps) = match (frame.keyframe, self.params.get()) {
+                (true, Some(params)) => params,
+                _ => return Ok(()),
+            };
+            let (_, avcc) = avc_config(sps, pps)?;
+
+            // Version 1, video only, followed by the size of the first previous tag.
+            buf.extend_from_slice(b"FLV\x01\x01\x00\x00\x00\x09\x00\x00\x00\x00");
+            let mut body = vec![FRAME_KEY | CODEC_AVC, AVC_SEQUENCE_HEADER, 0, 0, 0];
+            body.extend_from_slice(&avcc);
+            put_tag(&mut buf, 0, &body);
+            self.started = true;
+        }
+
+        let time = self.timeline.push(frame.timestamp) * 1000 / u64::from(TIMESCALE);
+
+        let kind = if frame.keyframe { FRAME_KEY } else { FRAME_INTER };
+        // Without B-frames the composition time offset is always zero.
+        let mut body = vec![kind | CODEC_AVC, AVC_NALU, 0, 0, 0];
+        body.extend_from_slice(&sample_data(frame));
+        put_tag(&mut buf, time as u32, &body);
+
+        self.wr.write_all(&buf)?;
+        self.wr.flush()
+    }
+}
+
+/// Appends a video tag with the given timestamp in milliseconds, followed by its size.
+fn put_tag(buf: &mut Vec<u8>, time: u32, body: &[u8]) {
+    buf.push(TAG_VIDEO);
+    buf.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    // The lower 24 bits, then the upper 8 ones.
+    buf.extend_from_slice(&time.to_be_bytes()[1..]);
+    buf.push((time >> 24) as u8);
+    // Stream ID, always zero.
+    buf.extend_from_slice(&[0, 0, 0]);
+    buf.extend_from_slice(body);
+    buf.extend_from_slice(&(11 + body.len() as u32).to_be_bytes());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPS: [u8; 20] = [
+        0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+        0xca, 0x10,
+    ];
+
+    #[test]
+    fn test_tags() {
+        let mut wr = FlvWriter::new(Vec::new());
+        // Dropped, nothing precedes it to decode it from.
+        wr.write_frame(&Frame {
+            timestamp: 0,
+            keyframe: false,
+            nals: vec![vec![0x41, 1]],
+        })
+        .unwrap();
+        assert!(!wr.is_started());
+
+        let frames = [
+            Frame {
+                timestamp: 90_000,
+                keyframe: true,
+                nals: vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, 1]],
+            },
+            Frame {
+                timestamp: 99_000,
+                keyframe: false,
+                nals: vec![vec![0x41, 2]],
+            },
+        ];
+        for frame in &frames {
+            wr.write_frame(frame).unwrap();
+        }
+        let buf = wr.into_inner();
+
+        // The sequence header tag: type, size, timestamp and stream ID, then the video header.
+        let size = 5 + 6 + 2 + SPS.len() + 1 + 2 + 2;
+        assert_eq!(&[9, 0, 0, size as u8, 0, 0, 0, 0, 0, 0, 0], &buf[13..24]);
+        assert_eq!(&[0x17, 0, 0, 0, 0, 1, 0x42, 0xc0, 0x1e], &buf[24..33]);
+        assert_eq!(&(11 + size as u32).to_be_bytes(), &buf[24 + size..28 + size]);
+
+        // Both frames, the first at zero and the second 100ms later.
+        let frames = &buf[28 + size..];
+        assert_eq!(
+            &[9, 0, 0, 11, 0, 0, 0, 0, 0, 0, 0, 0x17, 1, 0, 0, 0, 0, 0, 0, 2, 0x65, 1, 0, 0, 0, 22],
+            &frames[..26]
+        );
+        assert_eq!(
+            &[9, 0, 0, 11, 0, 0, 100, 0, 0, 0, 0, 0x27, 1, 0, 0, 0, 0, 0, 0, 2, 0x41, 2, 0, 0, 0, 22],
+            &frames[26..]
+        );
+    }
+}
@@ -0,0 +1,717 @@
+//! Fragmented MP4, as played by Media Source Extensions in browsers and packaged by HLS and
+//! DASH.
+//!
+//! The stream starts with an init segment, holding the codec configuration, followed by a
+//! `moof`/`mdat` fragment per frame. Each fragment can be handed to a player as soon as it is
+//! written. Recordings end with an `mfra` index of keyframes, which [`repair`] rebuilds for
+//! files cut off by a crash.
+
+use std::{
+    fs::OpenOptions,
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{avc_config, sample_data, ParameterSets, Timeline, TIMESCALE};
+use crate::{h264::Frame, sink::finalize};
+
+/// Track ID of the only, video, track.
+const TRACK_ID: u32 = 1;
+
+/// Sample flags of a sync sample, which depends on no other.
+const SYNC_SAMPLE: u32 = 0x0200_0000;
+/// Sample flags of a non-sync sample, depending on others.
+const NON_SYNC_SAMPLE: u32 = 0x0101_0000;
+/// Sample flag bit set for samples that are not sync samples.
+const NON_SYNC_FLAG: u32 = 0x0001_0000;
+
+/// Identity transformation matrix of movie and track headers.
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Writes frames as fragmented MP4.
+///
+/// Frames preceding the first keyframe with known parameter sets are dropped, since the init
+/// segment needs them. Each frame is held until the next one arrives, which determines its
+/// duration, so [`finish`](Fmp4Writer::finish) must be called to write the last one, followed by
+/// the keyframe index.
+///
+/// ```
+/// use cleverdog::{h264::Frame, mux::fmp4::Fmp4Writer};
+///
+/// let sps = vec![
+///     0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+///     0xca, 0x10,
+/// ];
+/// let mut wr = Fmp4Writer::new(Vec::new());
+/// wr.write_frame(&Frame { timestamp: 0, keyframe: true, nals: vec![sps, vec![0x68, 0xce], vec![0x65, 1]] })?;
+/// wr.write_frame(&Frame { timestamp: 3600, keyframe: false, nals: vec![vec![0x41, 2]] })?;
+///
+/// let buf = wr.finish()?;
+/// assert_eq!(b"ftyp", &buf[4..8]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct Fmp4Writer<W> {
+    wr: W,
+    params: ParameterSets,
+    started: bool,
+    sequence: u32,
+    timeline: Timeline,
+    /// Frame waiting for its duration, with its decode time.
+    pending: Option<(Frame, u64)>,
+    /// Number of bytes written so far.
+    offset: u64,
+    /// Decode time and fragment offset of each keyframe written.
+    keyframes: Vec<(u64, u64)>,
+}
+
+impl<W: Write> Fmp4Writer<W> {
+    /// Constructs a new writer into the given writer.
+    pub fn new(wr: W) -> Self {
+        Self {
+            wr,
+            params: ParameterSets::default(),
+            started: false,
+            sequence: 0,
+            timeline: Timeline::default(),
+            pending: None,
+            offset: 0,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Returns whether the init segment has been written.
+    #[inline]
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Writes the given frame, preceded by the init segment if it is the first keyframe.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), io::Error> {
+        self.params.update(frame);
+
+        if !self.started {
+            let (sps, pps) = match (frame.keyframe, self.params.get()) {
+                (true, Some(params)) => params,
+                _ => return Ok(()),
+            };
+            let buf = init_segment(sps, pps)?;
+            self.wr.write_all(&buf)?;
+            self.offset += buf.len() as u64;
+            self.started = true;
+        }
+
+        let time = self.timeline.push(frame.timestamp);
+        if let Some((prev, prev_time)) = self.pending.replace((frame.clone(), time)) {
+            self.write_fragment(&prev, prev_time, (time - prev_time) as u32)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the last frame and the keyframe index, returning the inner writer.
+    pub fn finish(mut self) -> Result<W, io::Error> {
+        if let Some((frame, time)) = self.pending.take() {
+            self.write_fragment(&frame, time, self.timeline.last_duration())?;
+        }
+        if self.started {
+            self.wr.write_all(&index(&self.keyframes))?;
+        }
+        self.wr.flush()?;
+        Ok(self.wr)
+    }
+
+    fn write_fragment(&mut self, frame: &Frame, time: u64, duration: u32) -> Result<(), io::Error> {
+        if frame.keyframe {
+            self.keyframes.push((time, self.offset));
+        }
+
+        self.sequence += 1;
+        let buf = fragment(self.sequence, time, duration, frame);
+        self.wr.write_all(&buf)?;
+        self.offset += buf.len() as u64;
+
+        Ok(())
+    }
+}
+
+/// Encodes the init segment of a video track with the given parameter sets.
+pub fn init_segment(sps: &[u8], pps: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let (info, avcc) = avc_config(sps, pps)?;
+
+    let mut ftyp = Vec::new();
+    ftyp.extend_from_slice(b"iso5");
+    ftyp.extend_from_slice(&0x200u32.to_be_bytes());
+    ftyp.extend_from_slice(b"iso5iso6avc1mp41");
+
+    let mut mvhd = full(0, 0);
+    put(&mut mvhd, &[0, 0, 1000, 0]);
+    put(&mut mvhd, &[0x0001_0000]);
+    mvhd.extend_from_slice(&[0x01, 0x00, 0, 0]);
+    put(&mut mvhd, &[0, 0]);
+    put(&mut mvhd, &MATRIX);
+    put(&mut mvhd, &[0; 6]);
+    put(&mut mvhd, &[TRACK_ID + 1]);
+
+    let mut tkhd = full(0, 0x03);
+    put(&mut tkhd, &[0, 0, TRACK_ID, 0, 0, 0, 0, 0, 0]);
+    put(&mut tkhd, &MATRIX);
+    put(&mut tkhd, &[info.width << 16, info.height << 16]);
+
+    let mut mdhd = full(0, 0);
+    put(&mut mdhd, &[0, 0, TIMESCALE, 0]);
+    // Undetermined language, packed.
+    mdhd.extend_from_slice(&[0x55, 0xc4, 0, 0]);
+
+    let mut hdlr = full(0, 0);
+    put(&mut hdlr, &[0]);
+    hdlr.extend_from_slice(b"vide");
+    put(&mut hdlr, &[0, 0, 0]);
+    hdlr.extend_from_slice(b"VideoHandler\0");
+
+    let mut vmhd = full(0, 1);
+    vmhd.extend_from_slice(&[0; 8]);
+
+    let mut dref = full(0, 0);
+    put(&mut dref, &[1]);
+    dref.extend_from_slice(&boxed(b"url ", &full(0, 1)));
+
+    let mut avc1 = vec![0; 6];
+    avc1.extend_from_slice(&1u16.to_be_bytes());
+    avc1.extend_from_slice(&[0; 16]);
+    avc1.extend_from_slice(&(info.width as u16).to_be_bytes());
+    avc1.extend_from_slice(&(info.height as u16).to_be_bytes());
+    put(&mut avc1, &[0x0048_0000, 0x0048_0000, 0]);
+    avc1.extend_from_slice(&1u16.to_be_bytes());
+    avc1.extend_from_slice(&[0; 32]);
+    avc1.extend_from_slice(&[0x00, 0x18, 0xff, 0xff]);
+    avc1.extend_from_slice(&boxed(b"avcC", &avcc));
+
+    let mut stsd = full(0, 0);
+    put(&mut stsd, &[1]);
+    stsd.extend_from_slice(&boxed(b"avc1", &avc1));
+
+    let mut empty = full(0, 0);
+    put(&mut empty, &[0]);
+    let mut stsz = full(0, 0);
+    put(&mut stsz, &[0, 0]);
+
+    let stbl = [
+        boxed(b"stsd", &stsd),
+        boxed(b"stts", &empty),
+        boxed(b"stsc", &empty),
+        boxed(b"stsz", &stsz),
+        boxed(b"stco", &empty),
+    ]
+    .concat();
+    let minf = [
+        boxed(b"vmhd", &vmhd),
+        boxed(b"dinf", &boxed(b"dref", &dref)),
+        boxed(b"stbl", &stbl),
+    ]
+    .concat();
+    let mdia = [boxed(b"mdhd", &mdhd), boxed(b"hdlr", &hdlr), boxed(b"minf", &minf)].concat();
+    let trak = [boxed(b"tkhd", &tkhd), boxed(b"mdia", &mdia)].concat();
+
+    let mut trex = full(0, 0);
+    put(&mut trex, &[TRACK_ID, 1, 0, 0, 0]);
+
+    let moov = [
+        boxed(b"mvhd", &mvhd),
+        boxed(b"trak", &trak),
+        boxed(b"mvex", &boxed(b"trex", &trex)),
+    ]
+    .concat();
+
+    Ok([boxed(b"ftyp", &ftyp), boxed(b"moov", &moov)].concat())
+}
+
+/// Encodes a fragment holding the given frame as a single sample.
+pub fn fragment(sequence: u32, time: u64, duration: u32, frame: &Frame) -> Vec<u8> {
+    let mdat = sample_data(frame);
+
+    let mut mfhd = full(0, 0);
+    put(&mut mfhd, &[sequence]);
+
+    // Sample data directly follows the fragment, which the data offset is relative to.
+    let mut tfhd = full(0, 0x02_0000);
+    put(&mut tfhd, &[TRACK_ID]);
+
+    let mut tfdt = full(1, 0);
+    tfdt.extend_from_slice(&time.to_be_bytes());
+
+    let flags = match frame.keyframe {
+        true => SYNC_SAMPLE,
+        false => NON_SYNC_SAMPLE,
+    };
+    let mut trun = full(0, 0x000701);
+    // Sample count and the data offset, patched below once the fragment size is known.
+    put(&mut trun, &[1, 0, duration, mdat.len() as u32, flags]);
+
+    let traf = [boxed(b"tfhd", &tfhd), boxed(b"tfdt", &tfdt), boxed(b"trun", &trun)].concat();
+    let mut moof = boxed(b"moof", &[boxed(b"mfhd", &mfhd), boxed(b"traf", &traf)].concat());
+
+    // The data offset is the last field but three of the fragment.
+    let offset = moof.len() - 12;
+    let data_offset = (moof.len() + 8) as u32;
+    moof[offset - 4..offset].copy_from_slice(&data_offset.to_be_bytes());
+
+    moof.extend_from_slice(&boxed(b"mdat", &mdat));
+    moof
+}
+
+/// Encodes the movie fragment random access box, indexing keyframes by their decode time and
+/// the offset of the fragment holding them.
+pub fn index(keyframes: &[(u64, u64)]) -> Vec<u8> {
+    let mut tfra = full(1, 0);
+    // Traf, trun and sample numbers are a single byte each.
+    put(&mut tfra, &[TRACK_ID, 0, keyframes.len() as u32]);
+    for &(time, offset) in keyframes {
+        tfra.extend_from_slice(&time.to_be_bytes());
+        tfra.extend_from_slice(&offset.to_be_bytes());
+        tfra.extend_from_slice(&[1, 1, 1]);
+    }
+
+    let tfra = boxed(b"tfra", &tfra);
+    // The offset box closes the index with its total size, so that players find it from the end.
+    let size = 8 + tfra.len() + 16;
+    let mut mfro = full(0, 0);
+    put(&mut mfro, &[size as u32]);
+
+    boxed(b"mfra", &[tfra, boxed(b"mfro", &mfro)].concat())
+}
+
+/// Outcome of [`repair`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repaired {
+    /// Final path of the repaired recording.
+    pub path: PathBuf,
+    /// Number of complete fragments kept.
+    pub fragments: u64,
+    /// Number of keyframes in the rebuilt index.
+    pub keyframes: u64,
+    /// Number of trailing bytes cut off, i.e. an incomplete fragment and the previous index.
+    pub truncated: u64,
+}
+
+/// Finalizes a fragmented MP4 recording left behind by a crash.
+///
+/// The file is truncated to the last complete `moof`/`mdat` pair, after which the keyframe index
+/// is rebuilt from the sync samples of the fragments kept. Partial `*.part` recordings are then
+/// renamed to their final path; other files are repaired in place.
+///
+/// Files without a complete init segment are refused untouched, since nothing in them is
+/// playable.
+pub fn repair<P: AsRef<Path>>(path: P) -> Result<Repaired, io::Error> {
+    let path = path.as_ref();
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut offset = 0;
+    for kind in &[b"ftyp", b"moov"] {
+        match read_header(&mut file, offset, len)? {
+            Some((v, size, ..)) if &v == *kind => offset += size,
+            _ => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "not a fragmented MP4 file with a complete init segment",
+                ))
+            }
+        }
+    }
+
+    let mut fragments = 0;
+    let mut keyframes = Vec::new();
+    loop {
+        let (moof, header) = match read_header(&mut file, offset, len)? {
+            Some((kind, size, header)) if &kind == b"moof" => (size, header),
+            _ => break,
+        };
+        match read_header(&mut file, offset + moof, len)? {
+            Some((kind, size, ..)) if &kind == b"mdat" => {
+                let mut body = vec![0; (moof - header) as usize];
+                file.seek(SeekFrom::Start(offset + header))?;
+                file.read_exact(&mut body)?;
+                if let Some(time) = keyframe_time(&body) {
+                    keyframes.push((time, offset));
+                }
+
+                fragments += 1;
+                offset += moof + size;
+            }
+            _ => break,
+        }
+    }
+
+    let buf = index(&keyframes);
+    file.set_len(offset)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&buf)?;
+    file.sync_all()?;
+
+    Ok(Repaired {
+        path: finalize(path)?,
+        fragments,
+        keyframes: keyframes.len() as u64,
+        truncated: len - offset,
+    })
+}
+
+/// Reads the header of the box at the given offset, returning its type, total size and header
+/// size, or `None` if the box does not fit into the file of the given length.
+fn read_header<F: Read + Seek>(file: &mut F, offset: u64, len: u64) -> Result<Option<([u8; 4], u64, u64)>, io::Error> {
+    if offset + 8 > len {
+        return Ok(None);
+    }
+
+    let mut buf = [0; 8];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buf)?;
+    let kind = [buf[4], buf[5], buf[6], buf[7]];
+
+    let (size, header) = match u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) {
+        // Boxes extending to the end of the file are never known to be complete.
+        0 => return Ok(None),
+        1 if offset + 16 <= len => {
+            file.read_exact(&mut buf)?;
+            (u64::from_be_bytes(buf), 16)
+        }
+        1 => return Ok(None),
+        size => (u64::from(size), 8),
+    };
+
+    match size >= header && offset + size <= len {
+        true => Ok(Some((kind, size, header))),
+        false => Ok(None),
+    }
+}
+
+/// Returns the decode time of the fragment with the given `moof` body if its first sample is a
+/// sync sample.
+fn keyframe_time(moof: &[u8]) -> Option<u64> {
+    let traf = child(moof, b"traf")?;
+
+    let tfhd = child(traf, b"tfhd")?;
+    let flags = match child(traf, b"trun").and_then(first_sample_flags) {
+        Some(flags) => flags,
+        None => default_sample_flags(tfhd)?,
+    };
+    if flags & NON_SYNC_FLAG != 0 {
+        return None;
+    }
+
+    let tfdt = child(traf, b"tfdt")?;
+    match tfdt.first()? {
+        1 => tfdt
+            .get(4..12)
+            .map(|v| u64::from_be_bytes([v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]])),
+        _ => be32(tfdt, 4).map(u64::from),
+    }
+}
+
+/// Returns flags of the first sample of the given `trun` body, if it carries them.
+fn first_sample_flags(trun: &[u8]) -> Option<u32> {
+    let flags = be32(trun, 0)? & 0x00ff_ffff;
+    // Skip the sample count and the data offset.
+    let mut pos = 8 + if flags & 0x01 != 0 { 4 } else { 0 };
+
+    if flags & 0x04 != 0 {
+        return be32(trun, pos);
+    }
+    if flags & 0x400 == 0 {
+        return None;
+    }
+    for bit in &[0x100, 0x200] {
+        if flags & bit != 0 {
+            pos += 4;
+        }
+    }
+
+    be32(trun, pos)
+}
+
+/// Returns the default sample flags of the given `tfhd` body, if it carries them.
+fn default_sample_flags(tfhd: &[u8]) -> Option<u32> {
+    let flags = be32(tfhd, 0)? & 0x00ff_ffff;
+    if flags & 0x20 == 0 {
+        return None;
+    }
+
+    // Skip the track ID and the optional fields preceding the flags.
+    let mut pos = 8;
+    for &(bit, size) in &[(0x01, 8), (0x02, 4), (0x08, 4), (0x10, 4)] {
+        if flags & bit != 0 {
+            pos += size;
+        }
+    }
+
+    be32(tfhd, pos)
+}
+
+/// Returns the body of the first child box of the given type.
+fn child<'a>(mut buf: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    while buf.len() >= 8 {
+        let size = be32(buf, 0)? as usize;
+        if size < 8 || size > buf.len() {
+            return None;
+        }
+        if &buf[4..8] == kind {
+            return Some(&buf[8..size]);
+        }
+        buf = &buf[size..];
+    }
+
+    None
+}
+
+#[inline]
+fn be32(buf: &[u8], pos: usize) -> Option<u32> {
+    buf.get(pos..pos + 4)
+        .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]))
+}
+
+/// Encodes a box of the given type and body.
+fn boxed(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(8 + body.len());
+    buf.extend_from_slice(&(8 + body.len() as u32).to_be_bytes());
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// Starts the body of a full box, with the given version and flags.
+fn full(version: u8, flags: u32) -> Vec<u8> {
+    let mut buf = flags.to_be_bytes();
+    buf[0] = version;
+    buf.to_vec()
+}
+
+/// Appends the given 32-bit fields.
+fn put(buf: &mut Vec<u8>, fields: &[u32]) {
+    for v in fields {
+        buf.extend_from_slice(&v.to_be_bytes());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+
+    use super::*;
+
+    const SPS: [u8; 20] = [
+        0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+        0xca, 0x10,
+    ];
+
+    /// Returns types and bodies of top-level boxes in the given buffer.
+    fn boxes(mut buf: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = Vec::new();
+        while !buf.is_empty() {
+            let size = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+            boxes.push((&buf[4..8], &buf[8..size]));
+            buf = &buf[size..];
+        }
+        boxes
+    }
+
+    /// Returns offsets of top-level boxes of the given type.
+    fn offsets(buf: &[u8], kind: &[u8]) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        let mut offset = 0;
+        for (v, body) in boxes(buf) {
+            if v == kind {
+                offsets.push(offset);
+            }
+            offset += 8 + body.len() as u64;
+        }
+        offsets
+    }
+
+    /// Returns a recording of two GOPs, two frames each.
+    fn recording() -> Vec<u8> {
+        let mut wr = Fmp4Writer::new(Vec::new());
+        for idx in 0..4u8 {
+            let nals = match idx {
+                0 => vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, idx]],
+                2 => vec![vec![0x65, idx]],
+                _ => vec![vec![0x41, idx]],
+            };
+            let frame = Frame {
+                timestamp: u32::from(idx) * 3000,
+                keyframe: idx % 2 == 0,
+                nals,
+            };
+            wr.write_frame(&frame).unwrap();
+        }
+        wr.finish().unwrap()
+    }
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cleverdog-fmp4-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns the body of the box at the given path.
+    fn find<'a>(buf: &'a [u8], path: &[&[u8]]) -> &'a [u8] {
+        path.iter().fold(buf, |buf, kind| {
+            boxes(buf)
+                .into_iter()
+                .find(|(v, _)| v == kind)
+                .map(|(_, body)| body)
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn test_init_segment() {
+        let buf = init_segment(&SPS, &[0x68, 0xce]).unwrap();
+
+        let kinds: Vec<_> = boxes(&buf).into_iter().map(|(v, _)| v).collect();
+        assert_eq!(vec![&b"ftyp"[..], b"moov"], kinds);
+
+        let tkhd = find(&buf, &[b"moov", b"trak", b"tkhd"]);
+        assert_eq!(&[0x02, 0x80, 0, 0, 0x01, 0xe0, 0, 0], &tkhd[tkhd.len() - 8..]);
+
+        let mdhd = find(&buf, &[b"moov", b"trak", b"mdia", b"mdhd"]);
+        assert_eq!(TIMESCALE.to_be_bytes(), mdhd[12..16]);
+
+        let stsd = find(&buf, &[b"moov", b"trak", b"mdia", b"minf", b"stbl", b"stsd"]);
+        let avc1 = find(&stsd[8..], &[b"avc1"]);
+        let avcc = find(&avc1[78..], &[b"avcC"]);
+        assert_eq!(&[1, 0x42, 0xc0, 0x1e, 0xff, 0xe1, 0, 20], &avcc[..8]);
+        assert_eq!(&[1, 0, 2, 0x68, 0xce], &avcc[28..]);
+    }
+
+    #[test]
+    fn test_fragments() {
+        let mut wr = Fmp4Writer::new(Vec::new());
+        // Dropped, preceding the keyframe.
+        let frames = [
+            Frame {
+                timestamp: 100,
+                keyframe: false,
+                nals: vec![vec![0x41, 0]],
+            },
+            Frame {
+                timestamp: 1000,
+                keyframe: true,
+                nals: vec![vec![0x09, 0xf0], SPS.to_vec(), vec![0x68, 0xce], vec![0x65, 1, 2]],
+            },
+            Frame {
+                timestamp: 4000,
+                keyframe: false,
+                nals: vec![vec![0x41, 3]],
+            },
+        ];
+        for frame in &frames {
+            wr.write_frame(frame).unwrap();
+        }
+        let buf = wr.finish().unwrap();
+
+        let kinds: Vec<_> = boxes(&buf).into_iter().map(|(v, _)| v).collect();
+        assert_eq!(
+            vec![&b"ftyp"[..], b"moov", b"moof", b"mdat", b"moof", b"mdat", b"mfra"],
+            kinds
+        );
+
+        let fragments: Vec<_> = boxes(&buf).into_iter().filter(|(v, _)| v == b"moof").collect();
+        let tfdt = find(fragments[1].1, &[b"traf", b"tfdt"]);
+        assert_eq!(3000u64.to_be_bytes(), tfdt[4..]);
+
+        // Duration, size and flags of the keyframe, with the data offset pointing into mdat.
+        let moof = fragments[0].1;
+        let trun = find(moof, &[b"traf", b"trun"]);
+        assert_eq!(
+            [[3000u32.to_be_bytes(), 7u32.to_be_bytes(), SYNC_SAMPLE.to_be_bytes()].concat()],
+            [&trun[12..]]
+        );
+        let data_offset = u32::from_be_bytes([trun[8], trun[9], trun[10], trun[11]]) as usize;
+        let start = buf.windows(4).position(|v| v == b"moof").unwrap() - 4;
+        assert_eq!(
+            &[0, 0, 0, 3, 0x65, 1, 2],
+            &buf[start + data_offset..start + data_offset + 7]
+        );
+
+        // The last frame gets the duration of the previous one.
+        let trun = find(fragments[1].1, &[b"traf", b"trun"]);
+        assert_eq!(3000u32.to_be_bytes(), trun[12..16]);
+
+        // The index points at the keyframe fragment, and its size closes it.
+        let start = start as u64;
+        let tfra = find(&buf, &[b"mfra", b"tfra"]);
+        assert_eq!(
+            [&[0, 0, 0, 1][..], &[0; 8], &start.to_be_bytes(), &[1, 1, 1]].concat(),
+            &tfra[12..]
+        );
+        let mfra = buf.len() - find(&buf, &[b"mfra"]).len() - 8;
+        assert_eq!(&(buf.len() - mfra).to_be_bytes()[4..], &buf[buf.len() - 4..]);
+    }
+
+    #[test]
+    fn test_repair() {
+        let dir = tempdir("repair");
+        let clean = recording();
+        let moofs = offsets(&clean, b"moof");
+        let mfra = offsets(&clean, b"mfra")[0] as usize;
+        let expected = index(&[(0, moofs[0]), (6000, moofs[2])]);
+        assert_eq!(&expected[..], &clean[mfra..]);
+
+        // Cut off in the middle of the last sample.
+        let path = dir.join("rec.mp4.part");
+        let cut = mfra - 2;
+        fs::write(&path, &clean[..cut]).unwrap();
+
+        let repaired = repair(&path).unwrap();
+
+        assert_eq!(
+            Repaired {
+                path: dir.join("rec.mp4"),
+                fragments: 3,
+                keyframes: 2,
+                truncated: cut as u64 - moofs[3],
+            },
+            repaired
+        );
+        assert!(!path.exists());
+        let buf = fs::read(dir.join("rec.mp4")).unwrap();
+        let end = moofs[3] as usize;
+        assert_eq!(&clean[..end], &buf[..end]);
+        assert_eq!(&expected[..], &buf[end..]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair_finished() {
+        let dir = tempdir("finished");
+        let clean = recording();
+        let path = dir.join("rec.mp4");
+        fs::write(&path, &clean).unwrap();
+
+        let repaired = repair(&path).unwrap();
+
+        assert_eq!(4, repaired.fragments);
+        assert_eq!(path, repaired.path);
+        assert_eq!(clean, fs::read(&path).unwrap());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair_without_init_segment() {
+        let dir = tempdir("init");
+        let clean = recording();
+        let path = dir.join("rec.mp4.part");
+        let cut = offsets(&clean, b"moov")[0] as usize + 16;
+        fs::write(&path, &clean[..cut]).unwrap();
+
+        assert!(repair(&path).is_err());
+        assert_eq!(&clean[..cut], &fs::read(&path).unwrap()[..]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,376 @@
+//! HTTP Live Streaming, playable in any browser via hls.js and natively in Safari.
+//!
+//! Segments and the rolling `playlist.m3u8` are written into a directory, which any static HTTP
+//! server can serve as is. Files are written under temporary names and renamed once complete,
+//! so the server never exposes a partial segment or playlist.
+
+use core::time::Duration;
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{fmp4, mpegts::TsWriter, ParameterSets, Timeline, TIMESCALE};
+use crate::h264::Frame;
+
+/// Name of the playlist in the output directory.
+pub const PLAYLIST: &str = "playlist.m3u8";
+
+/// Name of the fMP4 init segment in the output directory.
+const INIT_SEGMENT: &str = "init.mp4";
+
+/// Default minimum duration of a segment.
+const TARGET_DURATION: Duration = Duration::from_secs(2);
+
+/// Default number of segments listed in the playlist.
+const WINDOW: usize = 6;
+
+/// Container of HLS segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SegmentFormat {
+    /// MPEG transport stream, supported by every HLS player.
+    Ts,
+    /// Fragmented MP4, sharing an init segment.
+    Fmp4,
+}
+
+impl SegmentFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            SegmentFormat::Ts => "ts",
+            SegmentFormat::Fmp4 => "m4s",
+        }
+    }
+}
+
+/// Segment listed in the playlist.
+#[derive(Debug)]
+struct Segment {
+    sequence: u64,
+    duration: Duration,
+}
+
+/// Segment being written.
+#[derive(Debug)]
+struct Current {
+    sequence: u64,
+    /// Time of the first frame, in RTP clock units.
+    start: u64,
+    buf: Vec<u8>,
+}
+
+/// Writes frames as HLS segments with a rolling playlist into a directory.
+///
+/// Each segment starts with a keyframe, once the previous one has lasted at least the target
+/// duration. Segments falling out of the playlist window are removed.
+///
+/// ```no_run
+/// use core::time::Duration;
+///
+/// use cleverdog::{
+///     h264::FrameAssembler,
+///     mux::hls::{HlsWriter, SegmentFormat},
+/// };
+///
+/// let mut hls = HlsWriter::new("/var/www/camera")
+///     .format(SegmentFormat::Fmp4)
+///     .target_duration(Duration::from_secs(4));
+/// let mut assembler = FrameAssembler::new();
+///
+/// let info = cleverdog::lookup()?;
+/// cleverdog::stream(info.cid(), info.addr(), |packet| {
+///     for frame in assembler.push(packet)? {
+///         hls.write_frame(&frame)?;
+///     }
+///     Ok(())
+/// })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct HlsWriter {
+    dir: PathBuf,
+    format: SegmentFormat,
+    target_duration: Duration,
+    window: usize,
+    params: ParameterSets,
+    timeline: Timeline,
+    /// Time of the last frame, in RTP clock units.
+    last: u64,
+    ts: TsWriter<Vec<u8>>,
+    /// Sequence number of the last fMP4 fragment.
+    fragments: u32,
+    /// fMP4 frame waiting for its duration, with its time.
+    pending: Option<(Frame, u64)>,
+    current: Option<Current>,
+    segments: VecDeque<Segment>,
+    next_sequence: u64,
+}
+
+impl HlsWriter {
+    /// Constructs a new writer into the given directory, writing transport stream segments of
+    /// at least 2 seconds and listing 6 most recent ones.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().into(),
+            format: SegmentFormat::Ts,
+            target_duration: TARGET_DURATION,
+            window: WINDOW,
+            params: ParameterSets::default(),
+            timeline: Timeline::default(),
+            last: 0,
+            ts: TsWriter::new(Vec::new()),
+            fragments: 0,
+            pending: None,
+            current: None,
+            segments: VecDeque::new(),
+            next_sequence: 0,
+        }
+    }
+
+    /// Sets the container of segments.
+    pub fn format(mut self, format: SegmentFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Sets the minimum duration of a segment, which is exceeded up to the next keyframe.
+    pub fn target_duration(mut self, duration: Duration) -> Self {
+        self.target_duration = duration;
+        self
+    }
+
+    /// Sets the number of segments listed in the playlist.
+    pub fn window(mut self, window: usize) -> Self {
+        self.window = window.max(1);
+        self
+    }
+
+    /// Writes the given frame, completing a segment if a new one starts with it.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), io::Error> {
+        self.params.update(frame);
+
+        if self.current.is_none() {
+            let (sps, pps) = match (frame.keyframe, self.params.get()) {
+                (true, Some(params)) => params,
+                _ => return Ok(()),
+            };
+            if self.format == SegmentFormat::Fmp4 {
+                write_atomic(&self.dir.join(INIT_SEGMENT), &fmp4::init_segment(sps, pps)?)?;
+            }
+        }
+
+        let time = self.timeline.push(frame.timestamp);
+        self.last = time;
+        if let Some((prev, prev_time)) = self.pending.take() {
+            self.write_fragment(&prev, prev_time, (time - prev_time) as u32);
+        }
+
+        let elapsed = self.current.as_ref().map(|v| ticks_duration(time - v.start));
+        match elapsed {
+            Some(elapsed) if !frame.keyframe || elapsed < self.target_duration => {}
+            _ => {
+                self.close(time)?;
+                self.current = Some(Current {
+                    sequence: self.next_sequence,
+                    start: time,
+                    buf: Vec::new(),
+                });
+                self.next_sequence += 1;
+            }
+        }
+
+        match self.format {
+            SegmentFormat::Ts => {
+                self.ts.write_frame(frame)?;
+                let buf = self.ts.get_mut();
+                if let Some(current) = &mut self.current {
+                    current.buf.append(buf);
+                }
+            }
+            SegmentFormat::Fmp4 => self.pending = Some((frame.clone(), time)),
+        }
+
+        Ok(())
+    }
+
+    /// Completes the last segment and marks the playlist as ended.
+    pub fn finish(mut self) -> Result<(), io::Error> {
+        let duration = self.timeline.last_duration();
+        if let Some((frame, time)) = self.pending.take() {
+            self.write_fragment(&frame, time, duration);
+        }
+        self.close(self.last + u64::from(duration))?;
+        self.write_playlist(true)
+    }
+
+    fn write_fragment(&mut self, frame: &Frame, time: u64, duration: u32) {
+        self.fragments += 1;
+        if let Some(current) = &mut self.current {
+            current
+                .buf
+                .extend(fmp4::fragment(self.fragments, time, duration, frame));
+        }
+    }
+
+    /// Writes the current segment, ending at the given time, and updates the playlist.
+    fn close(&mut self, end: u64) -> Result<(), io::Error> {
+        let current = match self.current.take() {
+            Some(current) => current,
+            None => return Ok(()),
+        };
+
+        write_atomic(&self.segment_path(current.sequence), &current.buf)?;
+        self.segments.push_back(Segment {
+            sequence: current.sequence,
+            duration: ticks_duration(end - current.start),
+        });
+        while self.segments.len() > self.window {
+            let expired = match self.segments.pop_front() {
+                Some(segment) => segment.sequence,
+                None => break,
+            };
+            // Players may still be downloading a segment that has just left the playlist, so
+            // files are only removed once they are another window behind.
+            if let Some(sequence) = expired.checked_sub(self.window as u64) {
+                let _ = fs::remove_file(self.segment_path(sequence));
+            }
+        }
+
+        self.write_playlist(false)
+    }
+
+    fn segment_path(&self, sequence: u64) -> PathBuf {
+        self.dir
+            .join(format!("segment{}.{}", sequence, self.format.extension()))
+    }
+
+    fn write_playlist(&self, ended: bool) -> Result<(), io::Error> {
+        let target = self
+            .segments
+            .iter()
+            .map(|v| v.duration)
+            .max()
+            .unwrap_or(self.target_duration);
+
+        let mut buf = Vec::new();
+        writeln!(buf, "#EXTM3U")?;
+        match self.format {
+            SegmentFormat::Ts => writeln!(buf, "#EXT-X-VERSION:3")?,
+            SegmentFormat::Fmp4 => writeln!(buf, "#EXT-X-VERSION:7")?,
+        }
+        writeln!(buf, "#EXT-X-TARGETDURATION:{}", target.as_secs_f64().ceil() as u64)?;
+        writeln!(
+            buf,
+            "#EXT-X-MEDIA-SEQUENCE:{}",
+            self.segments.front().map(|v| v.sequence).unwrap_or(0)
+        )?;
+        if self.format == SegmentFormat::Fmp4 {
+            writeln!(buf, "#EXT-X-MAP:URI=\"{}\"", INIT_SEGMENT)?;
+        }
+        for segment in &self.segments {
+            writeln!(buf, "#EXTINF:{:.3},", segment.duration.as_secs_f64())?;
+            writeln!(buf, "segment{}.{}", segment.sequence, self.format.extension())?;
+        }
+        if ended {
+            writeln!(buf, "#EXT-X-ENDLIST")?;
+        }
+
+        write_atomic(&self.dir.join(PLAYLIST), &buf)
+    }
+}
+
+/// Converts the given number of RTP clock units into a duration.
+fn ticks_duration(ticks: u64) -> Duration {
+    Duration::from_micros(ticks * 1_000_000 / u64::from(TIMESCALE))
+}
+
+/// Writes the file under a temporary name first, renaming it once complete.
+fn write_atomic(path: &Path, buf: &[u8]) -> Result<(), io::Error> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, buf)?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    const SPS: [u8; 20] = [
+        0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+        0xca, 0x10,
+    ];
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cleverdog-hls-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes 10 seconds of 1 fps video with a keyframe each 3 seconds.
+    fn write(hls: &mut HlsWriter) {
+        for idx in 0..10u32 {
+            let keyframe = idx % 3 == 0;
+            let nals = match keyframe {
+                true => vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, idx as u8]],
+                false => vec![vec![0x41, idx as u8]],
+            };
+            hls.write_frame(&Frame {
+                timestamp: idx * TIMESCALE,
+                keyframe,
+                nals,
+            })
+            .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_rolling_playlist() {
+        let dir = tempdir("ts");
+        let mut hls = HlsWriter::new(&dir).target_duration(Duration::from_secs(2)).window(1);
+        write(&mut hls);
+
+        // Segments starting at 0, 3 and 6 seconds are complete, with the second one out of the
+        // window but still kept and the first one removed.
+        assert_eq!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:3\n#EXT-X-MEDIA-SEQUENCE:2\n\
+             #EXTINF:3.000,\nsegment2.ts\n",
+            fs::read_to_string(dir.join(PLAYLIST)).unwrap()
+        );
+        assert!(!dir.join("segment0.ts").exists());
+        assert!(dir.join("segment1.ts").exists());
+
+        hls.finish().unwrap();
+        let playlist = fs::read_to_string(dir.join(PLAYLIST)).unwrap();
+        assert!(playlist.ends_with("#EXT-X-MEDIA-SEQUENCE:3\n#EXTINF:1.000,\nsegment3.ts\n#EXT-X-ENDLIST\n"));
+        assert!(!dir.join("segment1.ts").exists());
+
+        let segment = fs::read(dir.join("segment2.ts")).unwrap();
+        assert_eq!(0, segment.len() % 188);
+        // Each segment starts with PAT.
+        assert_eq!(&[0x47, 0x40, 0x00], &segment[..3]);
+    }
+
+    #[test]
+    fn test_fmp4() {
+        let dir = tempdir("fmp4");
+        let mut hls = HlsWriter::new(&dir).format(SegmentFormat::Fmp4);
+        write(&mut hls);
+        hls.finish().unwrap();
+
+        let playlist = fs::read_to_string(dir.join(PLAYLIST)).unwrap();
+        assert!(playlist.contains("#EXT-X-VERSION:7\n"));
+        assert!(playlist.contains("#EXT-X-MAP:URI=\"init.mp4\"\n#EXTINF:3.000,\nsegment0.m4s\n"));
+        assert_eq!(&b"ftyp"[..], &fs::read(dir.join("init.mp4")).unwrap()[4..8]);
+
+        // Three fragments of frames at 3, 4 and 5 seconds.
+        let segment = fs::read(dir.join("segment1.m4s")).unwrap();
+        assert_eq!(3, segment.windows(4).filter(|v| v == b"moof").count());
+        assert_eq!(&b"moof"[..], &segment[4..8]);
+    }
+}
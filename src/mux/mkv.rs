@@ -0,0 +1,285 @@
+//! Matroska, which stays playable when a recording is cut short.
+//!
+//! The segment and its clusters are written with unknown sizes, so nothing written earlier has
+//! to be patched later: a file truncated by a camera reboot or a power loss is readable up to
+//! its last complete block, without any repair step.
+
+use std::io::{self, Write};
+
+use super::{avc_config, sample_data, ParameterSets, Timeline, TIMESCALE};
+use crate::h264::Frame;
+
+const EBML: u32 = 0x1a45_dfa3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42f7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42f2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42f3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_a966;
+const TIMESTAMP_SCALE: u32 = 0x2a_d7b1;
+const MUXING_APP: u32 = 0x4d80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_UID: u32 = 0x73c5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const CLUSTER: u32 = 0x1f43_b675;
+const TIMESTAMP: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+
+/// Reserved size value meaning the element extends until an element not fitting into it.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+
+/// Longest cluster in milliseconds, well within the 16-bit relative timestamps of blocks.
+const MAX_CLUSTER_DURATION: u64 = 30_000;
+
+/// Writes frames as Matroska.
+///
+/// Frames preceding the first keyframe with known parameter sets are dropped, since the track
+/// header needs them. Each keyframe starts a new cluster, so players can seek to it. Block
+/// timestamps are in milliseconds, derived from RTP timestamps of the frames.
+///
+/// ```
+/// use cleverdog::{h264::Frame, mux::mkv::MkvWriter};
+///
+/// let sps = vec![
+///     0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+///     0xca, 0x10,
+/// ];
+/// let mut wr = MkvWriter::new(Vec::new());
+/// wr.write_frame(&Frame { timestamp: 0, keyframe: true, nals: vec![sps, vec![0x68, 0xce], vec![0x65, 1]] })?;
+/// wr.write_frame(&Frame { timestamp: 3600, keyframe: false, nals: vec![vec![0x41, 2]] })?;
+///
+/// let buf = wr.into_inner();
+/// assert_eq!(&[0x1a, 0x45, 0xdf, 0xa3], &buf[..4]);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct MkvWriter<W> {
+    wr: W,
+    params: ParameterSets,
+    started: bool,
+    timeline: Timeline,
+    /// Timestamp of the current cluster in milliseconds.
+    cluster: Option<u64>,
+}
+
+impl<W: Write> MkvWriter<W> {
+    /// Constructs a new writer into the given writer.
+    pub fn new(wr: W) -> Self {
+        Self {
+            wr,
+            params: ParameterSets::default(),
+            started: false,
+            timeline: Timeline::default(),
+            cluster: None,
+        }
+    }
+
+    /// Returns whether the header has been written.
+    #[inline]
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Returns the inner writer.
+    ///
+    /// There is nothing to finalize, the recording is complete after each frame.
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.wr
+    }
+
+    /// Writes the given frame, preceded by the header if it is the first keyframe.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), io::Error> {
+        self.params.update(frame);
+
+        if !self.started {
+            let (sps, pps) = match (frame.keyframe, self.params.get()) {
+                (true, Some(params)) => params,
+                _ => return Ok(()),
+            };
+            let buf = header(sps, pps)?;
+            self.wr.write_all(&buf)?;
+            self.started = true;
+        }
+
+        let time = self.timeline.push(frame.timestamp) * 1000 / u64::from(TIMESCALE);
+
+        let mut buf = Vec::new();
+        let cluster = match self.cluster {
+            Some(cluster) if !frame.keyframe && time - cluster < MAX_CLUSTER_DURATION => cluster,
+            _ => {
+                put_id(&mut buf, CLUSTER);
+                buf.extend_from_slice(&UNKNOWN_SIZE);
+                put_uint(&mut buf, TIMESTAMP, time);
+                self.cluster = Some(time);
+                time
+            }
+        };
+
+        let data = sample_data(frame);
+        let mut block = vec![0x80 | 1];
+        block.extend_from_slice(&((time - cluster) as i16).to_be_bytes());
+        block.push(if frame.keyframe { 0x80 } else { 0 });
+        block.extend_from_slice(&data);
+        put_element(&mut buf, SIMPLE_BLOCK, &block);
+
+        self.wr.write_all(&buf)?;
+        self.wr.flush()
+    }
+}
+
+/// Encodes the EBML header and the start of the segment, up to its first cluster.
+fn header(sps: &[u8], pps: &[u8]) -> Result<Vec<u8>, io::Error> {
+    let (info, avcc) = avc_config(sps, pps)?;
+
+    let mut ebml = Vec::new();
+    put_uint(&mut ebml, EBML_VERSION, 1);
+    put_uint(&mut ebml, EBML_READ_VERSION, 1);
+    put_uint(&mut ebml, EBML_MAX_ID_LENGTH, 4);
+    put_uint(&mut ebml, EBML_MAX_SIZE_LENGTH, 8);
+    put_element(&mut ebml, DOC_TYPE, b"matroska");
+    put_uint(&mut ebml, DOC_TYPE_VERSION, 4);
+    put_uint(&mut ebml, DOC_TYPE_READ_VERSION, 2);
+
+    let mut segment_info = Vec::new();
+    put_uint(&mut segment_info, TIMESTAMP_SCALE, 1_000_000);
+    put_element(&mut segment_info, MUXING_APP, b"cleverdog");
+    put_element(&mut segment_info, WRITING_APP, b"cleverdog");
+
+    let mut video = Vec::new();
+    put_uint(&mut video, PIXEL_WIDTH, u64::from(info.width));
+    put_uint(&mut video, PIXEL_HEIGHT, u64::from(info.height));
+
+    let mut track = Vec::new();
+    put_uint(&mut track, TRACK_NUMBER, 1);
+    put_uint(&mut track, TRACK_UID, 1);
+    put_uint(&mut track, TRACK_TYPE, 1);
+    put_element(&mut track, CODEC_ID, b"V_MPEG4/ISO/AVC");
+    put_element(&mut track, CODEC_PRIVATE, &avcc);
+    put_element(&mut track, VIDEO, &video);
+
+    let mut tracks = Vec::new();
+    put_element(&mut tracks, TRACK_ENTRY, &track);
+
+    let mut buf = Vec::new();
+    put_element(&mut buf, EBML, &ebml);
+    put_id(&mut buf, SEGMENT);
+    buf.extend_from_slice(&UNKNOWN_SIZE);
+    put_element(&mut buf, INFO, &segment_info);
+    put_element(&mut buf, TRACKS, &tracks);
+
+    Ok(buf)
+}
+
+/// Appends the given element ID, whose encoding includes its length marker.
+fn put_id(buf: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&v| v == 0).count();
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+/// Appends the given element size as a variable-length integer of the shortest length.
+fn put_size(buf: &mut Vec<u8>, size: u64) {
+    // All ones are reserved for the unknown size.
+    let len = (1..8).find(|len| size < (1 << (7 * len)) - 1).unwrap_or(8);
+    let v = size | 1 << (7 * len);
+    buf.extend_from_slice(&v.to_be_bytes()[8 - len..]);
+}
+
+fn put_element(buf: &mut Vec<u8>, id: u32, body: &[u8]) {
+    put_id(buf, id);
+    put_size(buf, body.len() as u64);
+    buf.extend_from_slice(body);
+}
+
+fn put_uint(buf: &mut Vec<u8>, id: u32, v: u64) {
+    let bytes = v.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&v| v == 0).count().min(7);
+    put_element(buf, id, &bytes[skip..]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPS: [u8; 20] = [
+        0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+        0xca, 0x10,
+    ];
+
+    #[test]
+    fn test_put_size() {
+        let encode = |size| {
+            let mut buf = Vec::new();
+            put_size(&mut buf, size);
+            buf
+        };
+
+        assert_eq!(vec![0x80], encode(0));
+        assert_eq!(vec![0xfe], encode(126));
+        // 127 would be all ones, reserved for the unknown size.
+        assert_eq!(vec![0x40, 0x7f], encode(127));
+        assert_eq!(vec![0x20, 0x40, 0x00], encode(0x4000));
+    }
+
+    #[test]
+    fn test_clusters() {
+        let mut wr = MkvWriter::new(Vec::new());
+        let frames = [
+            Frame {
+                timestamp: 0,
+                keyframe: true,
+                nals: vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, 1]],
+            },
+            Frame {
+                timestamp: 9000,
+                keyframe: false,
+                nals: vec![vec![0x41, 2]],
+            },
+            Frame {
+                timestamp: 18000,
+                keyframe: true,
+                nals: vec![vec![0x65, 3]],
+            },
+        ];
+        for frame in &frames {
+            wr.write_frame(frame).unwrap();
+        }
+        let buf = wr.into_inner();
+
+        // Each keyframe starts a cluster of unknown size, with the time in milliseconds.
+        let clusters: Vec<_> = buf
+            .windows(4)
+            .enumerate()
+            .filter(|(_, v)| *v == [0x1f, 0x43, 0xb6, 0x75])
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(2, clusters.len());
+        assert_eq!(&UNKNOWN_SIZE, &buf[clusters[0] + 4..clusters[0] + 12]);
+
+        // Cluster timestamp 0, the keyframe and the frame 100ms later.
+        assert_eq!(
+            &[
+                0xe7, 0x81, 0, 0xa3, 0x8a, 0x81, 0, 0, 0x80, 0, 0, 0, 2, 0x65, 1, 0xa3, 0x8a, 0x81, 0, 100, 0, 0, 0, 0,
+                2, 0x41, 2
+            ],
+            &buf[clusters[0] + 12..clusters[1]]
+        );
+        // Cluster timestamp 200.
+        assert_eq!(
+            &[0xe7, 0x81, 200, 0xa3, 0x8a, 0x81, 0, 0, 0x80, 0, 0, 0, 2, 0x65, 3],
+            &buf[clusters[1] + 12..]
+        );
+    }
+}
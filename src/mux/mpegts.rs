@@ -0,0 +1,358 @@
+//! MPEG transport stream, as consumed by IPTV equipment and `ffmpeg -f mpegts`.
+//!
+//! The stream carries a single program with the video as an H.264 elementary stream. PAT and
+//! PMT are repeated before every keyframe, so receivers can tune in at any of them.
+
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, UdpSocket},
+};
+
+use super::Timeline;
+use crate::h264::{AnnexBWriter, Frame, Nal, NalType};
+
+/// Size of a transport stream packet.
+pub const PACKET_SIZE: usize = 188;
+
+/// Number of packets carried by a single UDP datagram, the usual 1316 bytes.
+pub const PACKETS_PER_DATAGRAM: usize = 7;
+
+const SYNC_BYTE: u8 = 0x47;
+const PAT_PID: u16 = 0;
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x100;
+/// Stream type of H.264 video.
+const STREAM_TYPE_H264: u8 = 0x1b;
+const PROGRAM_NUMBER: u16 = 1;
+const TRANSPORT_STREAM_ID: u16 = 1;
+
+/// Delay of presentation timestamps after the clock reference, giving decoders room to buffer.
+const PTS_DELAY: u64 = 63_000;
+
+/// Access unit delimiter with any primary picture type.
+const ACCESS_UNIT_DELIMITER: [u8; 6] = [0, 0, 0, 1, 0x09, 0xf0];
+
+/// Writes frames as an MPEG transport stream.
+///
+/// Frames preceding the first keyframe are dropped, and the parameter sets are repeated before
+/// each keyframe. Presentation timestamps are the RTP timestamps of the frames, which share the
+/// 90 kHz clock of the transport stream. The writer is flushed after each frame.
+///
+/// ```
+/// use cleverdog::{h264::Frame, mux::mpegts::TsWriter};
+///
+/// let mut wr = TsWriter::new(Vec::new());
+/// let nals = vec![vec![0x67, 0x42, 0xc0, 0x1e], vec![0x68, 0xce], vec![0x65, 1]];
+/// wr.write_frame(&Frame { timestamp: 0, keyframe: true, nals })?;
+///
+/// let buf = wr.into_inner();
+/// // PAT, PMT and the keyframe.
+/// assert_eq!(3 * 188, buf.len());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct TsWriter<W> {
+    wr: W,
+    annexb: AnnexBWriter<Vec<u8>>,
+    timeline: Timeline,
+    pat_cc: u8,
+    pmt_cc: u8,
+    video_cc: u8,
+}
+
+impl<W: Write> TsWriter<W> {
+    /// Constructs a new writer into the given writer.
+    pub fn new(wr: W) -> Self {
+        Self {
+            wr,
+            annexb: AnnexBWriter::new(Vec::new()),
+            timeline: Timeline::default(),
+            pat_cc: 0,
+            pmt_cc: 0,
+            video_cc: 0,
+        }
+    }
+
+    #[inline]
+    pub fn get_ref(&self) -> &W {
+        &self.wr
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.wr
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.wr
+    }
+
+    /// Writes the given frame, preceded by PAT and PMT if it is a keyframe.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), io::Error> {
+        for nal in frame.nals.iter().filter_map(|v| Nal::new(v)) {
+            if nal.nal_type() != NalType::AccessUnitDelimiter {
+                self.annexb.write_nal(&nal)?;
+            }
+        }
+        if self.annexb.get_ref().is_empty() {
+            return Ok(());
+        }
+        let mut es = ACCESS_UNIT_DELIMITER.to_vec();
+        es.append(self.annexb.get_mut());
+
+        let pcr = self.timeline.push(frame.timestamp);
+        let pts = pcr + PTS_DELAY;
+
+        let mut buf = Vec::new();
+        if frame.keyframe {
+            psi(&mut buf, PAT_PID, &mut self.pat_cc, &pat());
+            psi(&mut buf, PMT_PID, &mut self.pmt_cc, &pmt());
+        }
+        let mut pes = pes_header(pts);
+        pes.extend_from_slice(&es);
+        packetize(&mut buf, &mut self.video_cc, &pes, pcr, frame.keyframe);
+
+        self.wr.write_all(&buf)?;
+        self.wr.flush()
+    }
+}
+
+/// Writer sending a transport stream as UDP datagrams of [`PACKETS_PER_DATAGRAM`] packets.
+///
+/// Data is buffered until a datagram is full, and flushing sends whatever is buffered.
+#[derive(Debug)]
+pub struct UdpWriter {
+    sock: UdpSocket,
+    dst: SocketAddr,
+    buf: Vec<u8>,
+}
+
+impl UdpWriter {
+    /// Constructs a new writer sending through the given socket to the specified destination.
+    pub fn new(sock: UdpSocket, dst: SocketAddr) -> Self {
+        Self {
+            sock,
+            dst,
+            buf: Vec::with_capacity(PACKET_SIZE * PACKETS_PER_DATAGRAM),
+        }
+    }
+}
+
+impl Write for UdpWriter {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let size = buf.len().min(PACKET_SIZE * PACKETS_PER_DATAGRAM - self.buf.len());
+        self.buf.extend_from_slice(&buf[..size]);
+        if self.buf.len() == PACKET_SIZE * PACKETS_PER_DATAGRAM {
+            self.flush()?;
+        }
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        if !self.buf.is_empty() {
+            self.sock.send_to(&self.buf, self.dst)?;
+            self.buf.clear();
+        }
+        Ok(())
+    }
+}
+
+/// Encodes the program association table section.
+fn pat() -> Vec<u8> {
+    let mut body = TRANSPORT_STREAM_ID.to_be_bytes().to_vec();
+    // Version 0, current, the only section.
+    body.extend_from_slice(&[0xc1, 0, 0]);
+    body.extend_from_slice(&PROGRAM_NUMBER.to_be_bytes());
+    body.extend_from_slice(&(0xe000 | PMT_PID).to_be_bytes());
+    section(0x00, &body)
+}
+
+/// Encodes the program map table section.
+fn pmt() -> Vec<u8> {
+    let mut body = PROGRAM_NUMBER.to_be_bytes().to_vec();
+    body.extend_from_slice(&[0xc1, 0, 0]);
+    // Clock references are carried by the video, no program descriptors.
+    body.extend_from_slice(&(0xe000 | VIDEO_PID).to_be_bytes());
+    body.extend_from_slice(&[0xf0, 0]);
+    body.push(STREAM_TYPE_H264);
+    body.extend_from_slice(&(0xe000 | VIDEO_PID).to_be_bytes());
+    body.extend_from_slice(&[0xf0, 0]);
+    section(0x02, &body)
+}
+
+/// Encodes a PSI section of the given table with the CRC appended.
+fn section(table_id: u8, body: &[u8]) -> Vec<u8> {
+    let len = body.len() as u16 + 4;
+    let mut buf = vec![table_id];
+    buf.extend_from_slice(&(0xb000 | len).to_be_bytes());
+    buf.extend_from_slice(body);
+    let crc = crc32(&buf);
+    buf.extend_from_slice(&crc.to_be_bytes());
+    buf
+}
+
+/// Appends a packet carrying the given PSI section, which must fit into it.
+fn psi(buf: &mut Vec<u8>, pid: u16, cc: &mut u8, section: &[u8]) {
+    let start = buf.len();
+    buf.extend_from_slice(&[SYNC_BYTE, 0x40 | (pid >> 8) as u8, pid as u8, 0x10 | *cc]);
+    *cc = (*cc + 1) & 0x0f;
+    // Pointer field, the section starts right away.
+    buf.push(0);
+    buf.extend_from_slice(section);
+    buf.resize(start + PACKET_SIZE, 0xff);
+}
+
+/// Encodes the header of a video PES packet with the given presentation timestamp.
+fn pes_header(pts: u64) -> Vec<u8> {
+    // Stream ID of video and an unbounded length, allowed for video only.
+    let mut buf = vec![0, 0, 1, 0xe0, 0, 0, 0x80, 0x80, 5];
+    buf.extend_from_slice(&[
+        0x21 | (pts >> 29 & 0x0e) as u8,
+        (pts >> 22) as u8,
+        (pts >> 14) as u8 | 1,
+        (pts >> 7) as u8,
+        (pts << 1) as u8 | 1,
+    ]);
+    buf
+}
+
+/// Splits the PES packet into transport packets of the video PID, the first one carrying the
+/// clock reference.
+fn packetize(buf: &mut Vec<u8>, cc: &mut u8, mut pes: &[u8], pcr: u64, random_access: bool) {
+    let mut first = true;
+
+    while !pes.is_empty() {
+        let mut adaptation = match first {
+            true => {
+                let mut af = vec![if random_access { 0x50 } else { 0x10 }];
+                // The 33-bit base and a zero extension.
+                af.extend_from_slice(&[
+                    (pcr >> 25) as u8,
+                    (pcr >> 17) as u8,
+                    (pcr >> 9) as u8,
+                    (pcr >> 1) as u8,
+                    (pcr << 7) as u8 | 0x7e,
+                    0,
+                ]);
+                Some(af)
+            }
+            false if pes.len() < PACKET_SIZE - 4 => Some(Vec::new()),
+            false => None,
+        };
+
+        let size = match &mut adaptation {
+            Some(af) => {
+                let available = PACKET_SIZE - 5 - af.len();
+                let size = pes.len().min(available);
+                let mut stuffing = available - size;
+                if stuffing > 0 && af.is_empty() {
+                    // Flags, none set.
+                    af.push(0);
+                    stuffing -= 1;
+                }
+                af.resize(af.len() + stuffing, 0xff);
+                size
+            }
+            None => PACKET_SIZE - 4,
+        };
+
+        let control = if adaptation.is_some() { 0x30 } else { 0x10 };
+        let start = if first { 0x40 } else { 0 };
+        buf.extend_from_slice(&[
+            SYNC_BYTE,
+            start | (VIDEO_PID >> 8) as u8,
+            VIDEO_PID as u8,
+            control | *cc,
+        ]);
+        *cc = (*cc + 1) & 0x0f;
+        if let Some(af) = adaptation {
+            buf.push(af.len() as u8);
+            buf.extend_from_slice(&af);
+        }
+        buf.extend_from_slice(&pes[..size]);
+
+        pes = &pes[size..];
+        first = false;
+    }
+}
+
+/// Computes the CRC-32 of PSI sections, as defined in ISO/IEC 13818-1, annex A.
+fn crc32(buf: &[u8]) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &byte in buf {
+        crc ^= u32::from(byte) << 24;
+        for _ in 0..8 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x04c1_1db7,
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pat() {
+        // As written by ffmpeg with the same PIDs.
+        assert_eq!(
+            vec![0x00, 0xb0, 0x0d, 0x00, 0x01, 0xc1, 0x00, 0x00, 0x00, 0x01, 0xf0, 0x00, 0x2a, 0xb1, 0x04, 0xb2],
+            pat()
+        );
+    }
+
+    #[test]
+    fn test_packets() {
+        let mut wr = TsWriter::new(Vec::new());
+        let frames = [
+            Frame {
+                timestamp: 1000,
+                keyframe: true,
+                nals: vec![vec![0x67, 0x42, 0xc0, 0x1e], vec![0x68, 0xce], vec![0x65; 400]],
+            },
+            Frame {
+                timestamp: 4600,
+                keyframe: false,
+                nals: vec![vec![0x09, 0xf0], vec![0x41; 10]],
+            },
+        ];
+        for frame in &frames {
+            wr.write_frame(frame).unwrap();
+        }
+        let buf = wr.into_inner();
+
+        assert_eq!(0, buf.len() % PACKET_SIZE);
+        let packets: Vec<_> = buf.chunks(PACKET_SIZE).collect();
+        assert!(packets.iter().all(|v| v[0] == SYNC_BYTE));
+
+        let pids: Vec<_> = packets
+            .iter()
+            .map(|v| u16::from_be_bytes([v[1], v[2]]) & 0x1fff)
+            .collect();
+        assert_eq!(vec![PAT_PID, PMT_PID, VIDEO_PID, VIDEO_PID, VIDEO_PID, VIDEO_PID], pids);
+        let counters: Vec<_> = packets[2..].iter().map(|v| v[3] & 0x0f).collect();
+        assert_eq!(vec![0, 1, 2, 3], counters);
+
+        // The keyframe starts with a random access point and the clock reference at zero.
+        assert_eq!(
+            &[0x47, 0x41, 0x00, 0x30, 7, 0x50, 0, 0, 0, 0, 0x7e, 0],
+            &packets[2][..12]
+        );
+        // PES header with the presentation timestamp delayed after the clock reference.
+        assert_eq!(&pes_header(PTS_DELAY)[..], &packets[2][12..26]);
+        assert_eq!(&ACCESS_UNIT_DELIMITER, &packets[2][26..32]);
+
+        // The second frame, 40ms later, with the delimiter not repeated.
+        let packet = packets[5];
+        let payload = &packet[5 + packet[4] as usize..];
+        assert_eq!(&pes_header(PTS_DELAY + 3600)[..], &payload[..14]);
+        assert_eq!(
+            &[&ACCESS_UNIT_DELIMITER[..], &[0, 0, 0, 1], &[0x41; 10]].concat(),
+            &payload[14..]
+        );
+    }
+}
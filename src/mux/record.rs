@@ -0,0 +1,715 @@
+//! Continuous recording into rotated files, i.e. a minimal standalone NVR.
+//!
+//! Files are named after a template with the camera ID and the UTC wall-clock time they start
+//! at, so a day of recordings sorts naturally. Each file starts with a keyframe preceded by the
+//! parameter sets and is playable on its own. Files are written under a temporary `*.part` name
+//! and renamed once complete.
+//!
+//! Recording can also be triggered, e.g. by motion or a button: an idle recorder keeps the last
+//! seconds of frames in a [`PreRecordBuffer`], so the file includes footage from before the
+//! trigger.
+
+use core::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+    time::Duration,
+};
+use std::{
+    collections::VecDeque,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+
+use super::{fmp4::Fmp4Writer, mkv::MkvWriter, ParameterSets, Timeline, TIMESCALE};
+use crate::{
+    h264::{Frame, Nal, NalType},
+    protocol::Cid,
+    rtsp::TemplateParseError,
+    sink::partial_path,
+};
+
+/// Default file name template.
+const TEMPLATE: &str = "{cid}/{date}/{time}";
+
+/// Default maximum duration of a file.
+const MAX_DURATION: Duration = Duration::from_secs(15 * 60);
+
+/// Container of recorded files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    /// Matroska, readable up to the last complete block after a crash.
+    Mkv,
+    /// Fragmented MP4, playable in browsers.
+    Fmp4,
+}
+
+impl Container {
+    fn extension(&self) -> &'static str {
+        match self {
+            Container::Mkv => "mkv",
+            Container::Fmp4 => "mp4",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Var(Var),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Cid,
+    Date,
+    Time,
+}
+
+/// Template of recorded file paths relative to the output directory, without the extension.
+///
+/// Supported placeholders are `{cid}`, `{date}` as `YYYY-MM-DD` and `{time}` as `HH-MM-SS`, both
+/// in UTC. Slashes separate directories, which are created as needed.
+///
+/// ```
+/// use std::time::{Duration, UNIX_EPOCH};
+///
+/// use cleverdog::{mux::record::FileTemplate, protocol::Cid};
+///
+/// let template: FileTemplate = "{cid}/{date}/{time}".parse()?;
+/// let cid = Cid::new(*b"xxxxS_AB12CD000\0");
+/// let at = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+/// assert_eq!("xxxxS_AB12CD000/2020-09-13/12-26-40", template.render(&cid, at));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTemplate {
+    parts: Vec<Part>,
+}
+
+impl Default for FileTemplate {
+    fn default() -> Self {
+        TEMPLATE.parse().expect("default template must be valid")
+    }
+}
+
+impl FileTemplate {
+    /// Renders the path of a file of the given camera starting at the given time.
+    ///
+    /// The camera ID is restricted to characters safe in file names, others are replaced by `_`.
+    pub fn render(&self, cid: &Cid, at: SystemTime) -> String {
+        let (year, month, day, secs) = utc(at);
+        let mut path = String::new();
+
+        for part in &self.parts {
+            let value = match part {
+                Part::Literal(v) => {
+                    path.push_str(v);
+                    continue;
+                }
+                Part::Var(Var::Cid) => cid.to_string(),
+                Part::Var(Var::Date) => format!("{:04}-{:02}-{:02}", year, month, day),
+                Part::Var(Var::Time) => format!("{:02}-{:02}-{:02}", secs / 3600, secs / 60 % 60, secs % 60),
+            };
+
+            path.extend(value.chars().map(|ch| match ch {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => ch,
+                _ => '_',
+            }));
+        }
+
+        path
+    }
+}
+
+impl Display for FileTemplate {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        for part in &self.parts {
+            match part {
+                Part::Literal(v) => fmt.write_str(v)?,
+                Part::Var(Var::Cid) => fmt.write_str("{cid}")?,
+                Part::Var(Var::Date) => fmt.write_str("{date}")?,
+                Part::Var(Var::Time) => fmt.write_str("{time}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FileTemplate {
+    type Err = TemplateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(TemplateParseError::Unbalanced);
+            }
+            let end = rest[start..].find('}').ok_or(TemplateParseError::Unbalanced)? + start;
+
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].into()));
+            }
+
+            let var = match &rest[start + 1..end] {
+                "cid" => Var::Cid,
+                "date" => Var::Date,
+                "time" => Var::Time,
+                name => return Err(TemplateParseError::UnknownPlaceholder(name.into())),
+            };
+            parts.push(Part::Var(var));
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.into()));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+/// Writer of the file being recorded.
+#[derive(Debug)]
+enum Output {
+    Mkv(MkvWriter<BufWriter<File>>),
+    Fmp4(Fmp4Writer<BufWriter<File>>),
+}
+
+/// File being recorded.
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    output: Output,
+    /// Time of the first frame, in RTP clock units.
+    start: u64,
+    /// Number of payload bytes written so far.
+    size: u64,
+}
+
+/// Keeps the last frames of the stream in memory, covering at least the given duration.
+///
+/// The buffer always starts with a keyframe, the latest one old enough to cover the duration,
+/// so its contents are decodable on their own. Frames preceding the first keyframe are dropped.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// use cleverdog::{h264::Frame, mux::record::PreRecordBuffer};
+///
+/// let mut buf = PreRecordBuffer::new(Duration::from_secs(1));
+/// for idx in 0..5u32 {
+///     let nals = vec![vec![if idx % 2 == 0 { 0x65 } else { 0x41 }, idx as u8]];
+///     buf.push(&Frame { timestamp: idx * 90_000, keyframe: idx % 2 == 0, nals });
+/// }
+///
+/// // The latest keyframe at least a second old and everything after it.
+/// let frames: Vec<_> = buf.drain().map(|v| v.timestamp).collect();
+/// assert_eq!(vec![180_000, 270_000, 360_000], frames);
+/// ```
+#[derive(Debug)]
+pub struct PreRecordBuffer {
+    duration: Duration,
+    timeline: Timeline,
+    /// Frames with their time in RTP clock units and the wall-clock time they arrived at.
+    frames: VecDeque<(u64, SystemTime, Frame)>,
+}
+
+impl PreRecordBuffer {
+    /// Constructs a new buffer covering the given duration.
+    pub fn new(duration: Duration) -> Self {
+        Self {
+            duration,
+            timeline: Timeline::default(),
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Returns the number of buffered frames.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Returns whether no frames are buffered.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Returns the wall-clock time the oldest buffered frame arrived at.
+    #[inline]
+    pub fn started_at(&self) -> Option<SystemTime> {
+        self.frames.front().map(|(_, at, _)| *at)
+    }
+
+    /// Buffers the given frame, dropping frames no longer needed to cover the duration.
+    pub fn push(&mut self, frame: &Frame) {
+        let time = self.timeline.push(frame.timestamp);
+        if self.frames.is_empty() && !frame.keyframe {
+            return;
+        }
+        self.frames.push_back((time, SystemTime::now(), frame.clone()));
+
+        // Drop the oldest group of pictures while the next keyframe alone covers the duration.
+        let horizon = time.saturating_sub(duration_ticks(self.duration));
+        while let Some(idx) = self.frames.iter().skip(1).position(|(.., v)| v.keyframe) {
+            if self.frames[idx + 1].0 > horizon {
+                break;
+            }
+            self.frames.drain(..=idx);
+        }
+    }
+
+    /// Removes and returns all buffered frames, oldest first.
+    pub fn drain(&mut self) -> impl Iterator<Item = Frame> + '_ {
+        self.frames.drain(..).map(|(.., frame)| frame)
+    }
+}
+
+/// Records frames into files rotated by duration or size.
+///
+/// A new file starts with a keyframe, once the current one has lasted at least the maximum
+/// duration or grown to the maximum size. Durations are derived from RTP timestamps, so they
+/// follow the camera's clock.
+///
+/// With [`pre_record`](Self::pre_record) the recorder stays idle until [`start`](Self::start),
+/// buffering frames meanwhile, and goes back to idle on [`stop`](Self::stop).
+///
+/// ```no_run
+/// use core::time::Duration;
+///
+/// use cleverdog::{
+///     h264::FrameAssembler,
+///     mux::record::{Container, Recorder},
+/// };
+///
+/// let info = cleverdog::lookup()?;
+/// let mut recorder = Recorder::new("/var/lib/cleverdog", info.cid())
+///     .container(Container::Fmp4)
+///     .max_duration(Duration::from_secs(600));
+/// let mut assembler = FrameAssembler::new();
+///
+/// cleverdog::stream(info.cid(), info.addr(), |packet| {
+///     for frame in assembler.push(packet)? {
+///         recorder.write_frame(&frame)?;
+///     }
+///     Ok(())
+/// })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Recorder {
+    dir: PathBuf,
+    cid: Cid,
+    container: Container,
+    template: FileTemplate,
+    max_duration: Duration,
+    max_size: Option<u64>,
+    params: ParameterSets,
+    timeline: Timeline,
+    segment: Option<Segment>,
+    recording: bool,
+    pre_record: Option<PreRecordBuffer>,
+}
+
+impl Recorder {
+    /// Constructs a new recorder of the given camera into the given directory, writing Matroska
+    /// files of 15 minutes named `{cid}/{date}/{time}.mkv`.
+    pub fn new<P: AsRef<Path>>(dir: P, cid: &Cid) -> Self {
+        Self {
+            dir: dir.as_ref().into(),
+            cid: *cid,
+            container: Container::Mkv,
+            template: FileTemplate::default(),
+            max_duration: MAX_DURATION,
+            max_size: None,
+            params: ParameterSets::default(),
+            timeline: Timeline::default(),
+            segment: None,
+            recording: true,
+            pre_record: None,
+        }
+    }
+
+    /// Makes the recorder idle until started, keeping the given duration of frames in memory so
+    /// that each recording includes footage preceding its trigger.
+    pub fn pre_record(mut self, duration: Duration) -> Self {
+        self.recording = false;
+        self.pre_record = Some(PreRecordBuffer::new(duration));
+        self
+    }
+
+    /// Sets the container of files.
+    pub fn container(mut self, container: Container) -> Self {
+        self.container = container;
+        self
+    }
+
+    /// Sets the template of file paths, relative to the directory and without the extension.
+    pub fn template(mut self, template: FileTemplate) -> Self {
+        self.template = template;
+        self
+    }
+
+    /// Sets the duration after which the next keyframe starts a new file.
+    pub fn max_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = duration;
+        self
+    }
+
+    /// Sets the size in bytes after which the next keyframe starts a new file.
+    pub fn max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Returns the final path of the file being recorded, if any.
+    #[inline]
+    pub fn current_path(&self) -> Option<&Path> {
+        self.segment.as_ref().map(|v| v.path.as_path())
+    }
+
+    /// Returns whether frames are being recorded rather than buffered.
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    /// Starts recording, beginning with the buffered frames.
+    ///
+    /// Does nothing if already recording.
+    pub fn start(&mut self) -> Result<(), io::Error> {
+        if self.recording {
+            return Ok(());
+        }
+        self.recording = true;
+
+        if let Some(buf) = &mut self.pre_record {
+            let at = buf.started_at().unwrap_or_else(SystemTime::now);
+            let frames: Vec<_> = buf.drain().collect();
+            for frame in &frames {
+                self.record(frame, at)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Completes the current file and goes back to buffering frames until started again.
+    pub fn stop(&mut self) -> Result<(), io::Error> {
+        self.recording = false;
+        self.close()
+    }
+
+    /// Writes the given frame, completing the current file if a new one starts with it.
+    ///
+    /// Frames preceding the first keyframe with known parameter sets are dropped.
+    pub fn write_frame(&mut self, frame: &Frame) -> Result<(), io::Error> {
+        if !self.recording {
+            self.params.update(frame);
+            if let Some(buf) = &mut self.pre_record {
+                buf.push(frame);
+            }
+            return Ok(());
+        }
+
+        self.record(frame, SystemTime::now())
+    }
+
+    /// Completes the current file.
+    pub fn finish(mut self) -> Result<(), io::Error> {
+        self.close()
+    }
+
+    /// Writes the given frame, opening a file named after the given time if needed.
+    fn record(&mut self, frame: &Frame, at: SystemTime) -> Result<(), io::Error> {
+        self.params.update(frame);
+        if self.segment.is_none() && !(frame.keyframe && self.params.get().is_some()) {
+            return Ok(());
+        }
+
+        let time = self.timeline.push(frame.timestamp);
+        let due = match &self.segment {
+            Some(segment) => frame.keyframe && self.is_due(segment, time),
+            None => true,
+        };
+        if due {
+            self.close()?;
+            self.open(time, at)?;
+        }
+
+        let segment = self.segment.as_mut().expect("segment must be open");
+        match &mut segment.output {
+            // A fresh writer drops keyframes without parameter sets, so repeat them.
+            Output::Mkv(wr) if !wr.is_started() => wr.write_frame(&with_parameter_sets(&self.params, frame))?,
+            Output::Fmp4(wr) if !wr.is_started() => wr.write_frame(&with_parameter_sets(&self.params, frame))?,
+            Output::Mkv(wr) => wr.write_frame(frame)?,
+            Output::Fmp4(wr) => wr.write_frame(frame)?,
+        }
+        segment.size += frame.nals.iter().map(|v| v.len() as u64).sum::<u64>();
+
+        Ok(())
+    }
+
+    fn is_due(&self, segment: &Segment, time: u64) -> bool {
+        let by_size = matches!(self.max_size, Some(v) if segment.size >= v);
+        time - segment.start >= duration_ticks(self.max_duration) || by_size
+    }
+
+    fn open(&mut self, start: u64, at: SystemTime) -> Result<(), io::Error> {
+        let name = self.template.render(&self.cid, at);
+        let mut path = self.dir.join(format!("{}.{}", name, self.container.extension()));
+        // Files rotated by size may start within the same second.
+        let mut idx = 1;
+        while path.exists() || partial_path(&path).exists() {
+            path = self
+                .dir
+                .join(format!("{}-{}.{}", name, idx, self.container.extension()));
+            idx += 1;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let wr = BufWriter::new(File::create(partial_path(&path))?);
+        let output = match self.container {
+            Container::Mkv => Output::Mkv(MkvWriter::new(wr)),
+            Container::Fmp4 => Output::Fmp4(Fmp4Writer::new(wr)),
+        };
+        self.segment = Some(Segment {
+            path,
+            output,
+            start,
+            size: 0,
+        });
+
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), io::Error> {
+        if let Some(segment) = self.segment.take() {
+            let mut wr = match segment.output {
+                Output::Mkv(wr) => wr.into_inner(),
+                Output::Fmp4(wr) => wr.finish()?,
+            };
+            wr.flush()?;
+            wr.get_ref().sync_all()?;
+            fs::rename(partial_path(&segment.path), &segment.path)?;
+            info!("finished recording {}", segment.path.display());
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            warn!("failed to finish recording: {}", err);
+        }
+    }
+}
+
+/// Returns the given keyframe preceded by the remembered parameter sets, unless it carries them.
+fn with_parameter_sets(params: &ParameterSets, frame: &Frame) -> Frame {
+    let has = |ty| {
+        frame
+            .nals
+            .iter()
+            .any(|v| matches!(Nal::new(v), Some(nal) if nal.nal_type() == ty))
+    };
+    let mut frame = frame.clone();
+    if let (false, Some((sps, pps))) = (has(NalType::Sps) && has(NalType::Pps), params.get()) {
+        frame.nals.splice(..0, [sps.to_vec(), pps.to_vec()]);
+    }
+    frame
+}
+
+/// Converts the given duration into RTP clock units.
+fn duration_ticks(duration: Duration) -> u64 {
+    (duration.as_micros() * u128::from(TIMESCALE) / 1_000_000) as u64
+}
+
+/// Returns the UTC year, month, day and seconds since midnight of the given time.
+fn utc(at: SystemTime) -> (i64, u32, u32, u32) {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = (secs / 86_400) as i64;
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day, (secs % 86_400) as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    const SPS: [u8; 20] = [
+        0x67, 0x42, 0xc0, 0x1e, 0xda, 0x02, 0x80, 0xf6, 0x84, 0x00, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x03, 0x00,
+        0xca, 0x10,
+    ];
+
+    const CID: Cid = Cid::new(*b"cam\0\0\0\0\0\0\0\0\0\0\0\0\0");
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cleverdog-record-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|v| v.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_utc() {
+        assert_eq!((1970, 1, 1, 0), utc(UNIX_EPOCH));
+        assert_eq!((2000, 2, 29, 3661), utc(UNIX_EPOCH + Duration::from_secs(951_786_061)));
+        assert_eq!(
+            (2024, 12, 31, 86_399),
+            utc(UNIX_EPOCH + Duration::from_secs(1_735_689_599))
+        );
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert_eq!(
+            "{cid}-{date}",
+            "{cid}-{date}".parse::<FileTemplate>().unwrap().to_string()
+        );
+        assert_eq!(Err(TemplateParseError::Unbalanced), "{cid".parse::<FileTemplate>());
+        assert_eq!(
+            Err(TemplateParseError::UnknownPlaceholder("mac".into())),
+            "{mac}".parse::<FileTemplate>()
+        );
+    }
+
+    #[test]
+    fn test_rotation() {
+        let dir = tempdir("rotation");
+        let mut recorder = Recorder::new(&dir, &CID)
+            .template("{cid}".parse().unwrap())
+            .max_duration(Duration::from_secs(2));
+
+        // 6 seconds of 1 fps video with a keyframe each 3 seconds, parameter sets only once.
+        for idx in 0..6u32 {
+            let nals = match idx {
+                0 => vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, 0]],
+                3 => vec![vec![0x65, 3]],
+                _ => vec![vec![0x41, idx as u8]],
+            };
+            recorder
+                .write_frame(&Frame {
+                    timestamp: idx * TIMESCALE,
+                    keyframe: idx % 3 == 0,
+                    nals,
+                })
+                .unwrap();
+            assert!(recorder.current_path().is_some());
+        }
+        // The current file is still partial.
+        assert_eq!(vec!["cam-1.mkv.part", "cam.mkv"], files(&dir));
+        recorder.finish().unwrap();
+
+        assert_eq!(vec!["cam-1.mkv", "cam.mkv"], files(&dir));
+        // Both files start with the track header carrying the parameter sets.
+        for name in ["cam.mkv", "cam-1.mkv"] {
+            let buf = fs::read(dir.join(name)).unwrap();
+            assert!(buf.windows(SPS.len()).any(|v| v == SPS), "{}", name);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_pre_record() {
+        let dir = tempdir("pre");
+        let mut recorder = Recorder::new(&dir, &CID)
+            .template("{cid}".parse().unwrap())
+            .pre_record(Duration::from_secs(3));
+
+        // 1 fps video with a keyframe each 2 seconds, parameter sets only once. Recording is
+        // triggered at 6 seconds and stopped at 9.
+        for idx in 0..12u32 {
+            match idx {
+                6 => recorder.start().unwrap(),
+                9 => recorder.stop().unwrap(),
+                _ => {}
+            }
+            let nals = match idx {
+                0 => vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, 0]],
+                _ if idx % 2 == 0 => vec![vec![0x65, idx as u8]],
+                _ => vec![vec![0x41, idx as u8]],
+            };
+            recorder
+                .write_frame(&Frame {
+                    timestamp: idx * TIMESCALE,
+                    keyframe: idx % 2 == 0,
+                    nals,
+                })
+                .unwrap();
+        }
+        assert!(!recorder.is_recording());
+        recorder.finish().unwrap();
+
+        assert_eq!(vec!["cam.mkv"], files(&dir));
+        let buf = fs::read(dir.join("cam.mkv")).unwrap();
+        let contains = |nal: [u8; 2]| buf.windows(6).any(|v| v == [0, 0, 0, 2, nal[0], nal[1]]);
+        // Starts at the keyframe 3 seconds before the trigger, with the parameter sets.
+        assert!(buf.windows(SPS.len()).any(|v| v == SPS));
+        assert!(!contains([0x41, 1]));
+        assert!(contains([0x65, 2]));
+        assert!(contains([0x65, 8]));
+        assert!(!contains([0x41, 9]));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_by_size() {
+        let dir = tempdir("size");
+        let mut recorder = Recorder::new(&dir, &CID)
+            .container(Container::Fmp4)
+            .template("{cid}/{date}/{time}".parse().unwrap())
+            .max_size(1);
+
+        for idx in 0..3u32 {
+            recorder
+                .write_frame(&Frame {
+                    timestamp: idx * TIMESCALE,
+                    keyframe: true,
+                    nals: vec![SPS.to_vec(), vec![0x68, 0xce], vec![0x65, idx as u8]],
+                })
+                .unwrap();
+        }
+        recorder.finish().unwrap();
+
+        // Nested directories, with a file per keyframe.
+        let day = fs::read_dir(dir.join("cam")).unwrap().next().unwrap().unwrap().path();
+        let files = files(&day);
+        assert_eq!(3, files.len());
+        assert!(files.iter().all(|v| v.ends_with(".mp4")));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::error::Error;
+
+pub use self::threaded::{StageMetrics, StageSnapshot, StageStopped, Threaded};
+use crate::sink::Sink;
+
+mod threaded;
+
+/// Decision made by a pipeline stage about a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// Pass the buffer unchanged to the next stage.
+    Pass,
+    /// Drop the buffer, e.g. a filtered out frame.
+    Drop,
+    /// Replace the buffer with a transformed one.
+    Replace(Vec<u8>),
+}
+
+/// Filter or transform installed between the receive loop and sinks.
+///
+/// Implemented for any `FnMut(&[u8]) -> Result<Verdict, Box<dyn Error>>` closure.
+pub trait Stage {
+    /// Processes the given buffer.
+    fn process(&mut self, buf: &[u8]) -> Result<Verdict, Box<dyn Error>>;
+}
+
+impl<F> Stage for F
+where
+    F: FnMut(&[u8]) -> Result<Verdict, Box<dyn Error>>,
+{
+    #[inline]
+    fn process(&mut self, buf: &[u8]) -> Result<Verdict, Box<dyn Error>> {
+        self(buf)
+    }
+}
+
+/// Sink that passes data through a chain of stages before handing it to the inner sink.
+///
+/// Stages must be `Send`, so that the whole pipeline can be moved onto its own thread with
+/// [`Threaded`].
+///
+/// ```
+/// use std::error::Error;
+///
+/// use cleverdog::{
+///     pipeline::{Pipeline, Verdict},
+///     sink::Sink,
+/// };
+///
+/// let mut frames = Vec::new();
+/// let mut pipeline = Pipeline::new(|buf: &[u8]| {
+///     frames.push(buf.to_vec());
+///     Ok(())
+/// })
+/// .stage(|buf: &[u8]| -> Result<Verdict, Box<dyn Error>> {
+///     match buf.is_empty() {
+///         true => Ok(Verdict::Drop),
+///         false => Ok(Verdict::Pass),
+///     }
+/// });
+///
+/// pipeline.send(b"").unwrap();
+/// pipeline.send(b"frame").unwrap();
+/// drop(pipeline);
+///
+/// assert_eq!(vec![b"frame".to_vec()], frames);
+/// ```
+pub struct Pipeline<S> {
+    stages: Vec<Box<dyn Stage + Send>>,
+    sink: S,
+}
+
+impl<S: Sink> Pipeline<S> {
+    /// Constructs a new pipeline without stages, delivering into the given sink.
+    pub fn new(sink: S) -> Self {
+        Self {
+            stages: Vec::new(),
+            sink,
+        }
+    }
+
+    /// Appends the given stage to the end of the chain.
+    pub fn push<T>(&mut self, stage: T)
+    where
+        T: Stage + Send + 'static,
+    {
+        self.stages.push(Box::new(stage));
+    }
+
+    /// Appends the given stage, returning `self` for chaining.
+    pub fn stage<T>(mut self, stage: T) -> Self
+    where
+        T: Stage + Send + 'static,
+    {
+        self.push(stage);
+        self
+    }
+
+    /// Consumes the pipeline, returning the inner sink.
+    pub fn into_inner(self) -> S {
+        self.sink
+    }
+}
+
+impl<S: Sink> Sink for Pipeline<S> {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut owned = None;
+
+        for stage in &mut self.stages {
+            let verdict = stage.process(owned.as_deref().unwrap_or(buf))?;
+
+            match verdict {
+                Verdict::Pass => {}
+                Verdict::Drop => return Ok(()),
+                Verdict::Replace(v) => owned = Some(v),
+            }
+        }
+
+        self.sink.send(owned.as_deref().unwrap_or(buf))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_transform() {
+        let mut frames = Vec::new();
+
+        {
+            let mut pipeline = Pipeline::new(|buf: &[u8]| -> Result<(), Box<dyn Error>> {
+                frames.push(buf.to_vec());
+                Ok(())
+            })
+            .stage(|buf: &[u8]| -> Result<Verdict, Box<dyn Error>> {
+                Ok(Verdict::Replace(buf.iter().map(|v| v + 1).collect()))
+            })
+            .stage(|buf: &[u8]| -> Result<Verdict, Box<dyn Error>> {
+                match buf[0] % 2 {
+                    0 => Ok(Verdict::Drop),
+                    _ => Ok(Verdict::Pass),
+                }
+            });
+
+            for v in 0..4u8 {
+                pipeline.send(&[v, v]).unwrap();
+            }
+        }
+
+        assert_eq!(vec![vec![1, 1], vec![3, 3]], frames);
+    }
+
+    #[test]
+    fn test_pipeline_stage_error() {
+        let mut pipeline = Pipeline::new(|_buf: &[u8]| -> Result<(), Box<dyn Error>> { Ok(()) })
+            .stage(|_buf: &[u8]| -> Result<Verdict, Box<dyn Error>> { Err("failed".into()) });
+
+        assert!(pipeline.send(b"frame").is_err());
+    }
+}
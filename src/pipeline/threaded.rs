@@ -0,0 +1,318 @@
+use core::{
+    fmt::{self, Display, Formatter},
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+use std::{
+    error::Error,
+    io,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use log::warn;
+
+use crate::sink::Sink;
+
+/// Queue metrics of a [`Threaded`] stage, updated lock-free from both of its ends.
+#[derive(Debug, Default)]
+pub struct StageMetrics {
+    enqueued: AtomicU64,
+    dropped: AtomicU64,
+    processed: AtomicU64,
+    depth: AtomicUsize,
+    max_depth: AtomicUsize,
+}
+
+impl StageMetrics {
+    /// Returns a copy of the current metric values.
+    pub fn snapshot(&self) -> StageSnapshot {
+        StageSnapshot {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            processed: self.processed.load(Ordering::Relaxed),
+            depth: self.depth.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reserves a queue slot before sending, returning the resulting depth.
+    #[inline]
+    fn on_reserved(&self) -> usize {
+        self.depth.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    #[inline]
+    fn on_enqueued(&self, depth: usize) {
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn on_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn on_dequeued(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn on_processed(&self) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Point-in-time copy of [`StageMetrics`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StageSnapshot {
+    /// Number of buffers accepted into the queue.
+    pub enqueued: u64,
+    /// Number of buffers dropped because the queue was full.
+    pub dropped: u64,
+    /// Number of buffers the inner sink has consumed successfully.
+    pub processed: u64,
+    /// Number of buffers currently waiting in the queue.
+    pub depth: usize,
+    /// Highest queue depth observed.
+    pub max_depth: usize,
+}
+
+/// An error returned by a [`Threaded`] stage whose thread has stopped.
+#[derive(Debug, Clone)]
+pub struct StageStopped {
+    name: String,
+    reason: Option<String>,
+}
+
+impl StageStopped {
+    /// Returns the name of the stopped stage.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Display for StageStopped {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match &self.reason {
+            Some(reason) => write!(fmt, "stage '{}' failed: {}", self.name, reason),
+            None => write!(fmt, "stage '{}' has stopped", self.name),
+        }
+    }
+}
+
+impl Error for StageStopped {}
+
+/// Sink that hands buffers over to the inner sink running on a dedicated thread.
+///
+/// Buffers are passed through a bounded queue. When the queue is full the buffer is dropped and
+/// counted instead of blocking, so that a slow consumer, e.g. a disk or a TLS uplink, never
+/// stalls the thread feeding the stage. Stages can be nested to split the streaming path into a
+/// receive thread, a processing thread and a sink thread, each with its own queue metrics.
+///
+/// Errors of the inner sink stop the thread, after which [`Sink::send`] fails with
+/// [`StageStopped`]. Dropping the stage processes the remaining buffers and joins the thread.
+///
+/// ```
+/// use std::error::Error;
+///
+/// use cleverdog::{
+///     pipeline::{Pipeline, Threaded, Verdict},
+///     sink::Sink,
+/// };
+///
+/// let sink = Threaded::spawn("sink", |_buf: &[u8]| -> Result<(), Box<dyn Error>> { Ok(()) }, 1024).unwrap();
+/// let pipeline = Pipeline::new(sink).stage(|_buf: &[u8]| -> Result<Verdict, Box<dyn Error>> { Ok(Verdict::Pass) });
+/// let mut stage = Threaded::spawn("processing", pipeline, 4096).unwrap();
+///
+/// stage.send(b"frame").unwrap();
+/// stage.join().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Threaded {
+    name: String,
+    tx: Option<SyncSender<Vec<u8>>>,
+    metrics: Arc<StageMetrics>,
+    error: Arc<Mutex<Option<String>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Threaded {
+    /// Spawns a new thread consuming buffers into the given sink, with a queue of the specified
+    /// capacity in buffers.
+    pub fn spawn<S>(name: &str, mut sink: S, capacity: usize) -> Result<Self, io::Error>
+    where
+        S: Sink + Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(capacity.max(1));
+        let metrics = Arc::new(StageMetrics::default());
+        let error = Arc::new(Mutex::new(None));
+
+        let thread = {
+            let metrics = metrics.clone();
+            let error = error.clone();
+            let name = name.to_string();
+
+            thread::Builder::new().name(format!("stage-{}", name)).spawn(move || {
+                for buf in rx {
+                    metrics.on_dequeued();
+                    if let Err(err) = sink.send(&buf) {
+                        warn!("stage '{}' failed: {}", name, err);
+                        *error.lock().expect("stage lock must not be poisoned") = Some(err.to_string());
+                        return;
+                    }
+                    metrics.on_processed();
+                }
+            })?
+        };
+
+        let stage = Self {
+            name: name.into(),
+            tx: Some(tx),
+            metrics,
+            error,
+            thread: Some(thread),
+        };
+
+        Ok(stage)
+    }
+
+    /// Returns the stage name.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns queue metrics, which can be shared with monitoring threads.
+    #[inline]
+    pub fn metrics(&self) -> &Arc<StageMetrics> {
+        &self.metrics
+    }
+
+    /// Closes the queue and waits until the remaining buffers are consumed.
+    ///
+    /// Returns an error if the inner sink has failed.
+    pub fn join(mut self) -> Result<(), StageStopped> {
+        self.close();
+
+        match self.take_error() {
+            Some(reason) => Err(self.stopped(Some(reason))),
+            None => Ok(()),
+        }
+    }
+
+    fn close(&mut self) {
+        self.tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    fn take_error(&self) -> Option<String> {
+        self.error.lock().expect("stage lock must not be poisoned").take()
+    }
+
+    fn stopped(&self, reason: Option<String>) -> StageStopped {
+        StageStopped {
+            name: self.name.clone(),
+            reason,
+        }
+    }
+}
+
+impl Sink for Threaded {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let tx = match &self.tx {
+            Some(tx) => tx,
+            None => return Err(self.stopped(None).into()),
+        };
+
+        // Reserve before sending, so that the consumer never drives the depth below zero.
+        let depth = self.metrics.on_reserved();
+        match tx.try_send(buf.to_vec()) {
+            Ok(()) => {
+                self.metrics.on_enqueued(depth);
+                Ok(())
+            }
+            Err(TrySendError::Full(..)) => {
+                self.metrics.on_dequeued();
+                self.metrics.on_dropped();
+                Ok(())
+            }
+            Err(TrySendError::Disconnected(..)) => {
+                self.metrics.on_dequeued();
+                self.close();
+                let reason = self.take_error();
+                Err(self.stopped(reason).into())
+            }
+        }
+    }
+}
+
+impl Drop for Threaded {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc::Receiver;
+
+    use super::*;
+
+    /// Returns a sink blocking on each buffer until allowed to proceed by the returned sender.
+    fn gated() -> (impl Sink + Send, SyncSender<()>, Receiver<Vec<u8>>) {
+        let (gate, opened) = mpsc::sync_channel(0);
+        let (tx, rx) = mpsc::channel();
+        let sink = move |buf: &[u8]| -> Result<(), Box<dyn Error>> {
+            opened.recv()?;
+            tx.send(buf.to_vec())?;
+            Ok(())
+        };
+
+        (sink, gate, rx)
+    }
+
+    #[test]
+    fn test_slow_sink_drops_instead_of_blocking() {
+        let (sink, gate, rx) = gated();
+        let mut stage = Threaded::spawn("test", sink, 2).unwrap();
+
+        for v in 0..10u8 {
+            stage.send(&[v]).unwrap();
+        }
+        let snapshot = stage.metrics().snapshot();
+        // The consumer may have taken the first buffer out of the queue, freeing one slot.
+        assert!(snapshot.enqueued == 2 || snapshot.enqueued == 3);
+        assert_eq!(10, snapshot.enqueued + snapshot.dropped);
+
+        let enqueued = snapshot.enqueued as usize;
+        for _ in 0..enqueued {
+            gate.send(()).unwrap();
+        }
+        stage.join().unwrap();
+
+        assert_eq!(enqueued, rx.try_iter().count());
+    }
+
+    #[test]
+    fn test_sink_error_stops_stage() {
+        let failing = |_buf: &[u8]| -> Result<(), Box<dyn Error>> { Err("disk full".into()) };
+        let mut stage = Threaded::spawn("file", failing, 16).unwrap();
+
+        stage.send(b"frame").unwrap();
+        let err = loop {
+            match stage.send(b"frame") {
+                Ok(()) => thread::yield_now(),
+                Err(err) => break err,
+            }
+        };
+
+        assert_eq!("stage 'file' failed: disk full", err.to_string());
+    }
+}
@@ -0,0 +1,104 @@
+use std::net::SocketAddr;
+
+use crate::{
+    conformance::Capabilities,
+    mac::MacAddr,
+    protocol::{Cid, Command, ScanInfo, Version, MAGIC},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LookupInfo {
+    /// Camera endpoint.
+    addr: SocketAddr,
+    /// Camera ID.
+    cid: Cid,
+    /// Scan info.
+    info: ScanInfo,
+    /// Capabilities detected by the conformance suite, if it has been run.
+    capabilities: Option<Capabilities>,
+}
+
+impl LookupInfo {
+    pub fn new(addr: SocketAddr, cid: [u8; 16], info: ScanInfo) -> Self {
+        Self {
+            addr,
+            cid: Cid::new(cid),
+            info,
+            capabilities: None,
+        }
+    }
+
+    /// Attaches the given capability set.
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    /// Returns socket address where the camera is bound.
+    #[inline]
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns camera's client id.
+    #[inline]
+    pub fn cid(&self) -> &Cid {
+        &self.cid
+    }
+
+    /// Returns camera's MAC address.
+    #[inline]
+    pub fn mac(&self) -> &MacAddr {
+        self.info.mac()
+    }
+
+    /// Returns camera's firmware version.
+    #[inline]
+    pub fn version(&self) -> &Version {
+        self.info.version()
+    }
+
+    /// Returns capabilities detected by the conformance suite for this camera and firmware.
+    ///
+    /// Lookup attaches capabilities cached by previous [`conformance::run`] calls within the
+    /// process, so this is `None` until the camera has been probed.
+    ///
+    /// [`conformance::run`]: crate::conformance::run
+    #[inline]
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// Encodes this info into a complete ScanReply datagram, as it would be sent by the camera.
+    ///
+    /// The camera address is not part of the wire format and is therefore omitted.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&MAGIC.to_be_bytes());
+        buf.extend_from_slice(&Command::ScanReply.as_u16().to_be_bytes());
+        buf.extend_from_slice(self.cid.as_bytes());
+        buf.extend_from_slice(&self.info.encode());
+        buf
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::TryFrom;
+
+    use super::*;
+
+    fn info() -> ScanInfo {
+        ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]))
+    }
+
+    #[test]
+    fn test_encode_lookup_info() {
+        let info = LookupInfo::new("127.0.0.1:10008".parse().unwrap(), *b"XXXXXXXXXXXXXXX\0", info());
+        let buf = info.encode();
+
+        assert_eq!(&[0x4d, 0x4a, 0x10, 0x0e], &buf[..4]);
+        assert_eq!(&info.cid()[..], &buf[4..20]);
+        assert_eq!(info.info, ScanInfo::try_from(&buf[20..]).unwrap());
+    }
+}
@@ -0,0 +1,519 @@
+//! Deterministic replay of recorded camera traffic.
+//!
+//! Runs the streaming session against a recorded capture with a virtual clock, driven by capture
+//! timestamps: no sockets are opened and nothing sleeps, so tests of the receive loop, RTCP
+//! scheduling and everything built on top are fast and reproducible.
+
+use core::{cell::Cell, time::Duration};
+use std::{
+    error::Error,
+    io::{self, ErrorKind, Read},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    protocol::Cid,
+    rtp::RtpPacket,
+    session::{self, Clock, Context, Shared, StreamOptions, Transport},
+    stats::StatsSnapshot,
+};
+
+/// A recorded UDP datagram.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    /// Capture time.
+    pub at: SystemTime,
+    /// Source address for received datagrams, destination address for sent ones.
+    pub addr: SocketAddr,
+    /// UDP payload.
+    pub buf: Vec<u8>,
+}
+
+/// Replays recorded datagrams through the streaming session.
+#[derive(Debug)]
+pub struct Replay {
+    datagrams: Vec<Datagram>,
+    sent: Vec<Datagram>,
+    shared: Shared,
+}
+
+impl Replay {
+    /// Constructs a new replay of the given datagrams, as if they were received from the camera.
+    pub fn new(datagrams: Vec<Datagram>) -> Self {
+        Self {
+            datagrams,
+            sent: Vec::new(),
+            shared: Shared::default(),
+        }
+    }
+
+    /// Runs the streaming session, returning once all datagrams have been consumed.
+    ///
+    /// Virtual time advances to the capture time of each datagram as it is received.
+    pub fn run<F>(&mut self, cid: &Cid, opts: &StreamOptions, f: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+    {
+        let start = self.datagrams.first().map(|v| v.at).unwrap_or(UNIX_EPOCH);
+        let clock = VirtualClock::new(start);
+
+        let mut transport = ReplayTransport {
+            datagrams: self.datagrams.iter(),
+            sent: &mut self.sent,
+            clock: &clock,
+        };
+
+        let cx = Context {
+            cid,
+            src: self
+                .datagrams
+                .first()
+                .map(|v| v.addr)
+                .unwrap_or_else(|| SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0)),
+            port: 0,
+            opts,
+            shared: &self.shared,
+            // Reports are sent from the receive loop, driven by the virtual clock.
+            keepalive: None,
+        };
+
+        match session::drive(&mut transport, &clock, &cx, f) {
+            Ok(()) => Ok(()),
+            Err(err) => match err.downcast_ref::<io::Error>() {
+                Some(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(()),
+                _ => Err(err),
+            },
+        }
+    }
+
+    /// Returns datagrams sent by the session, stamped with virtual time.
+    #[inline]
+    pub fn sent(&self) -> &[Datagram] {
+        &self.sent
+    }
+
+    /// Returns statistics accumulated over all runs.
+    #[inline]
+    pub fn stats(&self) -> StatsSnapshot {
+        self.shared.stats.snapshot()
+    }
+}
+
+struct VirtualClock {
+    base: Instant,
+    start: SystemTime,
+    now: Cell<SystemTime>,
+}
+
+impl VirtualClock {
+    fn new(start: SystemTime) -> Self {
+        Self {
+            base: Instant::now(),
+            start,
+            now: Cell::new(start),
+        }
+    }
+
+    fn advance_to(&self, at: SystemTime) {
+        if at > self.now.get() {
+            self.now.set(at);
+        }
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        self.base + self.now.get().duration_since(self.start).unwrap_or_default()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.now.get()
+    }
+}
+
+struct ReplayTransport<'a> {
+    datagrams: core::slice::Iter<'a, Datagram>,
+    sent: &'a mut Vec<Datagram>,
+    clock: &'a VirtualClock,
+}
+
+impl Transport for ReplayTransport<'_> {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> Result<(), io::Error> {
+        self.sent.push(Datagram {
+            at: self.clock.system_time(),
+            addr,
+            buf: buf.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr), io::Error> {
+        let datagram = self
+            .datagrams
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::UnexpectedEof, "end of replay"))?;
+
+        self.clock.advance_to(datagram.at);
+
+        let size = datagram.buf.len().min(buf.len());
+        buf[..size].copy_from_slice(&datagram.buf[..size]);
+
+        Ok((size, datagram.addr))
+    }
+}
+
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+
+const IPPROTO_UDP: u8 = 17;
+
+/// Reads all UDP datagrams from a classic pcap capture.
+///
+/// Ethernet, raw IP and Linux cooked captures of IPv4 and IPv6 are supported. Fragmented and
+/// non-UDP packets are skipped.
+pub fn read_pcap<R: Read>(mut rd: R) -> Result<Vec<Datagram>, io::Error> {
+    let invalid = |reason: &str| io::Error::new(ErrorKind::InvalidData, reason.to_string());
+
+    let mut header = [0u8; 24];
+    rd.read_exact(&mut header)?;
+
+    let magic = [header[0], header[1], header[2], header[3]];
+    let (big_endian, nanos) = match magic {
+        [0xd4, 0xc3, 0xb2, 0xa1] => (false, false),
+        [0xa1, 0xb2, 0xc3, 0xd4] => (true, false),
+        [0x4d, 0x3c, 0xb2, 0xa1] => (false, true),
+        [0xa1, 0xb2, 0x3c, 0x4d] => (true, true),
+        _ => return Err(invalid("not a pcap file")),
+    };
+
+    let u32_at = |buf: &[u8], idx: usize| {
+        let v = [buf[idx], buf[idx + 1], buf[idx + 2], buf[idx + 3]];
+        match big_endian {
+            true => u32::from_be_bytes(v),
+            false => u32::from_le_bytes(v),
+        }
+    };
+
+    let linktype = u32_at(&header, 20) & 0x0fff_ffff;
+
+    let mut datagrams = Vec::new();
+    let mut record = [0u8; 16];
+
+    loop {
+        match rd.read_exact(&mut record) {
+            Ok(()) => {}
+            Err(ref err) if err.kind() == ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        }
+
+        let secs = u32_at(&record, 0);
+        let frac = u32_at(&record, 4);
+        let len = u32_at(&record, 8) as usize;
+
+        let mut packet = vec![0; len];
+        rd.read_exact(&mut packet)?;
+
+        let frac = match nanos {
+            true => Duration::from_nanos(u64::from(frac)),
+            false => Duration::from_micros(u64::from(frac)),
+        };
+        let at = UNIX_EPOCH + Duration::from_secs(u64::from(secs)) + frac;
+
+        if let Some((addr, buf)) = decode_packet(linktype, &packet) {
+            datagrams.push(Datagram {
+                at,
+                addr,
+                buf: buf.to_vec(),
+            });
+        }
+    }
+
+    Ok(datagrams)
+}
+
+/// Extracts the UDP source address and payload from a captured link-layer packet.
+fn decode_packet(linktype: u32, buf: &[u8]) -> Option<(SocketAddr, &[u8])> {
+    let (ethertype, buf) = match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut ethertype = u16::from_be_bytes([*buf.get(12)?, *buf.get(13)?]);
+            let mut offset = 14;
+            if ethertype == ETHERTYPE_VLAN {
+                ethertype = u16::from_be_bytes([*buf.get(16)?, *buf.get(17)?]);
+                offset += 4;
+            }
+            (ethertype, buf.get(offset..)?)
+        }
+        LINKTYPE_LINUX_SLL => (u16::from_be_bytes([*buf.get(14)?, *buf.get(15)?]), buf.get(16..)?),
+        LINKTYPE_RAW => match buf.first()? >> 4 {
+            4 => (ETHERTYPE_IPV4, buf),
+            6 => (ETHERTYPE_IPV6, buf),
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (ip, buf): (IpAddr, _) = match ethertype {
+        ETHERTYPE_IPV4 => {
+            let ihl = usize::from(buf.first()? & 0x0f) * 4;
+            let fragment = u16::from_be_bytes([*buf.get(6)?, *buf.get(7)?]);
+            // Skip fragments: either "more fragments" flag or non-zero offset is set.
+            if fragment & 0x3fff != 0 || *buf.get(9)? != IPPROTO_UDP {
+                return None;
+            }
+            let src = [*buf.get(12)?, *buf.get(13)?, *buf.get(14)?, *buf.get(15)?];
+            (Ipv4Addr::from(src).into(), buf.get(ihl..)?)
+        }
+        ETHERTYPE_IPV6 => {
+            if *buf.get(6)? != IPPROTO_UDP {
+                return None;
+            }
+            let mut src = [0; 16];
+            src.copy_from_slice(buf.get(8..24)?);
+            (Ipv6Addr::from(src).into(), buf.get(40..)?)
+        }
+        _ => return None,
+    };
+
+    let port = u16::from_be_bytes([*buf.first()?, *buf.get(1)?]);
+    let len = usize::from(u16::from_be_bytes([*buf.get(4)?, *buf.get(5)?]));
+
+    Some((SocketAddr::new(ip, port), buf.get(8..len)?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    const CID: Cid = Cid::new(*b"XXXXXXXXXXXXXXX\0");
+    use crate::{
+        protocol::{CHANNEL_HEADER_SIZE, VIDEO_CHANNEL, VIDEO_SSRC},
+        rtp::Gap,
+        Stalled,
+    };
+
+    fn rtp(seq: u16) -> Vec<u8> {
+        let mut buf = vec![0x00, 0x00, VIDEO_CHANNEL, 0x00];
+        buf.extend_from_slice(&[0x80, 0x60]);
+        buf.extend_from_slice(&seq.to_be_bytes());
+        buf.extend_from_slice(&0u32.to_be_bytes());
+        buf.extend_from_slice(&VIDEO_SSRC.to_be_bytes());
+        buf.extend_from_slice(b"payload");
+        buf
+    }
+
+    /// Encodes the given datagrams into an Ethernet pcap capture.
+    fn pcap(datagrams: &[Datagram]) -> Vec<u8> {
+        let mut buf = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        buf.extend_from_slice(&[0; 8]);
+        buf.extend_from_slice(&65535u32.to_le_bytes());
+        buf.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+
+        for datagram in datagrams {
+            let ts = datagram.at.duration_since(UNIX_EPOCH).unwrap();
+            let ip = match datagram.addr.ip() {
+                IpAddr::V4(ip) => ip.octets(),
+                IpAddr::V6(..) => unreachable!(),
+            };
+
+            let mut packet = vec![0; 12];
+            packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+            packet.extend_from_slice(&[0x45, 0, 0, 0, 0, 0, 0x40, 0, 64, IPPROTO_UDP, 0, 0]);
+            packet.extend_from_slice(&ip);
+            packet.extend_from_slice(&[192, 168, 1, 2]);
+            packet.extend_from_slice(&datagram.addr.port().to_be_bytes());
+            packet.extend_from_slice(&40000u16.to_be_bytes());
+            packet.extend_from_slice(&(8 + datagram.buf.len() as u16).to_be_bytes());
+            packet.extend_from_slice(&[0, 0]);
+            packet.extend_from_slice(&datagram.buf);
+
+            buf.extend_from_slice(&(ts.as_secs() as u32).to_le_bytes());
+            buf.extend_from_slice(&ts.subsec_micros().to_le_bytes());
+            buf.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&(packet.len() as u32).to_le_bytes());
+            buf.extend_from_slice(&packet);
+        }
+
+        buf
+    }
+
+    fn datagrams() -> Vec<Datagram> {
+        let addr = "192.168.1.71:8000".parse().unwrap();
+
+        (0..30)
+            .map(|seq| Datagram {
+                at: UNIX_EPOCH + Duration::from_secs(1_500_000_000) + Duration::from_millis(100 * seq as u64),
+                addr,
+                buf: rtp(seq),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tap_bypasses_filters() {
+        let mut datagrams = datagrams();
+        datagrams[10].buf = b"garbage".to_vec();
+        let mut replay = Replay::new(datagrams.clone());
+
+        let (tx, rx) = mpsc::channel();
+        let opts = StreamOptions::new().tap(move |buf: &[u8]| -> Result<(), Box<dyn Error>> {
+            tx.send(buf.to_vec())?;
+            Ok(())
+        });
+        replay.run(&CID, &opts, |_buf| Ok(())).unwrap();
+
+        let tapped: Vec<_> = rx.try_iter().collect();
+        assert_eq!(datagrams.into_iter().map(|v| v.buf).collect::<Vec<_>>(), tapped);
+        assert_eq!(29, replay.stats().packets_delivered);
+    }
+
+    #[test]
+    fn test_ssrc_filter() {
+        let mut datagrams = datagrams();
+        datagrams[10].buf[CHANNEL_HEADER_SIZE + 8..CHANNEL_HEADER_SIZE + 12].copy_from_slice(&42u32.to_be_bytes());
+
+        let mut replay = Replay::new(datagrams.clone());
+        replay.run(&CID, &StreamOptions::new(), |_buf| Ok(())).unwrap();
+        assert_eq!(29, replay.stats().packets_delivered);
+        assert_eq!(1, replay.stats().packets_non_video);
+
+        let mut replay = Replay::new(datagrams);
+        let opts = StreamOptions::new().ssrc(None);
+        replay.run(&CID, &opts, |_buf| Ok(())).unwrap();
+        assert_eq!(30, replay.stats().packets_delivered);
+    }
+
+    #[test]
+    fn test_invalid_datagrams_restart() {
+        let mut datagrams = datagrams();
+        for datagram in datagrams.iter_mut().filter(|v| v.buf[7] % 4 != 0) {
+            datagram.buf[CHANNEL_HEADER_SIZE] = 0x40;
+        }
+
+        let opts = StreamOptions::new().stall_timeout(Duration::from_secs(1)).restarts(5);
+        let mut replay = Replay::new(datagrams.clone());
+        replay.run(&CID, &opts, |_buf| Ok(())).unwrap();
+        assert_eq!(22, replay.stats().packets_invalid);
+        assert_eq!(2, replay.stats().restarts);
+
+        let mut replay = Replay::new(datagrams.clone());
+        let err = replay.run(
+            &CID,
+            &StreamOptions::new().stall_timeout(Duration::from_secs(1)),
+            |_buf| Ok(()),
+        );
+        assert_eq!(0, err.unwrap_err().downcast_ref::<Stalled>().unwrap().restarts());
+
+        let opts = opts.min_valid_ratio(None);
+        let mut replay = Replay::new(datagrams);
+        replay.run(&CID, &opts, |_buf| Ok(())).unwrap();
+        assert_eq!(0, replay.stats().restarts);
+        assert_eq!(8, replay.stats().packets_delivered);
+    }
+
+    #[test]
+    fn test_jitter_buffer_reorders() {
+        let mut datagrams = datagrams();
+        for idx in (0..30).step_by(3) {
+            let at = datagrams[idx].at;
+            datagrams[idx].at = datagrams[idx + 1].at;
+            datagrams[idx + 1].at = at;
+            datagrams.swap(idx, idx + 1);
+        }
+
+        let mut seqs = Vec::new();
+        let opts = StreamOptions::new().jitter_buffer(4, Duration::from_millis(500));
+        Replay::new(datagrams)
+            .run(&CID, &opts, |packet| {
+                seqs.push(packet.header().sequence_number());
+                Ok(())
+            })
+            .unwrap();
+
+        // The tail is still held once the replay ends.
+        assert!(seqs.len() >= 25);
+        assert_eq!((0..seqs.len() as u16).collect::<Vec<_>>(), seqs);
+    }
+
+    #[test]
+    fn test_gap() {
+        let mut datagrams = datagrams();
+        datagrams.drain(10..12);
+
+        let mut gaps = Vec::new();
+        let mut replay = Replay::new(datagrams);
+        replay
+            .run(&CID, &StreamOptions::new(), |packet| {
+                gaps.extend(packet.gap().map(|v| (packet.header().sequence_number(), v)));
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(vec![(12, Gap::new(10, 2))], gaps);
+        assert_eq!(1, replay.stats().gaps);
+        assert_eq!(2, replay.stats().packets_lost);
+    }
+
+    #[test]
+    fn test_nack() {
+        let mut datagrams = datagrams();
+        // Packet 10 is lost on the way and arrives after being requested.
+        let lost = datagrams.remove(10);
+        datagrams.insert(
+            20,
+            Datagram {
+                at: datagrams[20].at,
+                ..lost
+            },
+        );
+
+        let opts = StreamOptions::new().nack(true);
+        let mut replay = Replay::new(datagrams);
+        replay.run(&CID, &opts, |_buf| Ok(())).unwrap();
+
+        assert_eq!(1, replay.stats().nacks_sent);
+        assert_eq!(1, replay.stats().packets_recovered);
+        let nack = replay
+            .sent()
+            .iter()
+            .find(|v| v.buf[CHANNEL_HEADER_SIZE + 1] == 205)
+            .unwrap();
+        assert_eq!(&[0, 10, 0, 0], &nack.buf[nack.buf.len() - 4..]);
+    }
+
+    #[test]
+    fn test_read_pcap() {
+        let datagrams = datagrams();
+        assert_eq!(datagrams, read_pcap(&pcap(&datagrams)[..]).unwrap());
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut replay = Replay::new(datagrams());
+
+        let mut seqs = Vec::new();
+        replay
+            .run(&CID, &StreamOptions::new(), |buf| {
+                assert_eq!(&rtp(seqs.len() as u16)[CHANNEL_HEADER_SIZE..], buf.as_bytes());
+                seqs.push(());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(30, seqs.len());
+        assert_eq!(30, replay.stats().packets_delivered);
+
+        // StartRtp, followed by RTCP reports once per virtual second over 2.9 seconds.
+        let sent = replay.sent();
+        assert_eq!(3, sent.len());
+        assert_eq!(2, replay.stats().rtcp_sent);
+    }
+}
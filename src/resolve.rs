@@ -0,0 +1,148 @@
+//! Hostname resolution for outgoing connections.
+//!
+//! Resolvers are queried on every connection attempt instead of once at startup, so that a relay
+//! endpoint moved to another address through DNS is picked up by a long-running session on the
+//! next reconnect.
+
+use core::time::Duration;
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+};
+
+use log::debug;
+
+/// Resolves hostnames into socket addresses.
+pub trait Resolver {
+    /// Resolves the given host into a list of addresses with the specified port, in the order of
+    /// preference.
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, io::Error>;
+}
+
+/// Resolver backed by the system resolver, i.e. `getaddrinfo(3)`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, io::Error> {
+        Ok((host, port).to_socket_addrs()?.collect())
+    }
+}
+
+/// Resolver with statically configured addresses for some hosts, delegating all other hosts to
+/// the fallback resolver.
+///
+/// ```
+/// use cleverdog::resolve::{Resolver, StaticResolver, SystemResolver};
+///
+/// let resolver = StaticResolver::new(SystemResolver).with("relay.example.com", "10.0.0.1".parse().unwrap());
+///
+/// let addrs = resolver.resolve("relay.example.com", 443).unwrap();
+/// assert_eq!("10.0.0.1:443", addrs[0].to_string());
+/// ```
+#[derive(Debug, Clone)]
+pub struct StaticResolver<R> {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: R,
+}
+
+impl<R: Resolver> StaticResolver<R> {
+    /// Constructs a new resolver without overrides.
+    pub fn new(fallback: R) -> Self {
+        Self {
+            hosts: HashMap::new(),
+            fallback,
+        }
+    }
+
+    /// Adds the given address for the host.
+    ///
+    /// Multiple addresses of the same host are tried in the order they were added.
+    pub fn push(&mut self, host: &str, addr: IpAddr) {
+        self.hosts.entry(host.to_ascii_lowercase()).or_default().push(addr);
+    }
+
+    /// Adds the given address for the host, returning `self` for chaining.
+    pub fn with(mut self, host: &str, addr: IpAddr) -> Self {
+        self.push(host, addr);
+        self
+    }
+}
+
+impl<R: Resolver> Resolver for StaticResolver<R> {
+    fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, io::Error> {
+        match self.hosts.get(&host.to_ascii_lowercase()) {
+            Some(addrs) => Ok(addrs.iter().map(|&ip| SocketAddr::new(ip, port)).collect()),
+            None => self.fallback.resolve(host, port),
+        }
+    }
+}
+
+/// Resolves the host and connects to the first address accepting a connection.
+///
+/// Each address is given the specified timeout. The error of the last attempt is returned if
+/// none of addresses are reachable.
+pub fn connect<R>(resolver: &R, host: &str, port: u16, timeout: Duration) -> Result<TcpStream, io::Error>
+where
+    R: Resolver + ?Sized,
+{
+    let addrs = resolver.resolve(host, port)?;
+    debug!("resolved {} into {:?}", host, addrs);
+
+    let mut last = io::Error::new(ErrorKind::NotFound, format!("no addresses for {}", host));
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => {
+                debug!("failed to connect to {}: {}", addr, err);
+                last = err;
+            }
+        }
+    }
+
+    Err(last)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_system_resolver_literal() {
+        assert_eq!(
+            vec!["127.0.0.1:80".parse::<SocketAddr>().unwrap()],
+            SystemResolver.resolve("127.0.0.1", 80).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_static_resolver_fallback() {
+        let resolver = StaticResolver::new(SystemResolver).with("Relay", "10.0.0.1".parse().unwrap());
+
+        assert_eq!(
+            vec!["10.0.0.1:443".parse::<SocketAddr>().unwrap()],
+            resolver.resolve("relay", 443).unwrap()
+        );
+        assert_eq!(
+            vec!["127.0.0.1:443".parse::<SocketAddr>().unwrap()],
+            resolver.resolve("127.0.0.1", 443).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_connect_failover() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Nothing listens on the first address.
+        let resolver = StaticResolver::new(SystemResolver)
+            .with("relay", "127.0.0.2".parse().unwrap())
+            .with("relay", "127.0.0.1".parse().unwrap());
+
+        let stream = connect(&resolver, "relay", port, Duration::from_secs(1)).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), stream.peer_addr().unwrap());
+    }
+}
@@ -0,0 +1,271 @@
+//! Retry policies shared by discovery, streaming and uplink reconnects.
+//!
+//! A [`RetryPolicy`] describes the delays between attempts: fixed or exponentially growing,
+//! optionally randomized by jitter, so that several relays restarted at once do not retry in
+//! lockstep. The delays can be consumed directly, e.g. as attempt windows, or by [`retry`], which
+//! also classifies errors into retryable and fatal ones.
+
+use core::time::Duration;
+use std::{
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::debug;
+
+/// Growth of delays between attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// The same delay before each attempt.
+    Fixed(Duration),
+    /// Delay starting at `initial` and doubling before each attempt, up to `max`.
+    Exponential {
+        /// The first delay.
+        initial: Duration,
+        /// Upper bound of the delay.
+        max: Duration,
+    },
+}
+
+/// Policy of retrying a failed operation.
+///
+/// ```
+/// use core::time::Duration;
+///
+/// use cleverdog::retry::RetryPolicy;
+///
+/// let policy = RetryPolicy::exponential(Duration::from_millis(250), Duration::from_secs(1)).retries(4);
+///
+/// assert_eq!(
+///     vec![250, 500, 1000, 1000],
+///     policy.delays().map(|v| v.as_millis()).collect::<Vec<_>>()
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    backoff: Backoff,
+    jitter: f64,
+    retries: Option<u32>,
+}
+
+impl RetryPolicy {
+    /// Constructs a new policy retrying forever with the given fixed delay.
+    pub fn fixed(delay: Duration) -> Self {
+        Self::new(Backoff::Fixed(delay))
+    }
+
+    /// Constructs a new policy retrying forever with an exponentially growing delay.
+    pub fn exponential(initial: Duration, max: Duration) -> Self {
+        Self::new(Backoff::Exponential { initial, max })
+    }
+
+    /// Constructs a new policy retrying forever with the given backoff.
+    pub fn new(backoff: Backoff) -> Self {
+        Self {
+            backoff,
+            jitter: 0.0,
+            retries: None,
+        }
+    }
+
+    /// Limits the number of retries, i.e. attempts following the first one.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    /// Randomizes each delay by up to the given fraction of it in either direction, e.g. `0.2`
+    /// for ±20%.
+    ///
+    /// Defaults to no jitter.
+    pub fn jitter(mut self, ratio: f64) -> Self {
+        self.jitter = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns the backoff.
+    #[inline]
+    pub fn backoff(&self) -> Backoff {
+        self.backoff
+    }
+
+    /// Returns delays before each retry, ending once retries are exhausted.
+    pub fn delays(&self) -> Delays {
+        let initial = match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, .. } => initial,
+        };
+
+        Delays {
+            policy: *self,
+            next: initial,
+            left: self.retries,
+            rng: seed(),
+        }
+    }
+}
+
+/// Iterator over delays of a [`RetryPolicy`].
+#[derive(Debug, Clone)]
+pub struct Delays {
+    policy: RetryPolicy,
+    next: Duration,
+    left: Option<u32>,
+    rng: u64,
+}
+
+impl Delays {
+    /// Returns a random number in `[-1.0, 1.0]`.
+    fn random(&mut self) -> f64 {
+        // Xorshift64, which is plenty for spreading retries apart.
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+}
+
+impl Iterator for Delays {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(left) = &mut self.left {
+            *left = left.checked_sub(1)?;
+        }
+
+        let delay = self.next;
+        if let Backoff::Exponential { max, .. } = self.policy.backoff {
+            self.next = self.next.saturating_mul(2).min(max);
+        }
+
+        match self.policy.jitter > 0.0 {
+            true => Some(delay.mul_f64(1.0 + self.policy.jitter * self.random())),
+            false => Some(delay),
+        }
+    }
+}
+
+/// Returns a jitter seed differing between processes and calls.
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_nanos() as u64)
+        .unwrap_or_default();
+    let local = 0u8;
+
+    // Xorshift never leaves the zero state.
+    (nanos ^ (&local as *const u8 as u64).rotate_left(32)) | 1
+}
+
+/// Runs the operation until it succeeds, fails with an error the classifier deems fatal, or the
+/// retries of the policy are exhausted, sleeping between attempts.
+///
+/// The operation is given the zero-based attempt number. The error of the last attempt is
+/// returned.
+///
+/// ```
+/// use core::time::Duration;
+/// use std::io::{self, ErrorKind};
+///
+/// use cleverdog::retry::{self, RetryPolicy};
+///
+/// let policy = RetryPolicy::fixed(Duration::from_millis(1)).retries(5);
+/// let result = retry::retry(
+///     &policy,
+///     |err: &io::Error| err.kind() != ErrorKind::PermissionDenied,
+///     |attempt| match attempt {
+///         0 | 1 => Err(io::Error::new(ErrorKind::TimedOut, "timed out")),
+///         n => Ok(n),
+///     },
+/// );
+///
+/// assert_eq!(2, result.unwrap());
+/// ```
+pub fn retry<T, E, C, F>(policy: &RetryPolicy, mut classify: C, mut op: F) -> Result<T, E>
+where
+    C: FnMut(&E) -> bool,
+    F: FnMut(u32) -> Result<T, E>,
+{
+    let mut delays = policy.delays();
+
+    for attempt in 0.. {
+        let err = match op(attempt) {
+            Ok(v) => return Ok(v),
+            Err(err) if classify(&err) => err,
+            Err(err) => return Err(err),
+        };
+
+        match delays.next() {
+            Some(delay) => {
+                debug!("attempt {} failed, retrying in {:?}", attempt + 1, delay);
+                thread::sleep(delay);
+            }
+            None => return Err(err),
+        }
+    }
+
+    unreachable!("attempts must not overflow")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fixed() {
+        let delays: Vec<_> = RetryPolicy::fixed(Duration::from_secs(1)).retries(3).delays().collect();
+        assert_eq!(vec![Duration::from_secs(1); 3], delays);
+
+        assert_eq!(100, RetryPolicy::fixed(Duration::ZERO).delays().take(100).count());
+    }
+
+    #[test]
+    fn test_jitter() {
+        let policy = RetryPolicy::fixed(Duration::from_secs(10)).jitter(0.2);
+
+        let delays: Vec<_> = policy.delays().take(1000).collect();
+        assert!(delays
+            .iter()
+            .all(|v| *v >= Duration::from_secs(8) && *v <= Duration::from_secs(12)));
+        assert!(delays.iter().any(|v| *v != delays[0]));
+    }
+
+    #[test]
+    fn test_retry_stops_on_fatal_error() {
+        let policy = RetryPolicy::fixed(Duration::ZERO).retries(10);
+
+        let mut attempts = 0;
+        let result: Result<(), &str> = retry(
+            &policy,
+            |err| *err != "fatal",
+            |attempt| {
+                attempts += 1;
+                match attempt {
+                    0 => Err("transient"),
+                    _ => Err("fatal"),
+                }
+            },
+        );
+
+        assert_eq!(Err("fatal"), result);
+        assert_eq!(2, attempts);
+    }
+
+    #[test]
+    fn test_retry_exhausted() {
+        let policy = RetryPolicy::fixed(Duration::ZERO).retries(2);
+
+        let mut attempts = 0;
+        let result: Result<(), u32> = retry(
+            &policy,
+            |_| true,
+            |attempt| {
+                attempts += 1;
+                Err(attempt)
+            },
+        );
+
+        assert_eq!(Err(2), result);
+        assert_eq!(3, attempts);
+    }
+}
@@ -0,0 +1,349 @@
+//! RTSP publishing and serving of the relayed video stream.
+//!
+//! Media servers like MediaMTX accept streams pushed by clients using the RTSP `ANNOUNCE` and
+//! `RECORD` methods, after which NVRs and players consume them as from any other RTSP camera. RTP
+//! packets received from the camera are forwarded unchanged, interleaved into the RTSP connection,
+//! which avoids any firewall or NAT issues with UDP ports.
+//!
+//! Without a media server at hand, the built-in [`Server`] exposes the camera to players directly.
+
+use core::time::Duration;
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Shutdown, TcpStream},
+    thread::{self, JoinHandle},
+};
+
+use log::{debug, info};
+
+pub use self::{
+    server::Server,
+    template::{PathTemplate, TemplateParseError},
+};
+use crate::{
+    resolve::{self, Resolver},
+    sink::{Endpoint, Sink},
+};
+
+mod server;
+mod template;
+
+/// Upper bound of the message head size, protecting against misbehaving peers.
+const MAX_RESPONSE_SIZE: usize = 16 * 1024;
+
+/// Interleaved channel RTP packets are sent on.
+const RTP_CHANNEL: u8 = 0;
+
+/// Publisher pushing the camera RTP stream to an RTSP server.
+///
+/// Implements [`Sink`], so RTP packets produced by [`stream`](crate::stream) can be passed to it
+/// directly. Data sent by the server, e.g. RTCP receiver reports, is drained and discarded by a
+/// background thread.
+///
+/// ```no_run
+/// use core::time::Duration;
+///
+/// use cleverdog::{resolve::SystemResolver, rtsp::Publisher, sink::Endpoint, sink::Sink};
+///
+/// let endpoint = Endpoint::new("mediamtx.local", 8554);
+/// let mut publisher = Publisher::connect(&SystemResolver, &endpoint, "front", Duration::from_secs(5)).unwrap();
+///
+/// let info = cleverdog::lookup().unwrap();
+/// cleverdog::stream(info.cid(), info.addr(), |buf| publisher.send(buf)).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Publisher {
+    url: String,
+    stream: TcpStream,
+    reader: Option<JoinHandle<()>>,
+}
+
+impl Publisher {
+    /// Connects to the RTSP server at the given endpoint and starts publishing under the specified
+    /// path, resolving the server with the given resolver.
+    ///
+    /// The timeout applies to establishing the connection, to each request of the handshake and to
+    /// sending packets afterwards.
+    pub fn connect<R>(resolver: &R, endpoint: &Endpoint, path: &str, timeout: Duration) -> Result<Self, io::Error>
+    where
+        R: Resolver + ?Sized,
+    {
+        let url = format!("rtsp://{}/{}", endpoint, path.trim_start_matches('/'));
+        let stream = resolve::connect(resolver, endpoint.host(), endpoint.port(), timeout)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+
+        let mut cx = Handshake {
+            url: &url,
+            stream: &stream,
+            rd: BufReader::new(stream.try_clone()?),
+            cseq: 0,
+        };
+
+        let sdp = sdp(path);
+        cx.request("ANNOUNCE", &url, &[("Content-Type", "application/sdp")], sdp.as_bytes())?;
+
+        let head = cx.request(
+            "SETUP",
+            &format!("{}/trackID=0", url),
+            &[("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1;mode=record")],
+            b"",
+        )?;
+        let session = header(&head, "Session")
+            .and_then(|v| v.split(';').next())
+            .map(|v| v.trim().to_string())
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "SETUP response carries no session"))?;
+
+        cx.request("RECORD", &url, &[("Session", &session), ("Range", "npt=0.000-")], b"")?;
+        info!("publishing to {}", url);
+
+        let mut rd = cx.rd;
+        stream.set_read_timeout(None)?;
+        let reader = thread::Builder::new().name("rtsp-reader".into()).spawn(move || {
+            let mut buf = [0; 4096];
+            while let Ok(1..) = rd.read(&mut buf) {}
+        })?;
+
+        let publisher = Self {
+            url,
+            stream,
+            reader: Some(reader),
+        };
+
+        Ok(publisher)
+    }
+
+    /// Returns the URL the stream is published at.
+    #[inline]
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+}
+
+impl Sink for Publisher {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        if buf.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "packet is too large to interleave").into());
+        }
+
+        let mut frame = Vec::with_capacity(4 + buf.len());
+        frame.extend_from_slice(&[b'$', RTP_CHANNEL]);
+        frame.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+        frame.extend_from_slice(buf);
+        self.stream.write_all(&frame)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        // Closing the connection ends the session on the server and wakes the reader up.
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(reader) = self.reader.take() {
+            let _ = reader.join();
+        }
+    }
+}
+
+/// State of the request/response exchange preceding the publishing.
+struct Handshake<'a> {
+    url: &'a str,
+    stream: &'a TcpStream,
+    rd: BufReader<TcpStream>,
+    cseq: u32,
+}
+
+impl Handshake<'_> {
+    /// Sends a request and reads the response, returning its header lines.
+    fn request(
+        &mut self,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<Vec<String>, io::Error> {
+        self.cseq += 1;
+
+        let mut request = format!("{} {} RTSP/1.0\r\nCSeq: {}\r\n", method, uri, self.cseq);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !body.is_empty() {
+            request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        request.push_str("User-Agent: cleverdog\r\n\r\n");
+
+        let mut buf = request.into_bytes();
+        buf.extend_from_slice(body);
+        self.stream.write_all(&buf)?;
+
+        let (status, head) = self.read_response()?;
+        debug!("{} {} -> {}", method, self.url, status);
+
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(head),
+            Some("401") => Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} of {} is not authorized: {}", method, self.url, status),
+            )),
+            _ => Err(io::Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("{} of {} failed: {}", method, self.url, status),
+            )),
+        }
+    }
+
+    /// Reads the response status line and headers, skipping the body.
+    fn read_response(&mut self) -> Result<(String, Vec<String>), io::Error> {
+        let mut status = String::new();
+        self.rd.read_line(&mut status)?;
+        let status = status.trim_end().to_string();
+        if !status.starts_with("RTSP/") {
+            return Err(io::Error::new(ErrorKind::InvalidData, "invalid RTSP response"));
+        }
+
+        let mut head = Vec::new();
+        let mut size = status.len();
+        loop {
+            let mut line = String::new();
+            let len = self.rd.read_line(&mut line)?;
+            size += len;
+            if len == 0 || size > MAX_RESPONSE_SIZE {
+                return Err(io::Error::new(ErrorKind::InvalidData, "invalid RTSP response"));
+            }
+            if line == "\r\n" || line == "\n" {
+                break;
+            }
+            head.push(line.trim_end().to_string());
+        }
+
+        let len = header(&head, "Content-Length")
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .unwrap_or(0);
+        io::copy(&mut self.rd.by_ref().take(len), &mut io::sink())?;
+
+        Ok((status, head))
+    }
+}
+
+/// Returns the value of the given header, matching its name case-insensitively.
+fn header<'a>(head: &'a [String], name: &str) -> Option<&'a str> {
+    head.iter().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        match key.trim().eq_ignore_ascii_case(name) {
+            true => Some(value.trim()),
+            false => None,
+        }
+    })
+}
+
+/// Returns the session description of the relayed H.264 stream.
+fn sdp(name: &str) -> String {
+    format!(
+        "v=0\r\n\
+         o=- 0 0 IN IP4 127.0.0.1\r\n\
+         s={}\r\n\
+         c=IN IP4 0.0.0.0\r\n\
+         t=0 0\r\n\
+         m=video 0 RTP/AVP 96\r\n\
+         a=rtpmap:96 H264/90000\r\n\
+         a=fmtp:96 packetization-mode=1\r\n\
+         a=control:trackID=0\r\n",
+        name
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::resolve::SystemResolver;
+
+    /// Reads a single request, returning its head and body.
+    fn read_request(rd: &mut BufReader<TcpStream>) -> (Vec<String>, Vec<u8>) {
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            rd.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push(line.trim_end().to_string());
+        }
+
+        let len = header(&head, "Content-Length").map(|v| v.parse().unwrap()).unwrap_or(0);
+        let mut body = vec![0; len];
+        rd.read_exact(&mut body).unwrap();
+
+        (head, body)
+    }
+
+    #[test]
+    fn test_publish() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let server = thread::spawn(move || {
+            let (mut stream, ..) = listener.accept().unwrap();
+            let mut rd = BufReader::new(stream.try_clone().unwrap());
+
+            let mut methods = Vec::new();
+            for (idx, session) in ["", "Session: 12345678;timeout=60\r\n", ""].iter().enumerate() {
+                let (head, body) = read_request(&mut rd);
+                methods.push(head[0].clone());
+                if idx == 0 {
+                    assert!(String::from_utf8(body).unwrap().contains("a=rtpmap:96 H264/90000"));
+                }
+                if idx == 2 {
+                    assert_eq!(Some("12345678"), header(&head, "Session"));
+                }
+                let response = format!("RTSP/1.0 200 OK\r\nCSeq: {}\r\n{}\r\n", idx + 1, session);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+
+            let mut frame = [0; 4 + 5];
+            rd.read_exact(&mut frame).unwrap();
+
+            (methods, frame)
+        });
+
+        let endpoint = Endpoint::new("127.0.0.1", port);
+        let mut publisher = Publisher::connect(&SystemResolver, &endpoint, "front", Duration::from_secs(5)).unwrap();
+        publisher.send(b"\x80rtp!").unwrap();
+
+        let (methods, frame) = server.join().unwrap();
+        let url = format!("rtsp://127.0.0.1:{}/front", port);
+        assert_eq!(
+            vec![
+                format!("ANNOUNCE {} RTSP/1.0", url),
+                format!("SETUP {}/trackID=0 RTSP/1.0", url),
+                format!("RECORD {} RTSP/1.0", url),
+            ],
+            methods
+        );
+        assert_eq!(url, publisher.url());
+        assert_eq!(b"$\x00\x00\x05\x80rtp!", &frame);
+    }
+
+    #[test]
+    fn test_unauthorized() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            let (mut stream, ..) = listener.accept().unwrap();
+            let mut rd = BufReader::new(stream.try_clone().unwrap());
+            read_request(&mut rd);
+            stream
+                .write_all(b"RTSP/1.0 401 Unauthorized\r\nCSeq: 1\r\nContent-Length: 4\r\n\r\nnope")
+                .unwrap();
+        });
+
+        let endpoint = Endpoint::new("127.0.0.1", port);
+        let err = Publisher::connect(&SystemResolver, &endpoint, "front", Duration::from_secs(5)).unwrap_err();
+        assert_eq!(ErrorKind::PermissionDenied, err.kind());
+    }
+}
@@ -0,0 +1,528 @@
+use core::time::Duration;
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+
+use super::{header, sdp, MAX_RESPONSE_SIZE, RTP_CHANNEL};
+use crate::sink::Sink;
+
+/// Time a write to a client may block before the client is dropped, so that a stalled player
+/// does not hold the camera stream up.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Session timeout announced to clients, in seconds.
+const SESSION_TIMEOUT: u32 = 60;
+
+/// Methods the server implements, as listed in responses to `OPTIONS`.
+const PUBLIC: &str = "OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN, GET_PARAMETER";
+
+/// Connection of a client that issued `PLAY`.
+#[derive(Debug)]
+struct Client {
+    session: String,
+    peer: SocketAddr,
+    stream: Arc<Mutex<TcpStream>>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    path: String,
+    clients: Mutex<Vec<Client>>,
+    sessions: AtomicU32,
+    stopped: AtomicBool,
+}
+
+/// Minimal RTSP server re-exposing the camera RTP stream, so that any player or NVR can consume
+/// it as a regular RTSP source.
+///
+/// Clients are served with `DESCRIBE`, `SETUP` and `PLAY`, and receive packets interleaved into
+/// their RTSP connection. Only the TCP transport is offered, which avoids any firewall or NAT
+/// issues with UDP ports; players requesting UDP are answered with `461 Unsupported Transport`
+/// and usually retry over TCP.
+///
+/// Implements [`Sink`], so RTP packets produced by [`stream`](crate::stream) can be passed to it
+/// directly. Packets are sent to every playing client, and clients failing to keep up are
+/// dropped.
+///
+/// ```no_run
+/// use cleverdog::{rtsp::Server, sink::Sink};
+///
+/// let mut server = Server::bind("0.0.0.0:8554", "live").unwrap();
+/// println!("serving at {}", server.url());
+///
+/// let info = cleverdog::lookup().unwrap();
+/// cleverdog::stream(info.cid(), info.addr(), |buf| server.send(buf)).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Server {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    acceptor: Option<JoinHandle<()>>,
+    buf: Vec<u8>,
+}
+
+impl Server {
+    /// Binds to the given address and starts accepting clients of the stream under the
+    /// specified path.
+    pub fn bind<A: ToSocketAddrs>(addr: A, path: &str) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+
+        let shared = Arc::new(Shared {
+            path: path.trim_matches('/').into(),
+            clients: Mutex::new(Vec::new()),
+            sessions: AtomicU32::new(0),
+            stopped: AtomicBool::new(false),
+        });
+
+        let acceptor = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("rtsp-server".into())
+                .spawn(move || accept(listener, shared))?
+        };
+
+        let server = Self {
+            addr,
+            shared,
+            acceptor: Some(acceptor),
+            buf: Vec::new(),
+        };
+        info!("serving at {}", server.url());
+
+        Ok(server)
+    }
+
+    /// Returns the address the server is bound to.
+    #[inline]
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the URL the stream is served at.
+    pub fn url(&self) -> String {
+        format!("rtsp://{}/{}", self.addr, self.shared.path)
+    }
+
+    /// Returns the number of clients currently playing the stream.
+    pub fn clients(&self) -> usize {
+        self.shared.clients.lock().expect("lock must not be poisoned").len()
+    }
+}
+
+impl Sink for Server {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        if buf.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "packet is too large to interleave").into());
+        }
+
+        let frame = &mut self.buf;
+        frame.clear();
+        frame.extend_from_slice(&[b'$', RTP_CHANNEL]);
+        frame.extend_from_slice(&(buf.len() as u16).to_be_bytes());
+        frame.extend_from_slice(buf);
+
+        let mut clients = self.shared.clients.lock().expect("lock must not be poisoned");
+        clients.retain(|client| {
+            let mut stream = client.stream.lock().expect("lock must not be poisoned");
+            match stream.write_all(frame) {
+                Ok(()) => true,
+                Err(err) => {
+                    info!("dropping client {}: {}", client.peer, err);
+                    // Wakes the connection thread up, which then exits.
+                    let _ = stream.shutdown(Shutdown::Both);
+                    false
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::SeqCst);
+
+        // Wake the acceptor up with a connection of our own.
+        let mut addr = self.addr;
+        match addr {
+            SocketAddr::V4(..) if addr.ip().is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+            SocketAddr::V6(..) if addr.ip().is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+            _ => {}
+        }
+        let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        if let Some(acceptor) = self.acceptor.take() {
+            let _ = acceptor.join();
+        }
+
+        for client in self.shared.clients.lock().expect("lock must not be poisoned").drain(..) {
+            let _ = client
+                .stream
+                .lock()
+                .expect("lock must not be poisoned")
+                .shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Accepts clients until the server is stopped, serving each on its own thread.
+fn accept(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        if shared.stopped.load(Ordering::SeqCst) {
+            break;
+        }
+
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("failed to accept RTSP client: {}", err);
+                continue;
+            }
+        };
+
+        let shared = shared.clone();
+        let spawned = thread::Builder::new().name("rtsp-client".into()).spawn(move || {
+            let peer = stream.peer_addr();
+            if let Err(err) = serve(stream, &shared) {
+                debug!("RTSP client {:?} disconnected: {}", peer, err);
+            }
+        });
+        if let Err(err) = spawned {
+            warn!("failed to spawn RTSP client thread: {}", err);
+        }
+    }
+}
+
+/// State of a single client connection.
+struct Connection {
+    session: String,
+    peer: SocketAddr,
+    stream: Arc<Mutex<TcpStream>>,
+    setup: bool,
+}
+
+/// Serves requests of a single client until it disconnects or tears the session down.
+fn serve(stream: TcpStream, shared: &Shared) -> Result<(), io::Error> {
+    let peer = stream.peer_addr()?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    debug!("RTSP client {} connected", peer);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let mut cx = Connection {
+        session: format!("{:08X}", nanos ^ shared.sessions.fetch_add(1, Ordering::Relaxed)),
+        peer,
+        stream: Arc::new(Mutex::new(stream.try_clone()?)),
+        setup: false,
+    };
+
+    let mut rd = BufReader::new(stream);
+    let result = cx.run(&mut rd, shared);
+
+    let mut clients = shared.clients.lock().expect("lock must not be poisoned");
+    clients.retain(|client| client.session != cx.session);
+
+    result
+}
+
+impl Connection {
+    fn run(&mut self, rd: &mut BufReader<TcpStream>, shared: &Shared) -> Result<(), io::Error> {
+        loop {
+            match rd.fill_buf()?.first() {
+                None => return Ok(()),
+                // Interleaved data, e.g. RTCP receiver reports, is of no use.
+                Some(b'$') => {
+                    let mut head = [0; 4];
+                    rd.read_exact(&mut head)?;
+                    let len = u16::from_be_bytes([head[2], head[3]]);
+                    io::copy(&mut rd.by_ref().take(u64::from(len)), &mut io::sink())?;
+                    continue;
+                }
+                Some(..) => {}
+            }
+
+            let (line, head) = read_request(rd)?;
+            let mut it = line.split_whitespace();
+            let method = it.next().unwrap_or_default();
+            let uri = it.next().unwrap_or_default();
+            debug!("{} {} from {}", method, uri, self.peer);
+
+            let (status, headers, body) = self.handle(method, uri, &head, shared);
+
+            let mut response = format!("RTSP/1.0 {}\r\n", status);
+            if let Some(cseq) = header(&head, "CSeq") {
+                response.push_str(&format!("CSeq: {}\r\n", cseq));
+            }
+            for (name, value) in headers {
+                response.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            if !body.is_empty() {
+                response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+            }
+            response.push_str("Server: cleverdog\r\n\r\n");
+            response.push_str(&body);
+
+            // Clients are locked ahead of the connection, in the same order as when sending
+            // packets, so that none is sent before the response to `PLAY`.
+            let mut clients = shared.clients.lock().expect("lock must not be poisoned");
+            self.stream
+                .lock()
+                .expect("lock must not be poisoned")
+                .write_all(response.as_bytes())?;
+
+            match method {
+                "PLAY" if status.starts_with('2') && clients.iter().all(|v| v.session != self.session) => {
+                    info!("RTSP client {} started playing", self.peer);
+                    clients.push(Client {
+                        session: self.session.clone(),
+                        peer: self.peer,
+                        stream: self.stream.clone(),
+                    });
+                }
+                "TEARDOWN" if status.starts_with('2') => {
+                    info!("RTSP client {} stopped playing", self.peer);
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Handles a single request, returning the response status, headers and body.
+    fn handle(
+        &mut self,
+        method: &str,
+        uri: &str,
+        head: &[String],
+        shared: &Shared,
+    ) -> (&'static str, Vec<(&'static str, String)>, String) {
+        let path = uri_path(uri);
+        if method != "OPTIONS" && path != shared.path && !path.starts_with(&format!("{}/", shared.path)) {
+            return ("404 Not Found", Vec::new(), String::new());
+        }
+
+        let session = header(head, "Session").and_then(|v| v.split(';').next()).map(str::trim);
+        if matches!(method, "PLAY" | "TEARDOWN" | "GET_PARAMETER") && matches!(session, Some(v) if v != self.session) {
+            return ("454 Session Not Found", Vec::new(), String::new());
+        }
+        let session_header = format!("{};timeout={}", self.session, SESSION_TIMEOUT);
+
+        match method {
+            "OPTIONS" => ("200 OK", vec![("Public", PUBLIC.into())], String::new()),
+            "DESCRIBE" => (
+                "200 OK",
+                vec![
+                    ("Content-Base", format!("{}/", uri.trim_end_matches('/'))),
+                    ("Content-Type", "application/sdp".into()),
+                ],
+                sdp(&shared.path),
+            ),
+            "SETUP" => {
+                let transport = header(head, "Transport").unwrap_or_default();
+                if !transport.contains("RTP/AVP/TCP") {
+                    return ("461 Unsupported Transport", Vec::new(), String::new());
+                }
+                self.setup = true;
+                (
+                    "200 OK",
+                    vec![
+                        ("Transport", "RTP/AVP/TCP;unicast;interleaved=0-1".into()),
+                        ("Session", session_header),
+                    ],
+                    String::new(),
+                )
+            }
+            "PLAY" if !self.setup => ("455 Method Not Valid in This State", Vec::new(), String::new()),
+            "PLAY" => (
+                "200 OK",
+                vec![("Session", session_header), ("Range", "npt=0.000-".into())],
+                String::new(),
+            ),
+            "TEARDOWN" | "GET_PARAMETER" => ("200 OK", vec![("Session", session_header)], String::new()),
+            _ => ("501 Not Implemented", vec![("Public", PUBLIC.into())], String::new()),
+        }
+    }
+}
+
+/// Reads the request line and headers, skipping the body.
+fn read_request(rd: &mut BufReader<TcpStream>) -> Result<(String, Vec<String>), io::Error> {
+    let mut line = String::new();
+    rd.read_line(&mut line)?;
+    let line = line.trim_end().to_string();
+    if !line.ends_with("RTSP/1.0") {
+        return Err(io::Error::new(ErrorKind::InvalidData, "invalid RTSP request"));
+    }
+
+    let mut head = Vec::new();
+    let mut size = line.len();
+    loop {
+        let mut v = String::new();
+        let len = rd.read_line(&mut v)?;
+        size += len;
+        if len == 0 || size > MAX_RESPONSE_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "invalid RTSP request"));
+        }
+        if v == "\r\n" || v == "\n" {
+            break;
+        }
+        head.push(v.trim_end().to_string());
+    }
+
+    let len = header(&head, "Content-Length")
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    io::copy(&mut rd.by_ref().take(len), &mut io::sink())?;
+
+    Ok((line, head))
+}
+
+/// Returns the path of the given request URI, without the scheme, authority and surrounding
+/// slashes.
+fn uri_path(uri: &str) -> &str {
+    let path = match uri.split_once("://") {
+        Some((.., rest)) => rest.find('/').map_or("", |idx| &rest[idx..]),
+        None => uri,
+    };
+    path.trim_matches('/')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Sends a request and reads the response status line and headers.
+    fn request(stream: &mut TcpStream, rd: &mut BufReader<TcpStream>, request: &str) -> (String, Vec<String>) {
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut status = String::new();
+        rd.read_line(&mut status).unwrap();
+        let mut head = Vec::new();
+        loop {
+            let mut line = String::new();
+            rd.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            head.push(line.trim_end().to_string());
+        }
+
+        let len = header(&head, "Content-Length").map(|v| v.parse().unwrap()).unwrap_or(0);
+        let mut body = vec![0; len];
+        rd.read_exact(&mut body).unwrap();
+
+        (status.trim_end().to_string(), head)
+    }
+
+    #[test]
+    fn test_uri_path() {
+        assert_eq!("live", uri_path("rtsp://127.0.0.1:8554/live"));
+        assert_eq!("live/trackID=0", uri_path("rtsp://127.0.0.1:8554/live/trackID=0"));
+        assert_eq!("", uri_path("rtsp://127.0.0.1:8554"));
+        assert_eq!("*", uri_path("*"));
+    }
+
+    #[test]
+    fn test_play() {
+        let mut server = Server::bind("127.0.0.1:0", "/live").unwrap();
+        let url = server.url();
+        assert_eq!(format!("rtsp://{}/live", server.local_addr()), url);
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut rd = BufReader::new(stream.try_clone().unwrap());
+
+        let (status, head) = request(
+            &mut stream,
+            &mut rd,
+            &format!("DESCRIBE {} RTSP/1.0\r\nCSeq: 1\r\n\r\n", url),
+        );
+        assert_eq!("RTSP/1.0 200 OK", status);
+        assert_eq!(Some("1"), header(&head, "CSeq"));
+        assert_eq!(Some("application/sdp"), header(&head, "Content-Type"));
+
+        let (status, ..) = request(
+            &mut stream,
+            &mut rd,
+            &format!(
+                "DESCRIBE {}/other RTSP/1.0\r\nCSeq: 2\r\n\r\n",
+                url.trim_end_matches("/live")
+            ),
+        );
+        assert_eq!("RTSP/1.0 404 Not Found", status);
+
+        let (status, ..) = request(
+            &mut stream,
+            &mut rd,
+            &format!(
+                "SETUP {}/trackID=0 RTSP/1.0\r\nCSeq: 3\r\nTransport: RTP/AVP;unicast;client_port=5000-5001\r\n\r\n",
+                url
+            ),
+        );
+        assert_eq!("RTSP/1.0 461 Unsupported Transport", status);
+
+        let (status, head) = request(
+            &mut stream,
+            &mut rd,
+            &format!(
+                "SETUP {}/trackID=0 RTSP/1.0\r\nCSeq: 4\r\nTransport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n\r\n",
+                url
+            ),
+        );
+        assert_eq!("RTSP/1.0 200 OK", status);
+        let session = header(&head, "Session").unwrap().split(';').next().unwrap().to_string();
+
+        let (status, ..) = request(
+            &mut stream,
+            &mut rd,
+            &format!("PLAY {} RTSP/1.0\r\nCSeq: 5\r\nSession: {}\r\n\r\n", url, session),
+        );
+        assert_eq!("RTSP/1.0 200 OK", status);
+        assert_eq!(1, server.clients());
+
+        server.send(b"\x80rtp!").unwrap();
+        let mut frame = [0; 4 + 5];
+        rd.read_exact(&mut frame).unwrap();
+        assert_eq!(b"$\x00\x00\x05\x80rtp!", &frame);
+
+        let (status, ..) = request(
+            &mut stream,
+            &mut rd,
+            &format!("TEARDOWN {} RTSP/1.0\r\nCSeq: 6\r\nSession: {}\r\n\r\n", url, session),
+        );
+        assert_eq!("RTSP/1.0 200 OK", status);
+
+        // The connection is closed once the session is torn down.
+        assert_eq!(0, rd.read(&mut [0; 1]).unwrap());
+        assert_eq!(0, server.clients());
+    }
+
+    #[test]
+    fn test_play_before_setup() {
+        let server = Server::bind("127.0.0.1:0", "live").unwrap();
+
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut rd = BufReader::new(stream.try_clone().unwrap());
+
+        let (status, ..) = request(
+            &mut stream,
+            &mut rd,
+            &format!("PLAY {} RTSP/1.0\r\nCSeq: 1\r\n\r\n", server.url()),
+        );
+        assert_eq!("RTSP/1.0 455 Method Not Valid in This State", status);
+        assert_eq!(0, server.clients());
+    }
+}
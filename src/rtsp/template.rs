@@ -0,0 +1,198 @@
+use core::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use std::error::Error;
+
+use crate::{bandwidth::Profile, protocol::LookupInfo};
+
+/// Placeholder of a [`PathTemplate`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Var {
+    Alias,
+    Cid,
+    Mac,
+    Profile,
+}
+
+impl Var {
+    fn name(&self) -> &'static str {
+        match self {
+            Var::Alias => "alias",
+            Var::Cid => "cid",
+            Var::Mac => "mac",
+            Var::Profile => "profile",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Var(Var),
+}
+
+/// Template of the path each camera is published under, e.g. `/cam/{alias}/{profile}`.
+///
+/// Supported placeholders are:
+///
+/// - `{alias}`, the camera alias, falling back to its ID.
+/// - `{cid}`, the camera ID.
+/// - `{mac}`, the MAC address as 12 lowercase hex digits.
+/// - `{profile}`, the stream profile, e.g. `main`.
+///
+/// Characters of substituted values other than ASCII letters, digits, `-`, `_` and `.` are
+/// replaced with `_`, so that a value never introduces extra path segments.
+///
+/// ```
+/// use cleverdog::{bandwidth::Profile, rtsp::PathTemplate};
+/// # use cleverdog::{mac::MacAddr, protocol::{LookupInfo, ScanInfo, Version}};
+/// # let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+/// # let info = LookupInfo::new("10.0.1.71:10008".parse().unwrap(), *b"xxxxS_AB12CD000\0", info);
+///
+/// let template: PathTemplate = "/cam/{alias}/{profile}".parse().unwrap();
+///
+/// assert_eq!("/cam/front/main", template.render(&info, Some("front"), Profile::Main));
+/// assert_eq!("/cam/xxxxS_AB12CD000/sub", template.render(&info, None, Profile::Sub));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathTemplate {
+    parts: Vec<Part>,
+}
+
+impl PathTemplate {
+    /// Returns the path of the given camera.
+    pub fn render(&self, info: &LookupInfo, alias: Option<&str>, profile: Profile) -> String {
+        let mut path = String::new();
+
+        for part in &self.parts {
+            let value = match part {
+                Part::Literal(v) => {
+                    path.push_str(v);
+                    continue;
+                }
+                Part::Var(Var::Alias) => match alias {
+                    Some(alias) => alias.to_string(),
+                    None => info.cid().to_string(),
+                },
+                Part::Var(Var::Cid) => info.cid().to_string(),
+                Part::Var(Var::Mac) => info.mac().as_bytes().iter().map(|v| format!("{:02x}", v)).collect(),
+                Part::Var(Var::Profile) => profile.to_string(),
+            };
+
+            path.extend(value.chars().map(|ch| match ch {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => ch,
+                _ => '_',
+            }));
+        }
+
+        path
+    }
+}
+
+impl Display for PathTemplate {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        for part in &self.parts {
+            match part {
+                Part::Literal(v) => fmt.write_str(v)?,
+                Part::Var(var) => write!(fmt, "{{{}}}", var.name())?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for PathTemplate {
+    type Err = TemplateParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(TemplateParseError::Unbalanced);
+            }
+            let end = rest[start..].find('}').ok_or(TemplateParseError::Unbalanced)? + start;
+
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].into()));
+            }
+
+            let var = match &rest[start + 1..end] {
+                "alias" => Var::Alias,
+                "cid" => Var::Cid,
+                "mac" => Var::Mac,
+                "profile" => Var::Profile,
+                name => return Err(TemplateParseError::UnknownPlaceholder(name.into())),
+            };
+            parts.push(Part::Var(var));
+            rest = &rest[end + 1..];
+        }
+
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.into()));
+        }
+
+        Ok(Self { parts })
+    }
+}
+
+/// An error that can occur during parsing a path template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateParseError {
+    /// A brace is not closed or opened.
+    Unbalanced,
+    /// The placeholder name is not supported.
+    UnknownPlaceholder(String),
+}
+
+impl Display for TemplateParseError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            TemplateParseError::Unbalanced => fmt.write_str("unbalanced braces"),
+            TemplateParseError::UnknownPlaceholder(name) => write!(fmt, "unknown placeholder: {{{}}}", name),
+        }
+    }
+}
+
+impl Error for TemplateParseError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        mac::MacAddr,
+        protocol::{ScanInfo, Version},
+    };
+
+    #[test]
+    fn test_render() {
+        let info = ScanInfo::new(MacAddr::new([220, 169, 4, 151, 157, 155]), Version::new([1, 2, 3, 4]));
+        let info = LookupInfo::new("10.0.1.71:10008".parse().unwrap(), *b"AB/C D\0\0\0\0\0\0\0\0\0\0", info);
+        let template: PathTemplate = "/{mac}/{cid}-{alias}".parse().unwrap();
+
+        assert_eq!(
+            "/dca904979d9b/AB_C_x20D-AB_C_x20D",
+            template.render(&info, None, Profile::Main)
+        );
+        assert_eq!("/{mac}/{cid}-{alias}", template.to_string());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            Err(TemplateParseError::Unbalanced),
+            "/cam/{alias".parse::<PathTemplate>()
+        );
+        assert_eq!(
+            Err(TemplateParseError::Unbalanced),
+            "/cam/alias}".parse::<PathTemplate>()
+        );
+        assert_eq!(
+            Err(TemplateParseError::UnknownPlaceholder("name".into())),
+            "/cam/{name}".parse::<PathTemplate>()
+        );
+    }
+}
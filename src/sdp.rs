@@ -0,0 +1,62 @@
+//! Session descriptions of the forwarded RTP stream, which players such as `ffplay` and VLC open
+//! to receive it.
+
+use core::fmt::Write;
+
+use crate::proxy::base64;
+
+/// Returns the session description of the H.264 RTP stream forwarded to the given local port
+/// with the specified payload type.
+///
+/// Parameter sets, if known, are announced out of band, which lets players start decoding at
+/// the first keyframe without waiting for in-band ones.
+///
+/// ```
+/// use cleverdog::sdp;
+///
+/// let sdp = sdp::generate(5004, 96, Some(&[0x67, 0x42, 0xc0, 0x1e]), Some(&[0x68, 0xce, 0x3c, 0x80]));
+///
+/// assert!(sdp.contains("m=video 5004 RTP/AVP 96\r\n"));
+/// assert!(sdp.contains(
+///     "a=fmtp:96 packetization-mode=1;profile-level-id=42c01e;sprop-parameter-sets=Z0LAHg==,aM48gA==\r\n"
+/// ));
+/// ```
+pub fn generate(local_port: u16, payload_type: u8, sps: Option<&[u8]>, pps: Option<&[u8]>) -> String {
+    let mut sdp = format!(
+        "v=0\r\n\
+         o=- 0 0 IN IP4 127.0.0.1\r\n\
+         s=cleverdog\r\n\
+         c=IN IP4 127.0.0.1\r\n\
+         t=0 0\r\n\
+         m=video {port} RTP/AVP {pt}\r\n\
+         a=rtpmap:{pt} H264/90000\r\n\
+         a=fmtp:{pt} packetization-mode=1",
+        port = local_port,
+        pt = payload_type,
+    );
+
+    if let Some(sps) = sps.filter(|v| v.len() >= 4) {
+        // Writing into a string never fails.
+        let _ = write!(sdp, ";profile-level-id={:02x}{:02x}{:02x}", sps[1], sps[2], sps[3]);
+    }
+    if let (Some(sps), Some(pps)) = (sps, pps) {
+        let _ = write!(sdp, ";sprop-parameter-sets={},{}", base64(sps), base64(pps));
+    }
+    sdp.push_str("\r\n");
+
+    sdp
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_generate_without_parameter_sets() {
+        assert_eq!(
+            "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=cleverdog\r\nc=IN IP4 127.0.0.1\r\nt=0 0\r\n\
+             m=video 8088 RTP/AVP 97\r\na=rtpmap:97 H264/90000\r\na=fmtp:97 packetization-mode=1\r\n",
+            generate(8088, 97, None, None)
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use core::fmt::{self, Display, Formatter};
+
+/// Transport security of a single leg of the streaming path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportSecurity {
+    /// Data is sent unencrypted and unauthenticated, readable by anyone on the path.
+    Plaintext,
+    /// Data is encrypted and the peer is authenticated using TLS.
+    Tls,
+}
+
+impl TransportSecurity {
+    /// Returns `true` if the leg is encrypted.
+    #[inline]
+    pub fn is_encrypted(&self) -> bool {
+        match self {
+            TransportSecurity::Plaintext => false,
+            TransportSecurity::Tls => true,
+        }
+    }
+}
+
+impl Display for TransportSecurity {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            TransportSecurity::Plaintext => fmt.write_str("plaintext"),
+            TransportSecurity::Tls => fmt.write_str("TLS"),
+        }
+    }
+}
+
+/// Security of the camera to host leg.
+///
+/// The LAN camera protocol, both commands and the RTP stream, is plain UDP without any encryption
+/// or authentication, so anyone on the same network can watch the stream or spoof replies.
+pub const CAMERA_LINK_SECURITY: TransportSecurity = TransportSecurity::Plaintext;
@@ -0,0 +1,1076 @@
+use core::{
+    fmt::{self, Debug, Display, Formatter},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use std::{
+    error::Error,
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Instant, SystemTime},
+};
+
+use log::{debug, info, warn};
+
+use crate::{
+    control::{ControlLog, Direction},
+    corpus::{Corpus, Kind},
+    discovery::is_timeout,
+    iface,
+    ntp::NtpTimestamp,
+    protocol::{
+        Cid, Command, Token, CHANNEL_HEADER_SIZE, CHANNEL_OFFSET, MAGIC, RTCP_CHANNEL_HEADER, RTCP_SSRC, VIDEO_CHANNEL,
+        VIDEO_SSRC,
+    },
+    retry::RetryPolicy,
+    rtp::{self, Gaps, JitterBuffer, Reception, Retransmissions, RtpPacket},
+    sink::Sink,
+    stats::{Quality, Stats, StatsSnapshot},
+};
+
+/// Default time without video packets from the camera after which the session is stalled.
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default lowest fraction of valid RTP datagrams, see [`StreamOptions::min_valid_ratio`].
+const MIN_VALID_RATIO: f64 = 0.5;
+/// Interval between RTCP keepalive reports.
+const RTCP_INTERVAL: Duration = Duration::from_secs(1);
+/// RTP clock rate of the H.264 video stream.
+const VIDEO_CLOCK_RATE: u32 = 90_000;
+/// Interval the receive loop checks whether it has been asked to stop.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Streaming session options.
+///
+/// ```
+/// use cleverdog::StreamOptions;
+///
+/// let opts = StreamOptions::new()
+///     .bind("0.0.0.0:40000".parse().unwrap())
+///     .advertised_port(50000)
+///     .ssrc(None)
+///     .recv_buffer_size(1 << 20);
+/// ```
+#[derive(Debug, Clone)]
+pub struct StreamOptions {
+    bind: Option<SocketAddr>,
+    interface: Option<String>,
+    advertised_port: Option<u16>,
+    corpus: Option<Arc<Corpus>>,
+    control_log: Option<Arc<ControlLog>>,
+    token: Token,
+    stall_timeout: Duration,
+    restarts: u32,
+    restart_policy: Option<RetryPolicy>,
+    tap: Option<Tap>,
+    ssrc: Option<u32>,
+    channel: Option<u8>,
+    recv_buffer_size: Option<usize>,
+    rtcp_interval: Duration,
+    min_valid_ratio: Option<f64>,
+    jitter_buffer: Option<(usize, Duration)>,
+    nack: bool,
+}
+
+impl StreamOptions {
+    /// Constructs new options with default values.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the local address the RTP socket is bound to.
+    ///
+    /// Defaults to an ephemeral port on all interfaces, of the same address family as the camera.
+    pub fn bind(mut self, addr: SocketAddr) -> Self {
+        self.bind = Some(addr);
+        self
+    }
+
+    /// Restricts the RTP socket to the given network interface, e.g. `eth0`.
+    ///
+    /// Only supported on Linux, see [`LookupOptions::interface`](crate::LookupOptions::interface).
+    pub fn interface(mut self, name: &str) -> Self {
+        self.interface = Some(name.into());
+        self
+    }
+
+    /// Overrides the RTP port advertised to the camera in the StartRtp command.
+    ///
+    /// By default the local port of the RTP socket is advertised. When the host is behind NAT
+    /// relative to the camera, set it to the externally reachable port forwarded to the bound
+    /// address. Note that the command carries ports only: the camera sends RTP to the source
+    /// address of the command datagram, as seen after translation.
+    pub fn advertised_port(mut self, port: u16) -> Self {
+        self.advertised_port = Some(port);
+        self
+    }
+
+    /// Saves datagrams that fail to parse as RTP into the given corpus.
+    pub fn corpus(mut self, corpus: Arc<Corpus>) -> Self {
+        self.corpus = Some(corpus);
+        self
+    }
+
+    /// Records command frames sent to and received from the camera into the given log.
+    pub fn control_log(mut self, log: Arc<ControlLog>) -> Self {
+        self.control_log = Some(log);
+        self
+    }
+
+    /// Sets the authentication token sent with the StartRtp command.
+    ///
+    /// Defaults to [`Token::ZERO`], which cameras not bound to a vendor account accept.
+    pub fn token(mut self, token: Token) -> Self {
+        self.token = token;
+        self
+    }
+
+    /// Sets the time without video packets from the camera after which the session is
+    /// considered stalled.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn stall_timeout(mut self, timeout: Duration) -> Self {
+        self.stall_timeout = timeout;
+        self
+    }
+
+    /// Sets how many times in a row the StartRtp command is re-sent to a stalled camera before
+    /// the session fails with [`Stalled`].
+    ///
+    /// The counter is reset once video packets arrive again. Defaults to 0, i.e. failing on the
+    /// first stall.
+    pub fn restarts(mut self, restarts: u32) -> Self {
+        self.restarts = restarts;
+        self
+    }
+
+    /// Sets the policy of re-sending the StartRtp command to a stalled camera, overriding
+    /// [`StreamOptions::restarts`].
+    ///
+    /// Each delay of the policy is the time to wait for video after the corresponding restart,
+    /// e.g. an exponential backoff gives a camera that is rebooting increasingly more time.
+    pub fn restart_policy(mut self, policy: RetryPolicy) -> Self {
+        self.restart_policy = Some(policy);
+        self
+    }
+
+    /// Passes every datagram received on the RTP socket into the given sink, e.g. for pcap
+    /// writing or decoding custom protocols.
+    ///
+    /// The tap sees datagrams exactly as received, including the channel header, before any
+    /// validation or filtering: datagrams from unknown sources, malformed packets, RTCP and audio
+    /// are passed through too, and nothing about their contents is guaranteed. The tap is called
+    /// before the regular callback, on the receive thread. If it fails, the error is logged and
+    /// the tap is disabled for the rest of the session, leaving the stream running.
+    pub fn tap<S>(mut self, sink: S) -> Self
+    where
+        S: Sink + Send + 'static,
+    {
+        self.tap = Some(Tap(Arc::new(Mutex::new(Box::new(sink)))));
+        self
+    }
+
+    /// Sets the SSRC of RTP packets passed to the callback, or `None` to pass packets of any
+    /// SSRC.
+    ///
+    /// Defaults to [`VIDEO_SSRC`].
+    pub fn ssrc(mut self, ssrc: Option<u32>) -> Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    /// Sets the channel of datagrams passed to the callback, or `None` to pass datagrams of any
+    /// channel.
+    ///
+    /// Defaults to [`VIDEO_CHANNEL`].
+    pub fn channel(mut self, channel: Option<u8>) -> Self {
+        self.channel = channel;
+        self
+    }
+
+    /// Sets the kernel receive buffer size of the RTP socket in bytes, which absorbs bursts of
+    /// packets, e.g. key frames, while the callback is busy.
+    ///
+    /// Defaults to the system default. Only supported on Unix.
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets the interval between RTCP keepalive reports.
+    ///
+    /// Defaults to 1 second. Cameras stop streaming when reports are missing for several
+    /// seconds, so the interval should be kept well below that.
+    pub fn rtcp_interval(mut self, interval: Duration) -> Self {
+        self.rtcp_interval = interval;
+        self
+    }
+
+    /// Sets the lowest fraction of valid RTP datagrams among all datagrams received within a
+    /// stall timeout, below which the session is considered stalled as if no video arrived.
+    ///
+    /// A camera sending mostly malformed datagrams, e.g. after a firmware glitch, otherwise keeps
+    /// the session alive with an occasional valid packet. Defaults to 0.5, `None` disables the
+    /// check.
+    pub fn min_valid_ratio(mut self, ratio: Option<f64>) -> Self {
+        self.min_valid_ratio = ratio;
+        self
+    }
+
+    /// Restores the sequence order of video packets before passing them to the callback, using
+    /// a [`JitterBuffer`] holding up to `depth` packets for up to `latency`.
+    ///
+    /// Disabled by default, i.e. packets are passed in the order they arrive.
+    pub fn jitter_buffer(mut self, depth: usize, latency: Duration) -> Self {
+        self.jitter_buffer = Some((depth, latency));
+        self
+    }
+
+    /// Sends RTCP generic NACKs to the camera for gaps in the video sequence, requesting
+    /// retransmission of the lost packets.
+    ///
+    /// Experimental: whether any firmware retransmits is unknown, which
+    /// [`StatsSnapshot::packets_recovered`] helps finding out. Retransmitted packets arrive out
+    /// of order, so combine with [`StreamOptions::jitter_buffer`]. Disabled by default.
+    pub fn nack(mut self, enabled: bool) -> Self {
+        self.nack = enabled;
+        self
+    }
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            bind: None,
+            interface: None,
+            advertised_port: None,
+            corpus: None,
+            control_log: None,
+            token: Token::ZERO,
+            stall_timeout: STALL_TIMEOUT,
+            restarts: 0,
+            restart_policy: None,
+            tap: None,
+            ssrc: Some(VIDEO_SSRC),
+            channel: Some(VIDEO_CHANNEL),
+            recv_buffer_size: None,
+            rtcp_interval: RTCP_INTERVAL,
+            min_valid_ratio: Some(MIN_VALID_RATIO),
+            jitter_buffer: None,
+            nack: false,
+        }
+    }
+}
+
+/// Raw datagram sink shared between clones of [`StreamOptions`].
+#[derive(Clone)]
+struct Tap(Arc<Mutex<Box<dyn Sink + Send>>>);
+
+impl Debug for Tap {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt.debug_struct("Tap").finish()
+    }
+}
+
+/// An error returned when the camera stops sending video, see [`StreamOptions::stall_timeout`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stalled {
+    elapsed: Duration,
+    restarts: u32,
+}
+
+impl Stalled {
+    /// Returns the time since the last video packet.
+    #[inline]
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// Returns how many times the StartRtp command has been re-sent before giving up.
+    #[inline]
+    pub fn restarts(&self) -> u32 {
+        self.restarts
+    }
+}
+
+impl Display for Stalled {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(
+            fmt,
+            "no video from camera for {:?} after {} restarts",
+            self.elapsed, self.restarts
+        )
+    }
+}
+
+impl Error for Stalled {}
+
+/// Streams RTP video packets from the camera into the given callback, blocking the current
+/// thread.
+///
+/// Returns only when an error occurs. Use [`spawn_stream`] to be able to stop streaming.
+pub fn stream<F>(cid: &Cid, src: SocketAddr, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+{
+    stream_with(cid, src, &StreamOptions::default(), f)
+}
+
+/// Streams RTP video packets from the camera into the given callback using the specified
+/// options.
+pub fn stream_with<F>(cid: &Cid, src: SocketAddr, opts: &StreamOptions, f: F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+{
+    run(cid, src, opts, &Shared::default(), f)
+}
+
+/// Streams RTP video packets from the camera into the given callback on a background thread.
+///
+/// Unlike [`stream_with`], returns immediately with a handle that allows to stop the session. The
+/// receive loop notices the request within a fraction of a second, after which the socket is
+/// closed and the thread exits.
+///
+/// ```no_run
+/// use core::time::Duration;
+/// use std::thread;
+///
+/// use cleverdog::StreamOptions;
+///
+/// let info = cleverdog::lookup().unwrap();
+/// let handle = cleverdog::spawn_stream(info.cid(), info.addr(), StreamOptions::new(), |buf| {
+///     println!("received {} bytes", buf.len());
+///     Ok(())
+/// })
+/// .unwrap();
+///
+/// thread::sleep(Duration::from_secs(10));
+/// handle.stop().unwrap();
+/// ```
+pub fn spawn_stream<F>(cid: &Cid, src: SocketAddr, opts: StreamOptions, f: F) -> Result<StreamHandle, io::Error>
+where
+    F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>> + Send + 'static,
+{
+    let cid = *cid;
+    let shared = Arc::new(Shared::default());
+
+    let thread = {
+        let shared = shared.clone();
+        thread::Builder::new()
+            .name("stream".into())
+            .spawn(move || run(&cid, src, &opts, &shared, f).map_err(|err| err.to_string()))?
+    };
+
+    let handle = StreamHandle {
+        shared,
+        thread: Some(thread),
+    };
+
+    Ok(handle)
+}
+
+/// Handle of a streaming session running on a background thread, see [`spawn_stream`].
+///
+/// Dropping the handle stops the session as well, discarding its result.
+#[derive(Debug)]
+pub struct StreamHandle {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl StreamHandle {
+    /// Stops the session and waits for its thread to exit.
+    ///
+    /// Returns the error the session has failed with before being stopped, if any.
+    pub fn stop(self) -> Result<(), Box<dyn Error>> {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        self.join()
+    }
+
+    /// Waits for the session to fail, without stopping it.
+    pub fn join(mut self) -> Result<(), Box<dyn Error>> {
+        match self.thread.take().map(JoinHandle::join) {
+            Some(Ok(result)) => result.map_err(Into::into),
+            Some(Err(..)) => Err("streaming thread panicked".into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns `true` if the session has finished, e.g. because of an error.
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().map(JoinHandle::is_finished).unwrap_or(true)
+    }
+
+    /// Pauses delivery of packets to the callback, keeping the session warm.
+    ///
+    /// See [`Camera::pause`](crate::Camera::pause) for details.
+    #[inline]
+    pub fn pause(&self) {
+        self.shared.paused.store(true, Ordering::Relaxed);
+    }
+
+    /// Resumes delivery of packets paused with [`StreamHandle::pause`].
+    #[inline]
+    pub fn resume(&self) {
+        self.shared.paused.store(false, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the session statistics.
+    #[inline]
+    pub fn stats(&self) -> StatsSnapshot {
+        self.shared.stats.snapshot()
+    }
+}
+
+impl Drop for StreamHandle {
+    fn drop(&mut self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Session state shared between the receive loop and its controlling handles.
+#[derive(Debug, Default)]
+pub(crate) struct Shared {
+    pub stats: Stats,
+    pub paused: AtomicBool,
+    /// Set to ask the receive loop to return.
+    pub stopped: AtomicBool,
+}
+
+/// Datagram transport the session runs over, abstracted to allow replaying captures.
+pub(crate) trait Transport {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> Result<(), io::Error>;
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr), io::Error>;
+}
+
+impl Transport for UdpSocket {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> Result<(), io::Error> {
+        UdpSocket::send_to(self, buf, addr).map(|_| ())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<(usize, SocketAddr), io::Error> {
+        UdpSocket::recv_from(self, buf)
+    }
+}
+
+/// Source of time for the session, abstracted to allow running with a virtual clock.
+pub(crate) trait Clock {
+    fn now(&self) -> Instant;
+    fn system_time(&self) -> SystemTime;
+}
+
+/// Clock backed by the operating system.
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    #[inline]
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Parameters of a single streaming session.
+pub(crate) struct Context<'a> {
+    pub cid: &'a Cid,
+    pub src: SocketAddr,
+    /// Port advertised to the camera in the StartRtp command.
+    pub port: u16,
+    pub opts: &'a StreamOptions,
+    pub shared: &'a Shared,
+    /// Keepalive thread to hand the RTCP destination over to, instead of sending reports from
+    /// the receive loop.
+    pub keepalive: Option<&'a Keepalive>,
+}
+
+/// RTCP keepalive sender running on its own timer, so that a slow callback or a burst of packets
+/// never delays reports past the camera timeout.
+#[derive(Debug)]
+pub(crate) struct Keepalive {
+    /// Source address of the last RTP datagram, which reports are sent back to.
+    peer: Mutex<Option<SocketAddr>>,
+    /// Reception statistics of the video source the reports carry.
+    reception: Mutex<Reception>,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            peer: Mutex::new(None),
+            reception: Mutex::new(Reception::new(VIDEO_CLOCK_RATE)),
+        }
+    }
+}
+
+impl Keepalive {
+    /// Accounts a video packet, returning the interarrival jitter updated with it.
+    fn on_packet(&self, hdr: &rtp::Header, arrival: Instant) -> u32 {
+        let mut reception = self.reception.lock().expect("keepalive lock must not be poisoned");
+        reception.on_packet(hdr.ssrc(), hdr.sequence_number(), hdr.timestamp(), arrival);
+        reception.jitter()
+    }
+
+    fn on_sender_report(&self, ssrc: u32, ntp: NtpTimestamp, arrival: Instant) {
+        let mut reception = self.reception.lock().expect("keepalive lock must not be poisoned");
+        reception.on_sender_report(ssrc, ntp, arrival);
+    }
+
+    /// Encodes a receiver report of the video source as of the given time, advancing the
+    /// reporting interval.
+    fn report(&self, now: Instant) -> Vec<u8> {
+        let block = self
+            .reception
+            .lock()
+            .expect("keepalive lock must not be poisoned")
+            .report(now);
+
+        let mut buf = RTCP_CHANNEL_HEADER.to_vec();
+        buf.extend_from_slice(&rtp::receiver_report(RTCP_SSRC, block.as_slice()));
+        buf
+    }
+
+    fn set_peer(&self, addr: SocketAddr) {
+        *self.peer.lock().expect("keepalive lock must not be poisoned") = Some(addr);
+    }
+
+    fn peer(&self) -> Option<SocketAddr> {
+        *self.peer.lock().expect("keepalive lock must not be poisoned")
+    }
+
+    /// Sends a report each interval until the stop channel is disconnected.
+    fn run<T: Transport>(&self, mut transport: T, interval: Duration, stats: &Stats, stopped: Receiver<()>) {
+        loop {
+            match stopped.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => {}
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            if let Some(peer) = self.peer() {
+                match transport.send_to(&self.report(Instant::now()), peer) {
+                    Ok(()) => stats.on_rtcp_sent(),
+                    Err(err) => warn!("failed to send RTCP report to {}: {}", peer, err),
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn run<F>(
+    cid: &Cid,
+    src: SocketAddr,
+    opts: &StreamOptions,
+    shared: &Shared,
+    f: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+{
+    let bind = opts.bind.unwrap_or_else(|| iface::unspecified(&src));
+    let mut sock = iface::bind_udp(bind, opts.interface.as_deref())?;
+    sock.set_read_timeout(Some(STOP_POLL_INTERVAL))?;
+    if let Some(size) = opts.recv_buffer_size {
+        iface::set_recv_buffer_size(&sock, size)?;
+    }
+
+    let port = match opts.advertised_port {
+        Some(port) => port,
+        None => sock.local_addr()?.port(),
+    };
+
+    let keepalive = Keepalive::default();
+    let rtcp_sock = sock.try_clone()?;
+    let (stop, stopped) = mpsc::channel::<()>();
+
+    let cx = Context {
+        cid,
+        src,
+        port,
+        opts,
+        shared,
+        keepalive: Some(&keepalive),
+    };
+
+    thread::scope(|scope| {
+        thread::Builder::new().name("rtcp".into()).spawn_scoped(scope, || {
+            keepalive.run(rtcp_sock, opts.rtcp_interval, &shared.stats, stopped)
+        })?;
+
+        let result = drive(&mut sock, &SystemClock, &cx, f);
+        // Disconnecting the channel wakes the keepalive thread up.
+        drop(stop);
+        result
+    })
+}
+
+/// Runs the session over the given transport until an error occurs or it is asked to stop.
+pub(crate) fn drive<T, C, F>(transport: &mut T, clock: &C, cx: &Context, mut f: F) -> Result<(), Box<dyn Error>>
+where
+    T: Transport,
+    C: Clock,
+    F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+{
+    let Context { opts, shared, .. } = cx;
+    // Without a keepalive thread reports are sent from the receive loop.
+    let inline = Keepalive::default();
+    let keepalive = cx.keepalive.unwrap_or(&inline);
+
+    let comm = Command::StartRtp.encode(cx.cid, &start_rtp_args(&opts.token, cx.port))?;
+    transport.send_to(&comm, cx.src)?;
+    if let Some(log) = &opts.control_log {
+        log.record(clock.system_time(), Direction::Sent, cx.src, &comm);
+    }
+
+    let mut timestamp = clock.now();
+    let mut buf = [0; 4096];
+    let stats = &shared.stats;
+
+    let restart_policy = opts
+        .restart_policy
+        .unwrap_or_else(|| RetryPolicy::fixed(opts.stall_timeout).retries(opts.restarts));
+    let mut delays = restart_policy.delays();
+    let mut stall_timeout = opts.stall_timeout;
+    let mut tap = opts.tap.as_ref();
+    let mut video = clock.now();
+    let mut restarts = 0;
+    let mut quality = Quality::new();
+    let mut validity = Validity::new(clock.now());
+    // Whether the last window had too few valid datagrams, which holds off resetting restarts.
+    let mut degraded = false;
+    let mut jitter = opts
+        .jitter_buffer
+        .map(|(depth, latency)| JitterBuffer::new(depth, latency));
+    let mut gaps = Gaps::new();
+    let mut retransmissions = match opts.nack {
+        true => Some(Retransmissions::new()),
+        false => None,
+    };
+
+    loop {
+        if shared.stopped.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        let elapsed = clock.now().duration_since(video);
+        let mut window_degraded = false;
+        if clock.now().duration_since(validity.start) >= opts.stall_timeout {
+            degraded = opts.min_valid_ratio.map(|v| validity.ratio() < v).unwrap_or(false);
+            window_degraded = degraded;
+            if degraded {
+                info!(
+                    "only {:.0}% of datagrams from {} are valid RTP",
+                    validity.ratio() * 100.0,
+                    cx.src
+                );
+            }
+            validity = Validity::new(clock.now());
+        }
+
+        if elapsed >= stall_timeout || window_degraded {
+            stall_timeout = match delays.next() {
+                Some(delay) => delay,
+                None => return Err(Stalled { elapsed, restarts }.into()),
+            };
+
+            info!("no usable video from {} for {:?}, restarting", cx.src, elapsed);
+            transport.send_to(&comm, cx.src)?;
+            if let Some(log) = &opts.control_log {
+                log.record(clock.system_time(), Direction::Sent, cx.src, &comm);
+            }
+            stats.on_restart();
+            restarts += 1;
+            video = clock.now();
+        }
+
+        let (size, addr) = match transport.recv_from(&mut buf[..]) {
+            Ok(v) => v,
+            // Short read timeouts only wake the loop up to check for the stop request or a stall,
+            // and to release packets held by the jitter buffer for too long.
+            Err(ref err) if is_timeout(err) => {
+                if let Some(jitter) = &mut jitter {
+                    release(jitter, clock.now(), &mut gaps, stats, &mut f)?;
+                }
+                continue;
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let arrival = clock.now();
+        stats.on_received(size);
+
+        if let Some(Tap(sink)) = tap {
+            if let Err(err) = sink.lock().expect("tap lock must not be poisoned").send(&buf[..size]) {
+                warn!("disabling raw datagram tap: {}", err);
+                tap = None;
+            }
+        }
+
+        keepalive.set_peer(addr);
+        if cx.keepalive.is_none() && clock.now().duration_since(timestamp) >= opts.rtcp_interval {
+            timestamp = clock.now();
+            transport.send_to(&keepalive.report(clock.now()), addr)?;
+            stats.on_rtcp_sent();
+        }
+
+        if let (Some(log), true) = (&opts.control_log, buf[..size].starts_with(&MAGIC.to_be_bytes())) {
+            log.record(clock.system_time(), Direction::Received, addr, &buf[..size]);
+        }
+
+        if buf[..size].len() < CHANNEL_HEADER_SIZE + rtp::HEADER_SIZE {
+            stats.on_invalid();
+            validity.invalid += 1;
+            capture(opts, &buf[..size]);
+            continue;
+        }
+
+        // Sender reports share the channel, told apart by a packet type no RTP packet has, see
+        // RFC 5761.
+        if let Some((ssrc, ntp)) = rtp::parse_sender_report(&buf[CHANNEL_HEADER_SIZE..size]) {
+            keepalive.on_sender_report(ssrc, ntp, arrival);
+            stats.on_rtcp_received();
+            continue;
+        }
+
+        let packet = match RtpPacket::new(&buf[CHANNEL_HEADER_SIZE..size], buf[CHANNEL_OFFSET], arrival) {
+            Ok(packet) => packet,
+            Err(..) => {
+                stats.on_invalid();
+                validity.invalid += 1;
+                capture(opts, &buf[..size]);
+                continue;
+            }
+        };
+        let hdr = packet.header();
+
+        if hdr.version() != 2 {
+            stats.on_invalid();
+            validity.invalid += 1;
+            capture(opts, &buf[..size]);
+            continue;
+        }
+
+        // Skip non-video frames.
+        if opts.channel.map(|v| v != packet.channel()).unwrap_or(false) {
+            stats.on_non_video();
+            continue;
+        }
+
+        if opts.ssrc.map(|v| v != hdr.ssrc()).unwrap_or(false) {
+            stats.on_non_video();
+            continue;
+        }
+
+        let interarrival = keepalive.on_packet(hdr, arrival);
+        quality.on_packet(stats, hdr.sequence_number(), interarrival, packet.len(), arrival);
+        if let Some(retransmissions) = &mut retransmissions {
+            let (lost, recovered) = retransmissions.on_packet(hdr.sequence_number());
+            if recovered {
+                stats.on_recovered();
+            }
+            if !lost.is_empty() {
+                let mut nack = RTCP_CHANNEL_HEADER.to_vec();
+                nack.extend_from_slice(&rtp::generic_nack(RTCP_SSRC, hdr.ssrc(), &lost));
+                match transport.send_to(&nack, addr) {
+                    Ok(()) => stats.on_nack_sent(),
+                    Err(err) => warn!("failed to send NACK to {}: {}", addr, err),
+                }
+            }
+        }
+        validity.valid += 1;
+        video = arrival;
+        if restarts > 0 && !degraded {
+            restarts = 0;
+            delays = restart_policy.delays();
+            stall_timeout = opts.stall_timeout;
+        }
+
+        // Skipped packets still advance the sequence, so that a pause isn't reported as a gap.
+        if shared.paused.load(Ordering::Relaxed) {
+            gaps.on_packet(hdr.sequence_number());
+            stats.on_skipped();
+            continue;
+        }
+
+        match &mut jitter {
+            Some(jitter) => {
+                if !jitter.push(&packet) {
+                    stats.on_skipped();
+                }
+                release(jitter, arrival, &mut gaps, stats, &mut f)?;
+            }
+            None => deliver(packet, &mut gaps, stats, &mut f)?,
+        }
+    }
+}
+
+/// Passes packets released by the jitter buffer at the given time to the callback.
+fn release<F>(
+    jitter: &mut JitterBuffer,
+    now: Instant,
+    gaps: &mut Gaps,
+    stats: &Stats,
+    f: &mut F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+{
+    while let Some(buffered) = jitter.pop(now) {
+        deliver(buffered.packet(), gaps, stats, f)?;
+    }
+
+    Ok(())
+}
+
+/// Passes the packet to the callback, along with the gap preceding it.
+fn deliver<F>(packet: RtpPacket, gaps: &mut Gaps, stats: &Stats, f: &mut F) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&RtpPacket) -> Result<(), Box<dyn Error>>,
+{
+    let gap = gaps.on_packet(packet.header().sequence_number());
+    if let Some(gap) = gap {
+        debug!("packets {} are missing", gap);
+        stats.on_gap();
+    }
+
+    f(&packet.with_gap(gap))?;
+    stats.on_delivered();
+
+    Ok(())
+}
+
+/// Counts valid and malformed datagrams received within a stall timeout window.
+struct Validity {
+    start: Instant,
+    valid: u64,
+    invalid: u64,
+}
+
+impl Validity {
+    fn new(start: Instant) -> Self {
+        Self {
+            start,
+            valid: 0,
+            invalid: 0,
+        }
+    }
+
+    /// Returns the fraction of valid datagrams, which is 1 for an empty window, leaving silence
+    /// to the stall timeout.
+    fn ratio(&self) -> f64 {
+        match self.valid + self.invalid {
+            0 => 1.0,
+            n => self.valid as f64 / n as f64,
+        }
+    }
+}
+
+/// Saves the given malformed datagram into the corpus, if configured.
+fn capture(opts: &StreamOptions, buf: &[u8]) {
+    if let Some(corpus) = &opts.corpus {
+        if let Err(err) = corpus.save(Kind::Rtp, buf) {
+            warn!("failed to save datagram into the corpus: {}", err);
+        }
+    }
+}
+
+/// Encodes StartRtp command arguments, requesting RTP to be sent to the given port.
+fn start_rtp_args(token: &Token, port: u16) -> Vec<u8> {
+    let mut args = token.to_vec();
+    args.extend_from_slice(format!("{}:{}\0", port, port).as_bytes());
+    args
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    const CID: Cid = Cid::new(*b"AAAAAAAAAAAAAAA\0");
+
+    #[test]
+    fn test_stream_handle_stop() {
+        let camera = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = camera.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let (_, peer) = camera.recv_from(&mut buf).unwrap();
+
+            let mut datagram = vec![0x00, 0x00, VIDEO_CHANNEL, 0x00, 0x80, 96, 0, 1, 0, 0, 0, 0];
+            datagram.extend_from_slice(&VIDEO_SSRC.to_be_bytes());
+            datagram.extend_from_slice(b"payload");
+            camera.send_to(&datagram, peer).unwrap();
+        });
+
+        let (tx, rx) = mpsc::channel();
+        let opts = StreamOptions::new().bind("127.0.0.1:0".parse().unwrap());
+        let handle = spawn_stream(&CID, addr, opts, move |buf| {
+            tx.send(buf.to_vec())?;
+            Ok(())
+        })
+        .unwrap();
+
+        let buf = rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(buf.ends_with(b"payload"));
+        assert_eq!(1, handle.stats().packets_received);
+
+        let start = Instant::now();
+        handle.stop().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_pause_is_not_a_gap() {
+        let camera = UdpSocket::bind("127.0.0.1:0").unwrap();
+        camera.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = camera.local_addr().unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let opts = StreamOptions::new().bind("127.0.0.1:0".parse().unwrap());
+        let handle = spawn_stream(&CID, addr, opts, move |packet| {
+            tx.send(packet.gap())?;
+            Ok(())
+        })
+        .unwrap();
+
+        let mut buf = [0; 4096];
+        let (_, peer) = camera.recv_from(&mut buf).unwrap();
+        let send = |seq: u16| {
+            let mut datagram = vec![0x00, 0x00, VIDEO_CHANNEL, 0x00, 0x80, 96];
+            datagram.extend_from_slice(&seq.to_be_bytes());
+            datagram.extend_from_slice(&[0; 4]);
+            datagram.extend_from_slice(&VIDEO_SSRC.to_be_bytes());
+            camera.send_to(&datagram, peer).unwrap();
+        };
+
+        send(1);
+        assert_eq!(None, rx.recv_timeout(Duration::from_secs(5)).unwrap());
+
+        handle.pause();
+        send(2);
+        send(3);
+        let start = Instant::now();
+        while handle.stats().packets_skipped < 2 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        handle.resume();
+        send(4);
+        assert_eq!(None, rx.recv_timeout(Duration::from_secs(5)).unwrap());
+        assert_eq!(0, handle.stats().gaps);
+
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn test_stall_restarts_then_fails() {
+        let camera = UdpSocket::bind("127.0.0.1:0").unwrap();
+        camera.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = camera.local_addr().unwrap();
+
+        // The camera never replies, neither to the initial StartRtp nor to the restart.
+        let commands = thread::spawn(move || {
+            let mut buf = [0; 4096];
+            let mut is_command = || match camera.recv_from(&mut buf) {
+                Ok((size, ..)) => buf[..size].starts_with(&MAGIC.to_be_bytes()),
+                Err(..) => false,
+            };
+            (0..2).filter(|_| is_command()).count()
+        });
+
+        let opts = StreamOptions::new()
+            .bind("127.0.0.1:0".parse().unwrap())
+            .stall_timeout(Duration::from_millis(300))
+            .restarts(1);
+        let err = stream_with(&CID, addr, &opts, |_buf| Ok(())).unwrap_err();
+
+        let stalled = err.downcast_ref::<Stalled>().unwrap();
+        assert_eq!(1, stalled.restarts());
+        assert!(stalled.elapsed() >= Duration::from_millis(300));
+        assert_eq!(2, commands.join().unwrap());
+    }
+
+    #[test]
+    fn test_keepalive_not_starved_by_slow_callback() {
+        let camera = UdpSocket::bind("127.0.0.1:0").unwrap();
+        camera.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let addr = camera.local_addr().unwrap();
+
+        let handle = spawn_stream(&CID, addr, StreamOptions::new(), |_buf| {
+            thread::sleep(Duration::from_secs(3));
+            Ok(())
+        })
+        .unwrap();
+
+        let mut buf = [0; 4096];
+        let (_, peer) = camera.recv_from(&mut buf).unwrap();
+        let mut datagram = vec![0x00, 0x00, VIDEO_CHANNEL, 0x00, 0x80, 96, 0, 1, 0, 0, 0, 0];
+        datagram.extend_from_slice(&VIDEO_SSRC.to_be_bytes());
+        camera.send_to(&datagram, peer).unwrap();
+
+        // The callback is still busy with the packet when the report is due.
+        let start = Instant::now();
+        let (size, _) = camera.recv_from(&mut buf).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(RTCP_CHANNEL_HEADER, buf[..CHANNEL_HEADER_SIZE]);
+        assert_eq!(rtp::RTCP_RECEIVER_REPORT, buf[CHANNEL_HEADER_SIZE + 1]);
+        assert_eq!(CHANNEL_HEADER_SIZE + 8 + rtp::ReportBlock::SIZE, size);
+        // A single report block about the video source, with the packet received.
+        assert_eq!(0x81, buf[CHANNEL_HEADER_SIZE]);
+        assert_eq!(
+            VIDEO_SSRC.to_be_bytes(),
+            buf[CHANNEL_HEADER_SIZE + 8..CHANNEL_HEADER_SIZE + 12]
+        );
+        assert_eq!([0, 0, 0, 1], buf[CHANNEL_HEADER_SIZE + 16..CHANNEL_HEADER_SIZE + 20]);
+
+        handle.stop().unwrap();
+    }
+
+    #[test]
+    fn test_keepalive_answers_sender_report() {
+        let keepalive = Keepalive::default();
+        let now = Instant::now();
+        let mut packet = vec![0x80, 96, 0, 1, 0, 0, 0, 0];
+        packet.extend_from_slice(&VIDEO_SSRC.to_be_bytes());
+        keepalive.on_packet(&rtp::Header::from_slice(&packet).unwrap(), now);
+
+        let mut report = vec![0x80, rtp::RTCP_SENDER_REPORT, 0, 6];
+        report.extend_from_slice(&VIDEO_SSRC.to_be_bytes());
+        report.extend_from_slice(&NtpTimestamp::new(0x1234_5678, 0x9abc_def0).as_u64().to_be_bytes());
+        report.extend_from_slice(&[0; 12]);
+        let (ssrc, ntp) = rtp::parse_sender_report(&report).unwrap();
+        keepalive.on_sender_report(ssrc, ntp, now);
+
+        // The last SR field carries the middle bits, followed by the delay in 1/65536 seconds.
+        let buf = keepalive.report(now + Duration::from_secs(1));
+        let block = &buf[CHANNEL_HEADER_SIZE + 8..];
+        assert_eq!([0x56, 0x78, 0x9a, 0xbc], block[16..20]);
+        assert_eq!([0, 1, 0, 0], block[20..24]);
+    }
+
+    #[test]
+    fn test_start_rtp_args() {
+        assert_eq!(
+            &b"0000000000000000000000000000000000000040000:40000\0"[..],
+            &start_rtp_args(&Token::ZERO, 40000)[..]
+        );
+
+        let token = Token::new([b'x'; 38]);
+        assert_eq!(
+            &b"xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx40000:40000\0"[..],
+            &start_rtp_args(&token, 40000)[..]
+        );
+    }
+}
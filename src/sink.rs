@@ -0,0 +1,281 @@
+use core::fmt::{self, Display, Formatter};
+use std::error::Error;
+
+use log::warn;
+
+#[cfg(any(unix, windows))]
+pub use self::fifo::FifoSink;
+#[cfg(feature = "srt")]
+pub use self::srt::{SrtOptions, SrtSink};
+#[cfg(unix)]
+pub use self::unix::{UnixKind, UnixSink};
+pub use self::{
+    annexb::AnnexBSink,
+    destination::{Destination, DestinationParseError, Endpoint},
+    dynamic::{DynamicFanOut, FanOutControl},
+    file::{repair, FileSink, Framing, Repaired},
+    group::RecordingGroup,
+    storage::{available_space, DiskGuard, Enforcement, StorageEvent},
+    udp::UdpFanOut,
+};
+pub(crate) use self::{
+    destination::parse_endpoint,
+    file::{finalize, partial_path},
+};
+
+mod annexb;
+mod destination;
+mod dynamic;
+#[cfg(any(unix, windows))]
+mod fifo;
+mod file;
+mod group;
+#[cfg(unix)]
+pub mod shm;
+#[cfg(feature = "srt")]
+mod srt;
+mod storage;
+mod udp;
+#[cfg(unix)]
+mod unix;
+
+/// Destination of the received stream data.
+///
+/// Implemented for any `FnMut(&[u8]) -> Result<(), Box<dyn Error>>` closure.
+pub trait Sink {
+    /// Sends the given buffer to this sink.
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>>;
+}
+
+impl<F> Sink for F
+where
+    F: FnMut(&[u8]) -> Result<(), Box<dyn Error>>,
+{
+    #[inline]
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        self(buf)
+    }
+}
+
+/// Describes how a [`FanOut`] reacts to an error returned by one of its sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Propagate the error, aborting the whole session with all other sinks.
+    AbortAll,
+    /// Remove the failed sink, keeping others running.
+    DropSink,
+    /// Retry sending the same buffer up to the given number of times, skipping it for the failed
+    /// sink if all retries fail. The sink itself is kept.
+    Retry(u32),
+}
+
+/// An error returned when all sinks of a [`FanOut`] have been dropped.
+#[derive(Debug, Clone)]
+pub struct NoSinksLeft;
+
+impl Display for NoSinksLeft {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str("no sinks left")
+    }
+}
+
+impl Error for NoSinksLeft {}
+
+struct Entry<S: ?Sized> {
+    name: String,
+    sink: Box<S>,
+    policy: ErrorPolicy,
+}
+
+/// Sends the given buffer into each sink, removing or skipping failed ones according to their
+/// policies.
+fn send_all<S>(sinks: &mut Vec<Entry<S>>, buf: &[u8]) -> Result<(), Box<dyn Error>>
+where
+    S: Sink + ?Sized,
+{
+    let mut idx = 0;
+    while idx < sinks.len() {
+        let entry = &mut sinks[idx];
+
+        let mut result = entry.sink.send(buf);
+        if let ErrorPolicy::Retry(attempts) = entry.policy {
+            for _ in 0..attempts {
+                if result.is_ok() {
+                    break;
+                }
+                result = entry.sink.send(buf);
+            }
+        }
+
+        match (result, entry.policy) {
+            (Ok(()), ..) => idx += 1,
+            (Err(err), ErrorPolicy::AbortAll) => return Err(err),
+            (Err(err), ErrorPolicy::DropSink) => {
+                warn!("dropping sink '{}': {}", entry.name, err);
+                sinks.remove(idx);
+            }
+            (Err(err), ErrorPolicy::Retry(..)) => {
+                warn!("sink '{}' failed, skipping buffer: {}", entry.name, err);
+                idx += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Sink that duplicates data into several sinks, isolating their errors according to per-sink
+/// policies.
+///
+/// ```
+/// use cleverdog::sink::{ErrorPolicy, FanOut, Sink};
+///
+/// let mut fanout = FanOut::new()
+///     .with("recorder", |_buf: &[u8]| Ok(()), ErrorPolicy::AbortAll)
+///     .with("tunnel", |_buf: &[u8]| Err("disconnected".into()), ErrorPolicy::DropSink);
+///
+/// fanout.send(b"frame").unwrap();
+/// assert_eq!(1, fanout.len());
+/// ```
+#[derive(Default)]
+pub struct FanOut {
+    sinks: Vec<Entry<dyn Sink>>,
+}
+
+impl FanOut {
+    /// Constructs a new fan-out without sinks.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the given sink under the specified name, which is used for logging.
+    pub fn push<S>(&mut self, name: &str, sink: S, policy: ErrorPolicy)
+    where
+        S: Sink + 'static,
+    {
+        self.sinks.push(Entry {
+            name: name.into(),
+            sink: Box::new(sink),
+            policy,
+        });
+    }
+
+    /// Adds the given sink, returning `self` for chaining.
+    pub fn with<S>(mut self, name: &str, sink: S, policy: ErrorPolicy) -> Self
+    where
+        S: Sink + 'static,
+    {
+        self.push(name, sink, policy);
+        self
+    }
+
+    /// Returns the number of active sinks.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Returns `true` if there are no active sinks.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+}
+
+impl Sink for FanOut {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.sinks.is_empty() {
+            return Err(NoSinksLeft.into());
+        }
+
+        send_all(&mut self.sinks, buf)?;
+
+        if self.sinks.is_empty() {
+            return Err(NoSinksLeft.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    fn counter() -> (Rc<RefCell<usize>>, impl Sink) {
+        let count = Rc::new(RefCell::new(0));
+        let sink = {
+            let count = count.clone();
+            move |_buf: &[u8]| {
+                *count.borrow_mut() += 1;
+                Ok(())
+            }
+        };
+
+        (count, sink)
+    }
+
+    fn failing() -> impl Sink {
+        |_buf: &[u8]| Err("failed".into())
+    }
+
+    #[test]
+    fn test_drop_sink() {
+        let (count, sink) = counter();
+        let mut fanout = FanOut::new().with("failing", failing(), ErrorPolicy::DropSink).with(
+            "counter",
+            sink,
+            ErrorPolicy::AbortAll,
+        );
+
+        fanout.send(b"1").unwrap();
+        fanout.send(b"2").unwrap();
+
+        assert_eq!(1, fanout.len());
+        assert_eq!(2, *count.borrow());
+    }
+
+    #[test]
+    fn test_abort_all() {
+        let (count, sink) = counter();
+        let mut fanout = FanOut::new().with("failing", failing(), ErrorPolicy::AbortAll).with(
+            "counter",
+            sink,
+            ErrorPolicy::AbortAll,
+        );
+
+        assert!(fanout.send(b"1").is_err());
+        assert_eq!(0, *count.borrow());
+    }
+
+    #[test]
+    fn test_retry_sink() {
+        let mut failures = 2;
+        let flaky = move |_buf: &[u8]| -> Result<(), Box<dyn Error>> {
+            if failures > 0 {
+                failures -= 1;
+                return Err("failed".into());
+            }
+            Ok(())
+        };
+
+        let mut fanout =
+            FanOut::new()
+                .with("flaky", flaky, ErrorPolicy::Retry(2))
+                .with("failing", failing(), ErrorPolicy::Retry(1));
+
+        fanout.send(b"1").unwrap();
+        assert_eq!(2, fanout.len());
+    }
+
+    #[test]
+    fn test_no_sinks_left() {
+        let mut fanout = FanOut::new().with("failing", failing(), ErrorPolicy::DropSink);
+
+        assert!(fanout.send(b"1").is_err());
+        assert!(fanout.is_empty());
+    }
+}
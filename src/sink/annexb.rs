@@ -0,0 +1,113 @@
+use std::{error::Error, time::Instant};
+
+use log::debug;
+
+use super::Sink;
+use crate::{
+    h264::{AnnexBWriter, Depacketizer, Nal},
+    protocol::VIDEO_CHANNEL,
+    rtp::RtpPacket,
+};
+
+/// Sink converting RTP packets into an H.264 Annex-B elementary stream, which it forwards to
+/// the inner sink.
+///
+/// Whatever NAL units a packet completes are sent as a single buffer, starting with the first
+/// keyframe. Writing the stream into a file or a pipe makes it directly playable, e.g. by
+/// `ffplay -f h264`. Packets carrying malformed payloads are skipped.
+///
+/// ```no_run
+/// use std::{fs::File, io::Write};
+///
+/// use cleverdog::sink::{AnnexBSink, Sink};
+///
+/// let mut file = File::create("camera.h264")?;
+/// let mut sink = AnnexBSink::new(move |buf: &[u8]| Ok(file.write_all(buf)?));
+///
+/// let info = cleverdog::lookup()?;
+/// cleverdog::stream(info.cid(), info.addr(), |packet| sink.send(packet))?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct AnnexBSink<S> {
+    sink: S,
+    depacketizer: Depacketizer,
+    wr: AnnexBWriter<Vec<u8>>,
+}
+
+impl<S: Sink> AnnexBSink<S> {
+    /// Constructs a new converter forwarding the elementary stream into the given sink.
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            depacketizer: Depacketizer::new(),
+            wr: AnnexBWriter::new(Vec::new()),
+        }
+    }
+
+    /// Returns the inner sink.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.sink
+    }
+}
+
+impl<S: Sink> Sink for AnnexBSink<S> {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let units = match RtpPacket::new(buf, VIDEO_CHANNEL, Instant::now())
+            .map_err(Box::<dyn Error>::from)
+            .and_then(|packet| Ok(self.depacketizer.push(&packet)?))
+        {
+            Ok(units) => units,
+            Err(err) => {
+                debug!("skipping packet: {}", err);
+                return Ok(());
+            }
+        };
+
+        for unit in units.iter().filter_map(|v| Nal::new(v)) {
+            self.wr.write_nal(&unit)?;
+        }
+
+        if self.wr.get_ref().is_empty() {
+            return Ok(());
+        }
+        let result = self.sink.send(self.wr.get_ref());
+        self.wr.get_mut().clear();
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_annex_b_sink() {
+        let mut out = Vec::new();
+        let mut sink = AnnexBSink::new(|buf: &[u8]| {
+            out.push(buf.to_vec());
+            Ok(())
+        });
+
+        let packets: [&[u8]; 4] = [
+            // Slice preceding the keyframe, STAP-A with SPS and PPS, FU-A split IDR slice.
+            &[0x41, 1],
+            &[0x78, 0, 2, 0x67, 2, 0, 2, 0x68, 3],
+            &[0x7c, 0x85, 4],
+            &[0x7c, 0x45, 5],
+        ];
+        for (seq, payload) in packets.iter().enumerate() {
+            let mut buf = vec![0x80, 96, 0, seq as u8, 0, 0, 0, 0, 0, 0, 0, 16];
+            buf.extend_from_slice(payload);
+            sink.send(&buf).unwrap();
+        }
+        // Truncated RTP headers are skipped.
+        sink.send(&[0x80, 96]).unwrap();
+
+        assert_eq!(
+            vec![vec![0, 0, 0, 1, 0x67, 2, 0, 0, 0, 1, 0x68, 3, 0, 0, 0, 1, 0x65, 4, 5]],
+            out
+        );
+    }
+}
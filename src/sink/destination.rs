@@ -0,0 +1,476 @@
+use core::{
+    fmt::{self, Display, Formatter},
+    str::FromStr,
+};
+use std::{
+    error::Error,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::PathBuf,
+};
+
+use crate::security::TransportSecurity;
+
+/// Host and port of a network destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    host: String,
+    port: u16,
+}
+
+impl Endpoint {
+    /// Constructs a new endpoint.
+    pub fn new(host: &str, port: u16) -> Self {
+        Self {
+            host: host.into(),
+            port,
+        }
+    }
+
+    /// Returns the hostname or IP literal, without brackets.
+    #[inline]
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the port.
+    #[inline]
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the IP address if the host is an IP literal.
+    #[inline]
+    pub fn ip(&self) -> Option<IpAddr> {
+        self.host.parse().ok()
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self.host.contains(':') {
+            true => write!(fmt, "[{}]:{}", self.host, self.port),
+            false => write!(fmt, "{}:{}", self.host, self.port),
+        }
+    }
+}
+
+/// Output destination of the stream, configured by URL.
+///
+/// Supported schemes are `udp://`, `tcp://`, `tls://` (or `https://`), `ws://`, `wss://`,
+/// `file://`, `fifo://`, `shm://`, `unix://` (stream socket), `unixgram://` (datagram socket),
+/// `rtsp://` and `srt://`, optionally with a `?streamid=` query. Hosts may be bracketed IPv6
+/// literals. Ports default per scheme where there is a well-known one. A lone `-` stands for the
+/// standard output.
+///
+/// ```
+/// use cleverdog::sink::Destination;
+///
+/// let dst: Destination = "tls://[2001:db8::1]:8443".parse().unwrap();
+/// let dst = dst.with_sni("relay.example.com").unwrap();
+///
+/// assert_eq!(Some("relay.example.com"), dst.sni());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Destination {
+    /// Raw datagrams to the given address.
+    Udp(SocketAddr),
+    /// Plain TCP stream.
+    Tcp(Endpoint),
+    /// TLS stream.
+    Tls {
+        endpoint: Endpoint,
+        /// Server name sent in the TLS handshake, if any.
+        sni: Option<String>,
+    },
+    /// WebSocket connection, optionally over TLS.
+    WebSocket {
+        endpoint: Endpoint,
+        path: String,
+        secure: bool,
+    },
+    /// Local file.
+    File(PathBuf),
+    /// Named pipe, i.e. FIFO on Unix.
+    Fifo(PathBuf),
+    /// Shared-memory ring buffer, see [`shm`](super::shm).
+    Shm(PathBuf),
+    /// Unix domain socket, either stream or datagram one.
+    Unix { path: PathBuf, datagram: bool },
+    /// RTSP server.
+    Rtsp { endpoint: Endpoint, path: String },
+    /// SRT listener, called with the given stream ID if any.
+    Srt {
+        endpoint: Endpoint,
+        stream_id: Option<String>,
+    },
+    /// Standard output, for piping into another process.
+    Stdout,
+}
+
+impl Destination {
+    /// Overrides the server name sent in the TLS handshake.
+    ///
+    /// Only applicable to TLS destinations.
+    pub fn with_sni(self, name: &str) -> Result<Self, DestinationParseError> {
+        match self {
+            Destination::Tls { endpoint, .. } => Ok(Destination::Tls {
+                endpoint,
+                sni: Some(name.into()),
+            }),
+            _ => Err(DestinationParseError::SniNotApplicable),
+        }
+    }
+
+    /// Returns the server name sent in the TLS handshake.
+    ///
+    /// Defaults to the host for hostnames. IP literals have no default, because they are not
+    /// allowed as SNI server names.
+    pub fn sni(&self) -> Option<&str> {
+        match self {
+            Destination::Tls { sni, .. } => sni.as_deref(),
+            Destination::WebSocket {
+                endpoint, secure: true, ..
+            } if endpoint.ip().is_none() => Some(endpoint.host()),
+            _ => None,
+        }
+    }
+
+    /// Returns the transport security of the host to destination leg, or `None` for local
+    /// destinations.
+    pub fn security(&self) -> Option<TransportSecurity> {
+        match self {
+            Destination::Udp(..) | Destination::Tcp(..) | Destination::Rtsp { .. } | Destination::Srt { .. } => {
+                Some(TransportSecurity::Plaintext)
+            }
+            Destination::WebSocket { secure: false, .. } => Some(TransportSecurity::Plaintext),
+            Destination::Tls { .. } | Destination::WebSocket { secure: true, .. } => Some(TransportSecurity::Tls),
+            Destination::File(..)
+            | Destination::Fifo(..)
+            | Destination::Shm(..)
+            | Destination::Unix { .. }
+            | Destination::Stdout => None,
+        }
+    }
+}
+
+impl Display for Destination {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Destination::Udp(addr) => write!(fmt, "udp://{}", addr),
+            Destination::Tcp(endpoint) => write!(fmt, "tcp://{}", endpoint),
+            Destination::Tls { endpoint, .. } => write!(fmt, "tls://{}", endpoint),
+            Destination::WebSocket { endpoint, path, secure } => {
+                let scheme = if *secure { "wss" } else { "ws" };
+                write!(fmt, "{}://{}{}", scheme, endpoint, path)
+            }
+            Destination::File(path) => write!(fmt, "file://{}", path.display()),
+            Destination::Fifo(path) => write!(fmt, "fifo://{}", path.display()),
+            Destination::Shm(path) => write!(fmt, "shm://{}", path.display()),
+            Destination::Unix { path, datagram } => {
+                let scheme = if *datagram { "unixgram" } else { "unix" };
+                write!(fmt, "{}://{}", scheme, path.display())
+            }
+            Destination::Rtsp { endpoint, path } => write!(fmt, "rtsp://{}{}", endpoint, path),
+            Destination::Srt { endpoint, stream_id } => {
+                write!(fmt, "srt://{}", endpoint)?;
+                match stream_id {
+                    Some(id) => write!(fmt, "?streamid={}", id),
+                    None => Ok(()),
+                }
+            }
+            Destination::Stdout => fmt.write_str("-"),
+        }
+    }
+}
+
+impl FromStr for Destination {
+    type Err = DestinationParseError;
+
+    fn from_str(v: &str) -> Result<Self, Self::Err> {
+        if v == "-" {
+            return Ok(Destination::Stdout);
+        }
+
+        let mut it = v.splitn(2, "://");
+        let scheme = it.next().unwrap_or_default();
+        let rest = it.next().ok_or(DestinationParseError::MissingScheme)?;
+
+        match scheme {
+            "file" | "fifo" | "shm" | "unix" | "unixgram" if rest.is_empty() => {
+                return Err(DestinationParseError::MissingPath)
+            }
+            "file" => return Ok(Destination::File(rest.into())),
+            "fifo" => return Ok(Destination::Fifo(rest.into())),
+            "shm" => return Ok(Destination::Shm(rest.into())),
+            "unix" | "unixgram" => {
+                return Ok(Destination::Unix {
+                    path: rest.into(),
+                    datagram: scheme == "unixgram",
+                })
+            }
+            // Stream IDs may contain slashes, so the query is split off first.
+            "srt" => {
+                let (authority, query) = rest.split_once('?').unwrap_or((rest, ""));
+                let stream_id = query
+                    .split('&')
+                    .find_map(|v| v.strip_prefix("streamid="))
+                    .map(Into::into);
+                return Ok(Destination::Srt {
+                    endpoint: parse_endpoint(authority.trim_end_matches('/'), None)?,
+                    stream_id,
+                });
+            }
+            _ => {}
+        }
+
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+
+        match scheme {
+            "udp" => {
+                let endpoint = parse_endpoint(authority, None)?;
+                let ip = endpoint
+                    .ip()
+                    .ok_or_else(|| DestinationParseError::InvalidAddr(endpoint.host.clone()))?;
+                Ok(Destination::Udp(SocketAddr::new(ip, endpoint.port)))
+            }
+            "tcp" => Ok(Destination::Tcp(parse_endpoint(authority, None)?)),
+            "tls" | "https" => {
+                let endpoint = parse_endpoint(authority, Some(443))?;
+                let sni = match endpoint.ip() {
+                    Some(..) => None,
+                    None => Some(endpoint.host.clone()),
+                };
+                Ok(Destination::Tls { endpoint, sni })
+            }
+            "ws" | "wss" => {
+                let secure = scheme == "wss";
+                Ok(Destination::WebSocket {
+                    endpoint: parse_endpoint(authority, Some(if secure { 443 } else { 80 }))?,
+                    path: path.into(),
+                    secure,
+                })
+            }
+            "rtsp" => Ok(Destination::Rtsp {
+                endpoint: parse_endpoint(authority, Some(554))?,
+                path: path.into(),
+            }),
+            scheme => Err(DestinationParseError::UnknownScheme(scheme.into())),
+        }
+    }
+}
+
+/// Parses the given `host[:port]` string, where host may be a bracketed IPv6 literal.
+pub(crate) fn parse_endpoint(v: &str, default_port: Option<u16>) -> Result<Endpoint, DestinationParseError> {
+    let (host, port) = if v.starts_with('[') {
+        let end = v
+            .find(']')
+            .ok_or_else(|| DestinationParseError::InvalidAddr(v.into()))?;
+        let host = &v[1..end];
+        host.parse::<Ipv6Addr>()
+            .map_err(|_| DestinationParseError::InvalidAddr(host.into()))?;
+
+        match &v[end + 1..] {
+            "" => (host, None),
+            rest if rest.starts_with(':') => (host, Some(&rest[1..])),
+            _ => return Err(DestinationParseError::InvalidAddr(v.into())),
+        }
+    } else {
+        match v.rfind(':') {
+            Some(idx) => (&v[..idx], Some(&v[idx + 1..])),
+            None => (v, None),
+        }
+    };
+
+    if host.is_empty() {
+        return Err(DestinationParseError::MissingHost);
+    }
+    // Unbracketed IPv6 literals are ambiguous with the port separator.
+    if host.contains(':') && !v.starts_with('[') {
+        return Err(DestinationParseError::InvalidAddr(v.into()));
+    }
+
+    let port = match (port, default_port) {
+        (Some(port), ..) => port
+            .parse()
+            .map_err(|_| DestinationParseError::InvalidPort(port.into()))?,
+        (None, Some(port)) => port,
+        (None, None) => return Err(DestinationParseError::MissingPort),
+    };
+
+    Ok(Endpoint::new(host, port))
+}
+
+/// An error that can occur during parsing a destination URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DestinationParseError {
+    /// The string is not an URL.
+    MissingScheme,
+    /// The scheme is not supported.
+    UnknownScheme(String),
+    /// The URL has no host.
+    MissingHost,
+    /// The URL has no port and the scheme has no default one.
+    MissingPort,
+    /// The file URL has no path.
+    MissingPath,
+    /// The port is not a valid number.
+    InvalidPort(String),
+    /// The host is not a valid address.
+    InvalidAddr(String),
+    /// SNI override was given for a non-TLS destination.
+    SniNotApplicable,
+}
+
+impl Display for DestinationParseError {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            DestinationParseError::MissingScheme => fmt.write_str("invalid address - must be an URL"),
+            DestinationParseError::UnknownScheme(scheme) => write!(fmt, "unknown protocol: {}", scheme),
+            DestinationParseError::MissingHost => fmt.write_str("missing hostname"),
+            DestinationParseError::MissingPort => fmt.write_str("missing port"),
+            DestinationParseError::MissingPath => fmt.write_str("missing path"),
+            DestinationParseError::InvalidPort(port) => write!(fmt, "invalid port: {}", port),
+            DestinationParseError::InvalidAddr(addr) => write!(fmt, "invalid address: {}", addr),
+            DestinationParseError::SniNotApplicable => fmt.write_str("SNI is only applicable to TLS destinations"),
+        }
+    }
+}
+
+impl Error for DestinationParseError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(v: &str) -> Result<Destination, DestinationParseError> {
+        v.parse()
+    }
+
+    #[test]
+    fn test_parse_udp() {
+        assert_eq!(
+            Destination::Udp("[::1]:5000".parse().unwrap()),
+            parse("udp://[::1]:5000").unwrap()
+        );
+        assert_eq!(Err(DestinationParseError::MissingPort), parse("udp://127.0.0.1"));
+        assert_eq!(
+            Err(DestinationParseError::InvalidAddr("localhost".into())),
+            parse("udp://localhost:5000")
+        );
+    }
+
+    #[test]
+    fn test_parse_tls() {
+        let dst = parse("https://relay.example.com").unwrap();
+        assert_eq!(
+            Destination::Tls {
+                endpoint: Endpoint::new("relay.example.com", 443),
+                sni: Some("relay.example.com".into()),
+            },
+            dst
+        );
+        assert_eq!(Some(TransportSecurity::Tls), dst.security());
+
+        let dst = parse("tls://[2001:db8::1]:8443").unwrap();
+        assert_eq!(None, dst.sni());
+        assert_eq!("tls://[2001:db8::1]:8443", dst.to_string());
+    }
+
+    #[test]
+    fn test_parse_ws() {
+        assert_eq!(
+            Destination::WebSocket {
+                endpoint: Endpoint::new("localhost", 443),
+                path: "/live/camera".into(),
+                secure: true,
+            },
+            parse("wss://localhost/live/camera").unwrap()
+        );
+        assert_eq!("ws://localhost:80/", parse("ws://localhost").unwrap().to_string());
+    }
+
+    #[test]
+    fn test_parse_file() {
+        let dst = parse("file:///var/lib/camera/out.h264").unwrap();
+        assert_eq!(Destination::File("/var/lib/camera/out.h264".into()), dst);
+        assert_eq!(None, dst.security());
+
+        assert_eq!(
+            Destination::Fifo(r"\\.\pipe\camera".into()),
+            parse(r"fifo://\\.\pipe\camera").unwrap()
+        );
+        assert_eq!(
+            Destination::Shm("/dev/shm/camera".into()),
+            parse("shm:///dev/shm/camera").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_stdout() {
+        let dst = parse("-").unwrap();
+        assert_eq!(Destination::Stdout, dst);
+        assert_eq!(None, dst.security());
+        assert_eq!("-", dst.to_string());
+    }
+
+    #[test]
+    fn test_parse_unix() {
+        assert_eq!(
+            Destination::Unix {
+                path: "/run/camera.sock".into(),
+                datagram: true,
+            },
+            parse("unixgram:///run/camera.sock").unwrap()
+        );
+        assert_eq!(
+            "unix:///run/camera.sock",
+            parse("unix:///run/camera.sock").unwrap().to_string()
+        );
+        assert_eq!(Err(DestinationParseError::MissingPath), parse("unix://"));
+    }
+
+    #[test]
+    fn test_parse_rtsp() {
+        assert_eq!(
+            Destination::Rtsp {
+                endpoint: Endpoint::new("10.0.0.1", 554),
+                path: "/camera".into(),
+            },
+            parse("rtsp://10.0.0.1/camera").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_srt() {
+        assert_eq!(
+            Destination::Srt {
+                endpoint: Endpoint::new("10.0.0.1", 8890),
+                stream_id: Some("publish:cam/front".into()),
+            },
+            parse("srt://10.0.0.1:8890?streamid=publish:cam/front").unwrap()
+        );
+        assert_eq!("srt://10.0.0.1:8890", parse("srt://10.0.0.1:8890").unwrap().to_string());
+        assert_eq!(Err(DestinationParseError::MissingPort), parse("srt://10.0.0.1"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Err(DestinationParseError::MissingScheme), parse("127.0.0.1:5000"));
+        assert_eq!(
+            Err(DestinationParseError::UnknownScheme("ftp".into())),
+            parse("ftp://localhost")
+        );
+        assert_eq!(Err(DestinationParseError::MissingHost), parse("tcp://:5000"));
+        assert_eq!(
+            Err(DestinationParseError::InvalidAddr("::1:5000".into())),
+            parse("tcp://::1:5000")
+        );
+        assert_eq!(
+            Err(DestinationParseError::SniNotApplicable),
+            parse("tcp://localhost:5000").unwrap().with_sni("localhost")
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    error::Error,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{send_all, Entry, ErrorPolicy, Sink};
+
+type Sinks = Vec<Entry<dyn Sink + Send>>;
+
+/// Fan-out whose set of sinks can be changed while the stream is running.
+///
+/// Behaves as [`FanOut`](super::FanOut), except that sinks are added and removed through a
+/// [`FanOutControl`], e.g. from a thread serving control requests, without restarting the camera
+/// session. Having no sinks is not an error: buffers are discarded until a sink is added.
+///
+/// ```
+/// use cleverdog::sink::{DynamicFanOut, ErrorPolicy, Sink};
+///
+/// let mut fanout = DynamicFanOut::new();
+/// let control = fanout.control();
+///
+/// fanout.send(b"frame").unwrap();
+/// control.add("recorder", |_buf: &[u8]| Ok(()), ErrorPolicy::DropSink);
+/// fanout.send(b"frame").unwrap();
+/// assert!(control.remove("recorder"));
+/// ```
+#[derive(Default)]
+pub struct DynamicFanOut {
+    sinks: Arc<Mutex<Sinks>>,
+}
+
+impl DynamicFanOut {
+    /// Constructs a new fan-out without sinks.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a control handle, which can be cloned and sent to other threads.
+    pub fn control(&self) -> FanOutControl {
+        FanOutControl {
+            sinks: self.sinks.clone(),
+        }
+    }
+}
+
+impl Sink for DynamicFanOut {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        send_all(&mut lock(&self.sinks), buf)
+    }
+}
+
+/// Handle changing the sinks of a [`DynamicFanOut`].
+#[derive(Clone)]
+pub struct FanOutControl {
+    sinks: Arc<Mutex<Sinks>>,
+}
+
+impl FanOutControl {
+    /// Adds the given sink under the specified name, replacing the sink with the same name.
+    ///
+    /// Returns `true` if a sink has been replaced.
+    pub fn add<S>(&self, name: &str, sink: S, policy: ErrorPolicy) -> bool
+    where
+        S: Sink + Send + 'static,
+    {
+        let entry: Entry<dyn Sink + Send> = Entry {
+            name: name.into(),
+            sink: Box::new(sink),
+            policy,
+        };
+
+        let mut sinks = lock(&self.sinks);
+        match sinks.iter_mut().find(|v| v.name == name) {
+            Some(v) => {
+                let prev = core::mem::replace(v, entry);
+                // Release the lock before the previous sink is dropped, which may flush it.
+                drop(sinks);
+                drop(prev);
+                true
+            }
+            None => {
+                sinks.push(entry);
+                false
+            }
+        }
+    }
+
+    /// Removes the sink with the given name, returning `true` if it has been found.
+    pub fn remove(&self, name: &str) -> bool {
+        let mut sinks = lock(&self.sinks);
+        let prev = sinks.iter().position(|v| v.name == name).map(|idx| sinks.remove(idx));
+        drop(sinks);
+
+        prev.is_some()
+    }
+
+    /// Returns names of the active sinks, in the order they receive buffers.
+    ///
+    /// Sinks removed because of their error policy are not listed.
+    pub fn names(&self) -> Vec<String> {
+        lock(&self.sinks).iter().map(|v| v.name.clone()).collect()
+    }
+}
+
+fn lock(sinks: &Mutex<Sinks>) -> MutexGuard<'_, Sinks> {
+    sinks.lock().expect("fan-out lock must not be poisoned")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::mpsc, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_reconfigure_from_another_thread() {
+        let mut fanout = DynamicFanOut::new();
+        let control = fanout.control();
+        fanout.send(b"dropped").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let control = thread::spawn(move || {
+            let sink = move |buf: &[u8]| -> Result<(), Box<dyn Error>> {
+                tx.send(buf.to_vec())?;
+                Ok(())
+            };
+            control.add("forward", sink, ErrorPolicy::DropSink);
+            control.add("failing", |_buf: &[u8]| Err("failed".into()), ErrorPolicy::DropSink);
+            control
+        })
+        .join()
+        .unwrap();
+
+        fanout.send(b"frame").unwrap();
+        assert_eq!(vec!["forward".to_string()], control.names());
+
+        assert!(control.remove("forward"));
+        assert!(!control.remove("forward"));
+        fanout.send(b"dropped").unwrap();
+
+        assert_eq!(vec![b"frame".to_vec()], rx.try_iter().collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+
+use super::{Framing, Sink};
+
+/// Sink writing into a named pipe, for feeding players and transcoders such as ffmpeg or VLC
+/// without spawning them as child processes.
+///
+/// On Unix the FIFO is created at construction unless it already exists. On Windows the path
+/// must be in the `\\.\pipe\name` namespace and the sink acts as the pipe server.
+///
+/// Writing blocks until a reader connects. Once the reader goes away the sink returns an error
+/// and waits for the next reader on the following buffer.
+#[derive(Debug)]
+pub struct FifoSink {
+    path: PathBuf,
+    framing: Framing,
+    pipe: Option<File>,
+}
+
+impl FifoSink {
+    /// Constructs a new sink writing raw buffers into the named pipe at the given path.
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_path_buf();
+        imp::create(&path)?;
+
+        Ok(Self {
+            path,
+            framing: Framing::Raw,
+            pipe: None,
+        })
+    }
+
+    /// Sets the framing of written buffers.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+}
+
+impl Sink for FifoSink {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.pipe.is_none() {
+            debug!("waiting for a reader on {}", self.path.display());
+            self.pipe = Some(imp::open(&self.path)?);
+            info!("reader connected to {}", self.path.display());
+        }
+
+        let pipe = self.pipe.as_mut().expect("pipe must be open");
+        let mut result = Ok(());
+        if let Framing::LengthPrefixed = self.framing {
+            result = pipe.write_all(&(buf.len() as u32).to_be_bytes());
+        }
+        let result = result.and_then(|()| pipe.write_all(buf));
+
+        if let Err(err) = result {
+            debug!("reader disconnected from {}: {}", self.path.display(), err);
+            self.pipe = None;
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::{
+        ffi::CString,
+        fs::{self, File, OpenOptions},
+        io::{self, ErrorKind},
+        os::unix::{ffi::OsStrExt, fs::FileTypeExt},
+        path::Path,
+    };
+
+    pub fn create(path: &Path) -> Result<(), io::Error> {
+        match fs::metadata(path) {
+            Ok(meta) if meta.file_type().is_fifo() => return Ok(()),
+            Ok(..) => {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a FIFO", path.display()),
+                ))
+            }
+            Err(ref err) if err.kind() == ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        let cpath = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: the path is a valid NUL-terminated string.
+        if unsafe { libc::mkfifo(cpath.as_ptr(), 0o644) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    pub fn open(path: &Path) -> Result<File, io::Error> {
+        OpenOptions::new().write(true).open(path)
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use core::ptr;
+    use std::{
+        ffi::OsStr,
+        fs::File,
+        io::{self, ErrorKind},
+        os::windows::{ffi::OsStrExt, io::FromRawHandle},
+        path::Path,
+    };
+
+    type Handle = *mut core::ffi::c_void;
+
+    const PIPE_ACCESS_OUTBOUND: u32 = 0x0000_0002;
+    const PIPE_TYPE_BYTE: u32 = 0x0000_0000;
+    const PIPE_WAIT: u32 = 0x0000_0000;
+    const BUFFER_SIZE: u32 = 64 * 1024;
+    const INVALID_HANDLE_VALUE: Handle = !0 as Handle;
+    const ERROR_PIPE_CONNECTED: i32 = 535;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateNamedPipeW(
+            name: *const u16,
+            open_mode: u32,
+            pipe_mode: u32,
+            max_instances: u32,
+            out_buffer_size: u32,
+            in_buffer_size: u32,
+            default_timeout: u32,
+            security_attributes: *mut core::ffi::c_void,
+        ) -> Handle;
+        fn ConnectNamedPipe(pipe: Handle, overlapped: *mut core::ffi::c_void) -> i32;
+        fn CloseHandle(handle: Handle) -> i32;
+    }
+
+    pub fn create(path: &Path) -> Result<(), io::Error> {
+        if !path.as_os_str().to_string_lossy().starts_with(r"\\.\pipe\") {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not in the \\\\.\\pipe\\ namespace", path.display()),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new pipe instance and waits for a client to connect.
+    pub fn open(path: &Path) -> Result<File, io::Error> {
+        let name = OsStr::new(path).encode_wide().chain(Some(0)).collect::<Vec<_>>();
+
+        // SAFETY: the name is a valid NUL-terminated wide string.
+        let handle = unsafe {
+            CreateNamedPipeW(
+                name.as_ptr(),
+                PIPE_ACCESS_OUTBOUND,
+                PIPE_TYPE_BYTE | PIPE_WAIT,
+                1,
+                BUFFER_SIZE,
+                BUFFER_SIZE,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the handle is a valid pipe handle created above.
+        if unsafe { ConnectNamedPipe(handle, ptr::null_mut()) } == 0 {
+            let err = io::Error::last_os_error();
+            // The client may have connected between the two calls.
+            if err.raw_os_error() != Some(ERROR_PIPE_CONNECTED) {
+                unsafe { CloseHandle(handle) };
+                return Err(err);
+            }
+        }
+
+        // SAFETY: the handle is valid and exclusively owned by the returned file.
+        Ok(unsafe { File::from_raw_handle(handle) })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use std::{env, fs, io::Read, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_fifo() {
+        let path = env::temp_dir().join(format!("cleverdog-fifo-{}", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut sink = FifoSink::new(&path).unwrap();
+
+        let reader = {
+            let path = path.clone();
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                File::open(path).unwrap().read_to_end(&mut buf).unwrap();
+                buf
+            })
+        };
+
+        sink.send(b"abc").unwrap();
+        sink.send(b"def").unwrap();
+        drop(sink);
+
+        assert_eq!(b"abcdef", &reader.join().unwrap()[..]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_not_a_fifo() {
+        let path = env::temp_dir().join(format!("cleverdog-not-fifo-{}", std::process::id()));
+        fs::write(&path, b"").unwrap();
+
+        assert!(FifoSink::new(&path).is_err());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,523 @@
+use core::time::Duration;
+use std::{
+    error::Error,
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use log::{info, warn};
+
+use super::{group::RecordingGroup, storage::DiskGuard, Sink};
+use crate::metadata::{self, Metadata};
+
+/// Suffix of the file a segment is written into before being renamed to its final path.
+pub(crate) const PARTIAL_SUFFIX: &str = ".part";
+
+/// Leading bytes of a Matroska file, the EBML header ID.
+const EBML_MAGIC: [u8; 4] = [0x1a, 0x45, 0xdf, 0xa3];
+
+/// How buffers are laid out in the output file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    /// Buffers are written back to back, as is.
+    Raw,
+    /// Each buffer is prefixed with its length as a big-endian `u32`, preserving boundaries.
+    LengthPrefixed,
+}
+
+/// Sink writing into local files, optionally rotated by size or age.
+///
+/// Each segment is written into a temporary `*.part` file next to its final path and renamed on
+/// rotation, so downstream consumers watching the directory never pick up partially written
+/// segments. With rotation enabled segments are numbered, i.e. `out.h264` becomes
+/// `out-00000.h264`, `out-00001.h264` and so on.
+///
+/// The last segment is finalized by [`FileSink::finish`], or on drop.
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+    framing: Framing,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    metadata: Option<Metadata>,
+    guard: Option<DiskGuard>,
+    group: Option<(Arc<RecordingGroup>, String)>,
+    segment: Option<Segment>,
+    index: u64,
+}
+
+#[derive(Debug)]
+struct Segment {
+    wr: BufWriter<File>,
+    path: PathBuf,
+    size: u64,
+    opened_at: Instant,
+    started: SystemTime,
+    /// Wall-clock instant the segment ends at, when recording in a group.
+    boundary: Option<SystemTime>,
+}
+
+impl FileSink {
+    /// Constructs a new sink writing raw buffers to the given path, without rotation.
+    ///
+    /// Files are created lazily, on the first buffer.
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            framing: Framing::Raw,
+            max_size: None,
+            max_age: None,
+            metadata: None,
+            guard: None,
+            group: None,
+            segment: None,
+            index: 0,
+        }
+    }
+
+    /// Sets the framing of written buffers.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Starts a new segment once the current one reaches the given size in bytes.
+    pub fn max_size(mut self, size: u64) -> Self {
+        self.max_size = Some(size);
+        self
+    }
+
+    /// Starts a new segment once the current one has been open for the given duration.
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age);
+        self
+    }
+
+    /// Writes the given metadata as a sidecar next to each finished segment.
+    ///
+    /// See [`metadata::write_sidecar`].
+    pub fn metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    /// Enforces minimum free space on the recording volume using the given guard.
+    ///
+    /// While the guard keeps recording paused, the current segment is finalized and incoming
+    /// buffers are dropped. Only finished segments of a rotated sink are deleted, oldest first.
+    pub fn guard(mut self, guard: DiskGuard) -> Self {
+        self.guard = Some(guard);
+        self
+    }
+
+    /// Joins the given recording group under the specified camera name.
+    ///
+    /// Segments are then additionally rotated at the wall-clock boundaries of the group period
+    /// and listed in group manifests when finished. See [`RecordingGroup`].
+    pub fn group(mut self, group: Arc<RecordingGroup>, camera: &str) -> Self {
+        self.group = Some((group, camera.into()));
+        self
+    }
+
+    /// Finalizes the current segment, renaming it to its final path.
+    pub fn finish(mut self) -> Result<(), io::Error> {
+        self.close()
+    }
+
+    fn is_rotated(&self) -> bool {
+        self.max_size.is_some() || self.max_age.is_some() || self.group.is_some()
+    }
+
+    /// Returns the final path of the segment with the given index.
+    fn segment_path(&self, index: u64) -> PathBuf {
+        if !self.is_rotated() {
+            return self.path.clone();
+        }
+
+        let mut name = OsString::new();
+        if let Some(stem) = self.path.file_stem() {
+            name.push(stem);
+        }
+        name.push(format!("-{:05}", index));
+        if let Some(ext) = self.path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+
+        self.path.with_file_name(name)
+    }
+
+    /// Returns the directory segments are written into.
+    fn dir(&self) -> &Path {
+        match self.path.parent() {
+            Some(dir) if dir != Path::new("") => dir,
+            _ => Path::new("."),
+        }
+    }
+
+    /// Lists finished segments of this sink found on disk, oldest first.
+    fn finished_segments(&self) -> Result<Vec<PathBuf>, io::Error> {
+        if !self.is_rotated() {
+            return Ok(Vec::new());
+        }
+
+        let stem = self.path.file_stem().and_then(|v| v.to_str()).unwrap_or_default();
+        let ext = self.path.extension().and_then(|v| v.to_str());
+
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(self.dir())? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = match name.to_str() {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let index = match ext {
+                Some(ext) => name.strip_suffix(ext).and_then(|v| v.strip_suffix('.')),
+                None => Some(name),
+            };
+            let index = index
+                .and_then(|v| v.strip_prefix(stem))
+                .and_then(|v| v.strip_prefix('-'));
+            if let Some(index) = index {
+                if index.len() >= 5 && index.bytes().all(|ch| ch.is_ascii_digit()) {
+                    segments.push((entry.metadata()?.modified()?, entry.path()));
+                }
+            }
+        }
+        segments.sort();
+
+        Ok(segments.into_iter().map(|(_, path)| path).collect())
+    }
+
+    /// Returns `true` if recording is paused by the disk guard, finalizing the current segment.
+    fn is_paused(&mut self) -> Result<bool, io::Error> {
+        let mut guard = match self.guard.take() {
+            Some(guard) => guard,
+            None => return Ok(false),
+        };
+
+        let paused = guard.check(self.dir(), || self.finished_segments());
+        self.guard = Some(guard);
+
+        if paused {
+            self.close()?;
+        }
+
+        Ok(paused)
+    }
+
+    fn is_due(&self, segment: &Segment) -> bool {
+        let by_size = self.max_size.map(|v| segment.size >= v).unwrap_or(false);
+        let by_age = self.max_age.map(|v| segment.opened_at.elapsed() >= v).unwrap_or(false);
+        let by_boundary = segment.boundary.map(|v| SystemTime::now() >= v).unwrap_or(false);
+        by_size || by_age || by_boundary
+    }
+
+    fn open(&mut self) -> Result<&mut Segment, io::Error> {
+        if let Some(segment) = &self.segment {
+            if self.is_due(segment) {
+                self.close()?;
+            }
+        }
+
+        if self.segment.is_none() {
+            let path = self.segment_path(self.index);
+            self.index += 1;
+
+            let file = File::create(partial_path(&path))?;
+            let started = SystemTime::now();
+            self.segment = Some(Segment {
+                wr: BufWriter::new(file),
+                path,
+                size: 0,
+                opened_at: Instant::now(),
+                started,
+                boundary: self.group.as_ref().map(|(group, ..)| group.boundary(started)),
+            });
+        }
+
+        Ok(self.segment.as_mut().expect("segment must be open"))
+    }
+
+    fn close(&mut self) -> Result<(), io::Error> {
+        if let Some(mut segment) = self.segment.take() {
+            segment.wr.flush()?;
+            segment.wr.get_ref().sync_all()?;
+            if let Some(metadata) = &self.metadata {
+                metadata::write_sidecar(&segment.path, metadata)?;
+            }
+            fs::rename(partial_path(&segment.path), &segment.path)?;
+            info!("finished segment {}", segment.path.display());
+            if let Some((group, camera)) = &self.group {
+                group.record(camera, &segment.path, segment.started, SystemTime::now())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Outcome of [`repair`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Repaired {
+    /// Final path of the repaired segment.
+    pub path: PathBuf,
+    /// Number of complete length-prefixed records kept, or `None` for raw segments.
+    pub records: Option<u64>,
+    /// Number of trailing bytes of an incomplete record cut off.
+    pub truncated: u64,
+}
+
+/// Finalizes a segment left behind by a crashed [`FileSink`].
+///
+/// Length-prefixed segments are truncated to the last complete record. Raw segments carry no
+/// boundaries, so they are kept as is. Partial `*.part` segments are then renamed to their final
+/// path; other files are repaired in place.
+///
+/// Recordings in MP4 or Matroska containers are refused untouched, since their boxes would be
+/// taken for records. See [`fmp4::repair`](crate::mux::fmp4::repair) for those.
+pub fn repair<P: AsRef<Path>>(path: P, framing: Framing) -> Result<Repaired, io::Error> {
+    let path = path.as_ref();
+
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+
+    let mut head = [0; 8];
+    if len >= head.len() as u64 {
+        file.read_exact(&mut head)?;
+        file.seek(SeekFrom::Start(0))?;
+    }
+    if &head[4..] == b"ftyp" || head[..4] == EBML_MAGIC {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "container recordings are not file output segments",
+        ));
+    }
+
+    let mut records = None;
+    let mut valid = len;
+    if let Framing::LengthPrefixed = framing {
+        let mut rd = BufReader::new(&file);
+        let mut count = 0;
+        let mut offset = 0;
+        let mut prefix = [0; 4];
+
+        loop {
+            if offset + 4 > len {
+                break;
+            }
+            rd.read_exact(&mut prefix)?;
+            let size = u64::from(u32::from_be_bytes(prefix));
+            if offset + 4 + size > len {
+                break;
+            }
+            rd.seek_relative(size as i64)?;
+            offset += 4 + size;
+            count += 1;
+        }
+
+        records = Some(count);
+        valid = offset;
+    }
+
+    if valid < len {
+        file.set_len(valid)?;
+    }
+    file.sync_all()?;
+
+    Ok(Repaired {
+        path: finalize(path)?,
+        records,
+        truncated: len - valid,
+    })
+}
+
+impl Sink for FileSink {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        if self.is_paused()? {
+            return Ok(());
+        }
+
+        let framing = self.framing;
+        let segment = self.open()?;
+
+        if let Framing::LengthPrefixed = framing {
+            segment.wr.write_all(&(buf.len() as u32).to_be_bytes())?;
+            segment.size += 4;
+        }
+        segment.wr.write_all(buf)?;
+        segment.size += buf.len() as u64;
+
+        Ok(())
+    }
+}
+
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        if let Err(err) = self.close() {
+            warn!("failed to finish segment: {}", err);
+        }
+    }
+}
+
+/// Renames the given partial file to its final path, returning the path the file ends up at,
+/// which is unchanged for other files.
+pub(crate) fn finalize(path: &Path) -> Result<PathBuf, io::Error> {
+    match path.to_str().and_then(|v| v.strip_suffix(PARTIAL_SUFFIX)) {
+        Some(v) => {
+            let final_path = PathBuf::from(v);
+            fs::rename(path, &final_path)?;
+            Ok(final_path)
+        }
+        None => Ok(path.to_path_buf()),
+    }
+}
+
+/// Returns the path a file is written into before being renamed to the given one.
+pub(crate) fn partial_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(PARTIAL_SUFFIX);
+    path.into()
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    fn tempdir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("cleverdog-file-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_single_file() {
+        let dir = tempdir("single");
+        let path = dir.join("out.h264");
+
+        let mut sink = FileSink::new(&path);
+        sink.send(b"abc").unwrap();
+        sink.send(b"def").unwrap();
+
+        // Nothing is visible under the final name until finished.
+        assert!(!path.exists());
+        sink.finish().unwrap();
+
+        assert_eq!(b"abcdef", &fs::read(&path).unwrap()[..]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_rotation_by_size() {
+        let dir = tempdir("rotation");
+
+        let mut sink = FileSink::new(dir.join("out.bin"))
+            .framing(Framing::LengthPrefixed)
+            .max_size(10);
+        for buf in &[&b"abcd"[..], b"ef", b"ghij"] {
+            sink.send(buf).unwrap();
+        }
+
+        assert!(dir.join("out-00000.bin").exists());
+        assert!(dir.join("out-00001.bin.part").exists());
+        drop(sink);
+
+        assert_eq!(
+            &b"\0\0\0\x04abcd\0\0\0\x02ef"[..],
+            &fs::read(dir.join("out-00000.bin")).unwrap()[..]
+        );
+        assert_eq!(
+            &b"\0\0\0\x04ghij"[..],
+            &fs::read(dir.join("out-00001.bin")).unwrap()[..]
+        );
+        assert!(!dir.join("out-00001.bin.part").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair() {
+        let dir = tempdir("repair");
+        let path = dir.join("out.bin.part");
+        fs::write(&path, b"\0\0\0\x02ab\0\0\0\x01c\0\0\0\x05de").unwrap();
+
+        let repaired = repair(&path, Framing::LengthPrefixed).unwrap();
+
+        assert_eq!(
+            Repaired {
+                path: dir.join("out.bin"),
+                records: Some(2),
+                truncated: 6,
+            },
+            repaired
+        );
+        assert!(!path.exists());
+        assert_eq!(
+            &b"\0\0\0\x02ab\0\0\0\x01c"[..],
+            &fs::read(dir.join("out.bin")).unwrap()[..]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_repair_refuses_containers() {
+        let dir = tempdir("containers");
+        let recordings: [(&str, &[u8]); 2] = [
+            ("rec.mp4.part", b"\0\0\0\x18ftypiso5\0\0\x02\0iso5iso6\0\0\0\x08moov"),
+            ("rec.mkv.part", b"\x1a\x45\xdf\xa3\x9f\x42\x86\x81\x01"),
+        ];
+
+        for (name, content) in &recordings {
+            let path = dir.join(name);
+            fs::write(&path, content).unwrap();
+
+            assert!(repair(&path, Framing::LengthPrefixed).is_err());
+            assert_eq!(*content, &fs::read(&path).unwrap()[..]);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_finished_segments() {
+        let dir = tempdir("finished");
+        for name in &[
+            "out-00000.bin",
+            "out-00001.bin.part",
+            "out-00000.meta.json",
+            "other-00000.bin",
+        ] {
+            fs::write(dir.join(name), b"abc").unwrap();
+        }
+
+        let sink = FileSink::new(dir.join("out.bin")).max_size(10);
+
+        assert_eq!(vec![dir.join("out-00000.bin")], sink.finished_segments().unwrap());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_metadata_sidecar() {
+        let dir = tempdir("metadata");
+        let path = dir.join("out.h264");
+
+        let metadata = Metadata {
+            cid: "xxxxS_AB12CD".into(),
+            ..Default::default()
+        };
+        let mut sink = FileSink::new(&path).metadata(metadata);
+        sink.send(b"abc").unwrap();
+        sink.finish().unwrap();
+
+        let sidecar = fs::read_to_string(dir.join("out.meta.json")).unwrap();
+        assert!(sidecar.contains("\"camera_cid\":\"xxxxS_AB12CD\""));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,185 @@
+use core::time::Duration;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::json;
+
+/// Number of most recent slots kept in memory, bounding how late a segment may be reported.
+const MAX_SLOTS: usize = 16;
+
+/// Finished segment of a single camera, as listed in a group manifest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    camera: String,
+    path: PathBuf,
+    start: SystemTime,
+    end: SystemTime,
+}
+
+/// Synchronized recording of several cameras.
+///
+/// [`FileSink`](super::FileSink)s joined to a group start new segments at the same wall-clock
+/// instants, i.e. multiples of the group period since the Unix epoch, regardless of when each
+/// camera started streaming. For each such slot a `group-<start_ms>.json` manifest listing
+/// segments of all cameras is maintained in the group directory, which allows to play several
+/// angles back in sync.
+///
+/// Alignment is only as good as the host clock, which should be synchronized, e.g. using NTP.
+///
+/// ```
+/// use core::time::Duration;
+/// use std::sync::Arc;
+///
+/// use cleverdog::sink::{FileSink, RecordingGroup};
+///
+/// let group = Arc::new(RecordingGroup::new("/var/lib/cleverdog", Duration::from_secs(60)));
+///
+/// let front = FileSink::new("/var/lib/cleverdog/front.h264").group(group.clone(), "front");
+/// let back = FileSink::new("/var/lib/cleverdog/back.h264").group(group, "back");
+/// ```
+#[derive(Debug)]
+pub struct RecordingGroup {
+    dir: PathBuf,
+    period: Duration,
+    slots: Mutex<BTreeMap<u128, Vec<Entry>>>,
+}
+
+impl RecordingGroup {
+    /// Constructs a new group with segments of the given period and manifests written into the
+    /// specified directory.
+    pub fn new<P: AsRef<Path>>(dir: P, period: Duration) -> Self {
+        Self {
+            dir: dir.as_ref().into(),
+            period: period.max(Duration::from_millis(1)),
+            slots: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the segment period.
+    #[inline]
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Returns the end of the slot the given instant falls into.
+    pub(crate) fn boundary(&self, at: SystemTime) -> SystemTime {
+        let period = self.period.as_millis();
+        UNIX_EPOCH + Duration::from_millis(((millis(at) / period + 1) * period) as u64)
+    }
+
+    /// Returns the path of the manifest of the slot starting at the given Unix time in
+    /// milliseconds.
+    fn manifest_path(&self, slot: u128) -> PathBuf {
+        self.dir.join(format!("group-{}.json", slot))
+    }
+
+    /// Adds the given finished segment of a camera, rewriting the manifest of its slot.
+    pub(crate) fn record(
+        &self,
+        camera: &str,
+        path: &Path,
+        start: SystemTime,
+        end: SystemTime,
+    ) -> Result<(), io::Error> {
+        let period = self.period.as_millis();
+        let slot = millis(start) / period * period;
+
+        let mut slots = self.slots.lock().expect("group lock must not be poisoned");
+        let entries = slots.entry(slot).or_default();
+        entries.push(Entry {
+            camera: camera.into(),
+            path: path.into(),
+            start,
+            end,
+        });
+        entries.sort_by(|a, b| a.camera.cmp(&b.camera));
+
+        let buf = self.encode(slot, entries);
+        while slots.len() > MAX_SLOTS {
+            let oldest = *slots.keys().next().expect("slots must not be empty");
+            slots.remove(&oldest);
+        }
+        drop(slots);
+
+        // Replace atomically, so that players never read a partially written manifest.
+        let path = self.manifest_path(slot);
+        let tmp = path.with_extension("json.part");
+        fs::write(&tmp, buf)?;
+        fs::rename(tmp, path)
+    }
+
+    fn encode(&self, slot: u128, entries: &[Entry]) -> String {
+        let mut buf = format!(
+            "{{\"start_ms\":{},\"period_ms\":{},\"segments\":[",
+            slot,
+            self.period.as_millis()
+        );
+        for (idx, entry) in entries.iter().enumerate() {
+            if idx > 0 {
+                buf.push(',');
+            }
+            buf.push_str("{\"camera\":");
+            json::push_str(&mut buf, &entry.camera);
+            buf.push_str(",\"path\":");
+            json::push_str(&mut buf, &entry.path.to_string_lossy());
+            buf.push_str(&format!(
+                ",\"start_ms\":{},\"end_ms\":{}}}",
+                millis(entry.start),
+                millis(entry.end)
+            ));
+        }
+        buf.push_str("]}\n");
+        buf
+    }
+}
+
+fn millis(at: SystemTime) -> u128 {
+    at.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    fn at(ms: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn test_boundary() {
+        let group = RecordingGroup::new(".", Duration::from_secs(60));
+
+        assert_eq!(at(120_000), group.boundary(at(60_000)));
+        assert_eq!(at(120_000), group.boundary(at(119_999)));
+    }
+
+    #[test]
+    fn test_manifest() {
+        let dir = env::temp_dir().join(format!("cleverdog-group-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let group = RecordingGroup::new(&dir, Duration::from_secs(60));
+        group
+            .record("front", Path::new("front-00001.h264"), at(60_000), at(120_000))
+            .unwrap();
+        group
+            .record("back", Path::new("back-00000.h264"), at(61_500), at(120_000))
+            .unwrap();
+
+        assert_eq!(
+            "{\"start_ms\":60000,\"period_ms\":60000,\"segments\":[\
+             {\"camera\":\"back\",\"path\":\"back-00000.h264\",\"start_ms\":61500,\"end_ms\":120000},\
+             {\"camera\":\"front\",\"path\":\"front-00001.h264\",\"start_ms\":60000,\"end_ms\":120000}]}\n",
+            fs::read_to_string(dir.join("group-60000.json")).unwrap()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,396 @@
+//! Shared-memory ring buffer output.
+//!
+//! The ring is a file, usually under `/dev/shm`, mapped by the writer and any number of readers.
+//! Publishing a frame costs a copy and two atomic stores, without syscalls.
+//!
+//! Layout, all integers are little-endian `u64` unless noted:
+//!
+//! | Offset | Field       | Description                                            |
+//! |--------|-------------|--------------------------------------------------------|
+//! | 0      | magic       | `CDRING01` ASCII                                       |
+//! | 8      | capacity    | Size of the data area in bytes, a multiple of 8        |
+//! | 16     | reserve_pos | Total bytes reserved by the writer, updated first      |
+//! | 24     | write_pos   | Total bytes committed by the writer, updated last      |
+//! | 32     | frames      | Total frames committed                                 |
+//! | 64     | data        | Records                                                |
+//!
+//! A record at stream position `pos` starts at data offset `pos % capacity` with a `u32` payload
+//! length and a `u32` reserved field, followed by the payload, padded to 8 bytes. Records never
+//! wrap: when one does not fit before the end of the data area, a `u32::MAX` length marker is
+//! written and the record starts at offset 0.
+//!
+//! Readers keep their own position. A reader starting at `write_pos` reads records while its
+//! position is behind `write_pos`. After copying a record it must check that `reserve_pos` did
+//! not advance more than `capacity` past the start of the record; otherwise the record may have
+//! been overwritten while copying and the reader has been overrun.
+
+use core::{
+    fmt::{self, Display, Formatter},
+    ptr,
+    sync::atomic::{self, AtomicU64, Ordering},
+};
+use std::{
+    error::Error,
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind},
+    os::unix::io::AsRawFd,
+    path::Path,
+};
+
+use super::Sink;
+
+/// Default size of the data area.
+pub const DEFAULT_CAPACITY: usize = 4 * 1024 * 1024;
+
+const MAGIC: &[u8; 8] = b"CDRING01";
+const HEADER_SIZE: usize = 64;
+const RECORD_HEADER_SIZE: usize = 8;
+const WRAP_MARKER: u32 = u32::MAX;
+
+const CAPACITY_OFFSET: usize = 8;
+const RESERVE_POS_OFFSET: usize = 16;
+const WRITE_POS_OFFSET: usize = 24;
+const FRAMES_OFFSET: usize = 32;
+
+/// Memory-mapped file.
+#[derive(Debug)]
+struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+}
+
+// SAFETY: the mapping is exclusively owned and all shared accesses go through atomics.
+unsafe impl Send for Mmap {}
+
+impl Mmap {
+    fn new(file: &File, len: usize, writable: bool) -> Result<Self, io::Error> {
+        let prot = match writable {
+            true => libc::PROT_READ | libc::PROT_WRITE,
+            false => libc::PROT_READ,
+        };
+
+        // SAFETY: mapping a valid file descriptor, the result is checked below.
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, prot, libc::MAP_SHARED, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self {
+            ptr: ptr as *mut u8,
+            len,
+        })
+    }
+
+    fn atomic(&self, offset: usize) -> &AtomicU64 {
+        // SAFETY: the offset is 8-byte aligned within the page-aligned header.
+        unsafe { &*(self.ptr.add(offset) as *const AtomicU64) }
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        let mut v = [0; 8];
+        // SAFETY: callers only read within the mapping.
+        unsafe { ptr::copy_nonoverlapping(self.ptr.add(offset), v.as_mut_ptr(), 8) };
+        u64::from_le_bytes(v)
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: unmapping the region mapped in `new`.
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.len) };
+    }
+}
+
+/// An error returned when a frame does not fit into the ring.
+#[derive(Debug, Clone)]
+pub struct FrameTooLarge(pub usize);
+
+impl Display for FrameTooLarge {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "frame of {} bytes does not fit into the ring", self.0)
+    }
+}
+
+impl Error for FrameTooLarge {}
+
+/// Sink publishing frames into a shared-memory ring buffer, for local consumers that need the
+/// lowest latency possible.
+///
+/// There must be a single writer per ring. Slow readers are overrun rather than blocking it.
+#[derive(Debug)]
+pub struct ShmRing {
+    map: Mmap,
+    capacity: usize,
+    pos: u64,
+}
+
+impl ShmRing {
+    /// Creates the ring file at the given path with a data area of the specified capacity,
+    /// rounded up to 8 bytes.
+    ///
+    /// An existing file is truncated, which resets the ring.
+    pub fn create<P: AsRef<Path>>(path: P, capacity: usize) -> Result<Self, io::Error> {
+        let capacity = align(capacity);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len((HEADER_SIZE + capacity) as u64)?;
+
+        let map = Mmap::new(&file, HEADER_SIZE + capacity, true)?;
+        // SAFETY: the header lies within the mapping.
+        unsafe {
+            ptr::copy_nonoverlapping(MAGIC.as_ptr(), map.ptr, MAGIC.len());
+            ptr::copy_nonoverlapping(
+                (capacity as u64).to_le_bytes().as_ptr(),
+                map.ptr.add(CAPACITY_OFFSET),
+                8,
+            );
+        }
+
+        Ok(Self { map, capacity, pos: 0 })
+    }
+
+    /// Writes the given bytes at the data offset.
+    fn write(&mut self, offset: usize, buf: &[u8]) {
+        debug_assert!(offset + buf.len() <= self.capacity);
+        // SAFETY: the range lies within the data area, checked by callers.
+        unsafe { ptr::copy_nonoverlapping(buf.as_ptr(), self.map.ptr.add(HEADER_SIZE + offset), buf.len()) };
+    }
+}
+
+impl Sink for ShmRing {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let size = align(RECORD_HEADER_SIZE + buf.len());
+        if size > self.capacity || buf.len() >= WRAP_MARKER as usize {
+            return Err(FrameTooLarge(buf.len()).into());
+        }
+
+        let mut offset = (self.pos % self.capacity as u64) as usize;
+        let mut pos = self.pos;
+        if offset + size > self.capacity {
+            pos += (self.capacity - offset) as u64;
+        }
+
+        // Announce the overwritten range before touching it.
+        self.map
+            .atomic(RESERVE_POS_OFFSET)
+            .store(pos + size as u64, Ordering::Release);
+        // Keep the record writes below from being observed before the announcement.
+        atomic::fence(Ordering::Release);
+
+        if pos != self.pos {
+            self.write(offset, &WRAP_MARKER.to_le_bytes());
+            offset = 0;
+        }
+
+        self.write(offset, &(buf.len() as u32).to_le_bytes());
+        self.write(offset + 4, &[0; 4]);
+        self.write(offset + RECORD_HEADER_SIZE, buf);
+
+        self.pos = pos + size as u64;
+        self.map.atomic(WRITE_POS_OFFSET).store(self.pos, Ordering::Release);
+        self.map.atomic(FRAMES_OFFSET).fetch_add(1, Ordering::Release);
+
+        Ok(())
+    }
+}
+
+/// An error returned when a reader fell behind the writer by more than the ring capacity.
+#[derive(Debug, Clone)]
+pub struct Overrun {
+    /// Number of bytes lost.
+    pub skipped: u64,
+}
+
+impl Display for Overrun {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "reader overrun, {} bytes skipped", self.skipped)
+    }
+}
+
+impl Error for Overrun {}
+
+/// Reader of a shared-memory ring created by [`ShmRing`].
+#[derive(Debug)]
+pub struct ShmReader {
+    map: Mmap,
+    capacity: usize,
+    pos: u64,
+}
+
+impl ShmReader {
+    /// Opens the ring at the given path, starting at the latest committed frame.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER_SIZE {
+            return Err(io::Error::new(ErrorKind::InvalidData, "ring is too short"));
+        }
+
+        let map = Mmap::new(&file, len, false)?;
+        // SAFETY: the magic lies within the header.
+        let magic = unsafe { core::slice::from_raw_parts(map.ptr, MAGIC.len()) };
+        let capacity = map.u64_at(CAPACITY_OFFSET) as usize;
+        if magic != MAGIC || HEADER_SIZE + capacity != len {
+            return Err(io::Error::new(ErrorKind::InvalidData, "not a ring"));
+        }
+
+        let pos = map.atomic(WRITE_POS_OFFSET).load(Ordering::Acquire);
+
+        Ok(Self { map, capacity, pos })
+    }
+
+    /// Returns the next frame, if any has been committed since the previous one.
+    ///
+    /// On overrun the reader skips to the latest committed frame.
+    pub fn try_recv(&mut self) -> Result<Option<Vec<u8>>, Overrun> {
+        let write_pos = self.map.atomic(WRITE_POS_OFFSET).load(Ordering::Acquire);
+        if self.pos >= write_pos {
+            return Ok(None);
+        }
+
+        let start = self.pos;
+        let mut offset = (self.pos % self.capacity as u64) as usize;
+        let mut len = self.u32_at(offset);
+        if len == WRAP_MARKER {
+            self.pos += (self.capacity - offset) as u64;
+            offset = 0;
+            len = self.u32_at(offset);
+        }
+
+        // Only a record being overwritten can claim more than the rest of the data area.
+        let len = len as usize;
+        if RECORD_HEADER_SIZE + len > self.capacity - offset {
+            return Err(self.skip(start));
+        }
+
+        let mut frame = vec![0; len];
+        // SAFETY: the range lies within the data area, checked above.
+        unsafe {
+            let src = self.map.ptr.add(HEADER_SIZE + offset + RECORD_HEADER_SIZE);
+            ptr::copy_nonoverlapping(src, frame.as_mut_ptr(), len);
+        }
+        self.pos += align(RECORD_HEADER_SIZE + len) as u64;
+
+        // The writer may have been reusing the record while it was being copied. The fence keeps
+        // the copy above from being reordered after the check.
+        atomic::fence(Ordering::Acquire);
+        let reserve_pos = self.map.atomic(RESERVE_POS_OFFSET).load(Ordering::Acquire);
+        if reserve_pos - start > self.capacity as u64 {
+            return Err(self.skip(start));
+        }
+
+        Ok(Some(frame))
+    }
+
+    /// Skips to the latest committed frame after being overrun while reading the one at `start`.
+    fn skip(&mut self, start: u64) -> Overrun {
+        let write_pos = self.map.atomic(WRITE_POS_OFFSET).load(Ordering::Acquire);
+        self.pos = write_pos;
+        Overrun {
+            skipped: write_pos - start,
+        }
+    }
+
+    /// Returns the total number of frames committed by the writer.
+    pub fn frames(&self) -> u64 {
+        self.map.atomic(FRAMES_OFFSET).load(Ordering::Acquire)
+    }
+
+    fn u32_at(&self, offset: usize) -> u32 {
+        let mut v = [0; 4];
+        // SAFETY: record headers are 8-byte aligned and lie within the data area.
+        unsafe { ptr::copy_nonoverlapping(self.map.ptr.add(HEADER_SIZE + offset), v.as_mut_ptr(), 4) };
+        u32::from_le_bytes(v)
+    }
+}
+
+#[inline]
+fn align(size: usize) -> usize {
+    (size + 7) & !7
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        env, fs,
+        io::{Seek, SeekFrom, Write},
+        path::PathBuf,
+    };
+
+    use super::*;
+
+    fn ring_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("cleverdog-shm-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_ring() {
+        let path = ring_path("ring");
+        let mut ring = ShmRing::create(&path, 64).unwrap();
+        let mut reader = ShmReader::open(&path).unwrap();
+
+        assert_eq!(None, reader.try_recv().unwrap());
+
+        // Each record takes 24 bytes, so the third one wraps around.
+        for frame in &[&b"frame-0000"[..], b"frame-0001", b"frame-0002"] {
+            ring.send(frame).unwrap();
+            assert_eq!(Some(frame.to_vec()), reader.try_recv().unwrap());
+        }
+
+        assert_eq!(None, reader.try_recv().unwrap());
+        assert_eq!(3, reader.frames());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_overrun() {
+        let path = ring_path("overrun");
+        let mut ring = ShmRing::create(&path, 64).unwrap();
+        let mut reader = ShmReader::open(&path).unwrap();
+
+        for _ in 0..4 {
+            ring.send(b"frame-0000").unwrap();
+        }
+
+        assert!(reader.try_recv().is_err());
+        assert_eq!(None, reader.try_recv().unwrap());
+
+        ring.send(b"frame-0001").unwrap();
+        assert_eq!(Some(b"frame-0001".to_vec()), reader.try_recv().unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_torn_length() {
+        let path = ring_path("torn");
+        let mut ring = ShmRing::create(&path, 64).unwrap();
+        let mut reader = ShmReader::open(&path).unwrap();
+
+        ring.send(b"frame-0000").unwrap();
+        // Pretend the writer was caught mid-way rewriting the record header.
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(HEADER_SIZE as u64)).unwrap();
+        file.write_all(&1000u32.to_le_bytes()).unwrap();
+
+        assert!(reader.try_recv().is_err());
+        assert_eq!(None, reader.try_recv().unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_frame_too_large() {
+        let path = ring_path("large");
+        let mut ring = ShmRing::create(&path, 64).unwrap();
+
+        assert!(ring.send(&[0; 57]).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,586 @@
+use core::time::Duration;
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io::{self, ErrorKind, Write},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{debug, info, warn};
+
+use super::{Endpoint, Sink};
+use crate::resolve::Resolver;
+
+/// Largest payload of a data packet, fitting a 1500 bytes MTU after IP, UDP and SRT headers.
+const MAX_PAYLOAD: usize = 1456;
+
+/// Payload size of [`Write`] chunks: seven MPEG-TS packets, as SRT receivers expect.
+const TS_PAYLOAD: usize = 7 * 188;
+
+/// Interval of retransmitting handshake requests left unanswered.
+const HANDSHAKE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Number of sent packets kept for retransmission, regardless of their age.
+const MAX_BUFFERED: usize = 8192;
+
+const MAX_SEQUENCE: u32 = 0x7fff_ffff;
+const MAX_MESSAGE: u32 = 0x03ff_ffff;
+
+const CTRL_HANDSHAKE: u16 = 0;
+const CTRL_KEEPALIVE: u16 = 1;
+const CTRL_ACK: u16 = 2;
+const CTRL_NAK: u16 = 3;
+const CTRL_SHUTDOWN: u16 = 5;
+const CTRL_ACKACK: u16 = 6;
+
+const HS_INDUCTION: u32 = 1;
+const HS_CONCLUSION: u32 = 0xffff_ffff;
+/// Handshake types from this value on are rejection reasons.
+const HS_REJECTION: u32 = 1000;
+
+/// Magic value of the extension field in a listener's induction response.
+const HS_MAGIC: u16 = 0x4a17;
+const HS_EXT_HSREQ: u16 = 1;
+const HS_EXT_CONFIG: u16 = 4;
+
+const EXT_HSREQ: u16 = 1;
+const EXT_SID: u16 = 5;
+
+/// Version 1.5.0, the first supporting every capability announced.
+const SRT_VERSION: u32 = 0x0001_0500;
+/// Timestamp-based packet delivery in both directions, encryption capability, too-late packet
+/// drop, periodic NAKs and the retransmission flag.
+const SRT_FLAGS: u32 = 0x3f;
+
+/// Packet position flags of a data packet.
+const PP_SOLO: u32 = 0b11 << 30;
+const PP_FIRST: u32 = 0b10 << 30;
+const PP_MIDDLE: u32 = 0;
+const PP_LAST: u32 = 0b01 << 30;
+/// Retransmitted packet flag.
+const RETRANSMITTED: u32 = 1 << 26;
+
+/// Parameters of an SRT connection.
+#[derive(Debug, Clone)]
+pub struct SrtOptions {
+    stream_id: Option<String>,
+    latency: Duration,
+    timeout: Duration,
+}
+
+impl Default for SrtOptions {
+    fn default() -> Self {
+        Self {
+            stream_id: None,
+            latency: Duration::from_millis(120),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl SrtOptions {
+    /// Constructs new options with default values.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the stream ID sent to the listener, e.g. `publish:live` for MediaMTX.
+    pub fn stream_id(mut self, id: &str) -> Self {
+        self.stream_id = Some(id.into());
+        self
+    }
+
+    /// Sets the latency the receiver buffers packets for, bounding the time available for
+    /// retransmissions.
+    ///
+    /// Defaults to 120 ms. Links with larger round-trip times need about four of them.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Sets the timeout of the handshake.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+}
+
+/// Sink pushing the stream to an SRT listener, e.g. a media server, in caller mode.
+///
+/// SRT recovers lost packets by retransmitting those the receiver reports missing, within the
+/// configured latency, which plain UDP forwarding cannot do over lossy WAN links. Each buffer
+/// passed to [`Sink::send`] is sent as a single message. Data written using [`Write`] instead is
+/// packed into payloads of seven MPEG-TS packets, so that an [MPEG-TS
+/// muxer](crate::mux::mpegts::TsWriter) can be put on top.
+///
+/// Control packets are processed whenever data is sent, which a live stream does continuously.
+///
+/// ```no_run
+/// use cleverdog::{
+///     mux::mpegts::TsWriter,
+///     resolve::SystemResolver,
+///     sink::{Endpoint, SrtOptions, SrtSink},
+/// };
+///
+/// let endpoint = Endpoint::new("mediamtx.local", 8890);
+/// let opts = SrtOptions::new().stream_id("publish:front");
+/// let sink = SrtSink::connect(&SystemResolver, &endpoint, &opts).unwrap();
+/// let mut wr = TsWriter::new(sink);
+/// ```
+#[derive(Debug)]
+pub struct SrtSink {
+    sock: UdpSocket,
+    peer_id: u32,
+    started: Instant,
+    latency: Duration,
+    sequence: u32,
+    message: u32,
+    /// Sent packets, oldest first, along with their sequence number and sending time.
+    sent: VecDeque<(u32, Instant, Vec<u8>)>,
+    retransmitted: u64,
+    buf: Vec<u8>,
+}
+
+impl SrtSink {
+    /// Connects to the SRT listener at the given endpoint, resolving it with the specified
+    /// resolver.
+    pub fn connect<R>(resolver: &R, endpoint: &Endpoint, opts: &SrtOptions) -> Result<Self, io::Error>
+    where
+        R: Resolver + ?Sized,
+    {
+        let addr = resolver
+            .resolve(endpoint.host(), endpoint.port())?
+            .into_iter()
+            .next()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("no addresses for {}", endpoint.host())))?;
+        let sock = match addr {
+            SocketAddr::V4(..) => UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?,
+            SocketAddr::V6(..) => UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?,
+        };
+        sock.connect(addr)?;
+
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let socket_id = (seed.subsec_nanos() ^ std::process::id().rotate_left(16)) & MAX_SEQUENCE;
+        let sequence = (seed.as_secs() as u32).wrapping_mul(2_654_435_761) & MAX_SEQUENCE;
+
+        let mut sink = Self {
+            sock,
+            peer_id: 0,
+            started: Instant::now(),
+            latency: opts.latency,
+            sequence,
+            message: 1,
+            sent: VecDeque::new(),
+            retransmitted: 0,
+            buf: Vec::with_capacity(TS_PAYLOAD),
+        };
+        sink.handshake(socket_id, opts)?;
+        sink.sock.set_nonblocking(true)?;
+        info!("connected to srt://{}", endpoint);
+
+        Ok(sink)
+    }
+
+    /// Returns the number of packets retransmitted on request of the receiver.
+    #[inline]
+    pub fn retransmitted(&self) -> u64 {
+        self.retransmitted
+    }
+
+    fn handshake(&mut self, socket_id: u32, opts: &SrtOptions) -> Result<(), io::Error> {
+        let deadline = Instant::now() + opts.timeout;
+
+        let induction = handshake(4, 0, 2, self.sequence, HS_INDUCTION, socket_id, 0);
+        let response = self.exchange(&self.control(CTRL_HANDSHAKE, 0, &induction), deadline)?;
+        let cookie = read_u32(&response, 28);
+        if read_u32(&response, 0) != 5 || read_u16(&response, 6) != HS_MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "listener does not support SRT handshake v5",
+            ));
+        }
+
+        let mut flags = HS_EXT_HSREQ;
+        if opts.stream_id.is_some() {
+            flags |= HS_EXT_CONFIG;
+        }
+        let mut conclusion = handshake(5, 0, flags, self.sequence, HS_CONCLUSION, socket_id, cookie);
+
+        let latency = opts.latency.as_millis().min(u128::from(u16::MAX)) as u32;
+        conclusion.extend_from_slice(&EXT_HSREQ.to_be_bytes());
+        conclusion.extend_from_slice(&3u16.to_be_bytes());
+        conclusion.extend_from_slice(&SRT_VERSION.to_be_bytes());
+        conclusion.extend_from_slice(&SRT_FLAGS.to_be_bytes());
+        conclusion.extend_from_slice(&(latency << 16 | latency).to_be_bytes());
+
+        if let Some(id) = &opts.stream_id {
+            let mut words = id.as_bytes().to_vec();
+            words.resize(words.len().div_ceil(4) * 4, 0);
+            conclusion.extend_from_slice(&EXT_SID.to_be_bytes());
+            conclusion.extend_from_slice(&(words.len() as u16 / 4).to_be_bytes());
+            // Stream ID words are sent in little-endian order.
+            for word in words.chunks(4) {
+                conclusion.extend(word.iter().rev());
+            }
+        }
+
+        let response = self.exchange(&self.control(CTRL_HANDSHAKE, 0, &conclusion), deadline)?;
+        match read_u32(&response, 20) {
+            HS_CONCLUSION => {}
+            reason if reason >= HS_REJECTION => {
+                return Err(io::Error::new(
+                    ErrorKind::ConnectionRefused,
+                    format!("listener rejected the connection, reason {}", reason - HS_REJECTION),
+                ))
+            }
+            ty => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("unexpected handshake type {:#x}", ty),
+                ))
+            }
+        }
+        self.peer_id = read_u32(&response, 24);
+
+        Ok(())
+    }
+
+    /// Sends the handshake request until a handshake response arrives, returning its control
+    /// information field.
+    fn exchange(&self, request: &[u8], deadline: Instant) -> Result<Vec<u8>, io::Error> {
+        let mut buf = [0; 1500];
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(ErrorKind::TimedOut, "SRT handshake timed out"));
+            }
+            self.sock.send(request)?;
+            self.sock
+                .set_read_timeout(Some(HANDSHAKE_INTERVAL.min(deadline - now)))?;
+
+            match self.sock.recv(&mut buf) {
+                Ok(size) if size >= 16 + 48 && read_u16(&buf, 0) == 0x8000 | CTRL_HANDSHAKE => {
+                    return Ok(buf[16..size].to_vec())
+                }
+                Ok(..) => {}
+                Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Encodes a control packet of the given type.
+    fn control(&self, ty: u16, info: u32, cif: &[u8]) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(16 + cif.len());
+        buf.extend_from_slice(&(0x8000 | ty).to_be_bytes());
+        buf.extend_from_slice(&[0, 0]);
+        buf.extend_from_slice(&info.to_be_bytes());
+        buf.extend_from_slice(&self.timestamp().to_be_bytes());
+        buf.extend_from_slice(&self.peer_id.to_be_bytes());
+        buf.extend_from_slice(cif);
+        buf
+    }
+
+    /// Returns the packet timestamp, in microseconds since the connection started.
+    fn timestamp(&self) -> u32 {
+        self.started.elapsed().as_micros() as u32
+    }
+
+    /// Sends the given buffer as a single message, split into as many packets as needed.
+    fn send_message(&mut self, buf: &[u8]) -> Result<(), io::Error> {
+        self.poll()?;
+
+        let count = buf.len().div_ceil(MAX_PAYLOAD).max(1);
+        for (idx, payload) in buf.chunks(MAX_PAYLOAD).enumerate() {
+            let position = match (idx, count) {
+                (_, 1) => PP_SOLO,
+                (0, _) => PP_FIRST,
+                (idx, count) if idx + 1 == count => PP_LAST,
+                _ => PP_MIDDLE,
+            };
+
+            let mut packet = Vec::with_capacity(16 + payload.len());
+            packet.extend_from_slice(&self.sequence.to_be_bytes());
+            packet.extend_from_slice(&(position | self.message).to_be_bytes());
+            packet.extend_from_slice(&self.timestamp().to_be_bytes());
+            packet.extend_from_slice(&self.peer_id.to_be_bytes());
+            packet.extend_from_slice(payload);
+            self.transmit(&packet)?;
+
+            self.sent.push_back((self.sequence, Instant::now(), packet));
+            self.sequence = (self.sequence + 1) & MAX_SEQUENCE;
+        }
+        self.message = self.message % MAX_MESSAGE + 1;
+
+        // Packets the receiver dropped as too late are of no use anymore.
+        while let Some((_, sent, ..)) = self.sent.front() {
+            if self.sent.len() <= MAX_BUFFERED && sent.elapsed() <= 2 * self.latency {
+                break;
+            }
+            self.sent.pop_front();
+        }
+
+        Ok(())
+    }
+
+    /// Sends a packet, leaving it for the receiver to request again if the socket is full.
+    fn transmit(&self, packet: &[u8]) -> Result<(), io::Error> {
+        match self.sock.send(packet) {
+            Ok(..) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Processes control packets received so far.
+    fn poll(&mut self) -> Result<(), io::Error> {
+        let mut buf = [0; 1500];
+        loop {
+            let size = match self.sock.recv(&mut buf) {
+                Ok(size) => size,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                // Reported by some platforms after an ICMP unreachable, the listener may be back.
+                Err(err) if err.kind() == ErrorKind::ConnectionRefused => {
+                    debug!("SRT listener is unreachable");
+                    return Ok(());
+                }
+                Err(err) => return Err(err),
+            };
+            if size < 16 || buf[0] & 0x80 == 0 {
+                continue;
+            }
+
+            let info = read_u32(&buf, 4);
+            let cif = &buf[16..size];
+            match read_u16(&buf, 0) & 0x7fff {
+                CTRL_ACK if cif.len() >= 4 => {
+                    let acked = read_u32(cif, 0) & MAX_SEQUENCE;
+                    while let Some((seq, ..)) = self.sent.front() {
+                        if !precedes(*seq, acked) {
+                            break;
+                        }
+                        self.sent.pop_front();
+                    }
+                    // Light ACKs carry nothing but the sequence number and are not acknowledged.
+                    if cif.len() > 4 {
+                        self.sock.send(&self.control(CTRL_ACKACK, info, &[0; 4]))?;
+                    }
+                }
+                CTRL_NAK => {
+                    let lost = loss_list(cif);
+                    for (seq, _, packet) in &mut self.sent {
+                        if lost
+                            .iter()
+                            .any(|&(first, last)| !precedes(*seq, first) && !precedes(last, *seq))
+                        {
+                            packet[4] |= (RETRANSMITTED >> 24) as u8;
+                            match self.sock.send(packet) {
+                                Ok(..) => self.retransmitted += 1,
+                                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                                Err(err) => return Err(err),
+                            }
+                        }
+                    }
+                }
+                CTRL_SHUTDOWN => {
+                    warn!("SRT listener closed the connection");
+                    return Err(io::Error::new(
+                        ErrorKind::ConnectionAborted,
+                        "SRT listener closed the connection",
+                    ));
+                }
+                CTRL_KEEPALIVE => {}
+                ty => debug!("skipping SRT control packet {:#x}", ty),
+            }
+        }
+    }
+}
+
+impl Sink for SrtSink {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.send_message(buf)?;
+        Ok(())
+    }
+}
+
+impl Write for SrtSink {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, io::Error> {
+        let size = buf.len().min(TS_PAYLOAD - self.buf.len());
+        self.buf.extend_from_slice(&buf[..size]);
+        if self.buf.len() == TS_PAYLOAD {
+            self.flush()?;
+        }
+        Ok(size)
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        if !self.buf.is_empty() {
+            let buf = std::mem::take(&mut self.buf);
+            let result = self.send_message(&buf);
+            self.buf = buf;
+            self.buf.clear();
+            result?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SrtSink {
+    fn drop(&mut self) {
+        let _ = self.sock.send(&self.control(CTRL_SHUTDOWN, 0, &[0; 4]));
+    }
+}
+
+/// Encodes the handshake control information field, without extensions.
+fn handshake(version: u32, encryption: u16, extension: u16, sequence: u32, ty: u32, id: u32, cookie: u32) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(48);
+    buf.extend_from_slice(&version.to_be_bytes());
+    buf.extend_from_slice(&encryption.to_be_bytes());
+    buf.extend_from_slice(&extension.to_be_bytes());
+    buf.extend_from_slice(&sequence.to_be_bytes());
+    // Maximum transmission unit and flow window size.
+    buf.extend_from_slice(&1500u32.to_be_bytes());
+    buf.extend_from_slice(&8192u32.to_be_bytes());
+    buf.extend_from_slice(&ty.to_be_bytes());
+    buf.extend_from_slice(&id.to_be_bytes());
+    buf.extend_from_slice(&cookie.to_be_bytes());
+    // Peer IP address, which listeners do not rely on.
+    buf.extend_from_slice(&[0; 16]);
+    buf
+}
+
+/// Decodes the loss list of a NAK into inclusive ranges of sequence numbers.
+fn loss_list(cif: &[u8]) -> Vec<(u32, u32)> {
+    let mut ranges = Vec::new();
+    let mut it = cif.chunks_exact(4).map(|v| read_u32(v, 0));
+    while let Some(v) = it.next() {
+        match v & 0x8000_0000 {
+            0 => ranges.push((v, v)),
+            _ => match it.next() {
+                Some(last) => ranges.push((v & MAX_SEQUENCE, last & MAX_SEQUENCE)),
+                None => break,
+            },
+        }
+    }
+    ranges
+}
+
+/// Returns whether the first sequence number precedes the second one, accounting for
+/// wraparound.
+fn precedes(a: u32, b: u32) -> bool {
+    let delta = b.wrapping_sub(a) & MAX_SEQUENCE;
+    delta != 0 && delta < 0x4000_0000
+}
+
+#[inline]
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([buf[offset], buf[offset + 1]])
+}
+
+#[inline]
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::resolve::SystemResolver;
+
+    #[test]
+    fn test_loss_list() {
+        let mut cif = Vec::new();
+        for v in [5u32, 0x8000_0007, 9, 0x8000_0000 | MAX_SEQUENCE, 1] {
+            cif.extend_from_slice(&v.to_be_bytes());
+        }
+
+        assert_eq!(vec![(5, 5), (7, 9), (MAX_SEQUENCE, 1)], loss_list(&cif));
+    }
+
+    #[test]
+    fn test_precedes() {
+        assert!(precedes(1, 2));
+        assert!(!precedes(2, 2));
+        assert!(!precedes(3, 2));
+        assert!(precedes(MAX_SEQUENCE, 0));
+    }
+
+    #[test]
+    fn test_connect_and_retransmit() {
+        let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let thread = thread::spawn(move || {
+            let mut buf = [0; 1500];
+
+            let (size, peer) = listener.recv_from(&mut buf).unwrap();
+            assert_eq!(16 + 48, size);
+            assert_eq!(HS_INDUCTION, read_u32(&buf, 16 + 20));
+            let mut response = buf[..size].to_vec();
+            response[16..20].copy_from_slice(&5u32.to_be_bytes());
+            response[22..24].copy_from_slice(&HS_MAGIC.to_be_bytes());
+            response[16 + 28..16 + 32].copy_from_slice(&42u32.to_be_bytes());
+            listener.send_to(&response, peer).unwrap();
+
+            let (size, ..) = listener.recv_from(&mut buf).unwrap();
+            let request = buf[..size].to_vec();
+            assert_eq!(42, read_u32(&request, 16 + 28));
+            let mut response = request[..16 + 48].to_vec();
+            response[16 + 24..16 + 28].copy_from_slice(&7u32.to_be_bytes());
+            listener.send_to(&response, peer).unwrap();
+
+            let (size, ..) = listener.recv_from(&mut buf).unwrap();
+            let first = buf[..size].to_vec();
+            let (size, ..) = listener.recv_from(&mut buf).unwrap();
+            let second = buf[..size].to_vec();
+
+            // Report the first packet lost.
+            let mut nak = vec![0x80, CTRL_NAK as u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+            nak.extend_from_slice(&first[..4]);
+            listener.send_to(&nak, peer).unwrap();
+
+            let (size, ..) = listener.recv_from(&mut buf).unwrap();
+            let retransmitted = buf[..size].to_vec();
+            let (size, ..) = listener.recv_from(&mut buf).unwrap();
+            let third = buf[..size].to_vec();
+
+            (request, first, second, third, retransmitted)
+        });
+
+        let endpoint = Endpoint::new("127.0.0.1", port);
+        let opts = SrtOptions::new().stream_id("publish:live");
+        let mut sink = SrtSink::connect(&SystemResolver, &endpoint, &opts).unwrap();
+        sink.send(b"first").unwrap();
+        sink.send(b"second").unwrap();
+        // Give the NAK time to arrive, it is processed when sending the next buffer.
+        thread::sleep(Duration::from_millis(100));
+        sink.send(b"third").unwrap();
+
+        let (request, first, second, third, retransmitted) = thread.join().unwrap();
+
+        // HSREQ extension followed by the stream ID, with words reversed.
+        assert_eq!(HS_EXT_HSREQ | HS_EXT_CONFIG, read_u16(&request, 16 + 6));
+        assert_eq!(&[0, 1, 0, 3], &request[64..68]);
+        assert_eq!(&[0, 5, 0, 3], &request[80..84]);
+        assert_eq!(b"lbup:hsievil", &request[84..96]);
+
+        assert_eq!(7, read_u32(&first, 12));
+        assert_eq!(PP_SOLO | 1, read_u32(&first, 4));
+        assert_eq!(b"first", &first[16..]);
+        assert_eq!(read_u32(&first, 0) + 1, read_u32(&second, 0));
+        assert_eq!(PP_SOLO | 2, read_u32(&second, 4));
+        assert_eq!(b"third", &third[16..]);
+
+        assert_eq!(&first[..4], &retransmitted[..4]);
+        assert_eq!(PP_SOLO | RETRANSMITTED | 1, read_u32(&retransmitted, 4));
+        assert_eq!(b"first", &retransmitted[16..]);
+        assert_eq!(1, sink.retransmitted());
+    }
+}
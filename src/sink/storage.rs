@@ -0,0 +1,276 @@
+use core::time::Duration;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::Sender,
+    time::Instant,
+};
+
+use log::{info, warn};
+
+use crate::metadata;
+
+/// How often free space is checked by default.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Describes how a [`DiskGuard`] reacts to free space dropping below the threshold.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Enforcement {
+    /// Delete the oldest finished segments until enough space is freed, pausing recording if
+    /// there is nothing left to delete.
+    DeleteOldest,
+    /// Pause recording until enough space is freed externally.
+    Pause,
+}
+
+/// Event emitted by a [`DiskGuard`] while enforcing the free space threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorageEvent {
+    /// The given finished segment has been deleted.
+    Deleted(PathBuf),
+    /// Recording has been paused, with the given number of bytes available on the volume.
+    Paused(u64),
+    /// Recording has been resumed, with the given number of bytes available on the volume.
+    Resumed(u64),
+}
+
+/// Storage watchdog keeping the given amount of space free on the recording volume.
+///
+/// Attached to a [`FileSink`](super::FileSink), it periodically checks space available to
+/// unprivileged users on the volume segments are written to. Once it drops below the threshold,
+/// either the oldest finished segments are deleted or recording is paused, dropping incoming
+/// buffers, until enough space is available again.
+///
+/// ```
+/// use cleverdog::sink::{DiskGuard, Enforcement, FileSink};
+///
+/// let guard = DiskGuard::new(1 << 30).enforcement(Enforcement::Pause);
+/// let sink = FileSink::new("out.h264").guard(guard);
+/// ```
+#[derive(Debug)]
+pub struct DiskGuard {
+    min_free: u64,
+    enforcement: Enforcement,
+    interval: Duration,
+    events: Option<Sender<StorageEvent>>,
+    probe: fn(&Path) -> Result<u64, io::Error>,
+    checked_at: Option<Instant>,
+    paused: bool,
+}
+
+impl DiskGuard {
+    /// Constructs a new guard keeping at least the given number of bytes free, deleting the
+    /// oldest segments when needed.
+    pub fn new(min_free: u64) -> Self {
+        Self {
+            min_free,
+            enforcement: Enforcement::DeleteOldest,
+            interval: DEFAULT_INTERVAL,
+            events: None,
+            probe: |dir| available_space(dir),
+            checked_at: None,
+            paused: false,
+        }
+    }
+
+    /// Sets how the threshold is enforced.
+    pub fn enforcement(mut self, enforcement: Enforcement) -> Self {
+        self.enforcement = enforcement;
+        self
+    }
+
+    /// Sets how often free space is checked.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sends events about deleted segments and paused recording into the given channel.
+    pub fn events(mut self, tx: Sender<StorageEvent>) -> Self {
+        self.events = Some(tx);
+        self
+    }
+
+    /// Returns `true` if recording is paused because of low free space.
+    #[inline]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    #[cfg(test)]
+    fn probe(mut self, probe: fn(&Path) -> Result<u64, io::Error>) -> Self {
+        self.probe = probe;
+        self
+    }
+
+    /// Enforces the threshold for the volume containing the given directory, if a check is due.
+    ///
+    /// Deletion candidates are listed lazily by the given function, oldest first. Returns `true`
+    /// if recording should be paused.
+    pub(crate) fn check<F>(&mut self, dir: &Path, candidates: F) -> bool
+    where
+        F: FnOnce() -> Result<Vec<PathBuf>, io::Error>,
+    {
+        let now = Instant::now();
+        match self.checked_at {
+            Some(at) if now.duration_since(at) < self.interval => return self.paused,
+            _ => self.checked_at = Some(now),
+        }
+
+        let mut available = match (self.probe)(dir) {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("failed to check free space in {}: {}", dir.display(), err);
+                return self.paused;
+            }
+        };
+
+        if available < self.min_free && self.enforcement == Enforcement::DeleteOldest {
+            available = self.delete_oldest(dir, available, candidates);
+        }
+
+        match (available >= self.min_free, self.paused) {
+            (true, true) => {
+                info!("resuming recording, {} bytes available", available);
+                self.paused = false;
+                self.emit(StorageEvent::Resumed(available));
+            }
+            (false, false) => {
+                warn!("pausing recording, only {} bytes available", available);
+                self.paused = true;
+                self.emit(StorageEvent::Paused(available));
+            }
+            _ => {}
+        }
+
+        self.paused
+    }
+
+    /// Deletes the oldest candidates until the threshold is met, returning available space.
+    fn delete_oldest<F>(&mut self, dir: &Path, mut available: u64, candidates: F) -> u64
+    where
+        F: FnOnce() -> Result<Vec<PathBuf>, io::Error>,
+    {
+        let candidates = match candidates() {
+            Ok(v) => v,
+            Err(err) => {
+                warn!("failed to list segments in {}: {}", dir.display(), err);
+                return available;
+            }
+        };
+
+        for path in candidates {
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("failed to delete segment {}: {}", path.display(), err);
+                continue;
+            }
+            let _ = fs::remove_file(metadata::sidecar_path(&path));
+            info!("deleted segment {} to free space", path.display());
+            self.emit(StorageEvent::Deleted(path));
+
+            match (self.probe)(dir) {
+                Ok(v) => available = v,
+                Err(..) => break,
+            }
+            if available >= self.min_free {
+                break;
+            }
+        }
+
+        available
+    }
+
+    fn emit(&self, event: StorageEvent) {
+        if let Some(tx) = &self.events {
+            let _ = tx.send(event);
+        }
+    }
+}
+
+/// Returns the number of bytes available to unprivileged users on the volume containing the
+/// given path.
+#[cfg(unix)]
+pub fn available_space<P: AsRef<Path>>(path: P) -> Result<u64, io::Error> {
+    use std::{ffi::CString, mem, os::unix::ffi::OsStrExt};
+
+    let path = CString::new(path.as_ref().as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Returns the number of bytes available to unprivileged users on the volume containing the
+/// given path.
+#[cfg(not(unix))]
+pub fn available_space<P: AsRef<Path>>(_path: P) -> Result<u64, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "free space check is not supported",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use core::sync::atomic::{AtomicU64, Ordering};
+    use std::{env, sync::mpsc};
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_available_space() {
+        assert!(available_space(env::temp_dir()).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_delete_oldest() {
+        static AVAILABLE: AtomicU64 = AtomicU64::new(0);
+
+        let dir = env::temp_dir().join(format!("cleverdog-storage-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let paths = vec![dir.join("out-00000.bin"), dir.join("out-00001.bin")];
+        for path in &paths {
+            fs::write(path, b"abc").unwrap();
+        }
+
+        // Each deletion frees 10 bytes.
+        fn probe(dir: &Path) -> Result<u64, io::Error> {
+            let count = fs::read_dir(dir)?.count() as u64;
+            Ok(AVAILABLE.load(Ordering::SeqCst) + (2 - count) * 10)
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let mut guard = DiskGuard::new(25).interval(Duration::ZERO).events(tx).probe(probe);
+
+        AVAILABLE.store(100, Ordering::SeqCst);
+        assert!(!guard.check(&dir, || Ok(paths.clone())));
+        assert!(paths[0].exists());
+
+        AVAILABLE.store(0, Ordering::SeqCst);
+        assert!(guard.check(&dir, || Ok(paths.clone())));
+        assert!(!paths[0].exists());
+        assert!(!paths[1].exists());
+
+        AVAILABLE.store(15, Ordering::SeqCst);
+        assert!(!guard.check(&dir, || Ok(vec![])));
+
+        assert_eq!(
+            vec![
+                StorageEvent::Deleted(paths[0].clone()),
+                StorageEvent::Deleted(paths[1].clone()),
+                StorageEvent::Paused(20),
+                StorageEvent::Resumed(35),
+            ],
+            rx.try_iter().collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    error::Error,
+    net::{SocketAddr, UdpSocket},
+};
+
+use log::warn;
+
+use super::Sink;
+
+/// Sink that forwards each buffer as a UDP datagram to several destinations.
+///
+/// On Linux all datagrams of a single buffer are submitted with one `sendmmsg(2)` call, which
+/// keeps the syscall load flat when a relay serves many viewers. Elsewhere it falls back to
+/// sending datagrams one by one.
+///
+/// A destination failing to receive a datagram is logged and skipped, without affecting others.
+/// Only when every destination fails is the last error returned, so that a misconfigured
+/// single-destination fan-out doesn't fail silently.
+#[derive(Debug)]
+pub struct UdpFanOut {
+    sock: UdpSocket,
+    dsts: Vec<SocketAddr>,
+}
+
+impl UdpFanOut {
+    /// Constructs a new fan-out sending through the given socket, without destinations.
+    pub fn new(sock: UdpSocket) -> Self {
+        Self { sock, dsts: Vec::new() }
+    }
+
+    /// Adds the given destination.
+    pub fn push(&mut self, addr: SocketAddr) {
+        self.dsts.push(addr);
+    }
+
+    /// Adds the given destination, returning `self` for chaining.
+    pub fn with(mut self, addr: SocketAddr) -> Self {
+        self.push(addr);
+        self
+    }
+
+    /// Returns destination addresses.
+    #[inline]
+    pub fn destinations(&self) -> &[SocketAddr] {
+        &self.dsts
+    }
+
+    /// Returns the number of destinations.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.dsts.len()
+    }
+
+    /// Returns `true` if there are no destinations.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.dsts.is_empty()
+    }
+}
+
+impl Sink for UdpFanOut {
+    #[cfg(target_os = "linux")]
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut offset = 0;
+        let mut sent = 0;
+        let mut last_err = None;
+        while offset < self.dsts.len() {
+            match mmsg::send(&self.sock, buf, &self.dsts[offset..]) {
+                Ok(count) => {
+                    offset += count;
+                    sent += count;
+                }
+                Err(err) => {
+                    warn!("failed to send datagram to {}: {}", self.dsts[offset], err);
+                    offset += 1;
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if sent == 0 => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut sent = 0;
+        let mut last_err = None;
+        for addr in &self.dsts {
+            match self.sock.send_to(buf, addr) {
+                Ok(..) => sent += 1,
+                Err(err) => {
+                    warn!("failed to send datagram to {}: {}", addr, err);
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        match last_err {
+            Some(err) if sent == 0 => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod mmsg {
+    use core::mem;
+    use std::{
+        io,
+        net::{SocketAddr, UdpSocket},
+        os::unix::io::AsRawFd,
+    };
+
+    /// Maximum number of datagrams submitted with a single syscall, matching `UIO_MAXIOV`.
+    const BATCH_SIZE: usize = 1024;
+
+    /// Sends the buffer to the leading destinations with a single `sendmmsg(2)` call, returning
+    /// the number of datagrams sent.
+    pub fn send(sock: &UdpSocket, buf: &[u8], dsts: &[SocketAddr]) -> Result<usize, io::Error> {
+        let dsts = &dsts[..dsts.len().min(BATCH_SIZE)];
+
+        let mut addrs = dsts.iter().map(sockaddr).collect::<Vec<_>>();
+        let mut iov = libc::iovec {
+            iov_base: buf.as_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+
+        let mut msgs = addrs
+            .iter_mut()
+            .map(|(addr, len)| {
+                // SAFETY: `msghdr` is a plain C struct, for which all-zeroes is a valid value.
+                let mut hdr: libc::msghdr = unsafe { mem::zeroed() };
+                hdr.msg_name = addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+                hdr.msg_namelen = *len;
+                hdr.msg_iov = &mut iov;
+                hdr.msg_iovlen = 1;
+
+                libc::mmsghdr {
+                    msg_hdr: hdr,
+                    msg_len: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // SAFETY: all headers point into `addrs` and `iov`, which outlive the call. The kernel
+        // only reads from the payload buffer.
+        let rc = unsafe { libc::sendmmsg(sock.as_raw_fd(), msgs.as_mut_ptr(), msgs.len() as libc::c_uint, 0) };
+        if rc < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(rc as usize)
+    }
+
+    fn sockaddr(addr: &SocketAddr) -> (libc::sockaddr_storage, libc::socklen_t) {
+        // SAFETY: `sockaddr_storage` is a plain C struct, for which all-zeroes is a valid value.
+        let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+
+        let len = match addr {
+            SocketAddr::V4(addr) => {
+                // SAFETY: `sockaddr_storage` is large and aligned enough to hold any address.
+                let v = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+                v.sin_family = libc::AF_INET as libc::sa_family_t;
+                v.sin_port = addr.port().to_be();
+                v.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+                mem::size_of::<libc::sockaddr_in>()
+            }
+            SocketAddr::V6(addr) => {
+                // SAFETY: `sockaddr_storage` is large and aligned enough to hold any address.
+                let v = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+                v.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                v.sin6_port = addr.port().to_be();
+                v.sin6_flowinfo = addr.flowinfo();
+                v.sin6_addr.s6_addr = addr.ip().octets();
+                v.sin6_scope_id = addr.scope_id();
+                mem::size_of::<libc::sockaddr_in6>()
+            }
+        };
+
+        (storage, len as libc::socklen_t)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_fan_out() {
+        let viewers = (0..3)
+            .map(|_| {
+                let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+                sock.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+                sock
+            })
+            .collect::<Vec<_>>();
+
+        let mut fanout = UdpFanOut::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        for viewer in &viewers {
+            fanout.push(viewer.local_addr().unwrap());
+        }
+
+        fanout.send(b"frame").unwrap();
+
+        for viewer in &viewers {
+            let mut buf = [0; 16];
+            let size = viewer.recv(&mut buf).unwrap();
+            assert_eq!(b"frame", &buf[..size]);
+        }
+    }
+
+    #[test]
+    fn test_fan_out_partial_failure() {
+        let viewer = UdpSocket::bind("127.0.0.1:0").unwrap();
+        viewer.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // An IPv4 socket can't reach an IPv6 destination.
+        let mut fanout = UdpFanOut::new(UdpSocket::bind("127.0.0.1:0").unwrap())
+            .with("[::1]:9".parse().unwrap())
+            .with(viewer.local_addr().unwrap());
+
+        fanout.send(b"frame").unwrap();
+
+        let mut buf = [0; 16];
+        let size = viewer.recv(&mut buf).unwrap();
+        assert_eq!(b"frame", &buf[..size]);
+    }
+
+    #[test]
+    fn test_fan_out_all_failed() {
+        let mut fanout = UdpFanOut::new(UdpSocket::bind("127.0.0.1:0").unwrap()).with("[::1]:9".parse().unwrap());
+
+        assert!(fanout.send(b"frame").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use std::{
+    error::Error,
+    io::Write,
+    os::unix::net::{UnixDatagram, UnixStream},
+    path::{Path, PathBuf},
+};
+
+use log::{debug, info};
+
+use super::{Framing, Sink};
+
+/// Socket type of a Unix domain socket destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnixKind {
+    /// Connection-oriented `SOCK_STREAM` socket.
+    Stream,
+    /// Connectionless `SOCK_DGRAM` socket, preserving buffer boundaries.
+    Datagram,
+}
+
+#[derive(Debug)]
+enum Conn {
+    Stream(UnixStream),
+    Datagram(UnixDatagram),
+}
+
+/// Sink writing into a Unix domain socket, for co-located consumers.
+///
+/// Unlike loopback UDP, Unix sockets are not subject to packet loss and datagram size limits
+/// are much larger. Stream sockets carry no buffer boundaries, so buffers are length-prefixed by
+/// default.
+///
+/// The socket is connected lazily. After an I/O error the connection is dropped and reestablished
+/// on the next buffer.
+#[derive(Debug)]
+pub struct UnixSink {
+    path: PathBuf,
+    kind: UnixKind,
+    framing: Framing,
+    conn: Option<Conn>,
+    buf: Vec<u8>,
+}
+
+impl UnixSink {
+    /// Constructs a new sink connecting to the socket at the given path.
+    pub fn new<P: AsRef<Path>>(path: P, kind: UnixKind) -> Self {
+        Self {
+            path: path.as_ref().into(),
+            kind,
+            framing: Framing::LengthPrefixed,
+            conn: None,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sets the framing of buffers written into stream sockets.
+    ///
+    /// Datagram sockets preserve boundaries, so buffers are always sent as is.
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    fn connect(&mut self) -> Result<&mut Conn, Box<dyn Error>> {
+        if self.conn.is_none() {
+            let conn = match self.kind {
+                UnixKind::Stream => Conn::Stream(UnixStream::connect(&self.path)?),
+                UnixKind::Datagram => {
+                    let sock = UnixDatagram::unbound()?;
+                    sock.connect(&self.path)?;
+                    Conn::Datagram(sock)
+                }
+            };
+
+            info!("connected to {}", self.path.display());
+            self.conn = Some(conn);
+        }
+
+        Ok(self.conn.as_mut().expect("socket must be connected"))
+    }
+}
+
+impl Sink for UnixSink {
+    fn send(&mut self, buf: &[u8]) -> Result<(), Box<dyn Error>> {
+        let mut frame = std::mem::take(&mut self.buf);
+        frame.clear();
+        if let (UnixKind::Stream, Framing::LengthPrefixed) = (self.kind, self.framing) {
+            frame.extend_from_slice(&(buf.len() as u32).to_be_bytes());
+        }
+        frame.extend_from_slice(buf);
+
+        let result = match self.connect()? {
+            Conn::Stream(stream) => stream.write_all(&frame),
+            Conn::Datagram(sock) => sock.send(&frame).map(|_| ()),
+        };
+        self.buf = frame;
+
+        if let Err(err) = result {
+            debug!("dropping connection to {}: {}", self.path.display(), err);
+            self.conn = None;
+            return Err(err.into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, io::Read, os::unix::net::UnixListener};
+
+    use super::*;
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("cleverdog-{}-{}.sock", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_stream() {
+        let path = socket_path("stream");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let mut sink = UnixSink::new(&path, UnixKind::Stream);
+        sink.send(b"frame").unwrap();
+        drop(sink);
+
+        let mut buf = Vec::new();
+        listener.accept().unwrap().0.read_to_end(&mut buf).unwrap();
+        assert_eq!(&b"\0\0\0\x05frame"[..], &buf[..]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_datagram() {
+        let path = socket_path("datagram");
+        let sock = UnixDatagram::bind(&path).unwrap();
+
+        let mut sink = UnixSink::new(&path, UnixKind::Datagram);
+        sink.send(b"frame").unwrap();
+
+        let mut buf = [0; 16];
+        let size = sock.recv(&mut buf).unwrap();
+        assert_eq!(b"frame", &buf[..size]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let path = socket_path("reconnect");
+
+        let mut sink = UnixSink::new(&path, UnixKind::Datagram);
+        assert!(sink.send(b"frame").is_err());
+
+        let sock = UnixDatagram::bind(&path).unwrap();
+        sink.send(b"frame").unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(5, sock.recv(&mut buf).unwrap());
+
+        fs::remove_file(path).unwrap();
+    }
+}
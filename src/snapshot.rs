@@ -0,0 +1,267 @@
+//! Still images of the stream, for dashboards and notifications.
+//!
+//! A [`KeyframeGrabber`] picks the next keyframe out of assembled frames, together with the
+//! parameter sets needed to decode it on its own. A [`Decoder`] then turns it into a JPEG image or
+//! raw RGB pixels using an external `ffmpeg` binary, which keeps codec libraries and their
+//! licensing out of this crate. Pair decoding with a [`Budget`](crate::budget::Budget) on weak
+//! hardware.
+
+use std::{
+    io::{self, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::h264::{AnnexB, AnnexBWriter, Frame, Nal, NalType, StreamInfo};
+
+/// Keyframe along with the parameter sets preceding it, as an Annex-B elementary stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keyframe {
+    /// RTP timestamp of the frame.
+    pub timestamp: u32,
+    /// Elementary stream of the SPS, the PPS and the picture.
+    pub data: Vec<u8>,
+}
+
+impl Keyframe {
+    /// Returns the stream parameters decoded from the SPS.
+    pub fn stream_info(&self) -> Option<StreamInfo> {
+        AnnexB::new(&self.data)
+            .filter(|v| v.nal_type() == NalType::Sps)
+            .find_map(|v| StreamInfo::from_sps(&v).ok())
+    }
+}
+
+/// Picks keyframes out of assembled frames.
+///
+/// ```
+/// use cleverdog::{h264::Frame, snapshot::KeyframeGrabber};
+///
+/// let mut grabber = KeyframeGrabber::new();
+/// let params = vec![vec![0x67, 1], vec![0x68, 2]];
+/// assert_eq!(None, grabber.push(&Frame { timestamp: 0, keyframe: false, nals: params }));
+///
+/// let keyframe = grabber.push(&Frame { timestamp: 3600, keyframe: true, nals: vec![vec![0x65, 3]] }).unwrap();
+/// assert_eq!(&[0, 0, 0, 1, 0x67, 1, 0, 0, 0, 1, 0x68, 2, 0, 0, 0, 1, 0x65, 3][..], &keyframe.data[..]);
+/// ```
+#[derive(Debug)]
+pub struct KeyframeGrabber {
+    wr: AnnexBWriter<Vec<u8>>,
+}
+
+impl Default for KeyframeGrabber {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl KeyframeGrabber {
+    /// Constructs a new grabber.
+    pub fn new() -> Self {
+        Self {
+            wr: AnnexBWriter::new(Vec::new()),
+        }
+    }
+
+    /// Consumes the given frame, returning it if it is a keyframe decodable on its own, i.e. once
+    /// both parameter sets are known.
+    pub fn push(&mut self, frame: &Frame) -> Option<Keyframe> {
+        self.wr.get_mut().clear();
+        for nal in frame.nals.iter().filter_map(|v| Nal::new(v)) {
+            // Writing into memory never fails.
+            let _ = self.wr.write_nal(&nal);
+        }
+
+        if !frame.keyframe {
+            return None;
+        }
+
+        let data = self.wr.get_ref();
+        let has = |ty| AnnexB::new(data).any(|v| v.nal_type() == ty);
+        if !(has(NalType::Sps) && has(NalType::Pps) && has(NalType::Idr)) {
+            return None;
+        }
+
+        let keyframe = Keyframe {
+            timestamp: frame.timestamp,
+            data: data.clone(),
+        };
+        Some(keyframe)
+    }
+}
+
+/// Decoded picture as packed 8-bit RGB pixels, row by row.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbImage {
+    /// Width in pixels.
+    pub width: u32,
+    /// Height in pixels.
+    pub height: u32,
+    /// Three bytes per pixel, without any row padding.
+    pub data: Vec<u8>,
+}
+
+/// Decoder of keyframes, running an external `ffmpeg` binary for each of them.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    program: PathBuf,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            program: "ffmpeg".into(),
+        }
+    }
+}
+
+impl Decoder {
+    /// Constructs a new decoder running `ffmpeg` from `PATH`.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the path of the `ffmpeg` binary.
+    pub fn program<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.program = path.as_ref().into();
+        self
+    }
+
+    /// Decodes the given keyframe into a JPEG image.
+    pub fn jpeg(&self, keyframe: &Keyframe) -> Result<Vec<u8>, io::Error> {
+        self.run(keyframe, &["-c:v", "mjpeg", "-q:v", "3", "-f", "image2pipe"])
+    }
+
+    /// Decodes the given keyframe into RGB pixels.
+    pub fn rgb(&self, keyframe: &Keyframe) -> Result<RgbImage, io::Error> {
+        let info = keyframe
+            .stream_info()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "keyframe carries no valid SPS"))?;
+        let data = self.run(keyframe, &["-pix_fmt", "rgb24", "-f", "rawvideo"])?;
+        if data.len() != info.width as usize * info.height as usize * 3 {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "decoded {} bytes of a {}x{} picture",
+                    data.len(),
+                    info.width,
+                    info.height
+                ),
+            ));
+        }
+
+        let image = RgbImage {
+            width: info.width,
+            height: info.height,
+            data,
+        };
+        Ok(image)
+    }
+
+    /// Runs the decoder with the keyframe on its input and the given output options, returning
+    /// its output.
+    fn run(&self, keyframe: &Keyframe, output: &[&str]) -> Result<Vec<u8>, io::Error> {
+        let mut child = Command::new(&self.program)
+            .args([
+                "-hide_banner",
+                "-loglevel",
+                "error",
+                "-f",
+                "h264",
+                "-i",
+                "pipe:0",
+                "-frames:v",
+                "1",
+            ])
+            .args(output)
+            .arg("pipe:1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Feed the input from another thread, so that neither pipe can fill up and block both.
+        let mut stdin = child.stdin.take().expect("stdin must be piped");
+        let data = keyframe.data.clone();
+        let feeder = thread::spawn(move || stdin.write_all(&data));
+
+        let output = child.wait_with_output()?;
+        let fed = feeder.join().unwrap_or(Ok(()));
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(io::Error::other(format!(
+                "{} failed with {}: {}",
+                self.program.display(),
+                output.status,
+                stderr.trim()
+            )));
+        }
+        fed?;
+
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_grab_repeats_parameter_sets() {
+        let mut grabber = KeyframeGrabber::new();
+        // No parameter sets yet.
+        assert_eq!(
+            None,
+            grabber.push(&Frame {
+                timestamp: 0,
+                keyframe: true,
+                nals: vec![vec![0x65, 1]],
+            })
+        );
+
+        let frames = [
+            Frame {
+                timestamp: 3600,
+                keyframe: true,
+                nals: vec![vec![0x67, 1], vec![0x68, 2], vec![0x65, 3], vec![0x65, 4]],
+            },
+            Frame {
+                timestamp: 7200,
+                keyframe: false,
+                nals: vec![vec![0x41, 5]],
+            },
+        ];
+        let keyframe = grabber.push(&frames[0]).unwrap();
+        assert_eq!(3600, keyframe.timestamp);
+        assert_eq!(None, grabber.push(&frames[1]));
+
+        // Parameter sets are remembered for the next keyframe lacking them.
+        let keyframe = grabber
+            .push(&Frame {
+                timestamp: 10800,
+                keyframe: true,
+                nals: vec![vec![0x65, 6]],
+            })
+            .unwrap();
+        assert_eq!(
+            &[0, 0, 0, 1, 0x67, 1, 0, 0, 0, 1, 0x68, 2, 0, 0, 0, 1, 0x65, 6][..],
+            &keyframe.data[..]
+        );
+    }
+
+    #[test]
+    fn test_missing_decoder() {
+        let keyframe = Keyframe {
+            timestamp: 0,
+            data: vec![0, 0, 0, 1, 0x65, 1],
+        };
+        let err = Decoder::new()
+            .program("/nonexistent/ffmpeg")
+            .jpeg(&keyframe)
+            .unwrap_err();
+
+        assert_eq!(ErrorKind::NotFound, err.kind());
+    }
+}
@@ -0,0 +1,412 @@
+//! Long-run stability reports.
+//!
+//! A soak run streams from a camera for hours, sampling streaming statistics and memory usage of
+//! the process at a fixed interval and recording every stall and reconnect. The resulting
+//! [`Report`] is written as JSON for tooling and as a self-contained HTML page for people
+//! validating new firmware or crate releases.
+
+use core::{
+    fmt::{self, Display, Formatter},
+    time::Duration,
+};
+use std::{
+    io::{self, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{json, stats::StatsSnapshot};
+
+/// Kind of a notable soak run event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// A streaming session has been started.
+    Started,
+    /// The camera stopped sending video and the session restarted it.
+    Stalled,
+    /// The streaming session ended with an error and is about to be reconnected.
+    Disconnected,
+}
+
+impl EventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Started => "started",
+            EventKind::Stalled => "stalled",
+            EventKind::Disconnected => "disconnected",
+        }
+    }
+}
+
+impl Display for EventKind {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        fmt.write_str(self.as_str())
+    }
+}
+
+/// Notable event of a soak run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// Time since the start of the run.
+    pub elapsed: Duration,
+    pub kind: EventKind,
+    pub detail: String,
+}
+
+/// Periodic sample of a soak run, with counters accumulated over all sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// Time since the start of the run.
+    pub elapsed: Duration,
+    pub packets_received: u64,
+    pub bytes_received: u64,
+    pub packets_lost: u64,
+    /// Current video bitrate in bits per second.
+    pub bitrate: u64,
+    /// Current interarrival jitter.
+    pub jitter: Duration,
+    /// Resident memory of the process in bytes, if it could be measured.
+    pub memory: Option<u64>,
+}
+
+/// Report of a soak run, built up while the run progresses.
+///
+/// Streaming statistics restart from zero with each session, so the report carries the totals of
+/// finished sessions over, keeping the sampled counters monotonic across reconnects.
+///
+/// ```
+/// use core::time::Duration;
+/// use std::time::SystemTime;
+///
+/// use cleverdog::{soak::{EventKind, Report}, stats::StatsSnapshot};
+///
+/// let mut report = Report::new(SystemTime::now());
+/// report.event(Duration::ZERO, EventKind::Started, "");
+///
+/// let stats = StatsSnapshot { packets_received: 100, ..Default::default() };
+/// report.sample(Duration::from_secs(60), &stats, Some(8 << 20));
+/// report.disconnected(Duration::from_secs(90), &stats, "connection refused");
+/// report.sample(Duration::from_secs(120), &StatsSnapshot { packets_received: 20, ..Default::default() }, None);
+///
+/// assert_eq!(120, report.samples().last().unwrap().packets_received);
+/// assert_eq!(1, report.reconnects());
+/// ```
+#[derive(Debug, Clone)]
+pub struct Report {
+    started: SystemTime,
+    samples: Vec<Sample>,
+    events: Vec<Event>,
+    /// Totals of finished sessions.
+    base: StatsSnapshot,
+    /// Restarts of the current session seen by the last sample.
+    restarts: u64,
+}
+
+impl Report {
+    /// Constructs a new empty report of a run started at the given time.
+    pub fn new(started: SystemTime) -> Self {
+        Self {
+            started,
+            samples: Vec::new(),
+            events: Vec::new(),
+            base: StatsSnapshot::default(),
+            restarts: 0,
+        }
+    }
+
+    /// Returns samples, in the order they were taken.
+    #[inline]
+    pub fn samples(&self) -> &[Sample] {
+        &self.samples
+    }
+
+    /// Returns events, in the order they occurred.
+    #[inline]
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Returns the number of times the session has been reconnected.
+    pub fn reconnects(&self) -> usize {
+        self.count(EventKind::Disconnected)
+    }
+
+    /// Returns the number of stalls recovered from by restarting the stream.
+    pub fn stalls(&self) -> usize {
+        self.count(EventKind::Stalled)
+    }
+
+    /// Returns the highest sampled resident memory.
+    pub fn peak_memory(&self) -> Option<u64> {
+        self.samples.iter().filter_map(|v| v.memory).max()
+    }
+
+    fn count(&self, kind: EventKind) -> usize {
+        self.events.iter().filter(|v| v.kind == kind).count()
+    }
+
+    /// Records an event.
+    pub fn event(&mut self, elapsed: Duration, kind: EventKind, detail: &str) {
+        self.events.push(Event {
+            elapsed,
+            kind,
+            detail: detail.into(),
+        });
+    }
+
+    /// Records a sample of the current session statistics, along with stall events for restarts
+    /// since the previous sample.
+    pub fn sample(&mut self, elapsed: Duration, stats: &StatsSnapshot, memory: Option<u64>) {
+        for _ in self.restarts..stats.restarts {
+            self.event(elapsed, EventKind::Stalled, "");
+        }
+        self.restarts = self.restarts.max(stats.restarts);
+
+        self.samples.push(Sample {
+            elapsed,
+            packets_received: self.base.packets_received + stats.packets_received,
+            bytes_received: self.base.bytes_received + stats.bytes_received,
+            packets_lost: self.base.packets_lost + stats.packets_lost,
+            bitrate: stats.bitrate,
+            jitter: stats.jitter,
+            memory,
+        });
+    }
+
+    /// Records the end of the current session with the given final statistics and error.
+    pub fn disconnected(&mut self, elapsed: Duration, stats: &StatsSnapshot, detail: &str) {
+        self.sample(elapsed, stats, None);
+        self.base.packets_received += stats.packets_received;
+        self.base.bytes_received += stats.bytes_received;
+        self.base.packets_lost += stats.packets_lost;
+        self.restarts = 0;
+        self.event(elapsed, EventKind::Disconnected, detail);
+    }
+
+    /// Encodes the report as JSON.
+    pub fn encode<W: Write>(&self, wr: &mut W) -> Result<(), io::Error> {
+        let last = self.samples.last();
+        let mut buf = String::new();
+
+        buf.push_str(&format!(
+            "{{\"started_ms\":{},\"duration_s\":{},\"packets_received\":{},\"packets_lost\":{},\
+             \"reconnects\":{},\"stalls\":{},\"peak_memory\":{},\"samples\":[",
+            self.started.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis(),
+            last.map(|v| v.elapsed.as_secs()).unwrap_or(0),
+            last.map(|v| v.packets_received).unwrap_or(0),
+            last.map(|v| v.packets_lost).unwrap_or(0),
+            self.reconnects(),
+            self.stalls(),
+            optional(self.peak_memory()),
+        ));
+        for (idx, v) in self.samples.iter().enumerate() {
+            if idx > 0 {
+                buf.push(',');
+            }
+            buf.push_str(&format!(
+                "{{\"at_s\":{},\"packets_received\":{},\"bytes_received\":{},\"packets_lost\":{},\
+                 \"bitrate\":{},\"jitter_us\":{},\"memory\":{}}}",
+                v.elapsed.as_secs(),
+                v.packets_received,
+                v.bytes_received,
+                v.packets_lost,
+                v.bitrate,
+                v.jitter.as_micros(),
+                optional(v.memory),
+            ));
+        }
+        buf.push_str("],\"events\":[");
+        for (idx, v) in self.events.iter().enumerate() {
+            if idx > 0 {
+                buf.push(',');
+            }
+            buf.push_str(&format!(
+                "{{\"at_s\":{},\"kind\":\"{}\",\"detail\":",
+                v.elapsed.as_secs(),
+                v.kind
+            ));
+            json::push_str(&mut buf, &v.detail);
+            buf.push('}');
+        }
+        buf.push_str("]}\n");
+
+        wr.write_all(buf.as_bytes())
+    }
+
+    /// Encodes the report as a self-contained HTML page.
+    pub fn encode_html<W: Write>(&self, wr: &mut W) -> Result<(), io::Error> {
+        let last = self.samples.last();
+
+        writeln!(wr, "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">")?;
+        writeln!(wr, "<title>cleverdog soak report</title>")?;
+        writeln!(
+            wr,
+            "<style>body{{font-family:sans-serif}}td,th{{padding:2px 8px;text-align:right}}</style>"
+        )?;
+        writeln!(wr, "</head>\n<body>\n<h1>Soak report</h1>\n<table>")?;
+        writeln!(
+            wr,
+            "<tr><th>Duration</th><td>{}</td></tr>",
+            format_duration(last.map(|v| v.elapsed).unwrap_or_default())
+        )?;
+        writeln!(
+            wr,
+            "<tr><th>Packets</th><td>{}</td></tr>",
+            last.map(|v| v.packets_received).unwrap_or(0)
+        )?;
+        writeln!(
+            wr,
+            "<tr><th>Lost</th><td>{}</td></tr>",
+            last.map(|v| v.packets_lost).unwrap_or(0)
+        )?;
+        writeln!(wr, "<tr><th>Reconnects</th><td>{}</td></tr>", self.reconnects())?;
+        writeln!(wr, "<tr><th>Stalls</th><td>{}</td></tr>", self.stalls())?;
+        if let Some(memory) = self.peak_memory() {
+            writeln!(wr, "<tr><th>Peak memory</th><td>{} KiB</td></tr>", memory / 1024)?;
+        }
+        writeln!(wr, "</table>")?;
+
+        writeln!(
+            wr,
+            "<h2>Events</h2>\n<table>\n<tr><th>At</th><th>Event</th><th>Detail</th></tr>"
+        )?;
+        for v in &self.events {
+            writeln!(
+                wr,
+                "<tr><td>{}</td><td>{}</td><td>{}</td></tr>",
+                format_duration(v.elapsed),
+                v.kind,
+                escape_html(&v.detail)
+            )?;
+        }
+        writeln!(wr, "</table>")?;
+
+        writeln!(
+            wr,
+            "<h2>Samples</h2>\n<table>\n<tr><th>At</th><th>Packets</th><th>Lost</th><th>Bitrate, kbit/s</th>\
+             <th>Jitter, ms</th><th>Memory, KiB</th></tr>"
+        )?;
+        for v in &self.samples {
+            writeln!(
+                wr,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}</td><td>{}</td></tr>",
+                format_duration(v.elapsed),
+                v.packets_received,
+                v.packets_lost,
+                v.bitrate / 1000,
+                v.jitter.as_secs_f64() * 1000.0,
+                v.memory.map(|v| (v / 1024).to_string()).unwrap_or_default()
+            )?;
+        }
+        writeln!(wr, "</table>\n</body>\n</html>")
+    }
+}
+
+fn optional(v: Option<u64>) -> String {
+    v.map(|v| v.to_string()).unwrap_or_else(|| "null".into())
+}
+
+fn format_duration(v: Duration) -> String {
+    let secs = v.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+fn escape_html(v: &str) -> String {
+    let mut buf = String::with_capacity(v.len());
+    for ch in v.chars() {
+        match ch {
+            '<' => buf.push_str("&lt;"),
+            '>' => buf.push_str("&gt;"),
+            '&' => buf.push_str("&amp;"),
+            '"' => buf.push_str("&quot;"),
+            ch => buf.push(ch),
+        }
+    }
+    buf
+}
+
+/// Returns the resident memory of the current process in bytes.
+#[cfg(target_os = "linux")]
+pub fn resident_memory() -> Result<u64, io::Error> {
+    let status = std::fs::read_to_string("/proc/self/status")?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|v| v.trim().trim_end_matches("kB").trim().parse::<u64>().ok())
+        .map(|v| v * 1024)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "no VmRSS in /proc/self/status"))
+}
+
+/// Returns the resident memory of the current process in bytes.
+#[cfg(not(target_os = "linux"))]
+pub fn resident_memory() -> Result<u64, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "measuring memory usage is not supported on this platform",
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn report() -> Report {
+        let mut report = Report::new(UNIX_EPOCH + Duration::from_secs(1_500_000_000));
+        report.event(Duration::ZERO, EventKind::Started, "");
+
+        let stats = StatsSnapshot {
+            packets_received: 100,
+            bytes_received: 1000,
+            restarts: 1,
+            ..Default::default()
+        };
+        report.sample(Duration::from_secs(60), &stats, Some(4096));
+        report.disconnected(Duration::from_secs(61), &stats, "timed <out>");
+
+        let stats = StatsSnapshot {
+            packets_received: 10,
+            bytes_received: 100,
+            packets_lost: 2,
+            ..Default::default()
+        };
+        report.sample(Duration::from_secs(120), &stats, Some(8192));
+        report
+    }
+
+    #[test]
+    fn test_accumulate_across_sessions() {
+        let report = report();
+
+        assert_eq!(110, report.samples().last().unwrap().packets_received);
+        assert_eq!(1, report.stalls());
+        assert_eq!(1, report.reconnects());
+        assert_eq!(Some(8192), report.peak_memory());
+    }
+
+    #[test]
+    fn test_encode() {
+        let mut buf = Vec::new();
+        report().encode(&mut buf).unwrap();
+        let buf = String::from_utf8(buf).unwrap();
+
+        assert!(buf.starts_with(
+            "{\"started_ms\":1500000000000,\"duration_s\":120,\"packets_received\":110,\"packets_lost\":2,\
+             \"reconnects\":1,\"stalls\":1,\"peak_memory\":8192,\"samples\":[{\"at_s\":60,"
+        ));
+        assert!(buf.ends_with("{\"at_s\":61,\"kind\":\"disconnected\",\"detail\":\"timed <out>\"}]}\n"));
+    }
+
+    #[test]
+    fn test_encode_html_escapes() {
+        let mut buf = Vec::new();
+        report().encode_html(&mut buf).unwrap();
+        let buf = String::from_utf8(buf).unwrap();
+
+        assert!(buf.contains("<td>0:01:01</td><td>disconnected</td><td>timed &lt;out&gt;</td>"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_resident_memory() {
+        assert!(resident_memory().unwrap() > 0);
+    }
+}
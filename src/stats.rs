@@ -0,0 +1,278 @@
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use std::time::Instant;
+
+use crate::bandwidth::RateEstimator;
+
+/// RTP clock rate of the video stream, in Hz.
+const CLOCK_RATE: f64 = 90_000.0;
+/// Half-life of the bitrate moving average.
+const BITRATE_HALF_LIFE: Duration = Duration::from_secs(2);
+
+/// Streaming statistics, updated lock-free from the receive loop.
+///
+/// Counters are relaxed atomics, so they can be polled cheaply from monitoring threads while the
+/// stream is running. Use [`Stats::snapshot`] to read a consistent-enough copy of all counters.
+#[derive(Debug, Default)]
+pub struct Stats {
+    packets_received: AtomicU64,
+    bytes_received: AtomicU64,
+    packets_delivered: AtomicU64,
+    packets_skipped: AtomicU64,
+    packets_non_video: AtomicU64,
+    packets_invalid: AtomicU64,
+    rtcp_received: AtomicU64,
+    rtcp_sent: AtomicU64,
+    nacks_sent: AtomicU64,
+    packets_recovered: AtomicU64,
+    restarts: AtomicU64,
+    packets_lost: AtomicU64,
+    gaps: AtomicU64,
+    jitter_us: AtomicU64,
+    bitrate: AtomicU64,
+}
+
+impl Stats {
+    /// Constructs new zeroed statistics.
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of the current counter values.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            packets_received: self.packets_received.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            packets_delivered: self.packets_delivered.load(Ordering::Relaxed),
+            packets_skipped: self.packets_skipped.load(Ordering::Relaxed),
+            packets_non_video: self.packets_non_video.load(Ordering::Relaxed),
+            packets_invalid: self.packets_invalid.load(Ordering::Relaxed),
+            rtcp_received: self.rtcp_received.load(Ordering::Relaxed),
+            rtcp_sent: self.rtcp_sent.load(Ordering::Relaxed),
+            nacks_sent: self.nacks_sent.load(Ordering::Relaxed),
+            packets_recovered: self.packets_recovered.load(Ordering::Relaxed),
+            restarts: self.restarts.load(Ordering::Relaxed),
+            packets_lost: self.packets_lost.load(Ordering::Relaxed),
+            gaps: self.gaps.load(Ordering::Relaxed),
+            jitter: Duration::from_micros(self.jitter_us.load(Ordering::Relaxed)),
+            bitrate: self.bitrate.load(Ordering::Relaxed),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn on_received(&self, size: usize) {
+        self.packets_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received.fetch_add(size as u64, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_delivered(&self) {
+        self.packets_delivered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_skipped(&self) {
+        self.packets_skipped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_non_video(&self) {
+        self.packets_skipped.fetch_add(1, Ordering::Relaxed);
+        self.packets_non_video.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_invalid(&self) {
+        self.packets_skipped.fetch_add(1, Ordering::Relaxed);
+        self.packets_invalid.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_rtcp_received(&self) {
+        self.rtcp_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_rtcp_sent(&self) {
+        self.rtcp_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_nack_sent(&self) {
+        self.nacks_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_recovered(&self) {
+        self.packets_recovered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_gap(&self) {
+        self.gaps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub(crate) fn on_restart(&self) {
+        self.restarts.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Tracks stream quality of video packets: loss from sequence gaps and the current bitrate,
+/// publishing them into [`Stats`] along with the interarrival jitter.
+///
+/// The jitter is estimated by the [`Reception`](crate::rtp::Reception) statistics RTCP reports
+/// are built from, so that both always agree.
+#[derive(Debug)]
+pub(crate) struct Quality {
+    seq: Option<u16>,
+    rate: RateEstimator,
+}
+
+impl Quality {
+    pub fn new() -> Self {
+        Self {
+            seq: None,
+            rate: RateEstimator::new(BITRATE_HALF_LIFE),
+        }
+    }
+
+    /// Accounts a video packet with the given sequence number and size, received at the specified
+    /// time, along with the jitter in RTP timestamp units including it.
+    pub fn on_packet(&mut self, stats: &Stats, seq: u16, jitter: u32, size: usize, arrival: Instant) {
+        match self.seq.map(|v| seq.wrapping_sub(v)) {
+            // Duplicated or reordered packets, which are already accounted by the gap before.
+            Some(0) | Some(0x8000..=0xffff) => {}
+            Some(delta) => {
+                stats.packets_lost.fetch_add(u64::from(delta - 1), Ordering::Relaxed);
+                self.seq = Some(seq);
+            }
+            None => self.seq = Some(seq),
+        }
+
+        let jitter_us = (f64::from(jitter) / CLOCK_RATE * 1e6) as u64;
+        stats.jitter_us.store(jitter_us, Ordering::Relaxed);
+
+        self.rate.push(size, arrival);
+        if let Some(rate) = self.rate.rate() {
+            stats.bitrate.store(rate as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Point-in-time copy of streaming statistics.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Number of datagrams received from the camera.
+    pub packets_received: u64,
+    /// Number of bytes received from the camera.
+    pub bytes_received: u64,
+    /// Number of RTP packets passed to the callback.
+    pub packets_delivered: u64,
+    /// Number of datagrams skipped because they were malformed or filtered out.
+    pub packets_skipped: u64,
+    /// Number of skipped RTP packets that belong to other streams than video, e.g. audio.
+    pub packets_non_video: u64,
+    /// Number of skipped datagrams that failed to parse as RTP, e.g. too short or of a wrong
+    /// RTP version.
+    pub packets_invalid: u64,
+    /// Number of RTCP sender reports received from the camera, not counted as RTP packets.
+    pub rtcp_received: u64,
+    /// Number of RTCP keepalive reports sent to the camera.
+    pub rtcp_sent: u64,
+    /// Number of RTCP NACKs sent to the camera, see [`StreamOptions::nack`](crate::StreamOptions::nack).
+    pub nacks_sent: u64,
+    /// Number of packets requested by NACKs that have arrived afterwards, i.e. were
+    /// retransmitted by the camera or merely reordered.
+    pub packets_recovered: u64,
+    /// Number of times the StartRtp command has been re-sent to a stalled camera.
+    pub restarts: u64,
+    /// Number of video packets missing from the sequence.
+    pub packets_lost: u64,
+    /// Number of gaps in the sequence of packets passed to the callback, see
+    /// [`RtpPacket::gap`](crate::rtp::RtpPacket::gap).
+    pub gaps: u64,
+    /// Interarrival jitter of video packets, as defined in RFC 3550.
+    pub jitter: Duration,
+    /// Current video bitrate in bits per second, averaged over a few seconds.
+    pub bitrate: u64,
+}
+
+impl StatsSnapshot {
+    /// Returns the fraction of received datagrams that failed to parse as RTP.
+    pub fn invalid_ratio(&self) -> f64 {
+        match self.packets_received {
+            0 => 0.0,
+            n => self.packets_invalid as f64 / n as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let stats = Stats::new();
+        stats.on_received(100);
+        stats.on_received(50);
+        stats.on_delivered();
+        stats.on_skipped();
+        stats.on_non_video();
+        stats.on_invalid();
+        stats.on_rtcp_received();
+        stats.on_rtcp_sent();
+        stats.on_restart();
+
+        let expected = StatsSnapshot {
+            packets_received: 2,
+            bytes_received: 150,
+            packets_delivered: 1,
+            packets_skipped: 3,
+            packets_non_video: 1,
+            packets_invalid: 1,
+            rtcp_received: 1,
+            rtcp_sent: 1,
+            restarts: 1,
+            ..Default::default()
+        };
+        assert_eq!(expected, stats.snapshot());
+        assert_eq!(0.5, stats.snapshot().invalid_ratio());
+    }
+
+    #[test]
+    fn test_quality() {
+        let stats = Stats::new();
+        let mut quality = Quality::new();
+        let start = Instant::now();
+
+        // 25 fps, packets 3 and 4 lost, packet 1 duplicated, 2ms of jitter.
+        for seq in [0u16, 1, 1, 2, 5, 6, 7, 8] {
+            let arrival = start + Duration::from_millis(40 * u64::from(seq));
+            quality.on_packet(&stats, seq, 180, 1000, arrival);
+        }
+
+        let snapshot = stats.snapshot();
+        assert_eq!(2, snapshot.packets_lost);
+        assert_eq!(Duration::from_millis(2), snapshot.jitter);
+        // 1000 bytes every 40ms.
+        assert!((150_000..250_000).contains(&snapshot.bitrate));
+    }
+
+    #[test]
+    fn test_quality_sequence_wraps() {
+        let stats = Stats::new();
+        let mut quality = Quality::new();
+        let start = Instant::now();
+
+        for seq in [0xfffeu16, 0xffff, 1] {
+            quality.on_packet(&stats, seq, 0, 100, start);
+        }
+
+        assert_eq!(1, stats.snapshot().packets_lost);
+    }
+}
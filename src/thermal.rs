@@ -0,0 +1,211 @@
+//! Host temperature monitoring for fanless devices.
+//!
+//! Fanless boxes running 24/7 throttle their CPUs unpredictably once they overheat, stalling the
+//! stream. The monitor reads Linux hwmon sensors and, once the hottest one exceeds the high
+//! threshold, switches into throttled mode, in which applications are expected to shed load: fall
+//! back to the sub stream and disable features that decode the stream. Normal mode is restored
+//! once the temperature drops below the low threshold.
+
+use core::fmt::{self, Display, Formatter};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+
+use crate::bandwidth::Profile;
+
+/// Directory Linux exposes hardware monitoring devices in.
+const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+/// Operation mode selected by a [`ThermalMonitor`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Full operation.
+    Normal,
+    /// Reduced load operation.
+    Throttled,
+}
+
+impl Mode {
+    /// Returns the stream profile recommended in this mode.
+    #[inline]
+    pub fn profile(&self) -> Profile {
+        match self {
+            Mode::Normal => Profile::Main,
+            Mode::Throttled => Profile::Sub,
+        }
+    }
+
+    /// Returns `true` if features decoding the stream, such as audio level metering, are allowed.
+    #[inline]
+    pub fn allows_decode(&self) -> bool {
+        match self {
+            Mode::Normal => true,
+            Mode::Throttled => false,
+        }
+    }
+}
+
+impl Display for Mode {
+    fn fmt(&self, fmt: &mut Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Mode::Normal => fmt.write_str("normal"),
+            Mode::Throttled => fmt.write_str("throttled"),
+        }
+    }
+}
+
+/// Event emitted when the operation mode changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThermalEvent {
+    /// The new operation mode.
+    pub mode: Mode,
+    /// Temperature in degrees Celsius that triggered the change.
+    pub celsius: f32,
+}
+
+/// Switches between normal and throttled operation based on the host temperature.
+///
+/// ```
+/// use cleverdog::thermal::{Mode, ThermalMonitor};
+///
+/// let mut monitor = ThermalMonitor::new(80.0, 70.0);
+///
+/// assert_eq!(Mode::Throttled, monitor.update(85.0).unwrap().mode);
+/// assert_eq!(None, monitor.update(75.0));
+/// assert_eq!(Mode::Normal, monitor.update(65.0).unwrap().mode);
+/// ```
+#[derive(Debug, Clone)]
+pub struct ThermalMonitor {
+    high: f32,
+    low: f32,
+    root: PathBuf,
+    mode: Mode,
+}
+
+impl ThermalMonitor {
+    /// Constructs a new monitor, throttling above `high` and recovering below `low` degrees
+    /// Celsius.
+    pub fn new(high: f32, low: f32) -> Self {
+        Self {
+            high,
+            low,
+            root: HWMON_ROOT.into(),
+            mode: Mode::Normal,
+        }
+    }
+
+    /// Sets the directory hwmon devices are read from.
+    ///
+    /// Defaults to `/sys/class/hwmon`.
+    pub fn root<P: AsRef<Path>>(mut self, root: P) -> Self {
+        self.root = root.as_ref().into();
+        self
+    }
+
+    /// Returns the current operation mode.
+    #[inline]
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Updates the mode with the given temperature, returning an event if it has changed.
+    pub fn update(&mut self, celsius: f32) -> Option<ThermalEvent> {
+        let mode = match self.mode {
+            Mode::Normal if celsius >= self.high => Mode::Throttled,
+            Mode::Throttled if celsius < self.low => Mode::Normal,
+            mode => mode,
+        };
+
+        if mode == self.mode {
+            return None;
+        }
+
+        match mode {
+            Mode::Throttled => warn!("host temperature is {:.1}°C, throttling", celsius),
+            Mode::Normal => info!("host temperature is {:.1}°C, resuming normal operation", celsius),
+        }
+
+        self.mode = mode;
+        Some(ThermalEvent { mode, celsius })
+    }
+
+    /// Reads the current temperature from hwmon sensors and updates the mode with it.
+    pub fn poll(&mut self) -> Result<Option<ThermalEvent>, io::Error> {
+        let celsius = read_hwmon(&self.root)?;
+        Ok(self.update(celsius))
+    }
+}
+
+/// Returns the highest temperature in degrees Celsius reported by hwmon devices under the given
+/// directory.
+///
+/// Sensors report millidegrees in `hwmonN/tempM_input` files.
+pub fn read_hwmon<P: AsRef<Path>>(root: P) -> Result<f32, io::Error> {
+    let mut max: Option<i64> = None;
+
+    for device in fs::read_dir(root)? {
+        let device = device?.path();
+        let entries = match fs::read_dir(&device) {
+            Ok(v) => v,
+            Err(..) => continue,
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let name = entry.file_name();
+            let is_input = name
+                .to_str()
+                .map(|v| v.starts_with("temp") && v.ends_with("_input"))
+                .unwrap_or(false);
+            if !is_input {
+                continue;
+            }
+
+            // Sensors of powered down devices fail to read, skip them.
+            let value = fs::read_to_string(entry.path())
+                .ok()
+                .and_then(|v| v.trim().parse::<i64>().ok());
+            if let Some(value) = value {
+                max = Some(max.map_or(value, |max| max.max(value)));
+            }
+        }
+    }
+
+    match max {
+        Some(v) => Ok(v as f32 / 1000.0),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no temperature sensors found")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn test_read_hwmon() {
+        let root = env::temp_dir().join(format!("cleverdog-hwmon-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("hwmon0")).unwrap();
+        fs::create_dir_all(root.join("hwmon1")).unwrap();
+        fs::write(root.join("hwmon0/temp1_input"), "45000\n").unwrap();
+        fs::write(root.join("hwmon0/temp1_label"), "Core 0\n").unwrap();
+        fs::write(root.join("hwmon1/temp2_input"), "81500\n").unwrap();
+
+        let mut monitor = ThermalMonitor::new(80.0, 70.0).root(&root);
+        assert_eq!(
+            Some(ThermalEvent {
+                mode: Mode::Throttled,
+                celsius: 81.5
+            }),
+            monitor.poll().unwrap()
+        );
+        assert_eq!(Profile::Sub, monitor.mode().profile());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
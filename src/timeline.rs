@@ -0,0 +1,182 @@
+use core::time::Duration;
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Period of time during which no decodable video was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    /// Time of the last frame received before the gap.
+    pub start: SystemTime,
+    /// Time of the first frame received after the gap, or the end of the segment.
+    pub end: SystemTime,
+}
+
+impl Gap {
+    /// Returns the gap duration.
+    #[inline]
+    pub fn duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+}
+
+/// Tracks gaps in a recording timeline, such as camera stalls and reconnects.
+///
+/// Feed it with the arrival time of every recorded frame; any silence longer than the configured
+/// threshold is reported as a [`Gap`].
+#[derive(Debug, Clone)]
+pub struct Timeline {
+    threshold: Duration,
+    start: SystemTime,
+    last: Option<SystemTime>,
+    gaps: Vec<Gap>,
+}
+
+impl Timeline {
+    /// Constructs a new timeline for a segment started at the given time.
+    pub fn new(start: SystemTime, threshold: Duration) -> Self {
+        Self {
+            threshold,
+            start,
+            last: None,
+            gaps: Vec::new(),
+        }
+    }
+
+    /// Records a frame received at the given time.
+    pub fn on_frame(&mut self, at: SystemTime) {
+        self.push_gap(self.last.unwrap_or(self.start), at);
+        self.last = Some(at);
+    }
+
+    /// Finishes the segment at the given time, returning all gaps found, including the trailing
+    /// one.
+    pub fn finish(mut self, at: SystemTime) -> Vec<Gap> {
+        self.push_gap(self.last.unwrap_or(self.start), at);
+        self.gaps
+    }
+
+    /// Returns gaps found so far.
+    #[inline]
+    pub fn gaps(&self) -> &[Gap] {
+        &self.gaps
+    }
+
+    fn push_gap(&mut self, start: SystemTime, end: SystemTime) {
+        let gap = Gap { start, end };
+        if gap.duration() > self.threshold {
+            self.gaps.push(gap);
+        }
+    }
+}
+
+/// Returns the path of the gap report for the given recording segment, e.g.
+/// `camera-0001.gaps.json` for `camera-0001.mkv`.
+pub fn report_path(segment: &Path) -> PathBuf {
+    segment.with_extension("gaps.json")
+}
+
+/// Writes the machine-readable gap report for the given recording segment next to it.
+pub fn write_report(segment: &Path, gaps: &[Gap]) -> Result<(), io::Error> {
+    let mut wr = BufWriter::new(File::create(report_path(segment))?);
+    encode_report(&mut wr, gaps)?;
+    wr.flush()
+}
+
+/// Encodes the gap report as JSON, with times represented as milliseconds since the UNIX epoch.
+pub fn encode_report<W: Write>(wr: &mut W, gaps: &[Gap]) -> Result<(), io::Error> {
+    wr.write_all(b"{\"gaps\":[")?;
+
+    for (idx, gap) in gaps.iter().enumerate() {
+        if idx > 0 {
+            wr.write_all(b",")?;
+        }
+
+        write!(
+            wr,
+            "{{\"start_ms\":{},\"end_ms\":{},\"duration_ms\":{}}}",
+            unix_millis(gap.start),
+            unix_millis(gap.end),
+            gap.duration().as_millis()
+        )?;
+    }
+
+    wr.write_all(b"]}\n")
+}
+
+fn unix_millis(v: SystemTime) -> u128 {
+    v.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_gaps() {
+        let mut timeline = Timeline::new(at(100), Duration::from_secs(2));
+        timeline.on_frame(at(101));
+        timeline.on_frame(at(102));
+        timeline.on_frame(at(110));
+        timeline.on_frame(at(111));
+
+        let gaps = timeline.finish(at(120));
+        assert_eq!(
+            vec![
+                Gap {
+                    start: at(102),
+                    end: at(110)
+                },
+                Gap {
+                    start: at(111),
+                    end: at(120)
+                }
+            ],
+            gaps
+        );
+    }
+
+    #[test]
+    fn test_leading_gap() {
+        let mut timeline = Timeline::new(at(100), Duration::from_secs(2));
+        timeline.on_frame(at(105));
+
+        assert_eq!(
+            &[Gap {
+                start: at(100),
+                end: at(105)
+            }],
+            timeline.gaps()
+        );
+    }
+
+    #[test]
+    fn test_encode_report() {
+        let mut buf = Vec::new();
+        let gaps = [Gap {
+            start: at(102),
+            end: at(110),
+        }];
+        encode_report(&mut buf, &gaps).unwrap();
+
+        assert_eq!(
+            "{\"gaps\":[{\"start_ms\":102000,\"end_ms\":110000,\"duration_ms\":8000}]}\n",
+            String::from_utf8(buf).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_report_path() {
+        assert_eq!(
+            Path::new("/var/rec/camera-0001.gaps.json"),
+            report_path(Path::new("/var/rec/camera-0001.mkv"))
+        );
+    }
+}